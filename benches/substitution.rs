@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use std::collections::HashMap;
-use varsubst::substitute;
+use varsubst::{substitute, Template};
 
 fn bench_single_variable(c: &mut Criterion) {
     let mut vars = HashMap::new();
@@ -72,11 +72,28 @@ fn bench_many_lookups(c: &mut Criterion) {
         // Template that references a few variables
         let template = "${VAR1} ${VAR5} ${VAR10}";
 
-        group.bench_with_input(
-            BenchmarkId::new("map_size", map_size),
-            map_size,
-            |b, _| b.iter(|| substitute(black_box(template), black_box(&vars))),
-        );
+        group.bench_with_input(BenchmarkId::new("map_size", map_size), map_size, |b, _| {
+            b.iter(|| substitute(black_box(template), black_box(&vars)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_many_references(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reference_lookups");
+
+    // Lookup-heavy: every reference is a map lookup, so hashing dominates
+    for count in [100, 1000].iter() {
+        let vars: HashMap<String, String> = (0..*count)
+            .map(|i| (format!("VARIABLE_NAME_{}", i), format!("v{}", i)))
+            .collect();
+        let template: String = (0..*count)
+            .map(|i| format!("${{VARIABLE_NAME_{}}} ", i))
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("references", count), count, |b, _| {
+            b.iter(|| substitute(black_box(&template), black_box(&vars)))
+        });
     }
     group.finish();
 }
@@ -138,6 +155,27 @@ fn bench_real_world_template(c: &mut Criterion) {
     });
 }
 
+fn bench_template_render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render_many");
+
+    let vars: HashMap<String, String> = (0..20)
+        .map(|i| (format!("VAR{}", i), format!("value{}", i)))
+        .collect();
+    let text: String = (0..20)
+        .map(|i| format!("key{} = ${{VAR{}}}\n", i, i))
+        .collect::<String>()
+        .repeat(5);
+    let template = Template::parse(&text).unwrap();
+
+    group.bench_function("substitute", |b| {
+        b.iter(|| substitute(black_box(&text), black_box(&vars)))
+    });
+    group.bench_function("Template::render", |b| {
+        b.iter(|| black_box(&template).render(black_box(&vars)))
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_single_variable,
@@ -145,8 +183,10 @@ criterion_group!(
     bench_fast_path_no_variables,
     bench_large_template,
     bench_many_lookups,
+    bench_many_references,
     bench_undefined_variables,
     bench_escape_sequences,
-    bench_real_world_template
+    bench_real_world_template,
+    bench_template_render
 );
 criterion_main!(benches);
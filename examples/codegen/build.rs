@@ -0,0 +1,11 @@
+use std::env;
+use std::path::Path;
+
+fn main() {
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("templates.rs");
+    println!("cargo:rerun-if-changed=templates");
+    // A syntax error fails the build with the template's file, line and column
+    if let Err(error) = varsubst::codegen::generate("templates", &out) {
+        panic!("{}", error);
+    }
+}
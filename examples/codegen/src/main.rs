@@ -0,0 +1,12 @@
+use std::collections::HashMap;
+
+/// One `pub static` per file in `templates/`, named after it
+mod templates {
+    include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+}
+
+fn main() {
+    let vars = HashMap::from([("NAME", "World"), ("SERVICE", "api"), ("PORT", "8080")]);
+    print!("{}", templates::GREETING.render(&vars).unwrap());
+    print!("{}", templates::SERVICE_UNIT.render(&vars).unwrap());
+}
@@ -0,0 +1,262 @@
+//! Atomic replacement of files: write a temporary file, then rename it.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A file that replaces `path` only once it is completely written.
+///
+/// The data goes to a temporary file in the same directory, which is synced
+/// and then renamed over `path` by [`AtomicFile::commit`], so readers never
+/// observe a partially written file. If the file is dropped without being
+/// committed, or committing fails, the temporary file is removed and `path`
+/// is untouched.
+///
+/// On Unix, the temporary file is created with the permissions it will end
+/// up with, so there is no moment when a file meant to be private is readable
+/// by others. Windows only knows whether a file is read-only, which is set
+/// when committing.
+///
+/// A symlinked `path` has its target replaced. Special files such as
+/// `/dev/null` or a pipe cannot be replaced and are written directly.
+///
+/// Not part of the public API: it is only exported for the `varsubst`
+/// binary, and may change in any release.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: fs::File,
+    /// The temporary file and the path it replaces, unless written directly
+    /// or already committed
+    temp: Option<(PathBuf, PathBuf)>,
+    permissions: Option<fs::Permissions>,
+}
+
+impl AtomicFile {
+    /// Start writing a file to replace `path`, which gets `permissions` if
+    /// given
+    pub fn create(
+        path: impl AsRef<Path>,
+        permissions: Option<fs::Permissions>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let path = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => link_target(path)?,
+            _ => path.to_path_buf(),
+        };
+        if fs::metadata(&path).is_ok_and(|meta| !meta.is_file()) {
+            return Ok(AtomicFile {
+                file: fs::File::create(&path)?,
+                temp: None,
+                permissions: None,
+            });
+        }
+
+        let (temp, file) = create_temp(&path, permissions.as_ref())?;
+        Ok(AtomicFile {
+            file,
+            temp: Some((temp, path)),
+            permissions,
+        })
+    }
+
+    /// Sync the data, apply the permissions (exactly, as creating the file
+    /// was subject to the umask) and rename over the target
+    pub fn commit(mut self) -> io::Result<()> {
+        let Some((temp, path)) = &self.temp else {
+            return self.file.flush();
+        };
+        if let Some(permissions) = &self.permissions {
+            self.file.set_permissions(permissions.clone())?;
+        }
+        self.file.sync_all()?;
+        fs::rename(temp, path)?;
+        self.temp = None;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if let Some((temp, _)) = &self.temp {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// The file that the symlink `path` finally points to, which may not exist
+/// yet, in a canonical directory
+fn link_target(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // As many links as Linux follows before giving up
+    for _ in 0..40 {
+        if !fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            let name = path.file_name().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "symlink target has no file name",
+                )
+            })?;
+            let dir = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            return Ok(fs::canonicalize(dir)?.join(name));
+        }
+        let target = fs::read_link(&path)?;
+        // A relative target is relative to the directory of the link
+        path = match path.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "too many levels of symbolic links",
+    ))
+}
+
+/// Create a new file named `.{name}.{pid}-{n}.tmp` next to `path`
+fn create_temp(
+    path: &Path,
+    permissions: Option<&fs::Permissions>,
+) -> io::Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+    })?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // New outputs get the usual permissions (subject to the umask)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(permissions.map_or(0o666, |permissions| permissions.mode()));
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+
+    loop {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp = path.with_file_name(temp_name);
+        match options.open(&temp) {
+            // Left behind by an earlier process with the same id
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|file| (temp, file)),
+        }
+    }
+}
+
+/// Write `content` to `path` atomically, see [`AtomicFile`]. The file gets
+/// `permissions` if given and otherwise keeps those of an existing file.
+pub(crate) fn write_atomic(
+    path: &Path,
+    content: &[u8],
+    permissions: Option<fs::Permissions>,
+) -> io::Result<()> {
+    let permissions = permissions.or_else(|| fs::metadata(path).ok().map(|m| m.permissions()));
+    let mut file = AtomicFile::create(path, permissions)?;
+    file.write_all(content)?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dropped_file_leaves_target_untouched() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("out.txt");
+        fs::write(&target, "old").unwrap();
+
+        let mut file = AtomicFile::create(&target, None).unwrap();
+        file.write_all(b"new").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_temp_file_is_created_with_final_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("secret.conf");
+        let permissions = Some(fs::Permissions::from_mode(0o600));
+        let mut file = AtomicFile::create(&target, permissions).unwrap();
+        let (temp, _) = file.temp.as_ref().expect("expected a temporary file");
+        let mode = fs::metadata(temp).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        file.write_all(b"key").unwrap();
+        file.commit().unwrap();
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read(&target).unwrap(), b"key");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_target_is_replaced() {
+        let dir = TempDir::new().unwrap();
+        let real = dir.path().join("real.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&real, "old").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        write_atomic(&link, b"new", None).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_symlink_target_is_created() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink("sub/../new.txt", &link).unwrap();
+        let chained = dir.path().join("sub/chained.txt");
+        std::os::unix::fs::symlink("../link.txt", &chained).unwrap();
+
+        write_atomic(&link, b"new", None).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+            "new"
+        );
+        write_atomic(&chained, b"again", None).unwrap();
+        assert!(fs::symlink_metadata(&chained)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+            "again"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Substitution over byte strings that need not be valid UTF-8.
+
+use crate::{segments_with, Expander, Reference, Segment, SubstOptions, SubstResult, SyntaxConfig};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Substitute variables in a byte string, which need not be valid UTF-8.
+///
+/// Variable names are ASCII, so references are found exactly as in
+/// [`substitute`](crate::substitute); every other byte, valid UTF-8 or not,
+/// is copied to the output unchanged. Error positions are byte offsets into
+/// `template`.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute_bytes;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("NAME", "World")]);
+/// let result = substitute_bytes(b"\xffHello ${NAME}\xfe", &vars).unwrap();
+/// assert_eq!(result, b"\xffHello World\xfe");
+/// ```
+pub fn substitute_bytes<K, V, S>(
+    template: &[u8],
+    variables: &HashMap<K, V, S>,
+) -> SubstResult<Vec<u8>>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    substitute_bytes_with_options(template, variables, &SubstOptions::default())
+}
+
+/// Substitute variables in a byte string with explicit options.
+///
+/// Behaves like [`substitute_bytes`], with undefined variables, syntax and
+/// recursion handled according to `options` as in
+/// [`substitute_with_options`](crate::substitute_with_options).
+pub fn substitute_bytes_with_options<K, V, S>(
+    template: &[u8],
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> SubstResult<Vec<u8>>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let text = match utf8_view(template, &options.syntax) {
+        // Valid UTF-8 takes the string path unchanged
+        Cow::Borrowed(text) => {
+            return crate::substitute_with_options(text, variables, options).map(String::into_bytes)
+        }
+        Cow::Owned(text) => text,
+    };
+
+    let mut expander = Expander::new(variables, options);
+    let mut output = Vec::with_capacity(template.len());
+    let mut value = String::new();
+    let mut pos = 0;
+    for segment in segments_with(&text, &options.syntax) {
+        match segment? {
+            // Copy the original bytes, not their stand-ins
+            Segment::Text(run) => {
+                output.extend_from_slice(&template[pos..pos + run.len()]);
+                pos += run.len();
+            }
+            Segment::Escaped(ch) => {
+                output.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                pos += 1 + ch.len_utf8();
+            }
+            Segment::Var {
+                name,
+                span,
+                quoted,
+                length,
+                transform,
+                operator,
+                ..
+            } => {
+                value.clear();
+                let reference = Reference {
+                    name,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                    text: &text[span.clone()],
+                };
+                expander.var(&reference, span.start, &mut value)?;
+                output.extend_from_slice(value.as_bytes());
+                pos = span.end;
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// `bytes` as text with the same byte offsets: each byte of an invalid
+/// UTF-8 sequence is replaced by a single ASCII byte that has no meaning in
+/// `syntax`
+fn utf8_view<'a>(bytes: &'a [u8], syntax: &SyntaxConfig) -> Cow<'a, str> {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+    // A control character, so it is neither a name character nor `$` or `\`;
+    // whitespace is never part of a delimiter
+    let stand_in = (1..0x20u8)
+        .map(char::from)
+        .find(|&ch| {
+            !ch.is_whitespace() && !syntax.open().contains(ch) && !syntax.close().contains(ch)
+        })
+        .unwrap_or('\0');
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        text.extend(std::iter::repeat_n(stand_in, chunk.invalid().len()));
+    }
+    Cow::Owned(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MissingAction, SubstError};
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("A", "1"), ("B", "${A}")])
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_preserved() {
+        let template = b"\x80\xff${A}\xc3(\xe2\x82 ${B} \xf0\x9f\x92\x96";
+        let result = substitute_bytes(template, &vars()).unwrap();
+        assert_eq!(result, b"\x80\xff1\xc3(\xe2\x82 ${A} \xf0\x9f\x92\x96");
+
+        let options = SubstOptions::new().syntax(SyntaxConfig::new().escape(true));
+        let result = substitute_bytes_with_options(b"\xff\\${A}\\\\", &vars(), &options);
+        assert_eq!(result.unwrap(), b"\xff${A}\\");
+    }
+
+    #[test]
+    fn test_error_positions_are_byte_offsets() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_bytes_with_options(b"\xff\xfe ${NOPE}", &vars(), &options);
+        assert_eq!(
+            result,
+            Err(SubstError::UndefinedVariable {
+                name: "NOPE".to_string(),
+                position: 3,
+                suggestions: Vec::new(),
+            })
+        );
+        let result = substitute_bytes(b"\xff ${A", &vars());
+        assert_eq!(result, Err(SubstError::UnclosedBrace { position: 2 }));
+    }
+
+    #[test]
+    fn test_options_apply() {
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_bytes_with_options(b"\xff${B}", &vars(), &options).unwrap();
+        assert_eq!(result, b"\xff1");
+
+        let syntax = SyntaxConfig::new().delimiters("\x01", "\x01").unwrap();
+        let options = SubstOptions::new().syntax(syntax);
+        let result = substitute_bytes_with_options(b"\xff-\x01A\x01", &vars(), &options).unwrap();
+        assert_eq!(result, b"\xff-1");
+    }
+}
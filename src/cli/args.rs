@@ -0,0 +1,1129 @@
+//! Command-line argument definitions.
+
+use crate::cli::bom::Bom;
+use crate::cli::config;
+use crate::cli::diagnostic;
+use crate::cli::escape::Escape;
+use crate::cli::files::{OutputMode, OutputSettings};
+use crate::cli::man;
+use crate::cli::newline::Newline;
+use crate::cli::policy::NameRule;
+use crate::cli::report;
+use crate::cli::status::{self, Failure, Status};
+use crate::cli::varfile::Format;
+use clap::builder::{PossibleValue, TypedValueParser};
+use clap::{
+    Arg, ArgAction, ArgMatches, ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use clap_complete::Shell;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, IsTerminal};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process;
+use std::thread;
+use varsubst::{MissingAction, SyntaxConfig};
+
+/// High-performance variable substitution tool with single-pass parsing
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = status::HELP,
+    after_long_help = man::after_long_help(),
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub render: Args,
+}
+
+/// Options of `varsubst render`, which are also accepted without a subcommand
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Input file (or stdin if not specified)
+    #[arg(value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// Substitute TEMPLATE given on the command line instead of a file. May
+    /// be repeated; each result is printed on its own line
+    #[arg(
+        short = 'e',
+        long = "expr",
+        value_name = "TEMPLATE",
+        conflicts_with_all = [
+            "inputs", "globs", "recursive", "output", "output_dir", "in_place", "stream",
+            "null_data", "watch", "check", "list_vars"
+        ]
+    )]
+    pub exprs: Vec<String>,
+
+    /// Behave like GNU envsubst: read stdin, substitute $NAME and ${NAME}
+    /// (undefined ones become empty) without escapes or syntax errors, and
+    /// take the only positional argument as SHELL-FORMAT, the list of
+    /// variables to substitute
+    #[arg(
+        long = "envsubst-compat",
+        conflicts_with_all = [
+            "exprs", "globs", "recursive", "output_dir", "in_place", "stream", "null_data",
+            "watch", "check", "list_vars", "diff", "print_filenames", "always_print_filenames",
+            "separator", "frontmatter", "binary_safe", "report", "vars_stdin"
+        ]
+    )]
+    pub envsubst_compat: bool,
+
+    /// With -e, print the results one after another without newlines
+    #[arg(long = "no-newline", requires = "exprs")]
+    pub no_newline: bool,
+
+    /// Output file (or stdout if not specified)
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// When several inputs are written to stdout, print a '==> FILE <==' line
+    /// before each output
+    #[arg(
+        long = "print-filenames",
+        conflicts_with_all = [
+            "output", "output_dir", "in_place", "stream", "null_data", "diff", "check", "list_vars"
+        ]
+    )]
+    pub print_filenames: bool,
+
+    /// Like --print-filenames, but also for a single input
+    #[arg(
+        long = "always-print-filenames",
+        conflicts_with_all = [
+            "output", "output_dir", "in_place", "stream", "null_data", "diff", "check", "list_vars"
+        ]
+    )]
+    pub always_print_filenames: bool,
+
+    /// Format of the --print-filenames line; {} is replaced by the input's
+    /// path
+    #[arg(
+        long = "filename-format",
+        value_name = "FORMAT",
+        default_value = "==> {} <==",
+        allow_hyphen_values = true
+    )]
+    pub filename_format: String,
+
+    /// Print STRING between two outputs written to stdout (e.g. $'\n' for a
+    /// blank line)
+    #[arg(
+        long = "separator",
+        value_name = "STRING",
+        allow_hyphen_values = true,
+        conflicts_with_all = [
+            "output", "output_dir", "in_place", "stream", "null_data", "diff", "check", "list_vars"
+        ]
+    )]
+    pub separator: Option<String>,
+
+    /// Write each output into this directory (created if missing), named
+    /// after its input file
+    #[arg(
+        long = "output-dir",
+        value_name = "DIR",
+        conflicts_with_all = ["output", "in_place", "check", "list_vars"]
+    )]
+    pub output_dir: Option<String>,
+
+    /// Read the list of input files from FILE ('-' for stdin), one per line
+    /// or NUL-separated with -0 (as from 'find -print0'). Empty entries are
+    /// skipped
+    #[arg(
+        long = "files-from",
+        value_name = "FILE",
+        conflicts_with_all = [
+            "inputs", "exprs", "globs", "recursive", "stream", "null_data", "envsubst_compat"
+        ]
+    )]
+    pub files_from: Option<String>,
+
+    /// Whether -0 was given with --files-from, making the list NUL-separated
+    #[arg(skip)]
+    pub files_from_null: bool,
+
+    /// With --output-dir, recreate each input's path relative to DIR instead
+    /// of using only its file name
+    #[arg(
+        long = "base-dir",
+        value_name = "DIR",
+        requires = "output_dir",
+        conflicts_with = "recursive"
+    )]
+    pub base_dir: Option<String>,
+
+    /// Render every file matching a glob pattern such as 'configs/**/*.tmpl',
+    /// in sorted order. May be repeated
+    #[arg(long = "glob", value_name = "PATTERN")]
+    pub globs: Vec<String>,
+
+    /// Do not fail when a --glob pattern matches no files
+    #[arg(long = "allow-empty-glob", requires = "globs")]
+    pub allow_empty_glob: bool,
+
+    /// Include hidden files and directories in --glob matches
+    #[arg(long = "hidden", requires = "globs")]
+    pub hidden: bool,
+
+    /// Render every file under DIR into a mirrored tree under --output-dir
+    #[arg(
+        long = "recursive",
+        value_name = "DIR",
+        requires = "output_dir",
+        conflicts_with_all = ["inputs", "globs", "check", "list_vars"]
+    )]
+    pub recursive: Option<String>,
+
+    /// With --recursive, only render files whose name ends with EXT (e.g. .tmpl)
+    #[arg(long = "ext", value_name = "EXT")]
+    pub ext: Option<String>,
+
+    /// Remove the --ext suffix from output file names
+    #[arg(long = "strip-ext", requires = "ext")]
+    pub strip_ext: bool,
+
+    /// With --recursive, copy files not matching --ext verbatim instead of
+    /// skipping them
+    #[arg(long = "copy-others", requires_all = ["recursive", "ext"])]
+    pub copy_others: bool,
+
+    /// Leave out files and directories matching a gitignore-style PATTERN,
+    /// relative to the --recursive directory (or, for --glob, the current
+    /// directory); ignored directories are not read. May be repeated
+    #[arg(long = "ignore", value_name = "PATTERN")]
+    pub ignore: Vec<String>,
+
+    /// With --recursive, do not read .varsubstignore files
+    #[arg(long = "no-ignore-file", requires = "recursive")]
+    pub no_ignore_file: bool,
+
+    /// Render up to N input files at the same time (default: the number of
+    /// CPUs); output and messages still come in input order
+    #[arg(short = 'j', long = "jobs", value_name = "N", value_parser = parse_jobs)]
+    pub jobs: Option<usize>,
+
+    /// Keep rendering the remaining inputs after one fails, reporting each
+    /// failure; exits with the status of the most severe one
+    #[arg(
+        short = 'k',
+        long = "keep-going",
+        conflicts_with_all = ["stream", "null_data", "check", "list_vars"]
+    )]
+    pub keep_going: bool,
+
+    /// Stop at the first error, instead of reporting every error in the
+    /// failed input and rendering the inputs after it to report theirs
+    #[arg(
+        long = "fail-fast",
+        overrides_with = "no_fail_fast",
+        conflicts_with_all = ["stream", "null_data", "check", "list_vars"]
+    )]
+    pub fail_fast: bool,
+
+    /// Report every error of every input before exiting (the default)
+    #[arg(long = "no-fail-fast", overrides_with = "fail_fast")]
+    pub no_fail_fast: bool,
+
+    /// Substitute line by line with constant memory instead of reading the
+    /// whole input first; references must not span lines
+    #[arg(
+        long = "stream",
+        conflicts_with_all = ["in_place", "output_dir", "recursive", "globs", "check", "list_vars"]
+    )]
+    pub stream: bool,
+
+    /// Read NUL-terminated records instead of lines, substituting within each
+    /// record and writing it back NUL-terminated; streams like --stream
+    #[arg(
+        short = '0',
+        long = "null-data",
+        conflicts_with_all = ["in_place", "output_dir", "recursive", "globs", "check", "list_vars"]
+    )]
+    pub null_data: bool,
+
+    /// Keep running and re-render whenever an input or variables file
+    /// changes, printing a status line (UTC time) per render
+    #[arg(
+        long = "watch",
+        conflicts_with_all = [
+            "stream", "null_data", "in_place", "recursive", "check", "list_vars", "fail_on_unused",
+            "vars_stdin"
+        ]
+    )]
+    pub watch: bool,
+
+    #[command(flatten)]
+    pub syntax: SyntaxArgs,
+
+    /// Edit the input file in place, optionally keeping a backup with the
+    /// given suffix (-i .bak, -i.bak or --in-place=.bak)
+    #[arg(
+        short = 'i',
+        long = "in-place",
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        conflicts_with_all = ["output", "check", "list_vars"]
+    )]
+    pub in_place: Option<String>,
+
+    /// Set the permission bits of written files to MODE (octal, e.g. 0644)
+    /// instead of copying them from the input
+    #[arg(long = "mode", value_name = "MODE", value_parser = parse_mode)]
+    pub mode: Option<u32>,
+
+    /// Leave output files untouched (keeping their modification time) when
+    /// their contents would not change
+    #[arg(long = "if-changed", conflicts_with_all = ["stream", "null_data"])]
+    pub if_changed: bool,
+
+    /// Exit with status 8 if any output file was changed (or would be, with
+    /// --dry-run)
+    #[arg(long = "exit-code-on-change", requires = "if_changed")]
+    pub exit_code_on_change: bool,
+
+    /// Do not copy the input file's permission bits to the output file
+    #[arg(long = "no-preserve-mode", conflicts_with = "in_place")]
+    pub no_preserve_mode: bool,
+
+    /// Convert line endings in the output, after substitution (so also
+    /// inside values); mixed line endings are normalized
+    #[arg(long = "newline", value_name = "STYLE", default_value = "preserve")]
+    pub newline: Newline,
+
+    /// Keep a UTF-8 byte order mark at the start of the input instead of
+    /// dropping it
+    #[arg(long = "keep-bom", conflicts_with = "add_bom")]
+    pub keep_bom: bool,
+
+    /// Start the output with a UTF-8 byte order mark
+    #[arg(long = "add-bom")]
+    pub add_bom: bool,
+
+    /// Take default values from a block of KEY=VALUE lines between a
+    /// '---varsubst' first line and a '---' line, removed from the output;
+    /// every other source overrides them
+    #[arg(long = "frontmatter", conflicts_with_all = ["stream", "null_data"])]
+    pub frontmatter: bool,
+
+    /// Escape every substituted value for the output language; the template
+    /// text itself is never changed
+    #[arg(
+        long = "escape-values",
+        value_name = "MODE",
+        default_value = "none",
+        conflicts_with_all = ["binary_safe", "recursive_values", "envsubst_compat"]
+    )]
+    pub escape_values: Escape,
+
+    /// Substitute over raw bytes, copying invalid UTF-8 through unchanged;
+    /// error positions are byte offsets
+    #[arg(
+        long = "binary-safe",
+        conflicts_with_all = ["stream", "null_data", "newline", "check", "list_vars"]
+    )]
+    pub binary_safe: bool,
+
+    #[command(flatten)]
+    pub sources: SourceArgs,
+
+    /// Fail if undefined variables are found (same as --missing error)
+    #[arg(short = 'f', long = "fail-on-undefined")]
+    pub fail_on_undefined: bool,
+
+    /// How to handle undefined variables: keep, empty, error, default=VALUE,
+    /// or marker=PATTERN where {name} and {position} are replaced
+    #[arg(
+        long = "missing",
+        value_name = "MODE",
+        default_value = "keep",
+        value_parser = MissingParser
+    )]
+    pub missing: MissingAction,
+
+    /// Also expand references inside variable values, e.g. an env-file entry
+    /// URL=${SCHEME}://${HOST}
+    #[arg(long = "recursive-values", conflicts_with_all = ["check", "list_vars"])]
+    pub recursive_values: bool,
+
+    /// With --recursive-values, how many levels of values may contain
+    /// references
+    #[arg(
+        long = "max-depth",
+        value_name = "N",
+        default_value_t = varsubst::DEFAULT_MAX_DEPTH,
+        requires = "recursive_values"
+    )]
+    pub max_depth: usize,
+
+    /// List the variables referenced by the template instead of substituting
+    #[arg(
+        long = "list-vars",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    pub list_vars: Option<ListFormat>,
+
+    /// Print statistics to stderr: references, substitutions, undefined names,
+    /// variable sources and elapsed time. Repeat to list every reference with
+    /// the source of its value (values are never printed)
+    #[arg(long = "verbose", action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only allow templates to reference variables matching PATTERN ('*' and
+    /// '?' wildcards). May be repeated
+    #[arg(long = "allow", value_name = "PATTERN")]
+    pub allow: Vec<String>,
+
+    /// Reject template references to variables matching PATTERN, even if
+    /// allowed. May be repeated
+    #[arg(long = "deny", value_name = "PATTERN")]
+    pub deny: Vec<String>,
+
+    /// Reject references whose names break a convention: any (default),
+    /// upper (SCREAMING_SNAKE_CASE) or regex=PATTERN (matching the whole
+    /// name). Also checked by --check
+    #[arg(
+        long = "strict-names",
+        value_name = "RULE",
+        default_value = "any",
+        value_parser = StrictNamesParser
+    )]
+    pub strict_names: NameRule,
+
+    /// Fail if a variable given with -v or a variables file (not the
+    /// environment) is never referenced by any template
+    #[arg(
+        long = "fail-on-unused",
+        conflicts_with_all = ["stream", "null_data", "check", "list_vars"]
+    )]
+    pub fail_on_unused: bool,
+
+    /// Do not report NAME as unused with --fail-on-unused. May be repeated
+    #[arg(
+        long = "allow-unused",
+        value_name = "NAME",
+        requires = "fail_on_unused"
+    )]
+    pub allow_unused: Vec<String>,
+
+    /// With --fail-on-unused, also fail on --default values that no template
+    /// references or that another source overrides
+    #[arg(long = "fail-on-unused-defaults", requires = "fail_on_unused")]
+    pub fail_on_unused_defaults: bool,
+
+    /// Read default options from this file instead of the varsubst.toml
+    /// found in the current directory or its parents
+    #[arg(long = "config", value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Do not read a varsubst.toml config file
+    #[arg(long = "no-config", conflicts_with = "config")]
+    pub no_config: bool,
+
+    /// Report diagnostics on stderr as JSON, one object per line
+    #[arg(
+        long = "json-errors",
+        long_help = diagnostic::SCHEMA,
+        conflicts_with_all = ["verbose", "dry_run"]
+    )]
+    pub json_errors: bool,
+
+    /// Color error excerpts and diffs: auto colors only when they go to a
+    /// terminal and NO_COLOR is unset
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+
+    /// Print a unified diff of each output against the current contents of
+    /// its destination (or, for stdout, its template) instead of writing it;
+    /// exits with status 1 if anything differs
+    #[arg(
+        long = "diff",
+        conflicts_with_all = [
+            "dry_run", "stream", "null_data", "binary_safe", "watch", "check", "list_vars",
+            "exit_code_on_change"
+        ]
+    )]
+    pub diff: bool,
+
+    /// Write a JSON summary of the run to PATH (names only, never values)
+    #[arg(
+        long = "report",
+        value_name = "PATH",
+        long_help = report::SCHEMA,
+        conflicts_with_all = ["stream", "null_data", "check", "list_vars", "watch"]
+    )]
+    pub report: Option<String>,
+
+    /// Report on stderr what would be written, and whether each output would
+    /// change, without writing anything; exits as the real run would
+    #[arg(long = "dry-run", conflicts_with_all = ["stream", "null_data"])]
+    pub dry_run: bool,
+
+    /// Check template syntax without substituting; reports every error
+    #[arg(long = "check", conflicts_with_all = ["list_vars", "output"])]
+    pub check: bool,
+}
+
+/// Options for the template syntax, shared by `render`, `check` and `vars`
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Syntax")]
+pub struct SyntaxArgs {
+    /// Delimiter style for variable references
+    #[arg(long = "style", value_name = "STYLE", default_value = "shell")]
+    pub style: Style,
+
+    /// Opening delimiter for variable references, overriding --style
+    #[arg(long = "delim-open", value_name = "TEXT")]
+    pub delim_open: Option<String>,
+
+    /// Closing delimiter for variable references, overriding --style
+    #[arg(long = "delim-close", value_name = "TEXT")]
+    pub delim_close: Option<String>,
+
+    /// Recognize $NAME references (default: as compiled)
+    #[arg(long = "short-syntax", overrides_with = "no_short_syntax")]
+    pub short_syntax: bool,
+
+    /// Do not recognize $NAME references
+    #[arg(long = "no-short-syntax", overrides_with = "short_syntax")]
+    pub no_short_syntax: bool,
+
+    /// Recognize backslash escapes such as \$ (default: as compiled)
+    #[arg(long = "escape", overrides_with = "no_escape")]
+    pub escape: bool,
+
+    /// Treat backslashes literally, e.g. for Windows paths
+    #[arg(long = "no-escape", overrides_with = "escape")]
+    pub no_escape: bool,
+
+    /// Fail on every unescaped '$' that does not start a reference, such as
+    /// '$ {NAME}' or a trailing '$'
+    #[arg(long = "strict-dollar")]
+    pub strict_dollar: bool,
+
+    /// Allow quoted names such as ${'Display Name'} for keys that are not
+    /// valid names
+    #[arg(long = "quoted-names")]
+    pub quoted_names: bool,
+}
+
+/// Where variable values come from
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Variables")]
+pub struct SourceArgs {
+    /// Define variables (format: KEY=VALUE); overrides all other sources.
+    /// KEY+=VALUE appends to the value from earlier sources. Names cannot
+    /// contain '+'. A VALUE of @PATH reads the file PATH without its
+    /// trailing newline, @@PATH reads it exactly, and \@ starts a literal '@'
+    #[arg(short = 'v', long = "var", value_name = "KEY=VALUE")]
+    pub variables: Vec<String>,
+
+    /// Separator inserted by -v KEY+=VALUE between the existing value and
+    /// the appended one (default: none)
+    #[arg(long = "append-sep", value_name = "SEP", default_value = "")]
+    pub append_sep: String,
+
+    /// Define KEY from the environment variable of that name, or else ask
+    /// for it on the terminal without echo; overrides -v. The value is
+    /// redacted from --verbose, --dry-run, --diff and --report output. May
+    /// be repeated
+    #[arg(long = "secret", value_name = "KEY")]
+    pub secrets: Vec<String>,
+
+    /// Define KEY as the standard output of COMMAND, without one trailing
+    /// newline; -v overrides it. Every command runs once before any template
+    /// is read, whether or not KEY is referenced, and a failing command is an
+    /// error. May be repeated
+    #[arg(long = "var-cmd", value_name = "KEY=COMMAND")]
+    pub var_cmds: Vec<String>,
+
+    /// Run --var-cmd commands with the platform shell (sh -c, or cmd /C on
+    /// Windows) instead of splitting them into words
+    #[arg(long = "var-cmd-shell", requires = "var_cmds")]
+    pub var_cmd_shell: bool,
+
+    /// Define KEY as VALUE only if no other source defines it, as a fallback
+    /// below the environment and every file. May be repeated
+    #[arg(long = "default", value_name = "KEY=VALUE")]
+    pub defaults: Vec<String>,
+
+    /// Load variables from a dotenv-style file; overrides the environment.
+    /// May be repeated, later files override earlier ones
+    #[arg(long = "env-file", value_name = "PATH")]
+    pub env_files: Vec<String>,
+
+    /// Load variables from a JSON object file ('-' for stdin); overrides
+    /// env files. May be repeated, later files override earlier ones
+    #[arg(long = "vars-json", value_name = "PATH")]
+    pub vars_json: Vec<String>,
+
+    /// Load variables from a YAML mapping file ('-' for stdin), like --vars-json
+    #[arg(long = "vars-yaml", value_name = "PATH")]
+    pub vars_yaml: Vec<String>,
+
+    /// Load variables from a TOML table file ('-' for stdin), like --vars-json
+    #[arg(long = "vars-toml", value_name = "PATH")]
+    pub vars_toml: Vec<String>,
+
+    /// Read variables from stdin as KEY=VALUE lines or a JSON object;
+    /// overrides variable files. Requires a template file
+    #[arg(
+        long = "vars-stdin",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "lines"
+    )]
+    pub vars_stdin: Option<StdinFormat>,
+
+    /// Flatten nested tables in variable files into PARENT_CHILD keys
+    #[arg(long = "flatten")]
+    pub flatten: bool,
+
+    /// All --vars-json/--vars-yaml/--vars-toml files in command-line order
+    #[arg(skip)]
+    pub var_files: Vec<(Format, String)>,
+
+    /// Don't use environment variables (by default, environment variables are used)
+    #[arg(long = "no-env")]
+    pub no_env: bool,
+
+    /// Only expose environment variables whose names start with PREFIX.
+    /// May be repeated; does not affect -v or file-provided variables
+    #[arg(long = "prefix", value_name = "PREFIX")]
+    pub prefixes: Vec<String>,
+
+    /// Expose environment variables starting with PREFIX under their names
+    /// without it (MYAPP_HOST becomes HOST), shadowing any real variable of
+    /// that name, which --verbose --verbose notes. May be repeated; applied
+    /// after --prefix
+    #[arg(long = "strip-prefix", value_name = "PREFIX")]
+    pub strip_prefixes: Vec<String>,
+
+    /// With --strip-prefix, also keep the variables under their full names
+    #[arg(long = "keep-prefixed", requires = "strip_prefixes")]
+    pub keep_prefixed: bool,
+
+    /// Ask on the terminal for each referenced variable no source defines,
+    /// in order of first appearance; fails if there is no terminal
+    #[arg(
+        long = "var-prompt",
+        conflicts_with_all = ["stream", "null_data", "binary_safe", "watch", "check", "list_vars"]
+    )]
+    pub var_prompt: bool,
+
+    /// With --var-prompt, do not echo the values of variables matching
+    /// PATTERN ('*' and '?' wildcards). May be repeated
+    #[arg(
+        long = "var-prompt-hidden",
+        value_name = "PATTERN",
+        requires = "var_prompt"
+    )]
+    pub var_prompt_hidden: Vec<String>,
+}
+
+/// Subcommands; without one, templates are rendered
+///
+/// To render a template file named like a subcommand, pass it as e.g.
+/// ./check.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Substitute variables in templates (the default without a subcommand)
+    Render(Box<Args>),
+    /// Check template syntax without substituting; reports every error
+    Check(CheckArgs),
+    /// List the variables referenced by a template
+    Vars(VarsArgs),
+    /// Print a shell completion script to stdout
+    ///
+    /// To render a template file named 'completions', pass it as
+    /// ./completions.
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
+    /// Print the man page, and those of the subcommands, in roff format
+    ///
+    /// To render a template file named 'man', pass it as ./man.
+    Man {
+        /// Write each page to DIR/varsubst[-SUBCOMMAND].1 instead of stdout
+        #[arg(long = "out-dir", value_name = "DIR")]
+        out_dir: Option<String>,
+    },
+}
+
+/// Options of `varsubst check`
+#[derive(clap::Args, Debug)]
+pub struct CheckArgs {
+    /// Template files (or stdin if not specified)
+    #[arg(value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// Report diagnostics on stderr as JSON, one object per line
+    #[arg(long = "json-errors", long_help = diagnostic::SCHEMA)]
+    pub json_errors: bool,
+
+    /// Reject references whose names break a convention: any (default),
+    /// upper (SCREAMING_SNAKE_CASE) or regex=PATTERN
+    #[arg(
+        long = "strict-names",
+        value_name = "RULE",
+        default_value = "any",
+        value_parser = StrictNamesParser
+    )]
+    pub strict_names: NameRule,
+
+    #[command(flatten)]
+    pub syntax: SyntaxArgs,
+}
+
+/// Options of `varsubst vars`
+#[derive(clap::Args, Debug)]
+pub struct VarsArgs {
+    /// Template file (or stdin if not specified)
+    #[arg(value_name = "FILE")]
+    pub input: Option<String>,
+
+    /// Print a JSON array with positions and occurrence counts instead of
+    /// one name per line
+    #[arg(long = "json")]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub syntax: SyntaxArgs,
+}
+
+/// Input format for `--vars-stdin`
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum StdinFormat {
+    /// KEY=VALUE lines; blank lines and # comments are ignored
+    Lines,
+    /// A JSON object, as for --vars-json
+    Json,
+}
+
+/// Delimiter style for `--style`
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Style {
+    /// ${NAME}
+    Shell,
+    /// {{NAME}}
+    Handlebars,
+    /// %NAME%
+    Percent,
+}
+
+impl Style {
+    /// Opening and closing delimiters of this style
+    pub fn delimiters(self) -> (&'static str, &'static str) {
+        match self {
+            Style::Shell => ("${", "}"),
+            Style::Handlebars => ("{{", "}}"),
+            Style::Percent => ("%", "%"),
+        }
+    }
+}
+
+/// Output format for `--list-vars`
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ListFormat {
+    /// One name per line
+    Text,
+    /// JSON array with positions and occurrence counts
+    Json,
+}
+
+impl Args {
+    /// Parse the process arguments, exiting with a usage error on failure.
+    ///
+    /// The `check` and `vars` subcommands are turned into the equivalent
+    /// `--check` and `--list-vars` options; `completions` prints its script
+    /// and exits.
+    pub fn parse_args() -> Self {
+        // Errors in the command line itself are found before --json-errors
+        // is parsed, so look for it directly
+        if std::env::args_os().any(|arg| arg == "--json-errors") {
+            diagnostic::enable_json();
+        }
+        let argv = in_place_suffixes(std::env::args_os().collect());
+        let (argv, files_from_null) = files_from_null(argv);
+        let matches = Cli::command()
+            .try_get_matches_from(argv)
+            .unwrap_or_else(|e| usage_exit(e));
+        let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| usage_exit(e));
+        // Without a subcommand, the top-level options hold their defaults
+        let mut args = cli.render;
+        let matches = matches.subcommand().map_or(&matches, |(_, sub)| sub);
+        match cli.command {
+            None => args.sources.var_files = var_files_in_order(matches),
+            Some(Command::Render(render)) => {
+                args = *render;
+                args.sources.var_files = var_files_in_order(matches);
+            }
+            Some(Command::Check(check)) => {
+                args.check = true;
+                args.inputs = check.inputs;
+                args.json_errors = check.json_errors;
+                args.strict_names = check.strict_names;
+                args.syntax = check.syntax;
+            }
+            Some(Command::Vars(vars)) => {
+                args.list_vars = Some(match vars.json {
+                    true => ListFormat::Json,
+                    false => ListFormat::Text,
+                });
+                args.inputs = vars.input.into_iter().collect();
+                args.syntax = vars.syntax;
+            }
+            Some(Command::Completions { shell }) => {
+                clap_complete::generate(shell, &mut Cli::command(), "varsubst", &mut io::stdout());
+                process::exit(0);
+            }
+            Some(Command::Man { out_dir }) => {
+                man::run(out_dir.as_deref()).unwrap_or_else(|failure| failure.exit());
+                process::exit(0);
+            }
+        }
+        args.files_from_null = files_from_null;
+        diagnostic::set_color(args.color(io::stderr().is_terminal()));
+        config::apply(&mut args, matches).unwrap_or_else(|failure| failure.exit());
+        args
+    }
+
+    /// How output is written: permissions and line endings
+    pub fn output_settings(&self) -> OutputSettings {
+        OutputSettings {
+            mode: self.output_mode(),
+            newline: self.newline,
+            binary_safe: self.binary_safe,
+            bom: match (self.keep_bom, self.add_bom) {
+                (true, _) => Bom::Keep,
+                (_, true) => Bom::Add,
+                _ => Bom::Strip,
+            },
+            frontmatter: self.frontmatter,
+            escape: self.escape_values,
+        }
+    }
+
+    /// Whether to color output for a stream that is a terminal or not
+    pub fn color(&self, terminal: bool) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+
+    /// Number of inputs to render in parallel
+    pub fn jobs(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+
+    /// How written files get their permission bits
+    fn output_mode(&self) -> OutputMode {
+        match self.mode {
+            Some(mode) => OutputMode::Set(mode),
+            None if self.no_preserve_mode => OutputMode::Default,
+            None => OutputMode::Preserve,
+        }
+    }
+}
+
+impl SyntaxArgs {
+    /// Template syntax selected by the delimiter flags
+    pub fn resolve(&self) -> Result<SyntaxConfig, String> {
+        let (open, close) = self.style.delimiters();
+        let open = self.delim_open.as_deref().unwrap_or(open);
+        let close = self.delim_close.as_deref().unwrap_or(close);
+        let defaults = SyntaxConfig::new();
+        let short_syntax = flag(
+            self.short_syntax,
+            self.no_short_syntax,
+            defaults.short_syntax,
+        );
+        let escape = flag(self.escape, self.no_escape, defaults.escape);
+        defaults
+            .short_syntax(short_syntax)
+            .escape(escape)
+            .strict_dollar(self.strict_dollar)
+            .quoted_names(self.quoted_names)
+            .delimiters(open, close)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Exit with a command-line error, as a JSON diagnostic with `--json-errors`
+pub fn usage_exit(error: clap::Error) -> ! {
+    if !diagnostic::json() || !error.use_stderr() {
+        error.exit()
+    }
+    let text = error.to_string();
+    let message = text.lines().next().unwrap_or_default();
+    Failure::new(Status::Usage, message.trim_start_matches("error: ")).exit()
+}
+
+/// With `--files-from`, `-0` and `--null-data` say the list is NUL-separated
+/// rather than asking for NUL-separated records, so take them out of `argv`
+/// before it is parsed and report whether there were any. Tokens that are the
+/// value of the option before them are left alone.
+fn files_from_null(argv: Vec<OsString>) -> (Vec<OsString>, bool) {
+    let is_files_from = |arg: &OsString| {
+        arg.to_str()
+            .is_some_and(|arg| arg == "--files-from" || arg.starts_with("--files-from="))
+    };
+    if !argv.iter().any(is_files_from) {
+        return (argv, false);
+    }
+    let command = Cli::command();
+    let arguments: Vec<&Arg> = (command.get_arguments())
+        .chain(
+            command
+                .get_subcommands()
+                .flat_map(|sub| sub.get_arguments()),
+        )
+        .collect();
+    let takes_value = |arg: &str| {
+        let found = match arg.strip_prefix("--") {
+            Some(long) => (arguments.iter()).find(|a| a.get_long() == Some(long)),
+            None => {
+                let mut chars = arg.chars().skip(1);
+                match (chars.next(), chars.next()) {
+                    (Some(short), None) => {
+                        (arguments.iter()).find(|a| a.get_short() == Some(short))
+                    }
+                    _ => None,
+                }
+            }
+        };
+        found.is_some_and(|a| a.get_action().takes_values() && !a.is_require_equals_set())
+    };
+
+    let mut kept = Vec::with_capacity(argv.len());
+    let mut null = false;
+    let mut value_next = false;
+    let mut args = argv.into_iter();
+    kept.extend(args.next());
+    while let Some(arg) = args.next() {
+        let text = arg.to_str().unwrap_or_default();
+        if value_next {
+            value_next = false;
+        } else if text == "--" {
+            kept.push(arg);
+            kept.extend(args);
+            break;
+        } else if text == "-0" || text == "--null-data" {
+            null = true;
+            continue;
+        } else if text.starts_with('-') && !text.contains('=') {
+            value_next = takes_value(text);
+        }
+        kept.push(arg);
+    }
+    (kept, null)
+}
+
+/// Rewrite the sed-style `-i.bak` and `-i .bak` to `-i=.bak`, which clap
+/// accepts. A separate argument is only the suffix if it starts with `.` and
+/// is not an existing file, so `-i .env` still edits `.env`.
+fn in_place_suffixes(argv: Vec<OsString>) -> Vec<OsString> {
+    let is_suffix = |arg: &OsString| {
+        arg.to_str()
+            .is_some_and(|arg| arg.starts_with('.') && !Path::new(arg).exists())
+    };
+    let mut kept = Vec::with_capacity(argv.len());
+    let mut args = argv.into_iter().peekable();
+    kept.extend(args.next());
+    while let Some(arg) = args.next() {
+        let text = arg.to_str().unwrap_or_default();
+        if text == "--" {
+            kept.push(arg);
+            kept.extend(args);
+            break;
+        }
+        let suffix = match text.strip_prefix("-i") {
+            Some("") => args.next_if(is_suffix),
+            Some(attached) if attached.starts_with('.') => Some(attached.into()),
+            _ => None,
+        };
+        match suffix {
+            Some(suffix) => {
+                let mut arg = OsString::from("-i=");
+                arg.push(suffix);
+                kept.push(arg);
+            }
+            None => kept.push(arg),
+        }
+    }
+    kept
+}
+
+/// Parse the octal value of `--mode`
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o7777)
+        .ok_or_else(|| format!("invalid mode '{}' (expected octal, e.g. 0644)", value))
+}
+
+/// Parse the value of `--jobs`, a positive number
+fn parse_jobs(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&jobs| jobs > 0)
+        .ok_or_else(|| format!("invalid number of jobs '{}' (expected 1 or more)", value))
+}
+
+/// Resolve a `--flag`/`--no-flag` pair, falling back to `default` if neither
+/// was given
+fn flag(enable: bool, disable: bool, default: bool) -> bool {
+    if enable {
+        true
+    } else if disable {
+        false
+    } else {
+        default
+    }
+}
+
+/// Collect the structured variable files in the order they appeared on the
+/// command line, regardless of format
+fn var_files_in_order(matches: &ArgMatches) -> Vec<(Format, String)> {
+    let mut files: Vec<(usize, Format, String)> = Vec::new();
+    for (id, format) in [
+        ("vars_json", Format::Json),
+        ("vars_yaml", Format::Yaml),
+        ("vars_toml", Format::Toml),
+    ] {
+        if let (Some(indices), Some(values)) =
+            (matches.indices_of(id), matches.get_many::<String>(id))
+        {
+            files.extend(
+                indices
+                    .zip(values)
+                    .map(|(i, path)| (i, format, path.clone())),
+            );
+        }
+    }
+    files.sort_by_key(|(index, _, _)| *index);
+    files
+        .into_iter()
+        .map(|(_, format, path)| (format, path))
+        .collect()
+}
+
+/// Value parser for `--missing` that also offers its modes to shell completion
+#[derive(Clone)]
+struct MissingParser;
+
+impl TypedValueParser for MissingParser {
+    type Value = MissingAction;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<MissingAction, clap::Error> {
+        parse_missing.parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            [
+                PossibleValue::new("keep").help("Keep the reference in the output"),
+                PossibleValue::new("empty").help("Replace with an empty string"),
+                PossibleValue::new("error").help("Fail with an error"),
+                PossibleValue::new("default=").help("Replace with the text after '='"),
+                PossibleValue::new("marker=")
+                    .help("Replace with the pattern after '=', expanding {name} and {position}"),
+            ]
+            .into_iter(),
+        ))
+    }
+}
+
+/// Value parser for `--strict-names` that also offers its rules to shell
+/// completion
+#[derive(Clone)]
+struct StrictNamesParser;
+
+impl TypedValueParser for StrictNamesParser {
+    type Value = NameRule;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<NameRule, clap::Error> {
+        parse_strict_names.parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            [
+                PossibleValue::new("any").help("Allow any name"),
+                PossibleValue::new("upper").help("Require SCREAMING_SNAKE_CASE"),
+                PossibleValue::new("regex=").help("Require a match of the regex after '='"),
+            ]
+            .into_iter(),
+        ))
+    }
+}
+
+/// Parse the value of `--strict-names`
+pub fn parse_strict_names(value: &str) -> Result<NameRule, String> {
+    match value {
+        "any" => Ok(NameRule::Any),
+        "upper" => Ok(NameRule::Upper),
+        _ => match value.strip_prefix("regex=") {
+            Some(pattern) => {
+                NameRule::regex(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))
+            }
+            None => Err(format!(
+                "invalid rule '{}' (expected any, upper, or regex=PATTERN)",
+                value
+            )),
+        },
+    }
+}
+
+/// Parse the value of `--missing`
+pub fn parse_missing(value: &str) -> Result<MissingAction, String> {
+    match value {
+        "keep" => Ok(MissingAction::Keep),
+        "empty" => Ok(MissingAction::Empty),
+        "error" => Ok(MissingAction::Error),
+        _ => match (
+            value.strip_prefix("default="),
+            value.strip_prefix("marker="),
+        ) {
+            (Some(text), _) => Ok(MissingAction::UseDefault(text.to_string())),
+            (_, Some(pattern)) => Ok(MissingAction::Marker(pattern.to_string())),
+            _ => Err(format!(
+                "invalid mode '{}' (expected keep, empty, error, default=VALUE, or marker=PATTERN)",
+                value
+            )),
+        },
+    }
+}
@@ -0,0 +1,81 @@
+//! `--keep-bom` and `--add-bom`: the UTF-8 byte order mark.
+//!
+//! Editors on Windows often save templates with a leading BOM, which breaks
+//! parsers of the rendered output if it is copied through. By default a
+//! single BOM at the very start of each input is dropped; one anywhere else
+//! is content and kept. Offsets in diagnostics still count the dropped bytes,
+//! so they point into the file as it is on disk.
+
+/// The UTF-8 encoding of U+FEFF
+pub const BOM: &str = "\u{feff}";
+
+/// What happens to byte order marks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bom {
+    /// Drop a leading BOM from the input (default)
+    #[default]
+    Strip,
+    /// Keep a leading BOM of the input, so it is written like any text
+    Keep,
+    /// Drop a leading BOM from the input and start the output with one
+    Add,
+}
+
+impl Bom {
+    /// Remove a leading BOM from `text` unless it is kept, returning the
+    /// number of bytes removed
+    pub fn strip(self, text: &mut String) -> usize {
+        match self != Bom::Keep && text.starts_with(BOM) {
+            true => {
+                text.drain(..BOM.len());
+                BOM.len()
+            }
+            false => 0,
+        }
+    }
+
+    /// Like [`strip`](Bom::strip), for input that need not be UTF-8
+    pub fn strip_bytes(self, bytes: &mut Vec<u8>) -> usize {
+        match self != Bom::Keep && bytes.starts_with(BOM.as_bytes()) {
+            true => {
+                bytes.drain(..BOM.len());
+                BOM.len()
+            }
+            false => 0,
+        }
+    }
+
+    /// Start `output` with a BOM if one is added
+    pub fn apply(self, output: Vec<u8>) -> Vec<u8> {
+        match self {
+            Bom::Add => [BOM.as_bytes(), &output].concat(),
+            Bom::Strip | Bom::Keep => output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_a_leading_bom_is_stripped() {
+        let mut text = format!("{BOM}{BOM}a{BOM}");
+        assert_eq!(Bom::Strip.strip(&mut text), 3);
+        assert_eq!(text, format!("{BOM}a{BOM}"));
+
+        let mut text = format!("{BOM}a");
+        assert_eq!(Bom::Keep.strip(&mut text), 0);
+        assert_eq!(text, format!("{BOM}a"));
+
+        let mut bytes = b"\xef\xbb\xbf\xff".to_vec();
+        assert_eq!(Bom::Add.strip_bytes(&mut bytes), 3);
+        assert_eq!(bytes, b"\xff");
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Bom::Add.apply(b"a".to_vec()), b"\xef\xbb\xbfa");
+        assert_eq!(Bom::Keep.apply(b"a".to_vec()), b"a");
+    }
+}
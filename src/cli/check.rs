@@ -0,0 +1,87 @@
+//! `--check`: syntax validation without substitution.
+//!
+//! Names that break `--strict-names` are reported along with syntax errors.
+
+use crate::cli::diagnostic::{self, Diagnostic};
+use crate::cli::files::{input_sources, read_input};
+use crate::cli::policy::Policy;
+use crate::cli::status::Status;
+use varsubst::SyntaxConfig;
+
+/// Check the syntax of each input, printing one diagnostic per error.
+///
+/// Returns the process exit code of the most severe problem: I/O errors,
+/// then syntax errors, then names rejected by `policy`.
+pub fn run(inputs: &[String], syntax: &SyntaxConfig, policy: &Policy) -> i32 {
+    let sources = input_sources(inputs);
+
+    let mut errors = 0;
+    let mut failed_files = 0;
+    let mut status = None;
+
+    for &source in &sources {
+        let name = source.map_or("<stdin>", String::as_str);
+        let content = match read_input(source) {
+            Ok(content) => content,
+            Err(e) => {
+                report(Diagnostic::new("io", format!("error reading input: {}", e)).file(name));
+                errors += 1;
+                failed_files += 1;
+                status = Some(Status::Io);
+                continue;
+            }
+        };
+
+        let mut file_status = None;
+        for error in varsubst::segments_with(&content, syntax).filter_map(Result::err) {
+            report(Diagnostic::of_error(&error, &content, syntax).file(name));
+            errors += 1;
+            file_status = Some(Status::Syntax);
+        }
+        for diagnostic in policy.violations(name, &content, syntax) {
+            report(diagnostic);
+            errors += 1;
+            file_status.get_or_insert(Status::Denied);
+        }
+        if let Some(file_status) = file_status {
+            failed_files += 1;
+            status = status
+                .into_iter()
+                .chain([file_status])
+                .max_by_key(|status| status.severity());
+        }
+    }
+
+    if errors == 0 {
+        if !diagnostic::json() {
+            eprintln!("{} file(s) checked, no errors", sources.len());
+        }
+        0
+    } else {
+        if diagnostic::json() {
+            return status.map_or(0, Status::code);
+        }
+        eprintln!(
+            "{} error(s) in {} of {} file(s)",
+            errors,
+            failed_files,
+            sources.len()
+        );
+        status.map_or(0, Status::code)
+    }
+}
+
+/// Print a diagnostic as `file[:line:column]: message` followed by an
+/// excerpt, or as JSON
+fn report(diagnostic: Diagnostic) {
+    if diagnostic::json() {
+        diagnostic.emit();
+        return;
+    }
+    let file = diagnostic.file.as_deref().unwrap_or_default();
+    match diagnostic.location {
+        Some((line, column)) => eprintln!("{}:{}:{}: {}", file, line, column, diagnostic.message),
+        None => eprintln!("{}: {}", file, diagnostic.message),
+    }
+    eprint!("{}", diagnostic.snippet(false).unwrap_or_default());
+}
@@ -0,0 +1,227 @@
+//! `varsubst.toml`: project defaults for command-line options.
+//!
+//! Unless `--config` names a file or `--no-config` is given, the first
+//! `varsubst.toml` found in the current directory or one of its parents is
+//! loaded. Its keys are named after the long flags they set:
+//!
+//! ```toml
+//! missing = "error"
+//! style = "handlebars"        # or delim-open / delim-close
+//! short-syntax = false
+//! escape = true
+//! env-file = [".env", "config/local.env"]
+//! allow = ["APP_*"]
+//! deny = ["*_SECRET"]
+//! strict-names = "upper"
+//! newline = "lf"
+//! ```
+//!
+//! A flag given on the command line always wins over the file; for repeatable
+//! flags, the command-line values replace the configured list. Env-file paths
+//! are relative to the directory containing the config file. Unknown keys are
+//! reported and ignored, so a config written for a newer version still works.
+
+use crate::cli::args::{parse_missing, parse_strict_names, Args, Style};
+use crate::cli::diagnostic::{self, Diagnostic};
+use crate::cli::newline::Newline;
+use crate::cli::status::{Failure, Status};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Name of the config file looked for in the current directory and its parents
+pub const FILE_NAME: &str = "varsubst.toml";
+
+/// The config file that applies in `dir`, if any
+pub fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Load the config file selected by `--config`/`--no-config` and fill in
+/// every option not given on the command line
+pub fn apply(args: &mut Args, matches: &ArgMatches) -> Result<(), Failure> {
+    if args.no_config {
+        return Ok(());
+    }
+    let path = match &args.config {
+        Some(path) => PathBuf::from(path),
+        None => match std::env::current_dir().ok().and_then(|dir| discover(&dir)) {
+            Some(path) => path,
+            None => return Ok(()),
+        },
+    };
+    let display = path.display().to_string();
+    let content = fs::read_to_string(&path).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error reading config file '{}': {}", display, e),
+        )
+        .with(Diagnostic::new("io", format!("error reading config file: {}", e)).file(&display))
+    })?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    load(&content, base, args, matches).map_err(|e| {
+        Failure::new(
+            Status::Failure,
+            format!("Invalid config file '{}': {}", display, e),
+        )
+        .with(Diagnostic::new("failure", format!("invalid config file: {}", e)).file(&display))
+    })?;
+    for key in unknown_keys(&content) {
+        if !diagnostic::json() {
+            eprintln!("{}: unknown key '{}' ignored", display, key);
+        }
+    }
+    Ok(())
+}
+
+/// Keys understood in the config file
+const KEYS: &[&str] = &[
+    "missing",
+    "style",
+    "delim-open",
+    "delim-close",
+    "short-syntax",
+    "escape",
+    "env-file",
+    "allow",
+    "deny",
+    "strict-names",
+    "newline",
+];
+
+/// Set the options in `content` that `matches` did not get from the command
+/// line, resolving paths against `base`
+fn load(content: &str, base: &Path, args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+    let table: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+    // `check` and `vars` lack most options; those keep the configured value
+    let unset = |id: &str| match matches.try_contains_id(id) {
+        Ok(true) => matches.value_source(id) != Some(ValueSource::CommandLine),
+        _ => true,
+    };
+
+    if let Some(value) = table.get("missing").filter(|_| unset("missing")) {
+        args.missing =
+            parse_missing(string("missing", value)?).map_err(|e| format!("'missing': {}", e))?;
+    }
+    if let Some(value) = table.get("style").filter(|_| unset("style")) {
+        args.syntax.style = choice::<Style>("style", value)?;
+    }
+    if let Some(value) = table.get("delim-open").filter(|_| unset("delim_open")) {
+        args.syntax.delim_open = Some(string("delim-open", value)?.to_string());
+    }
+    if let Some(value) = table.get("delim-close").filter(|_| unset("delim_close")) {
+        args.syntax.delim_close = Some(string("delim-close", value)?.to_string());
+    }
+    if let Some(value) = table.get("short-syntax") {
+        if unset("short_syntax") && unset("no_short_syntax") {
+            args.syntax.short_syntax = boolean("short-syntax", value)?;
+            args.syntax.no_short_syntax = !args.syntax.short_syntax;
+        }
+    }
+    if let Some(value) = table.get("escape") {
+        if unset("escape") && unset("no_escape") {
+            args.syntax.escape = boolean("escape", value)?;
+            args.syntax.no_escape = !args.syntax.escape;
+        }
+    }
+    if let Some(value) = table.get("env-file").filter(|_| unset("env_files")) {
+        args.sources.env_files = strings("env-file", value)?
+            .into_iter()
+            .map(|path| base.join(path).display().to_string())
+            .collect();
+    }
+    if let Some(value) = table.get("allow").filter(|_| unset("allow")) {
+        args.allow = strings("allow", value)?;
+    }
+    if let Some(value) = table.get("deny").filter(|_| unset("deny")) {
+        args.deny = strings("deny", value)?;
+    }
+    if let Some(value) = table.get("strict-names").filter(|_| unset("strict_names")) {
+        args.strict_names = parse_strict_names(string("strict-names", value)?)
+            .map_err(|e| format!("'strict-names': {}", e))?;
+    }
+    if let Some(value) = table.get("newline").filter(|_| unset("newline")) {
+        args.newline = choice::<Newline>("newline", value)?;
+    }
+    Ok(())
+}
+
+/// Top-level keys of `content` that are not in [`KEYS`]
+fn unknown_keys(content: &str) -> Vec<String> {
+    let table: toml::Table = toml::from_str(content).unwrap_or_default();
+    table
+        .keys()
+        .filter(|key| !KEYS.contains(&key.as_str()))
+        .cloned()
+        .collect()
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("'{}' must be a string", key))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("'{}' must be true or false", key))
+}
+
+/// A string or an array of strings
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(text) => Ok(vec![text.clone()]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| string(key, item).map(str::to_string))
+            .collect(),
+        _ => Err(format!("'{}' must be a string or an array of strings", key)),
+    }
+}
+
+/// One of the values a flag of type `T` accepts
+fn choice<T: ValueEnum>(key: &str, value: &Value) -> Result<T, String> {
+    let text = string(key, value)?;
+    T::from_str(text, false).map_err(|_| {
+        let expected: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        format!(
+            "invalid value '{}' for '{}' (expected {})",
+            text,
+            key,
+            expected.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_walks_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(discover(&nested), None);
+
+        fs::write(dir.path().join(FILE_NAME), "").unwrap();
+        assert_eq!(discover(&nested), Some(dir.path().join(FILE_NAME)));
+        fs::write(nested.join(FILE_NAME), "").unwrap();
+        assert_eq!(discover(&nested), Some(nested.join(FILE_NAME)));
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let keys = unknown_keys("missing = \"error\"\nfuture = 1\n[table]\nx = 1\n");
+        assert_eq!(keys, ["future", "table"]);
+    }
+}
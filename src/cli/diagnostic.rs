@@ -0,0 +1,359 @@
+//! `--json-errors`: machine-readable diagnostics.
+//!
+//! In JSON mode every diagnostic is written to stderr as one JSON object per
+//! line instead of as text. The fields and codes are listed in
+//! [`SCHEMA`] and are part of the command-line interface; they must not
+//! change.
+//!
+//! As text, a diagnostic with a location is followed by the offending line
+//! with the reference underlined, colored according to `--color`.
+
+use crate::cli::files::line_col;
+use crate::cli::status::Status;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use varsubst::{Segment, Snippet, SubstError, SyntaxConfig};
+
+/// Description of the JSON diagnostics shown in `--help`
+pub const SCHEMA: &str = "\
+Report diagnostics on stderr as JSON, one object per line, instead of text
+
+Each object has these fields (null where not applicable):
+  severity  \"error\"
+  code      unclosed-brace, invalid-name, stray-dollar, unknown-transform,
+            undefined-variable, circular-reference, depth-exceeded,
+            denied-variable, naming-convention, unused-variable, io, usage
+            or failure
+  message   human-readable description
+  file      path of the file concerned, or \"<stdin>\"
+  line      1-based line of the error
+  column    1-based column of the error, in characters
+  span      [start, end] byte offsets of the reference in the file
+  variable  variable name the diagnostic is about";
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Switch all further diagnostics to JSON
+pub fn enable_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+/// Whether diagnostics are reported as JSON
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Color the excerpts in text diagnostics
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether text diagnostics are colored
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// A single problem to report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    /// 1-based line and column
+    pub location: Option<(usize, usize)>,
+    /// Byte offsets in the file
+    pub span: Option<Range<usize>>,
+    pub variable: Option<String>,
+    /// The line the span starts on, its 1-based number within the content
+    /// the diagnostic was located in, and the span within that line
+    pub excerpt: Option<(String, usize, Range<usize>)>,
+    /// A hint shown after the excerpt, such as a likely intended name
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code,
+            message: message.into(),
+            file: None,
+            location: None,
+            span: None,
+            variable: None,
+            excerpt: None,
+            help: None,
+        }
+    }
+
+    /// Diagnostic for a failure that has no more specific description
+    pub fn of_status(status: Status, message: impl Into<String>) -> Self {
+        let code = match status {
+            Status::Failure => "failure",
+            Status::Usage => "usage",
+            Status::Io => "io",
+            Status::Syntax => "invalid-name",
+            Status::Undefined => "undefined-variable",
+            Status::Unused => "unused-variable",
+            Status::Denied => "denied-variable",
+            Status::Changed => "changed",
+        };
+        Diagnostic::new(code, message)
+    }
+
+    /// Diagnostic for a substitution error in `content`
+    pub fn of_error(error: &SubstError, content: &str, syntax: &SyntaxConfig) -> Self {
+        let (code, variable) = match error {
+            SubstError::UnclosedBrace { .. } => ("unclosed-brace", None),
+            SubstError::InvalidVarName { name, .. } => ("invalid-name", Some(name)),
+            SubstError::StrayDollar { .. } => ("stray-dollar", None),
+            SubstError::UnknownTransform { .. } => ("unknown-transform", None),
+            SubstError::UndefinedVariable { name, .. } => ("undefined-variable", Some(name)),
+            SubstError::RequiredVariable { name, .. } => ("required-variable", Some(name)),
+            SubstError::CircularReference { chain, .. } => ("circular-reference", chain.first()),
+            SubstError::DepthLimitExceeded { variable, .. } => ("depth-exceeded", Some(variable)),
+            SubstError::Write { .. } => ("io", None),
+        };
+        let mut diagnostic = Diagnostic::new(code, error.to_string())
+            .at(content, error_span(error, content, syntax));
+        diagnostic.variable = variable.cloned();
+        if let SubstError::UndefinedVariable { suggestions, .. } = error {
+            diagnostic.help = help(suggestions);
+        }
+        diagnostic
+    }
+
+    /// Set the input the diagnostic is about
+    #[must_use]
+    pub fn file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Set the variable the diagnostic is about
+    #[must_use]
+    pub fn variable(mut self, name: impl Into<String>) -> Self {
+        self.variable = Some(name.into());
+        self
+    }
+
+    /// Locate the diagnostic at `span` within `content`
+    #[must_use]
+    pub fn at(mut self, content: &str, span: Range<usize>) -> Self {
+        let start = content[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let end = content[start..]
+            .find('\n')
+            .map_or(content.len(), |i| start + i);
+        let within = span.start - start..span.end.min(end) - start;
+        let location = line_col(content, span.start);
+        self.excerpt = Some((content[start..end].to_string(), location.0, within));
+        self.location = Some(location);
+        self.span = Some(span);
+        self
+    }
+
+    /// Shift the location of a diagnostic found in a fragment of a file that
+    /// starts at `line` and byte `offset`
+    #[must_use]
+    pub fn offset(mut self, line: usize, offset: usize) -> Self {
+        if let Some((l, _)) = &mut self.location {
+            *l += line - 1;
+        }
+        if let Some(span) = &mut self.span {
+            *span = span.start + offset..span.end + offset;
+        }
+        self
+    }
+
+    /// Drop the line and column but keep the excerpt, numbered within the
+    /// fragment, for a fragment whose lines are not lines of the file, such
+    /// as a `-0` record
+    #[must_use]
+    pub fn without_location(mut self) -> Self {
+        self.location = None;
+        self
+    }
+
+    /// The offending line with the span underlined, as text, if located.
+    ///
+    /// With `header`, the excerpt starts with a `--> file:line:column` line,
+    /// unless the diagnostic has no line and column.
+    pub fn snippet(&self, header: bool) -> Option<String> {
+        let (text, line, span) = self.excerpt.as_ref()?;
+        let line = self.location.map_or(*line, |(line, _)| line);
+        let mut snippet = Snippet::new(text, span.clone())
+            .first_line(line)
+            .color(color());
+        if let (true, Some(file), Some(_)) = (header, &self.file, self.location) {
+            snippet = snippet.path(file);
+        }
+        let help = match &self.help {
+            Some(help) => format!("help: {}\n", help),
+            None => String::new(),
+        };
+        Some(format!("{}{}", snippet, help))
+    }
+
+    /// Write the diagnostic to stderr as a JSON line
+    pub fn emit(&self) {
+        eprintln!("{}", self.to_json());
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "severity": "error",
+            "code": self.code,
+            "message": self.message,
+            "file": self.file,
+            "line": self.location.map(|(line, _)| line),
+            "column": self.location.map(|(_, column)| column),
+            "span": self.span.as_ref().map(|span| [span.start, span.end]),
+            "variable": self.variable,
+        })
+    }
+}
+
+/// The hint naming the variables an undefined name may be a misspelling of
+fn help(suggestions: &[String]) -> Option<String> {
+    let names: Vec<String> = suggestions.iter().map(|n| format!("'{}'", n)).collect();
+    match names.split_last()? {
+        (name, []) => Some(format!("a variable named {} exists", name)),
+        (last, rest) => Some(format!(
+            "variables named {} and {} exist",
+            rest.join(", "),
+            last
+        )),
+    }
+}
+
+/// Byte range of the reference an error is about
+fn error_span(error: &SubstError, content: &str, syntax: &SyntaxConfig) -> Range<usize> {
+    let start = error.position();
+    let end = match error {
+        SubstError::UnclosedBrace { .. } => content.len(),
+        SubstError::StrayDollar { .. } => start + 1,
+        SubstError::Write { .. } => start,
+        SubstError::UnknownTransform { directive, .. } => {
+            let suffix = format!("@{}{}", directive, syntax.close());
+            (content[start..].find(&suffix)).map_or(start, |at| start + at + suffix.len())
+        }
+        SubstError::InvalidVarName { name, .. } => {
+            let end = start + syntax.open().len() + name.len();
+            if content[end.min(content.len())..].starts_with(syntax.close()) {
+                end + syntax.close().len()
+            } else {
+                end
+            }
+        }
+        SubstError::UndefinedVariable { .. }
+        | SubstError::RequiredVariable { .. }
+        | SubstError::CircularReference { .. }
+        | SubstError::DepthLimitExceeded { .. } => varsubst::segments_with(content, syntax)
+            .flatten()
+            .find_map(|segment| match segment {
+                Segment::Var { span, .. } if span.start == start => Some(span.end),
+                _ => None,
+            })
+            .unwrap_or(start),
+    };
+    start..end.min(content.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(diagnostic: &Diagnostic) -> String {
+        diagnostic.to_json().to_string()
+    }
+
+    #[test]
+    fn test_undefined_variable_span() {
+        let content = "a\nb ${NAME} c";
+        let error = SubstError::UndefinedVariable {
+            name: "NAME".to_string(),
+            position: 4,
+            suggestions: Vec::new(),
+        };
+        let diagnostic = Diagnostic::of_error(&error, content, &SyntaxConfig::new()).file("t");
+        assert_eq!(diagnostic.location, Some((2, 3)));
+        assert_eq!(diagnostic.span, Some(4..11));
+        assert_eq!(diagnostic.variable.as_deref(), Some("NAME"));
+        assert_eq!(
+            json(&diagnostic),
+            r#"{"code":"undefined-variable","column":3,"file":"t","line":2,"message":"Undefined variable 'NAME' at position 4","severity":"error","span":[4,11],"variable":"NAME"}"#
+        );
+    }
+
+    #[test]
+    fn test_undefined_variable_help() {
+        let error = |suggestions: &[&str]| SubstError::UndefinedVariable {
+            name: "DB_HSOT".to_string(),
+            position: 0,
+            suggestions: suggestions.iter().map(|s| s.to_string()).collect(),
+        };
+        let snippet = |error| {
+            let diagnostic = Diagnostic::of_error(&error, "${DB_HSOT}", &SyntaxConfig::new());
+            diagnostic.snippet(false).unwrap()
+        };
+        assert!(snippet(error(&["DB_HOST"]))
+            .ends_with("^^^^^^^^^^\nhelp: a variable named 'DB_HOST' exists\n"));
+        assert!(snippet(error(&["A", "B", "C"]))
+            .ends_with("\nhelp: variables named 'A', 'B' and 'C' exist\n"));
+        assert!(snippet(error(&[])).ends_with("^^^^^^^^^^\n"));
+    }
+
+    #[test]
+    fn test_syntax_error_spans() {
+        let syntax = SyntaxConfig::new();
+        let invalid = |name: &str, position| SubstError::InvalidVarName {
+            name: name.to_string(),
+            position,
+        };
+        let span = |error, content| error_span(&error, content, &syntax);
+        assert_eq!(span(invalid("A", 0), "${A.B}"), 0..3);
+        assert_eq!(span(invalid("", 1), " ${}"), 1..4);
+        assert_eq!(
+            span(SubstError::UnclosedBrace { position: 2 }, "x ${A"),
+            2..5
+        );
+    }
+
+    #[test]
+    fn test_unlocated_fields_are_null() {
+        let diagnostic = Diagnostic::of_status(Status::Io, "gone");
+        assert_eq!(
+            json(&diagnostic),
+            r#"{"code":"io","column":null,"file":null,"line":null,"message":"gone","severity":"error","span":null,"variable":null}"#
+        );
+    }
+
+    #[test]
+    fn test_offset() {
+        let diagnostic = Diagnostic::new("denied-variable", "x")
+            .at("${A}", 0..4)
+            .offset(3, 10);
+        assert_eq!(diagnostic.location, Some((3, 1)));
+        assert_eq!(diagnostic.span, Some(10..14));
+        assert_eq!(
+            diagnostic.snippet(false).as_deref(),
+            Some("  |\n3 | ${A}\n  | ^^^^\n")
+        );
+    }
+
+    #[test]
+    fn test_snippet() {
+        let diagnostic = Diagnostic::new("unclosed-brace", "x")
+            .at("a\nb ${A\nc", 4..10)
+            .file("t");
+        assert_eq!(
+            diagnostic.snippet(true).as_deref(),
+            Some(" --> t:2:3\n  |\n2 | b ${A\n  |   ^^^\n")
+        );
+        assert_eq!(Diagnostic::new("io", "gone").file("t").snippet(true), None);
+        assert_eq!(
+            diagnostic.without_location().snippet(true).as_deref(),
+            Some("  |\n2 | b ${A\n  |   ^^^\n")
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! `--diff`: show what rendering would change instead of writing it.
+//!
+//! Each rendered output is compared with what its destination holds now. An
+//! output to stdout has no destination to compare with, so it is compared
+//! with its template, showing what substitution changes.
+
+use similar::TextDiff;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+
+/// Unified diff from `old` to `new` under the given file labels, or `None`
+/// if they are the same
+pub fn unified(old: &str, new: &str, labels: (&str, &str), color: bool) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(CONTEXT)
+        .header(labels.0, labels.1)
+        .to_string();
+    if !color {
+        return Some(diff);
+    }
+    let colored = diff
+        .split_inclusive('\n')
+        .map(|line| {
+            let style = match line.as_bytes().first() {
+                _ if line.starts_with("---") || line.starts_with("+++") => BOLD,
+                Some(b'@') => CYAN,
+                Some(b'-') => RED,
+                Some(b'+') => GREEN,
+                _ => return line.to_string(),
+            };
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+            format!("{style}{text}{RESET}{newline}")
+        })
+        .collect();
+    Some(colored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified() {
+        assert_eq!(unified("a\n", "a\n", ("x", "y"), false), None);
+        let diff = unified("a\nb\nc\n", "a\nB\nc\n", ("old", "new"), false).unwrap();
+        assert_eq!(diff, "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+    }
+
+    #[test]
+    fn test_color() {
+        let diff = unified("a\n", "b\n", ("old", "new"), true).unwrap();
+        assert!(diff.contains("\x1b[31m-a\x1b[0m\n"), "{:?}", diff);
+        assert!(diff.contains("\x1b[32m+b\x1b[0m\n"), "{:?}", diff);
+        assert!(diff.starts_with("\x1b[1m--- old\x1b[0m\n"), "{:?}", diff);
+    }
+}
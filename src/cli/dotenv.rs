@@ -0,0 +1,223 @@
+//! Parser for dotenv-style variable files.
+//!
+//! Supported syntax:
+//!
+//! - `KEY=VALUE` pairs, one per line, with an optional `export ` prefix
+//! - Blank lines and lines starting with `#`
+//! - Unquoted values, trimmed, with ` # comment` suffixes removed
+//! - `'single quoted'` values, taken literally
+//! - `"double quoted"` values, supporting `\n`, `\t`, `\"` and `\\` escapes
+
+use std::fmt;
+
+/// A malformed line in a dotenv file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number
+    pub line: usize,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parse dotenv content into `(key, value)` pairs in file order
+pub fn parse(content: &str) -> Result<Vec<(String, String)>, ParseError> {
+    let mut pairs = Vec::new();
+
+    for (index, raw) in content.lines().enumerate() {
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected KEY=VALUE, found '{}'", raw)))?;
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(error(format!("invalid variable name '{}'", key)));
+        }
+
+        let value = parse_value(value.trim()).map_err(error)?;
+        pairs.push((key.to_string(), value));
+    }
+
+    Ok(pairs)
+}
+
+/// Parse plain `KEY=VALUE` lines into `(key, value)` pairs.
+///
+/// Unlike [`parse`], values are taken verbatim: no quoting, trimming or
+/// inline comments. Blank lines and lines starting with `#` are skipped.
+pub fn parse_plain(content: &str) -> Result<Vec<(String, String)>, ParseError> {
+    let mut pairs = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| ParseError {
+            line: index + 1,
+            message: format!("expected KEY=VALUE, found '{}'", line),
+        })?;
+        pairs.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(pairs)
+}
+
+/// Parse the value part of a line (already trimmed)
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let end = rest
+            .find('\'')
+            .ok_or_else(|| "unterminated single quote".to_string())?;
+        check_trailing(&rest[end + 1..])?;
+        return Ok(rest[..end].to_string());
+    }
+
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    check_trailing(&rest[i + 1..])?;
+                    return Ok(result);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => result.push('\n'),
+                    Some((_, 't')) => result.push('\t'),
+                    Some((_, 'r')) => result.push('\r'),
+                    Some((_, other)) => {
+                        if other != '"' && other != '\\' {
+                            result.push('\\');
+                        }
+                        result.push(other);
+                    }
+                    None => break,
+                },
+                _ => result.push(ch),
+            }
+        }
+        return Err("unterminated double quote".to_string());
+    }
+
+    // Unquoted: strip an inline comment introduced by whitespace + '#'
+    let value = match value.find(" #").or_else(|| value.find("\t#")) {
+        Some(end) => &value[..end],
+        None => value,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+/// Only whitespace or a comment may follow a closing quote
+fn check_trailing(rest: &str) -> Result<(), String> {
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected text after closing quote: '{}'", rest))
+    }
+}
+
+/// Check that a key is a valid variable name
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(content: &str) -> Vec<(String, String)> {
+        parse(content).unwrap()
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_basic_pairs() {
+        assert_eq!(
+            pairs("A=1\nB = two words \n"),
+            vec![pair("A", "1"), pair("B", "two words")]
+        );
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        assert_eq!(
+            pairs("# comment\n\n  # indented\nA=1 # trailing\nB=x#y\n"),
+            vec![pair("A", "1"), pair("B", "x#y")]
+        );
+    }
+
+    #[test]
+    fn test_export_prefix() {
+        assert_eq!(pairs("export A=1"), vec![pair("A", "1")]);
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        assert_eq!(
+            pairs("A='lit # \\n'\nB=\"x\\n\\\"y\\\"\" # note\nC=\"\""),
+            vec![pair("A", "lit # \\n"), pair("B", "x\n\"y\""), pair("C", "")]
+        );
+    }
+
+    #[test]
+    fn test_empty_value() {
+        assert_eq!(pairs("A="), vec![pair("A", "")]);
+    }
+
+    #[test]
+    fn test_missing_equals() {
+        let err = parse("A=1\nJUNK\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        let err = parse("1A=x").unwrap_err();
+        assert_eq!(err.message, "invalid variable name '1A'");
+    }
+
+    #[test]
+    fn test_plain_values_are_verbatim() {
+        assert_eq!(
+            parse_plain("# c\n\nA= x # y \nB='q'\n").unwrap(),
+            vec![pair("A", " x # y "), pair("B", "'q'")]
+        );
+    }
+
+    #[test]
+    fn test_plain_missing_equals() {
+        let err = parse_plain("A=1\n\nnope\n").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+
+    #[test]
+    fn test_unterminated_quote() {
+        let err = parse("A=\"open").unwrap_err();
+        assert_eq!(err.message, "unterminated double quote");
+    }
+}
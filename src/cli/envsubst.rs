@@ -0,0 +1,146 @@
+//! `--envsubst-compat`: GNU `envsubst` behavior, so varsubst can stand in for
+//! it in existing scripts.
+//!
+//! The template is read from stdin and written to stdout. `$NAME` and
+//! `${NAME}` are both references, and every undefined one becomes empty.
+//! There are no escapes, and anything that is not a well-formed reference,
+//! such as `${NAME` or `${}`, is copied through instead of being an error.
+//! With a SHELL-FORMAT argument, only the variables it references are
+//! substituted and other references are copied through too.
+//!
+//! Like `envsubst`, this works on bytes: input need not be UTF-8.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The names referenced in a SHELL-FORMAT argument such as `'$HOME ${USER}'`
+pub fn names(shell_format: &str) -> HashSet<&str> {
+    references(shell_format.as_bytes())
+        .map(|(_, name)| &shell_format[name])
+        .collect()
+}
+
+/// Substitute the references in `template`: all of them, or with `only`,
+/// those to the listed names
+pub fn substitute(
+    template: &[u8],
+    vars: &HashMap<String, String>,
+    only: Option<&HashSet<&str>>,
+) -> Vec<u8> {
+    let mut output = Vec::with_capacity(template.len());
+    let mut copied = 0;
+    for (span, name) in references(template) {
+        // Names are ASCII, so this cannot fail
+        let name = std::str::from_utf8(&template[name]).unwrap_or_default();
+        if only.is_some_and(|only| !only.contains(name)) {
+            continue;
+        }
+        output.extend_from_slice(&template[copied..span.start]);
+        if let Some(value) = vars.get(name) {
+            output.extend_from_slice(value.as_bytes());
+        }
+        copied = span.end;
+    }
+    output.extend_from_slice(&template[copied..]);
+    output
+}
+
+/// The well-formed references in `text`: the span of each, and of its name
+fn references(text: &[u8]) -> impl Iterator<Item = (Range<usize>, Range<usize>)> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        while pos < text.len() {
+            let start = pos;
+            pos += 1;
+            if text[start] != b'$' {
+                continue;
+            }
+            let braced = text.get(pos) == Some(&b'{');
+            let name_start = pos + usize::from(braced);
+            if !text
+                .get(name_start)
+                .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_')
+            {
+                // A lone '$' (or '${'); what follows is scanned again
+                pos = name_start;
+                continue;
+            }
+            let mut end = name_start;
+            while text
+                .get(end)
+                .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_')
+            {
+                end += 1;
+            }
+            match braced {
+                true if text.get(end) != Some(&b'}') => pos = end,
+                true => {
+                    pos = end + 1;
+                    return Some((start..pos, name_start..end));
+                }
+                false => {
+                    pos = end;
+                    return Some((start..end, name_start..end));
+                }
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([
+            ("HOME".to_string(), "/home/me".to_string()),
+            ("USER".to_string(), "me".to_string()),
+        ])
+    }
+
+    fn render(template: &str, only: Option<&str>) -> String {
+        let only = only.map(names);
+        String::from_utf8(substitute(template.as_bytes(), &vars(), only.as_ref())).unwrap()
+    }
+
+    #[test]
+    fn test_every_reference_without_shell_format() {
+        assert_eq!(
+            render("$USER at ${HOME}: $NOPE.", None),
+            "me at /home/me: ."
+        );
+        assert_eq!(render("${USER}s $USERs", None), "mes ");
+    }
+
+    #[test]
+    fn test_only_listed_names_with_shell_format() {
+        let template = "$USER ${HOME} $NOPE $OTHER";
+        assert_eq!(
+            render(template, Some("$HOME $NOPE")),
+            "$USER /home/me  $OTHER"
+        );
+        assert_eq!(render(template, Some("no variables")), template);
+    }
+
+    #[test]
+    fn test_malformed_references_are_text() {
+        assert_eq!(
+            render("${USER ${} $ $1 ${9} \\$USER", None),
+            "${USER ${} $ $1 ${9} \\me"
+        );
+        assert_eq!(render("$${USER} ${", None), "$me ${");
+    }
+
+    #[test]
+    fn test_names_of_shell_format() {
+        let names = names("$A, ${B}, ${C, $$D");
+        assert_eq!(names, HashSet::from(["A", "B", "D"]));
+    }
+
+    #[test]
+    fn test_bytes_pass_through() {
+        let output = substitute(b"\xff$USER\xfe", &vars(), None);
+        assert_eq!(output, b"\xffme\xfe");
+    }
+}
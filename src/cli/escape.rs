@@ -0,0 +1,117 @@
+//! `--escape-values`: escaping substituted values for the output language.
+//!
+//! Only values are escaped; the template text around them is written as it
+//! is, since it is already in the output language. Values are escaped once,
+//! before any template is rendered, so reports and redaction see them as
+//! they are written.
+
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// How substituted values are escaped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Escape {
+    /// Insert values as they are (default)
+    #[default]
+    None,
+    /// Single-quote each value for POSIX shells
+    Shell,
+    /// Escape & < > " and ' as HTML character references
+    Html,
+    /// Escape & < > " and ' as XML entities
+    Xml,
+    /// Escape as the contents of a JSON string, without the quotes
+    Json,
+    /// Percent-encode everything but unreserved URL characters
+    Url,
+}
+
+impl Escape {
+    /// `value` escaped for this mode
+    pub fn apply(self, value: &str) -> Cow<'_, str> {
+        match self {
+            Escape::None => Cow::Borrowed(value),
+            Escape::Shell => Cow::Owned(format!("'{}'", value.replace('\'', r"'\''"))),
+            Escape::Html => markup(value, "&#39;"),
+            Escape::Xml => markup(value, "&apos;"),
+            Escape::Json => {
+                let quoted = serde_json::to_string(value).expect("strings serialize");
+                Cow::Owned(quoted[1..quoted.len() - 1].to_string())
+            }
+            Escape::Url => {
+                let mut encoded = String::with_capacity(value.len());
+                for byte in value.bytes() {
+                    match byte {
+                        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                            encoded.push(byte as char)
+                        }
+                        _ => write!(encoded, "%{:02X}", byte).expect("writing to a String"),
+                    }
+                }
+                Cow::Owned(encoded)
+            }
+        }
+    }
+
+    /// Escape every value in `values`
+    pub fn apply_all(self, values: &mut HashMap<String, String>) {
+        if self == Escape::None {
+            return;
+        }
+        for value in values.values_mut() {
+            *value = self.apply(value).into_owned();
+        }
+    }
+}
+
+fn markup<'a>(value: &'a str, apostrophe: &str) -> Cow<'a, str> {
+    if !value.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 16);
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str(apostrophe),
+            _ => escaped.push(ch),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: &str = "it's <script>alert(\"x&y\")</script>";
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Escape::None.apply(HOSTILE), HOSTILE);
+        assert_eq!(
+            Escape::Shell.apply(HOSTILE),
+            r#"'it'\''s <script>alert("x&y")</script>'"#
+        );
+        assert_eq!(
+            Escape::Html.apply(HOSTILE),
+            "it&#39;s &lt;script&gt;alert(&quot;x&amp;y&quot;)&lt;/script&gt;"
+        );
+        assert_eq!(Escape::Xml.apply("'&"), "&apos;&amp;");
+        assert_eq!(
+            Escape::Json.apply("a\"b\\c\n\u{1}"),
+            "a\\\"b\\\\c\\n\\u0001"
+        );
+        assert_eq!(Escape::Url.apply("a b&c/é~"), "a%20b%26c%2F%C3%A9~");
+    }
+
+    #[test]
+    fn test_empty_values() {
+        assert_eq!(Escape::Shell.apply(""), "''");
+        assert_eq!(Escape::Url.apply(""), "");
+    }
+}
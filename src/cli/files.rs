@@ -0,0 +1,175 @@
+//! Reading inputs and writing outputs.
+
+use crate::cli::bom::Bom;
+use crate::cli::escape::Escape;
+use crate::cli::newline::Newline;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use varsubst::AtomicFile;
+
+/// Inputs as read sources: each path in order, or stdin (`None`) if there are none
+pub fn input_sources(inputs: &[String]) -> Vec<Option<&String>> {
+    if inputs.is_empty() {
+        vec![None]
+    } else {
+        inputs.iter().map(Some).collect()
+    }
+}
+
+/// Read input from file or stdin
+pub fn read_input(path: Option<&String>) -> io::Result<String> {
+    match path {
+        Some(file_path) => fs::read_to_string(file_path),
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer)?;
+            Ok(buffer)
+        }
+    }
+}
+
+/// Read input from file or stdin as bytes, without UTF-8 validation
+pub fn read_input_bytes(path: Option<&String>) -> io::Result<Vec<u8>> {
+    match path {
+        Some(file_path) => fs::read(file_path),
+        None => {
+            let mut buffer = Vec::new();
+            io::stdin().read_to_end(&mut buffer)?;
+            Ok(buffer)
+        }
+    }
+}
+
+/// `bytes` as text with the same byte offsets, for checks and messages:
+/// each byte of an invalid UTF-8 sequence becomes U+001A (SUBSTITUTE)
+pub fn text_view(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        text.extend(std::iter::repeat_n('\u{1a}', chunk.invalid().len()));
+    }
+    text
+}
+
+/// Write output to file (atomically, see [`write_atomic`]) or stdout
+pub fn write_output(path: &Option<String>, content: &[u8]) -> io::Result<()> {
+    match path {
+        Some(file_path) => {
+            let path = Path::new(file_path);
+            write_atomic(
+                path,
+                content,
+                output_permissions(path, None, OutputMode::Default)?,
+            )
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(content)?;
+            stdout.flush()
+        }
+    }
+}
+
+/// Replace the contents of `path`, keeping a copy at `path + suffix` first
+/// unless `suffix` is empty. The permissions are kept unless `mode` sets them.
+pub fn write_in_place(
+    path: &str,
+    suffix: &str,
+    content: &[u8],
+    mode: OutputMode,
+) -> io::Result<()> {
+    if !suffix.is_empty() {
+        fs::copy(path, format!("{}{}", path, suffix))?;
+    }
+    let mode = match mode {
+        OutputMode::Set(_) => mode,
+        _ => OutputMode::Preserve,
+    };
+    let target = Path::new(path);
+    let permissions = output_permissions(target, Some(path), mode)?;
+    write_atomic(target, content, permissions)
+}
+
+/// How rendered output is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputSettings {
+    /// Permissions of written files
+    pub mode: OutputMode,
+    /// Line endings of the written text
+    pub newline: Newline,
+    /// Read and write templates as bytes instead of UTF-8 text
+    pub binary_safe: bool,
+    /// Leading byte order marks of inputs and outputs
+    pub bom: Bom,
+    /// Read default values from a frontmatter block at the start of inputs
+    pub frontmatter: bool,
+    /// Escaping of the values from frontmatter blocks; the others are
+    /// escaped when loaded
+    pub escape: Escape,
+}
+
+/// How written files get their permission bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Copy the input file's permissions (Unix mode bits, read-only on Windows)
+    Preserve,
+    /// Leave the permissions a newly written file gets
+    Default,
+    /// Set these Unix mode bits (on Windows, only whether the file is read-only)
+    Set(u32),
+}
+
+/// Permissions to give the file written to `target` according to `mode`;
+/// `input` is where the content came from (`None` for stdin).
+///
+/// `None` means the permissions of a newly created file. Without a mode to
+/// apply, an existing `target` keeps its permissions, as when overwriting it.
+pub fn output_permissions(
+    target: &Path,
+    input: Option<&str>,
+    mode: OutputMode,
+) -> io::Result<Option<fs::Permissions>> {
+    match (mode, input) {
+        (OutputMode::Preserve, Some(input)) => Ok(Some(fs::metadata(input)?.permissions())),
+        (OutputMode::Set(bits), _) => permissions(target, input, bits).map(Some),
+        (OutputMode::Preserve, None) | (OutputMode::Default, _) => {
+            Ok(fs::metadata(target).ok().map(|m| m.permissions()))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn permissions(_target: &Path, _input: Option<&str>, mode: u32) -> io::Result<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn permissions(target: &Path, input: Option<&str>, mode: u32) -> io::Result<fs::Permissions> {
+    // Permissions cannot be built from scratch here; start from a file's
+    let mut permissions = fs::metadata(target)
+        .or_else(|_| fs::metadata(input.unwrap_or(".")))?
+        .permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    Ok(permissions)
+}
+
+/// Write `content` to `path` atomically, see [`AtomicFile`]
+pub fn write_atomic(
+    path: &Path,
+    content: impl AsRef<[u8]>,
+    permissions: Option<fs::Permissions>,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, permissions)?;
+    file.write_all(content.as_ref())?;
+    file.commit()
+}
+
+/// Convert a byte offset into a 1-based line and column (counted in characters)
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
@@ -0,0 +1,105 @@
+//! `--frontmatter`: default values at the top of a template.
+//!
+//! A template may start with a block of dotenv-style definitions:
+//!
+//! ```text
+//! ---varsubst
+//! HOST=localhost
+//! PORT=8080
+//! ---
+//! listen ${HOST}:${PORT}
+//! ```
+//!
+//! The block is removed before substitution, and its values have the lowest
+//! precedence: the environment and every other source override them. Only a
+//! block on the very first line counts; anywhere else it is text.
+
+use crate::cli::dotenv::{self, ParseError};
+
+/// Line that opens a frontmatter block
+pub const OPEN: &str = "---varsubst";
+/// Line that closes a frontmatter block
+pub const CLOSE: &str = "---";
+
+/// A frontmatter block found at the start of a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frontmatter {
+    /// The definitions, in block order
+    pub pairs: Vec<(String, String)>,
+    /// Length of the block in bytes, including the closing line
+    pub len: usize,
+    /// Number of lines of the block
+    pub lines: usize,
+}
+
+/// The frontmatter block at the start of `content`, if there is one.
+///
+/// Line numbers in errors count from the opening line.
+pub fn parse(content: &str) -> Result<Option<Frontmatter>, ParseError> {
+    let mut lines = content.split_inclusive('\n');
+    let Some(first) = lines.next().filter(|line| trim_newline(line) == OPEN) else {
+        return Ok(None);
+    };
+    let mut end = first.len();
+    for (index, line) in lines.enumerate() {
+        if trim_newline(line) == CLOSE {
+            let pairs = dotenv::parse(&content[first.len()..end]).map_err(|e| ParseError {
+                line: e.line + 1,
+                message: e.message,
+            })?;
+            return Ok(Some(Frontmatter {
+                pairs,
+                len: end + line.len(),
+                lines: index + 2,
+            }));
+        }
+        end += line.len();
+    }
+    Err(ParseError {
+        line: 1,
+        message: format!("'{}' is not closed by a '{}' line", OPEN, CLOSE),
+    })
+}
+
+fn trim_newline(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_block() {
+        let content = "---varsubst\nA=1\n# comment\nB='two words'\n---\nrest ${A}\n";
+        let frontmatter = parse(content).unwrap().unwrap();
+        assert_eq!(
+            frontmatter.pairs,
+            [("A".into(), "1".into()), ("B".into(), "two words".into())]
+        );
+        assert_eq!(&content[frontmatter.len..], "rest ${A}\n");
+        assert_eq!(frontmatter.lines, 5);
+    }
+
+    #[test]
+    fn test_no_block() {
+        assert_eq!(parse("plain ${A}\n---varsubst\n---\n"), Ok(None));
+        assert_eq!(parse(""), Ok(None));
+    }
+
+    #[test]
+    fn test_crlf_and_block_at_end_of_file() {
+        let frontmatter = parse("---varsubst\r\nA=1\r\n---").unwrap().unwrap();
+        assert_eq!(frontmatter.pairs, [("A".into(), "1".into())]);
+        assert_eq!(frontmatter.len, 21);
+    }
+
+    #[test]
+    fn test_errors_count_lines_from_the_opening_line() {
+        let error = parse("---varsubst\nA=1\nnot a definition\n---\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        let error = parse("---varsubst\nA=1\n").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(error.message.contains("not closed"), "{}", error.message);
+    }
+}
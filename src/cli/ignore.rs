@@ -0,0 +1,276 @@
+//! `--ignore` and `.varsubstignore`: leaving paths out of `--recursive` and
+//! `--glob` inputs.
+//!
+//! Patterns are gitignore-style globs over `/`-separated paths relative to
+//! the traversal root (the `--recursive` directory, or the current directory
+//! for `--glob`):
+//!
+//! - a pattern without a `/` matches a file or directory name at any depth
+//! - a pattern with a `/` in it, or starting with one, matches the whole path
+//! - `*` and `?` stop at `/`, `**` crosses directories
+//! - a trailing `/` matches directories only; `dir/**` is treated as `dir/`
+//! - a leading `!` re-includes what an earlier pattern ignored
+//!
+//! With `--recursive`, a `.varsubstignore` file in the root or any directory
+//! below it adds patterns relative to its own directory, one per line, with
+//! blank lines and `#` comments skipped (`\#` and `\!` escape a leading `#`
+//! or `!`). When several patterns match a path the last one wins: the files
+//! from the root down in walk order, then the `--ignore` patterns.
+//!
+//! `--recursive` does not read ignored directories at all, so nothing inside
+//! one can be re-included; `--glob` leaves out the files it matches inside
+//! them.
+
+use crate::cli::status::{Failure, Status};
+use glob::{MatchOptions, Pattern};
+
+/// Name of the ignore file honored in `--recursive` directories
+pub const FILE_NAME: &str = ".varsubstignore";
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Compiled ignore patterns
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    /// Patterns from ignore files, in the order they were read
+    file_rules: Vec<Rule>,
+    /// Patterns from `--ignore`, which apply after the files
+    cli_rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The pattern as given
+    text: String,
+    glob: Pattern,
+    /// Directory the pattern is relative to, `""` for the root
+    base: String,
+    /// Match the whole path instead of the last name
+    anchored: bool,
+    /// Match directories only
+    dir_only: bool,
+    /// Re-include matching paths
+    negated: bool,
+}
+
+impl Ignore {
+    /// Compile `--ignore` patterns
+    pub fn new(patterns: &[String]) -> Result<Ignore, Failure> {
+        let cli_rules = patterns
+            .iter()
+            .map(|text| {
+                Rule::parse(text, "").map_err(|e| {
+                    Failure::new(
+                        Status::Usage,
+                        format!("Invalid --ignore pattern '{}': {}", text, e),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Ignore {
+            file_rules: Vec::new(),
+            cli_rules,
+        })
+    }
+
+    /// These patterns followed by those of an ignore file in the directory
+    /// `dir` (relative to the root), read from `path`
+    pub fn with_file(&self, dir: &str, content: &str, path: &str) -> Result<Ignore, Failure> {
+        let base = normalize(dir);
+        let mut ignore = self.clone();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = Rule::parse(line, &base).map_err(|e| {
+                Failure::new(
+                    Status::Failure,
+                    format!(
+                        "Invalid pattern in '{}' at line {}: '{}': {}",
+                        path,
+                        index + 1,
+                        line,
+                        e
+                    ),
+                )
+            })?;
+            ignore.file_rules.push(rule);
+        }
+        Ok(ignore)
+    }
+
+    /// The pattern that ignores `path`, relative to the traversal root, if any
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<&str> {
+        let path = normalize(path);
+        (self.file_rules.iter().chain(&self.cli_rules))
+            .rfind(|rule| rule.matches(&path, is_dir))
+            .filter(|rule| !rule.negated)
+            .map(|rule| rule.text.as_str())
+    }
+
+    /// Whether the file at `path` is ignored, itself or by a directory it is in
+    pub fn excludes(&self, path: &str) -> bool {
+        let path = normalize(path);
+        let mut dirs = path.match_indices('/').map(|(end, _)| &path[..end]);
+        dirs.any(|dir| self.matched(dir, true).is_some()) || self.matched(&path, false).is_some()
+    }
+}
+
+impl Rule {
+    fn parse(text: &str, base: &str) -> Result<Rule, glob::PatternError> {
+        let (negated, body) = match text.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('\\').unwrap_or(text)),
+        };
+        let trimmed = body.trim_end_matches('/');
+        let mut dir_only = trimmed.len() < body.len();
+        let anchored = trimmed.contains('/');
+        let body = trimmed.strip_prefix('/').unwrap_or(trimmed);
+        let body = match body.strip_suffix("/**") {
+            Some(dir) => {
+                dir_only = true;
+                dir
+            }
+            None => body,
+        };
+        Ok(Rule {
+            text: text.to_string(),
+            glob: Pattern::new(body)?,
+            base: base.to_string(),
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = match self.base.as_str() {
+            "" => path,
+            base => match path
+                .strip_prefix(base)
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            },
+        };
+        let subject = match self.anchored {
+            true => path,
+            false => path.rsplit('/').next().unwrap_or(path),
+        };
+        self.glob.matches_with(subject, OPTIONS)
+    }
+}
+
+/// `path` with `/` separators and without a leading `./`
+fn normalize(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut path = path.as_str();
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(patterns: &[&str]) -> Ignore {
+        Ignore::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_names_match_at_any_depth() {
+        let ignore = ignore(&["*.min.js"]);
+        assert_eq!(ignore.matched("app.min.js", false), Some("*.min.js"));
+        assert_eq!(
+            ignore.matched("static/js/app.min.js", false),
+            Some("*.min.js")
+        );
+        assert_eq!(ignore.matched("app.js", false), None);
+    }
+
+    #[test]
+    fn test_paths_are_anchored() {
+        let ignore = ignore(&["node_modules/**", "/build", "docs/*.md"]);
+        assert!(ignore.matched("node_modules", true).is_some());
+        assert!(ignore.matched("web/node_modules", true).is_none());
+        assert!(ignore.matched("build", false).is_some());
+        assert!(ignore.matched("src/build", false).is_none());
+        assert!(ignore.matched("./docs/a.md", false).is_some());
+        assert!(ignore.matched("docs/api/a.md", false).is_none());
+    }
+
+    #[test]
+    fn test_double_star_and_directories() {
+        let ignore = ignore(&["**/cache/", "a/**/z.txt"]);
+        assert!(ignore.matched("cache", true).is_some());
+        assert!(ignore.matched("x/y/cache", true).is_some());
+        assert!(ignore.matched("x/cache", false).is_none());
+        assert!(ignore.matched("a/z.txt", false).is_some());
+        assert!(ignore.matched("a/b/c/z.txt", false).is_some());
+    }
+
+    #[test]
+    fn test_excludes_files_in_ignored_directories() {
+        let ignore = ignore(&["node_modules/", "/vendor/**"]);
+        assert!(ignore.excludes("web/node_modules/lib/a.js"));
+        assert!(ignore.excludes("vendor/a.js"));
+        assert!(!ignore.excludes("web/vendor/a.js"));
+        assert!(!ignore.excludes("node_modules"));
+    }
+
+    #[test]
+    fn test_last_match_wins() {
+        let ignore = ignore(&["*.tmpl", "!keep.tmpl"]);
+        assert!(ignore.matched("a.tmpl", false).is_some());
+        assert_eq!(ignore.matched("sub/keep.tmpl", false), None);
+    }
+
+    #[test]
+    fn test_file_patterns_come_before_cli_patterns() {
+        let content = "# generated files\n\n*.gen\n!important.gen\n\\#literal\n";
+        let cli = ignore(&["important.gen"]);
+        let ignore = cli.with_file("", content, FILE_NAME).unwrap();
+        assert_eq!(ignore.matched("a.gen", false), Some("*.gen"));
+        assert_eq!(
+            ignore.matched("important.gen", false),
+            Some("important.gen")
+        );
+        assert_eq!(ignore.matched("#literal", false), Some("\\#literal"));
+        assert_eq!(ignore.matched("generated files", false), None);
+    }
+
+    #[test]
+    fn test_nested_file_is_relative_to_its_directory() {
+        let ignore = Ignore::default()
+            .with_file("sub", "/local.txt\n*.bak\n", "sub/.varsubstignore")
+            .unwrap();
+        assert!(ignore.matched("sub/local.txt", false).is_some());
+        assert!(ignore.matched("local.txt", false).is_none());
+        assert!(ignore.matched("sub/deep/local.txt", false).is_none());
+        assert!(ignore.matched("sub/deep/x.bak", false).is_some());
+        assert!(ignore.matched("x.bak", false).is_none());
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        let failure = Ignore::new(&["[".to_string()]).unwrap_err();
+        assert!(failure.message.contains("Invalid --ignore pattern '['"));
+        let failure = Ignore::default()
+            .with_file("", "ok\n[\n", FILE_NAME)
+            .unwrap_err();
+        assert!(failure
+            .message
+            .contains("Invalid pattern in '.varsubstignore' at line 2"));
+    }
+}
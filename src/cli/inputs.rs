@@ -0,0 +1,318 @@
+//! Input discovery: explicit files, `--glob` patterns and `--recursive` walks.
+
+use crate::cli::files;
+use crate::cli::ignore::{self, Ignore};
+use crate::cli::status::{Failure, Status};
+use glob::MatchOptions;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A file to process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    /// Path to read, or `None` for stdin
+    pub path: Option<String>,
+    /// Name used in messages
+    pub display: String,
+    /// Output path relative to an output directory
+    pub relative: PathBuf,
+    /// Copy the file verbatim instead of substituting
+    pub copy: bool,
+    /// Template text given with `-e`, used instead of reading `path`
+    pub text: Option<String>,
+}
+
+impl Input {
+    /// An input file given on the command line, written under its file name
+    pub fn file(path: &str) -> Self {
+        Input {
+            path: Some(path.to_string()),
+            display: path.to_string(),
+            relative: PathBuf::from(Path::new(path).file_name().unwrap_or_default()),
+            copy: false,
+            text: None,
+        }
+    }
+
+    /// Standard input
+    pub fn stdin() -> Self {
+        Input {
+            path: None,
+            display: "<stdin>".to_string(),
+            relative: PathBuf::new(),
+            copy: false,
+            text: None,
+        }
+    }
+
+    /// The `number`th (1-based) `-e` expression
+    pub fn expr(number: usize, text: impl Into<String>) -> Self {
+        Input {
+            path: None,
+            display: format!("<expr {}>", number),
+            relative: PathBuf::new(),
+            copy: false,
+            text: Some(text.into()),
+        }
+    }
+}
+
+/// Expand glob patterns into the matching files, sorted within each pattern.
+///
+/// Hidden files and directories (names starting with `.`) only match when
+/// `hidden` is set or the pattern names them literally. A pattern that matches
+/// nothing is an error unless `allow_empty` is set; files it matches that
+/// `ignore` leaves out still count as matches.
+pub fn expand_globs(
+    patterns: &[String],
+    hidden: bool,
+    allow_empty: bool,
+    ignore: &Ignore,
+) -> Result<Vec<String>, Failure> {
+    let options = MatchOptions {
+        require_literal_leading_dot: !hidden,
+        ..MatchOptions::new()
+    };
+
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let entries = glob::glob_with(pattern, options).map_err(|e| {
+            Failure::new(
+                Status::Usage,
+                format!("Invalid glob pattern '{}': {}", pattern, e),
+            )
+        })?;
+
+        let mut matches = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| {
+                Failure::new(Status::Io, format!("Error expanding '{}': {}", pattern, e))
+            })?;
+            if path.is_file() {
+                matches.push(path.to_string_lossy().into_owned());
+            }
+        }
+
+        if matches.is_empty() && !allow_empty {
+            return Err(Failure::new(
+                Status::Failure,
+                format!(
+                    "Glob pattern '{}' matched no files (use --allow-empty-glob to permit this)",
+                    pattern
+                ),
+            ));
+        }
+        matches.retain(|path| !ignore.excludes(path));
+        matches.sort();
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+/// The files listed in `list`, a file or `-` for stdin: one per line, or with
+/// `nul` NUL-separated. Empty entries are skipped; an entry of `-` is an
+/// error, since templates are not read from stdin with a list.
+pub fn files_from(list: &str, nul: bool) -> Result<Vec<String>, Failure> {
+    let path = (list != "-").then(|| list.to_string());
+    let bytes = files::read_input_bytes(path.as_ref()).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error reading file list '{}': {}", list, e),
+        )
+    })?;
+    let text = String::from_utf8(bytes).map_err(|_| {
+        Failure::new(
+            Status::Failure,
+            format!("File list '{}' is not valid UTF-8", list),
+        )
+    })?;
+    let entries: Vec<&str> = match nul {
+        true => text.split('\0').collect(),
+        false => text.lines().collect(),
+    };
+    if entries.contains(&"-") {
+        return Err(Failure::new(
+            Status::Usage,
+            format!(
+                "File list '{}' names '-': a template cannot be read from stdin with --files-from",
+                list
+            ),
+        ));
+    }
+    Ok((entries.into_iter())
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Files found by [`walk`]
+#[derive(Debug, Default)]
+pub struct Walk {
+    /// Files to render or copy, in sorted path order
+    pub inputs: Vec<Input>,
+    /// Files left out: non-matching files without `--copy-others`, symlinks,
+    /// special files and ignore files
+    pub skipped: usize,
+    /// Files and directories left out by `--ignore`, relative to the root
+    pub ignored: Vec<PathBuf>,
+}
+
+/// Walk `root` recursively, selecting files whose name ends with `ext` (all
+/// files if `None`).
+///
+/// Each input's output path mirrors its path under `root`. Non-matching files
+/// are copied verbatim if `copy_others` is set and skipped otherwise. Symlinks
+/// are never followed, and directories matched by `ignore` are not entered.
+/// With `ignore_files`, the patterns of each directory's `.varsubstignore`
+/// apply below it, and the file itself is skipped.
+pub fn walk(
+    root: &Path,
+    ext: Option<&str>,
+    copy_others: bool,
+    ignore: &Ignore,
+    ignore_files: bool,
+) -> Result<Walk, Failure> {
+    let ext = ext.map(normalize_ext);
+    let mut walk = Walk::default();
+    let selection = Selection {
+        ext: ext.as_deref(),
+        copy_others,
+        ignore_files,
+    };
+    visit(root, Path::new(""), &selection, ignore, &mut walk)?;
+    Ok(walk)
+}
+
+/// Which files of a walk are inputs
+struct Selection<'a> {
+    ext: Option<&'a str>,
+    copy_others: bool,
+    ignore_files: bool,
+}
+
+fn visit(
+    root: &Path,
+    relative: &Path,
+    selection: &Selection,
+    ignore: &Ignore,
+    walk: &mut Walk,
+) -> Result<(), Failure> {
+    let dir = root.join(relative);
+    let error = |e: std::io::Error| {
+        Failure::new(
+            Status::Io,
+            format!("Error reading directory '{}': {}", dir.display(), e),
+        )
+    };
+
+    let mut entries = fs::read_dir(&dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(error)?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let file = dir.join(ignore::FILE_NAME);
+    let local;
+    let ignore = match selection.ignore_files && file.is_file() {
+        true => {
+            let display = file.display().to_string();
+            let content = fs::read_to_string(&file).map_err(|e| {
+                Failure::new(
+                    Status::Io,
+                    format!("Error reading ignore file '{}': {}", display, e),
+                )
+            })?;
+            local = ignore.with_file(&relative.to_string_lossy(), &content, &display)?;
+            &local
+        }
+        false => ignore,
+    };
+
+    for entry in entries {
+        let relative = relative.join(entry.file_name());
+        // file_type() does not follow symlinks
+        let file_type = entry.file_type().map_err(error)?;
+        let path = relative.to_string_lossy();
+        if selection.ignore_files && entry.file_name() == ignore::FILE_NAME {
+            walk.skipped += 1;
+        } else if ignore.matched(&path, file_type.is_dir()).is_some() {
+            walk.ignored.push(relative);
+        } else if file_type.is_dir() {
+            visit(root, &relative, selection, ignore, walk)?;
+        } else if file_type.is_file() {
+            let selected = selection.ext.is_none_or(|ext| has_ext(&relative, ext));
+            if selected || selection.copy_others {
+                walk.inputs.push(Input {
+                    path: Some(entry.path().to_string_lossy().into_owned()),
+                    display: relative.display().to_string(),
+                    relative,
+                    copy: !selected,
+                    text: None,
+                });
+            } else {
+                walk.skipped += 1;
+            }
+        } else {
+            walk.skipped += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Make every input's output path its path relative to `base` instead of its
+/// file name
+pub fn rebase(inputs: &mut [Input], base: &str) -> Result<(), Failure> {
+    let base = lexical(Path::new(base));
+    for input in inputs.iter_mut() {
+        let Some(path) = &input.path else { continue };
+        let path = lexical(Path::new(path));
+        input.relative = path
+            .strip_prefix(&base)
+            .map_err(|_| {
+                Failure::new(
+                    Status::Usage,
+                    format!(
+                        "Input '{}' is not inside --base-dir '{}'",
+                        input.display,
+                        base.display()
+                    ),
+                )
+            })?
+            .to_path_buf();
+    }
+    Ok(())
+}
+
+/// Drop `.` components so that `./a/b` and `a/b` compare equal
+fn lexical(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+/// Remove `ext` from the output path of every rendered input that has it
+pub fn strip_ext(inputs: &mut [Input], ext: &str) {
+    let ext = normalize_ext(ext);
+    for input in inputs.iter_mut().filter(|input| !input.copy) {
+        if has_ext(&input.relative, &ext) {
+            let name = input.relative.file_name().unwrap().to_string_lossy();
+            let stem = name[..name.len() - ext.len()].to_string();
+            input.relative.set_file_name(stem);
+        }
+    }
+}
+
+/// Accept extensions with or without the leading dot
+fn normalize_ext(ext: &str) -> String {
+    if ext.starts_with('.') {
+        ext.to_string()
+    } else {
+        format!(".{}", ext)
+    }
+}
+
+/// Whether the file name ends with `ext` and has something before it
+fn has_ext(path: &Path, ext: &str) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.len() > ext.len() && name.ends_with(ext))
+}
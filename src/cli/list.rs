@@ -0,0 +1,55 @@
+//! `--list-vars`: print the variables a template references.
+
+use crate::cli::args::ListFormat;
+use crate::cli::files::line_col;
+use std::collections::HashMap;
+use varsubst::{Segment, SyntaxConfig};
+
+/// Print the variables referenced by a template, in first-appearance order
+pub fn run(input: &str, format: ListFormat, syntax: &SyntaxConfig) -> varsubst::SubstResult<()> {
+    let mut names: Vec<&str> = Vec::new();
+    let mut positions: HashMap<&str, Vec<usize>> = HashMap::new();
+
+    for segment in varsubst::segments_with(input, syntax) {
+        if let Segment::Var { name, span, .. } = segment? {
+            positions
+                .entry(name)
+                .or_insert_with(|| {
+                    names.push(name);
+                    Vec::new()
+                })
+                .push(span.start);
+        }
+    }
+
+    match format {
+        ListFormat::Text => {
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        ListFormat::Json => {
+            let entries: Vec<serde_json::Value> = names
+                .iter()
+                .map(|name| {
+                    let offsets = &positions[name];
+                    let locations: Vec<serde_json::Value> = offsets
+                        .iter()
+                        .map(|&offset| {
+                            let (line, column) = line_col(input, offset);
+                            serde_json::json!({ "offset": offset, "line": line, "column": column })
+                        })
+                        .collect();
+                    serde_json::json!({
+                        "name": name,
+                        "count": offsets.len(),
+                        "positions": locations,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::Value::Array(entries));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! `varsubst man`: manual pages generated from the argument definitions.
+//!
+//! The template syntax and the exit statuses get sections of their own,
+//! taken from the same text `--help` shows, so the two cannot drift apart.
+
+use crate::cli::args::Cli;
+use crate::cli::status::{self, Failure, Status};
+use clap::CommandFactory;
+use clap_mangen::roff::{roman, Roff};
+use clap_mangen::Man;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Template syntax summary shown in `--help` and the man page
+pub const SYNTAX: &str = "\
+Template syntax:
+  ${NAME}      The value of NAME; names are ASCII letters, digits and '_'
+  $NAME        The same, with --short-syntax (a name cannot start with a
+               digit)
+  \\$ \\{ \\} \\\\  A literal '$', '{', '}' or '\\' (unless --no-escape)
+  $${NAME}     Left as is: a '$' not starting a reference keeps the next
+               character literal
+  ${NAME:-x}   x if NAME is undefined or empty, where x is literal text up
+               to the first '}' not escaped as '\\}'
+  ${NAME:=x}   The same, also assigning x to NAME for the rest of the input
+  ${NAME:?x}   Fail with message x if NAME is undefined or empty
+  ${NAME:+x}   x if NAME is defined and not empty, else nothing
+  ${NAME-x}    The same four without the colon only treat an undefined NAME
+               as unset, not an empty one
+  ${#NAME}     The length of the value of NAME in characters
+  --style handlebars and --style percent use {{NAME}} and %NAME% instead;
+  --delim-open and --delim-close set any other delimiters. Undefined names
+  are kept as written unless --missing says otherwise.";
+
+/// What `--help` shows after the options
+pub fn after_long_help() -> String {
+    format!("{}\n\n{}", SYNTAX, status::HELP)
+}
+
+/// Print the pages of `varsubst` and its subcommands to stdout, one after
+/// another, or write each to its own file in `out_dir`
+pub fn run(out_dir: Option<&str>) -> Result<(), Failure> {
+    let mut command = Cli::command();
+    command.build();
+    let mut pages = vec![(
+        Man::new(command.clone()).get_filename(),
+        main_page(&command),
+    )];
+    for sub in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let man = Man::new(sub.clone()).source(format!("varsubst {}", env!("CARGO_PKG_VERSION")));
+        let mut page = Vec::new();
+        man.render(&mut page).expect("rendering to memory");
+        pages.push((man.get_filename(), page));
+    }
+
+    let Some(dir) = out_dir else {
+        let mut stdout = io::stdout().lock();
+        return (pages.iter())
+            .try_for_each(|(_, page)| stdout.write_all(page))
+            .and_then(|()| stdout.flush())
+            .map_err(|e| Failure::new(Status::Io, format!("Error writing output: {}", e)));
+    };
+    fs::create_dir_all(dir).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error creating directory '{}': {}", dir, e),
+        )
+    })?;
+    for (name, page) in pages {
+        let path = Path::new(dir).join(name);
+        fs::write(&path, page).map_err(|e| {
+            Failure::new(
+                Status::Io,
+                format!("Error writing man page '{}': {}", path.display(), e),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// The page of the top-level command, with the syntax and exit status
+/// sections in place of the generic text shown after the options
+fn main_page(command: &clap::Command) -> Vec<u8> {
+    let man = Man::new(command.clone());
+    let mut page = Vec::new();
+    let render = |page: &mut Vec<u8>| -> io::Result<()> {
+        man.render_title(page)?;
+        man.render_name_section(page)?;
+        man.render_synopsis_section(page)?;
+        man.render_description_section(page)?;
+        man.render_options_section(page)?;
+        man.render_subcommands_section(page)?;
+        let mut roff = Roff::default();
+        section(&mut roff, "TEMPLATE SYNTAX", SYNTAX);
+        section(&mut roff, "EXIT STATUS", status::HELP);
+        roff.to_writer(page)?;
+        man.render_version_section(page)?;
+        man.render_authors_section(page)
+    };
+    render(&mut page).expect("rendering to memory");
+    page
+}
+
+/// A section with the lines of `text` after its heading line, as written
+fn section(roff: &mut Roff, title: &str, text: &str) {
+    roff.control("SH", [title]);
+    roff.control("nf", []);
+    for line in text.lines().skip(1) {
+        roff.text([roman(line)]);
+    }
+    roff.control("fi", []);
+}
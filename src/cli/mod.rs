@@ -0,0 +1,29 @@
+//! Support modules for the `varsubst` command-line tool.
+
+pub mod args;
+pub mod bom;
+pub mod check;
+pub mod config;
+pub mod diagnostic;
+pub mod diff;
+pub mod dotenv;
+pub mod envsubst;
+pub mod escape;
+pub mod files;
+pub mod frontmatter;
+pub mod ignore;
+pub mod inputs;
+pub mod list;
+pub mod man;
+pub mod newline;
+pub mod policy;
+pub mod prompt;
+pub mod render;
+pub mod report;
+pub mod status;
+pub mod stream;
+pub mod varcmd;
+pub mod varfile;
+pub mod variables;
+pub mod verbose;
+pub mod watch;
@@ -0,0 +1,56 @@
+//! `--newline`: line-ending conversion of the rendered output.
+//!
+//! Conversion happens after substitution, so it applies to line breaks in
+//! the template text and inside substituted values alike. Input with mixed
+//! line endings comes out with a single kind. A lone `\r` is not a line
+//! ending and is left alone.
+
+use clap::ValueEnum;
+
+/// Line endings to write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Newline {
+    /// Write line endings as they are (default)
+    #[default]
+    Preserve,
+    /// \n
+    Lf,
+    /// \r\n
+    Crlf,
+    /// \r\n on Windows, \n elsewhere
+    Native,
+}
+
+impl Newline {
+    /// `text` with every line ending converted
+    pub fn apply(self, text: String) -> String {
+        let crlf = match self {
+            Newline::Preserve => return text,
+            Newline::Lf => false,
+            Newline::Crlf => true,
+            Newline::Native => cfg!(windows),
+        };
+        let lf = match text.contains("\r\n") {
+            true => text.replace("\r\n", "\n"),
+            false => text,
+        };
+        match crlf && lf.contains('\n') {
+            true => lf.replace('\n', "\r\n"),
+            false => lf,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mixed = || "a\r\nb\nc\rd\r\n".to_string();
+        assert_eq!(Newline::Preserve.apply(mixed()), mixed());
+        assert_eq!(Newline::Lf.apply(mixed()), "a\nb\nc\rd\n");
+        assert_eq!(Newline::Crlf.apply(mixed()), "a\r\nb\r\nc\rd\r\n");
+        assert_eq!(Newline::Crlf.apply("none".to_string()), "none");
+    }
+}
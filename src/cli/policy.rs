@@ -0,0 +1,254 @@
+//! `--allow` and `--deny`: restricting which variables templates may reference.
+//!
+//! Patterns are simple globs where `*` matches any run of characters and `?`
+//! matches exactly one. Deny patterns take precedence over allow patterns, and
+//! any allow pattern switches to allow-list mode where unmatched names are
+//! rejected.
+//!
+//! `--strict-names` adds a naming convention every referenced name must
+//! follow, whether or not the name is defined.
+
+use crate::cli::diagnostic::Diagnostic;
+use crate::cli::status::{Failure, Status};
+use regex::Regex;
+use varsubst::{Segment, SyntaxConfig};
+
+/// Allowed and denied variable name patterns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub names: NameRule,
+}
+
+/// Naming convention of `--strict-names`
+#[derive(Debug, Clone, Default)]
+pub enum NameRule {
+    /// Any name (default)
+    #[default]
+    Any,
+    /// SCREAMING_SNAKE_CASE: uppercase ASCII letters, digits and underscores
+    Upper,
+    /// Names matched as a whole by a regular expression
+    Regex { pattern: String, regex: Regex },
+}
+
+impl PartialEq for NameRule {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (NameRule::Regex { pattern: a, .. }, NameRule::Regex { pattern: b, .. }) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for NameRule {}
+
+impl NameRule {
+    /// A rule for names matched as a whole by `pattern`
+    pub fn regex(pattern: &str) -> Result<NameRule, regex::Error> {
+        Ok(NameRule::Regex {
+            pattern: pattern.to_string(),
+            regex: Regex::new(&format!("^(?:{})$", pattern))?,
+        })
+    }
+
+    /// How `name` breaks the convention, or `None` if it follows it
+    pub fn violation(&self, name: &str) -> Option<String> {
+        match self {
+            NameRule::Any => None,
+            NameRule::Upper => (!name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+            .then(|| "not SCREAMING_SNAKE_CASE".to_string()),
+            NameRule::Regex { pattern, regex } => (!regex.is_match(name))
+                .then(|| format!("not matched by --strict-names regex '{}'", pattern)),
+        }
+    }
+}
+
+impl Policy {
+    /// Whether no restrictions are configured
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.names == NameRule::Any
+    }
+
+    /// Why `name` may not be referenced, or `None` if it may
+    pub fn violation(&self, name: &str) -> Option<String> {
+        if let Some(pattern) = self.deny.iter().find(|p| matches(p, name)) {
+            return Some(format!("denied by pattern '{}'", pattern));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| matches(p, name)) {
+            return Some("not matched by any --allow pattern".to_string());
+        }
+        None
+    }
+
+    /// Every reference in `content` that may not be made, located within it
+    pub fn violations(&self, name: &str, content: &str, syntax: &SyntaxConfig) -> Vec<Diagnostic> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        varsubst::segments_with(content, syntax)
+            .flatten()
+            .filter_map(|segment| match segment {
+                Segment::Var {
+                    name: var, span, ..
+                } => {
+                    let (code, reason) = match self.names.violation(var) {
+                        Some(reason) => ("naming-convention", reason),
+                        None => ("denied-variable", self.violation(var)?),
+                    };
+                    let message = format!("reference to '{}' is {}", var, reason);
+                    Some(
+                        Diagnostic::new(code, message)
+                            .file(name)
+                            .variable(var)
+                            .at(content, span),
+                    )
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Check every reference in `content`, reporting all violations at once
+    ///
+    /// `name` identifies the input in messages; `first_line` and `offset`
+    /// are the line number and byte offset of the start of `content` within it.
+    pub fn check(
+        &self,
+        name: &str,
+        content: &str,
+        (first_line, offset): (usize, usize),
+        syntax: &SyntaxConfig,
+    ) -> Result<(), Failure> {
+        let diagnostics: Vec<Diagnostic> = self
+            .violations(name, content, syntax)
+            .into_iter()
+            .map(|d| d.offset(first_line, offset))
+            .collect();
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+        let messages: Vec<String> = diagnostics
+            .iter()
+            .map(|d| {
+                let (line, column) = d.location.unwrap_or_default();
+                format!("{}:{}:{}: {}", name, line, column, d.message)
+            })
+            .collect();
+        let mut failure = Failure::new(Status::Denied, messages.join("\n"));
+        failure.diagnostics = diagnostics;
+        Err(failure)
+    }
+}
+
+/// Match `name` against a glob `pattern` supporting `*` and `?`
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character
+                Some((star, tried)) => {
+                    p = star + 1;
+                    n = tried + 1;
+                    backtrack = Some((star, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("AWS_*", "AWS_SECRET_ACCESS_KEY"));
+        assert!(matches("AWS_*", "AWS_"));
+        assert!(!matches("AWS_*", "MY_AWS_KEY"));
+        assert!(matches("*_SECRET*", "DB_SECRET"));
+        assert!(matches("*_SECRET*", "APP_SECRET_KEY"));
+        assert!(!matches("*_SECRET*", "SECRET"));
+        assert!(matches("*", "ANYTHING"));
+        assert!(matches("HOST?", "HOST1"));
+        assert!(!matches("HOST?", "HOST"));
+        assert!(matches("HOST", "HOST"));
+        assert!(!matches("HOST", "HOSTS"));
+        assert!(matches("A*B*C", "AxxBxxC"));
+        assert!(!matches("A*B*C", "AxxBxx"));
+    }
+
+    #[test]
+    fn test_deny_overrides_allow() {
+        let policy = Policy {
+            allow: vec!["*".to_string()],
+            deny: vec!["AWS_*".to_string()],
+            ..Policy::default()
+        };
+        assert_eq!(policy.violation("HOST"), None);
+        assert_eq!(
+            policy.violation("AWS_KEY").as_deref(),
+            Some("denied by pattern 'AWS_*'")
+        );
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = Policy {
+            allow: vec!["APP_*".to_string()],
+            ..Policy::default()
+        };
+        assert_eq!(policy.violation("APP_NAME"), None);
+        assert!(policy.violation("HOME").is_some());
+    }
+
+    #[test]
+    fn test_name_rules() {
+        assert_eq!(NameRule::Any.violation("port"), None);
+        assert_eq!(NameRule::Upper.violation("DB_PORT_2"), None);
+        assert_eq!(
+            NameRule::Upper.violation("port").as_deref(),
+            Some("not SCREAMING_SNAKE_CASE")
+        );
+        assert!(NameRule::Upper.violation("Db_Port").is_some());
+
+        let rule = NameRule::regex("APP_[A-Z]+|CI").unwrap();
+        assert_eq!(rule.violation("APP_HOST"), None);
+        assert_eq!(rule.violation("CI"), None);
+        // The pattern must match the whole name
+        assert!(rule.violation("MY_APP_HOST").is_some());
+        assert!(rule.violation("CI_JOB").is_some());
+        assert!(NameRule::regex("(").is_err());
+    }
+
+    #[test]
+    fn test_name_violations_are_located() {
+        let policy = Policy {
+            names: NameRule::Upper,
+            ..Policy::default()
+        };
+        let diagnostics = policy.violations("t", "${HOST}\n${port}", &SyntaxConfig::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "naming-convention");
+        assert_eq!(diagnostics[0].location, Some((2, 1)));
+    }
+}
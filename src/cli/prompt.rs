@@ -0,0 +1,185 @@
+//! `--var-prompt`: ask on the terminal for variables no source defines.
+//!
+//! Questions and answers go through the controlling terminal rather than
+//! stdin and stdout, so a template can still be piped in and the output
+//! redirected. Without a terminal the run fails at once instead of waiting
+//! for an answer that cannot come.
+
+use crate::cli::policy;
+use crate::cli::status::{Failure, Status};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use varsubst::{OperatorKind, Segment, SyntaxConfig};
+
+/// Names referenced by `templates` that are not in `vars`, in order of first
+/// appearance. Templates that fail to parse are skipped; rendering reports them.
+///
+/// References with a default, as in `${NAME:-word}` or `${NAME:=word}`, need
+/// no value, nor do later references to a name assigned by `:=` or `=`.
+pub fn undefined<'a>(
+    templates: impl IntoIterator<Item = &'a str>,
+    vars: &HashMap<String, String>,
+    syntax: &SyntaxConfig,
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut names = Vec::new();
+    for template in templates {
+        let Ok(segments) = varsubst::segments_with(template, syntax).collect::<Result<Vec<_>, _>>()
+        else {
+            continue;
+        };
+        let mut assigned = HashSet::new();
+        for segment in segments {
+            let Segment::Var {
+                name,
+                quoted,
+                operator,
+                ..
+            } = segment
+            else {
+                continue;
+            };
+            let name = match quoted {
+                true => varsubst::unquote(name),
+                false => Cow::Borrowed(name),
+            };
+            match operator.map(|operator| operator.kind) {
+                Some(OperatorKind::Assign) => {
+                    assigned.insert(name);
+                    continue;
+                }
+                Some(OperatorKind::Default) => continue,
+                _ => {}
+            }
+            if !vars.contains_key(name.as_ref())
+                && !assigned.contains(&name)
+                && seen.insert(name.clone())
+            {
+                names.push(name.into_owned());
+            }
+        }
+    }
+    names
+}
+
+/// Ask for a value for each of `names`, without echo for names matching one
+/// of the `hidden` patterns
+pub fn ask(names: &[String], hidden: &[String]) -> Result<Vec<(String, String)>, Failure> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut terminal = Terminal::open().map_err(|e| {
+        Failure::new(
+            Status::Undefined,
+            format!(
+                "Cannot prompt for undefined variables ({}): no terminal available ({})",
+                names.join(", "),
+                e
+            ),
+        )
+    })?;
+    let mut values = Vec::new();
+    for name in names {
+        let prompt = format!("{}: ", name);
+        let value = match hidden.iter().any(|pattern| policy::matches(pattern, name)) {
+            true => terminal.read_hidden(&prompt),
+            false => terminal.read_line(&prompt),
+        };
+        let value = value.map_err(|e| {
+            Failure::new(
+                Status::Io,
+                format!("Error reading a value for '{}': {}", name, e),
+            )
+        })?;
+        values.push((name.clone(), value));
+    }
+    Ok(values)
+}
+
+/// The controlling terminal
+pub struct Terminal {
+    input: BufReader<File>,
+    output: File,
+}
+
+#[cfg(windows)]
+const TERMINAL: (&str, &str) = ("CONIN$", "CONOUT$");
+#[cfg(not(windows))]
+const TERMINAL: (&str, &str) = ("/dev/tty", "/dev/tty");
+
+impl Terminal {
+    /// Open the terminal, if the process is interactive: stdin or stderr is
+    /// attached to a terminal that can be opened
+    pub fn open() -> io::Result<Terminal> {
+        if !io::stdin().is_terminal() && !io::stderr().is_terminal() {
+            return Err(io::Error::other("stdin and stderr are not terminals"));
+        }
+        let input = BufReader::new(File::open(TERMINAL.0)?);
+        let output = OpenOptions::new().write(true).open(TERMINAL.1)?;
+        Ok(Terminal { input, output })
+    }
+
+    /// Show `prompt` and read one line, without its line ending
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<String> {
+        write!(self.output, "{}", prompt)?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no value entered",
+            ));
+        }
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(line)
+    }
+
+    /// Show `prompt` and read one line without echoing it
+    pub fn read_hidden(&mut self, prompt: &str) -> io::Result<String> {
+        rpassword::prompt_password(prompt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undefined_in_first_appearance_order() {
+        let vars = HashMap::from([("B".to_string(), "b".to_string())]);
+        let templates = ["${C} ${B} ${A}", "${A} ${D} ${C}"];
+        let names = undefined(templates, &vars, &SyntaxConfig::default());
+        assert_eq!(names, ["C", "A", "D"]);
+    }
+
+    #[test]
+    fn test_undefined_skips_unparsable_templates() {
+        let vars = HashMap::new();
+        let names = undefined(["${BROKEN", "${OK}"], &vars, &SyntaxConfig::default());
+        assert_eq!(names, ["OK"]);
+    }
+
+    #[test]
+    fn test_undefined_unquotes_names() {
+        let vars = HashMap::from([("a b".to_string(), "x".to_string())]);
+        let syntax = SyntaxConfig::default().quoted_names(true);
+        let names = undefined([r"${'a b'} ${'it\'s'} ${it}"], &vars, &syntax);
+        assert_eq!(names, ["it's", "it"]);
+    }
+
+    #[test]
+    fn test_undefined_skips_references_with_defaults() {
+        let vars = HashMap::new();
+        let templates = ["${A:-a} ${B-b} ${C:=c} ${C} ${D:?d} ${E:+e} ${F=f}", "${C}"];
+        let names = undefined(templates, &vars, &SyntaxConfig::default());
+        assert_eq!(names, ["D", "E", "C"]);
+    }
+
+    #[test]
+    fn test_nothing_to_ask_needs_no_terminal() {
+        assert_eq!(ask(&[], &[]).unwrap(), []);
+    }
+}
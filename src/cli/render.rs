@@ -0,0 +1,872 @@
+//! Rendering templates to their destinations.
+
+use crate::cli::args::Args;
+use crate::cli::diagnostic::Diagnostic;
+use crate::cli::diff;
+use crate::cli::files::{
+    output_permissions, read_input, read_input_bytes, text_view, write_atomic, write_in_place,
+    write_output, OutputMode, OutputSettings,
+};
+use crate::cli::frontmatter;
+use crate::cli::inputs::Input;
+use crate::cli::policy::Policy;
+use crate::cli::report::FileReport;
+use crate::cli::status::{Failure, Status};
+use crate::cli::variables::Variables;
+use crate::cli::verbose::{self, Stats};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use varsubst::{MissingAction, OperatorKind, Segment, SubstError, SubstOptions};
+
+/// Where rendered output goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// Concatenate all outputs to stdout in input order
+    Stdout(Headers),
+    /// A single output file
+    File(String),
+    /// One file per input inside a directory, at the input's relative path
+    Dir(PathBuf),
+    /// Overwrite each input, keeping a backup if the suffix is non-empty
+    InPlace(String),
+}
+
+impl Destination {
+    /// Determine the destination from the command-line arguments
+    pub fn from_args(args: &Args, inputs: &[Input]) -> Result<Self, String> {
+        let from_stdin = inputs.iter().any(|input| input.path.is_none());
+        if let Some(suffix) = &args.in_place {
+            return Ok(Destination::InPlace(suffix.clone()));
+        }
+        if let Some(dir) = &args.output_dir {
+            if from_stdin {
+                return Err("--output-dir requires input files, not stdin".to_string());
+            }
+            return Ok(Destination::Dir(PathBuf::from(dir)));
+        }
+        match &args.output {
+            Some(output) if Path::new(output).is_dir() && from_stdin => Err(format!(
+                "'{}' is a directory; output for stdin must be a file",
+                output
+            )),
+            Some(output) if Path::new(output).is_dir() => {
+                Ok(Destination::Dir(PathBuf::from(output)))
+            }
+            Some(_) if inputs.len() > 1 => {
+                Err("-o must be an existing directory when multiple inputs are given".to_string())
+            }
+            Some(output) => Ok(Destination::File(output.clone())),
+            None => Ok(Destination::Stdout(Headers {
+                format: (args.always_print_filenames || args.print_filenames && inputs.len() > 1)
+                    .then(|| args.filename_format.clone()),
+                separator: args.separator.clone(),
+            })),
+        }
+    }
+}
+
+/// What is printed around outputs concatenated on stdout
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    /// Line printed before each output, with `{}` replaced by the input's
+    /// name (`--print-filenames`)
+    pub format: Option<String>,
+    /// Printed between two outputs (`--separator`)
+    pub separator: Option<String>,
+}
+
+impl Headers {
+    /// What to print before the output of `input`, the first one or not
+    fn before(&self, input: &Input, first: bool) -> String {
+        let mut text = String::new();
+        if let (false, Some(separator)) = (first, &self.separator) {
+            text.push_str(separator);
+        }
+        if let Some(format) = &self.format {
+            text.push_str(&format.replace("{}", &input.display));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Counts of processed files
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub rendered: usize,
+    pub copied: usize,
+    /// Output files whose contents were (or, in a dry run, would be) written
+    pub changed: usize,
+    /// Output files left alone because their contents already matched
+    pub unchanged: usize,
+    /// Inputs that failed, with `--keep-going`
+    pub failed: usize,
+    /// The most severe status among the failed inputs
+    pub failure: Option<Status>,
+    /// Names referenced by the rendered templates, if tracked
+    pub referenced: HashSet<String>,
+    /// What happened to each input, if reported
+    pub files: Vec<FileReport>,
+}
+
+/// What to report on stderr, whether to write anything and how many inputs
+/// to render at once
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reporting {
+    /// `--verbose` level
+    pub verbosity: u8,
+    /// Report what would be written instead of writing it
+    pub dry_run: bool,
+    /// Collect the referenced names into [`Summary::referenced`]
+    pub track_references: bool,
+    /// Leave output files alone when their contents would not change
+    pub if_changed: bool,
+    /// Number of inputs substituted in parallel; 0 or 1 renders serially
+    pub jobs: usize,
+    /// Report a failed input and continue with the next one instead of
+    /// stopping
+    pub keep_going: bool,
+    /// Print a diff of each output against its destination instead of
+    /// writing it
+    pub diff: bool,
+    /// Color diffs with ANSI escape codes
+    pub diff_color: bool,
+    /// Collect a report on each input into [`Summary::files`]
+    pub report: bool,
+    /// Stop at the first error instead of reporting every error of each
+    /// input, and of the inputs after a failed one
+    pub fail_fast: bool,
+}
+
+/// What happened to an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// Written to stdout
+    Printed,
+    /// A file was (or would be) created or changed
+    Changed,
+    /// A file already had the contents and was left alone
+    Unchanged,
+}
+
+impl Outcome {
+    /// Name of the outcome in reports
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Printed => "printed",
+            Outcome::Changed => "changed",
+            Outcome::Unchanged => "unchanged",
+        }
+    }
+}
+
+impl Summary {
+    fn count(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Printed => {}
+            Outcome::Changed => self.changed += 1,
+            Outcome::Unchanged => self.unchanged += 1,
+        }
+    }
+}
+
+/// Render every input in order. What happens when one fails depends on
+/// `reporting`:
+///
+/// - By default, every error of the failed input is reported, and the
+///   inputs after it are still rendered, but only to add their errors to the
+///   failure: nothing more is written.
+/// - With [`fail_fast`](Reporting::fail_fast), the first error stops the
+///   run, without rendering the remaining inputs.
+/// - With [`keep_going`](Reporting::keep_going), each failure is reported as
+///   it happens and counted in the summary, the failed input's output is not
+///   written and the other inputs are written as usual.
+///
+/// With several [`jobs`](Reporting::jobs), inputs are read and substituted
+/// in parallel, but outputs are written and reported one at a time in input
+/// order, so stdout and stderr look exactly as in a serial run.
+///
+/// Inputs that would overwrite each other in a directory destination are
+/// rejected before anything is written.
+pub fn run(
+    inputs: &[Input],
+    destination: &Destination,
+    vars: &Variables,
+    options: &SubstOptions,
+    policy: &Policy,
+    reporting: Reporting,
+    output: OutputSettings,
+) -> Result<Summary, Failure> {
+    if let Destination::Dir(dir) = destination {
+        check_conflicts(dir, inputs)?;
+    }
+
+    let mut summary = Summary::default();
+    let mut printed = 0;
+    // Once an input fails, the rest are only rendered for their errors
+    let mut stopped: Option<Failure> = None;
+    let render = |input: &Input| {
+        let started = Instant::now();
+        let rendered = match input.copy {
+            true => Ok(None),
+            false => {
+                render_one(input, vars, options, policy, reporting.fail_fast, output).map(Some)
+            }
+        };
+        (started.elapsed(), rendered)
+    };
+    for_each_ordered(
+        inputs,
+        reporting.jobs,
+        render,
+        |input, (elapsed, rendered)| {
+            if let Some(failure) = stopped.take() {
+                stopped = Some(match rendered {
+                    Err(other) => failure.and(other),
+                    Ok(_) => failure,
+                });
+                return Ok(());
+            }
+            let started = Instant::now();
+            let result = rendered.and_then(|rendered| {
+                let outcome = match &rendered {
+                    None => copy_one(input, destination, reporting, output.mode)?,
+                    Some(rendered) => {
+                        if let Destination::Stdout(headers) = destination {
+                            if !reporting.dry_run && !reporting.diff {
+                                let header = headers.before(input, printed == 0);
+                                write_output(&None, header.as_bytes()).map_err(|e| {
+                                    Failure::new(Status::Io, format!("Error writing output: {}", e))
+                                })?;
+                                printed += 1;
+                            }
+                        }
+                        write_one(
+                            input,
+                            rendered,
+                            destination,
+                            rendered.vars.as_ref().unwrap_or(vars),
+                            options,
+                            reporting,
+                            output,
+                        )?
+                    }
+                };
+                summary.count(outcome);
+                match &rendered {
+                    None => summary.copied += 1,
+                    Some(rendered) => {
+                        if reporting.track_references {
+                            track_references(
+                                &rendered.content,
+                                rendered.vars.as_ref().unwrap_or(vars),
+                                options,
+                                &mut summary.referenced,
+                            );
+                        }
+                        summary.rendered += 1;
+                    }
+                }
+                Ok((rendered, outcome))
+            });
+            if reporting.report {
+                let elapsed = elapsed + started.elapsed();
+                let report = file_report(input, destination, &result, vars, options, elapsed);
+                summary.files.push(report);
+            }
+            match result.map(|_| ()) {
+                Err(failure) if reporting.keep_going => {
+                    failure.report();
+                    summary.failed += 1;
+                    summary.failure = summary
+                        .failure
+                        .into_iter()
+                        .chain([failure.status])
+                        .max_by_key(|status| status.severity());
+                    Ok(())
+                }
+                Err(failure) if !reporting.fail_fast => {
+                    stopped = Some(failure);
+                    Ok(())
+                }
+                result => result,
+            }
+        },
+    )?;
+    match stopped {
+        Some(failure) => Err(failure),
+        None => Ok(summary),
+    }
+}
+
+/// Pass `work(input)` for every input to `consume`, in input order, until
+/// `consume` fails.
+///
+/// With more than one job, `work` runs on that many threads at once while
+/// results that arrive early wait for their turn; once `consume` fails, no
+/// further work is started.
+fn for_each_ordered<T: Send>(
+    inputs: &[Input],
+    jobs: usize,
+    work: impl Fn(&Input) -> T + Sync,
+    mut consume: impl FnMut(&Input, T) -> Result<(), Failure>,
+) -> Result<(), Failure> {
+    if jobs <= 1 || inputs.len() <= 1 {
+        return inputs
+            .iter()
+            .try_for_each(|input| consume(input, work(input)));
+    }
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(inputs.len()) {
+            let (sender, next, stop, work) = (sender.clone(), &next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(index) else {
+                        break;
+                    };
+                    if sender.send((index, work(input))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut early = HashMap::new();
+        for (index, input) in inputs.iter().enumerate() {
+            let result = match early.remove(&index) {
+                Some(result) => result,
+                None => loop {
+                    let (done, result) = receiver.recv().expect("render worker failed");
+                    if done == index {
+                        break result;
+                    }
+                    early.insert(done, result);
+                },
+            };
+            if let Err(failure) = consume(input, result) {
+                stop.store(true, Ordering::Relaxed);
+                return Err(failure);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Add the names `content` references to `referenced`, including those
+/// referenced from values when values are expanded too
+fn track_references(
+    content: &str,
+    vars: &Variables,
+    options: &SubstOptions,
+    referenced: &mut HashSet<String>,
+) {
+    let names = |text: &str| -> Vec<String> {
+        varsubst::segments_with(text, &options.syntax)
+            .flatten()
+            .filter_map(|segment| match segment {
+                Segment::Var { name, .. } => Some(name.to_string()),
+                _ => None,
+            })
+            .collect()
+    };
+    let mut pending = names(content);
+    while let Some(name) = pending.pop() {
+        if let (true, Some(value)) = (options.recursive, vars.values.get(&name)) {
+            if !referenced.contains(&name) {
+                pending.extend(names(value));
+            }
+        }
+        referenced.insert(name);
+    }
+}
+
+/// The report on `input`, given how rendering and writing it went
+fn file_report(
+    input: &Input,
+    destination: &Destination,
+    result: &Result<(Option<Rendered>, Outcome), Failure>,
+    vars: &Variables,
+    options: &SubstOptions,
+    elapsed: Duration,
+) -> FileReport {
+    let file_size = || {
+        (input.path.as_ref())
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|meta| meta.len() as usize)
+    };
+    let mut report = FileReport {
+        input: input.display.clone(),
+        output: target_path(destination, input).map(|target| target.display().to_string()),
+        outcome: "failed",
+        copied: input.copy,
+        references: Vec::new(),
+        unused: Vec::new(),
+        input_bytes: None,
+        output_bytes: None,
+        elapsed,
+        error: None,
+    };
+    match result {
+        Err(failure) => {
+            report.input_bytes = file_size();
+            report.error = Some(failure.message.clone());
+        }
+        Ok((None, outcome)) => {
+            report.outcome = outcome.name();
+            report.input_bytes = file_size();
+            report.output_bytes = report.input_bytes;
+        }
+        Ok((Some(rendered), outcome)) => {
+            let vars = rendered.vars.as_ref().unwrap_or(vars);
+            report.outcome = outcome.name();
+            report.references = FileReport::references(&rendered.content, vars, options);
+            let mut referenced = HashSet::new();
+            track_references(&rendered.content, vars, options, &mut referenced);
+            report.unused = (vars.provided().into_iter())
+                .filter(|(name, _)| !referenced.contains(*name))
+                .map(|(name, _)| name.to_string())
+                .collect();
+            report.input_bytes = Some(rendered.content.len());
+            report.output_bytes = Some(rendered.result.len());
+        }
+    }
+    report
+}
+
+/// Fail if two inputs map to the same output path under `dir`
+fn check_conflicts(dir: &Path, inputs: &[Input]) -> Result<(), Failure> {
+    let mut targets: HashMap<&Path, &Input> = HashMap::new();
+    for input in inputs {
+        if let Some(other) = targets.insert(&input.relative, input) {
+            return Err(Failure::new(
+                Status::Usage,
+                format!(
+                    "Inputs '{}' and '{}' would both be written to '{}'",
+                    other.display,
+                    input.display,
+                    dir.join(&input.relative).display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A substituted input, ready to be written
+struct Rendered {
+    /// The template, as checked and substituted
+    content: String,
+    /// The output to write
+    result: Vec<u8>,
+    /// The variables, if the template defines its own in frontmatter
+    vars: Option<Variables>,
+}
+
+/// Read and substitute a single input, reporting every error in it unless
+/// `fail_fast` is set
+fn render_one(
+    input: &Input,
+    vars: &Variables,
+    options: &SubstOptions,
+    policy: &Policy,
+    fail_fast: bool,
+    output: OutputSettings,
+) -> Result<Rendered, Failure> {
+    let read = match (&input.text, output.binary_safe) {
+        (Some(text), _) => Ok((text.clone(), None)),
+        (None, false) => read_input(input.path.as_ref()).map(|content| (content, None)),
+        // The bytes are substituted; the text view with the same offsets is
+        // what checks and messages look at
+        (None, true) => {
+            read_input_bytes(input.path.as_ref()).map(|bytes| (text_view(&bytes), Some(bytes)))
+        }
+    };
+    let (mut content, mut bytes) = read.map_err(|e| {
+        let message = match input.path {
+            Some(_) => format!("Error reading input '{}': {}", input.display, e),
+            None => format!("Error reading input: {}", e),
+        };
+        Failure::new(Status::Io, message)
+            .with(Diagnostic::new("io", format!("error reading input: {}", e)).file(&input.display))
+    })?;
+    let mut skipped = output.bom.strip(&mut content);
+    if let Some(bytes) = &mut bytes {
+        output.bom.strip_bytes(bytes);
+    }
+    let mut first_line = 1;
+    let mut local = None;
+    if output.frontmatter {
+        let parsed = frontmatter::parse(&content).map_err(|e| {
+            Failure::new(
+                Status::Failure,
+                format!("Invalid frontmatter in '{}': {}", input.display, e),
+            )
+            .with(
+                Diagnostic::new("failure", format!("invalid frontmatter: {}", e))
+                    .file(&input.display),
+            )
+        })?;
+        if let Some(block) = parsed {
+            content.drain(..block.len);
+            if let Some(bytes) = &mut bytes {
+                bytes.drain(..block.len);
+            }
+            skipped += block.len;
+            first_line += block.lines;
+            let source = format!("frontmatter '{}'", input.display);
+            let pairs = (block.pairs.into_iter())
+                .map(|(name, value)| (name, output.escape.apply(&value).into_owned()));
+            local = Some(vars.under(source, pairs.collect()));
+        }
+    }
+    let vars = local.as_ref().unwrap_or(vars);
+
+    let denied = policy.check(
+        &input.display,
+        &content,
+        (first_line, skipped),
+        &options.syntax,
+    );
+    let denied = match (denied, fail_fast) {
+        (Err(failure), true) => return Err(failure),
+        (denied, _) => denied.err(),
+    };
+
+    let result = match &bytes {
+        Some(bytes) => varsubst::substitute_bytes_with_options(bytes, &vars.values, options),
+        None => varsubst::substitute_with_options(&content, &vars.values, options)
+            .map(|result| output.newline.apply(result).into_bytes()),
+    };
+    let result = result.map_err(|e| {
+        let errors = match fail_fast {
+            true => vec![e],
+            false => every_error(e, &content, vars, options),
+        };
+        let failures = errors.iter().map(|e| {
+            let message = match (&input.path, &input.text) {
+                (None, None) => format!("Substitution error: {}", e),
+                _ => format!("Substitution error in '{}': {}", input.display, e),
+            };
+            let diagnostic = Diagnostic::of_error(e, &content, &options.syntax);
+            Failure::new(Status::of(e), message)
+                .with(diagnostic.file(&input.display).offset(first_line, skipped))
+        });
+        failures.reduce(Failure::and).expect("at least one error")
+    });
+    let result = match (denied, result) {
+        (Some(denied), Err(failure)) => return Err(denied.and(failure)),
+        (Some(denied), Ok(_)) => return Err(denied),
+        (None, result) => result?,
+    };
+    Ok(Rendered {
+        content,
+        result: output.bom.apply(result),
+        vars: local,
+    })
+}
+
+/// The errors substituting `content` runs into, in template order: every
+/// syntax error and, when undefined variables are errors, every reference to
+/// one, along with `first`, the error substitution stopped at
+fn every_error(
+    first: SubstError,
+    content: &str,
+    vars: &Variables,
+    options: &SubstOptions,
+) -> Vec<SubstError> {
+    let missing_is_error = options.missing == MissingAction::Error;
+    // Names assigned by `${NAME:=word}` so far
+    let mut assigned = HashSet::new();
+    let mut errors: Vec<SubstError> = varsubst::segments_with(content, &options.syntax)
+        .filter_map(|segment| match segment {
+            Err(e) => Some(e),
+            Ok(Segment::Var {
+                name,
+                span,
+                operator: Some(operator),
+                ..
+            }) => match operator.kind {
+                OperatorKind::Assign => {
+                    assigned.insert(name);
+                    None
+                }
+                OperatorKind::Required
+                    if !operator.is_set(vars.values.get(name).map(String::as_str))
+                        && !assigned.contains(name) =>
+                {
+                    Some(SubstError::RequiredVariable {
+                        name: name.to_string(),
+                        message: operator.unescaped_word(&options.syntax).into_owned(),
+                        position: span.start,
+                    })
+                }
+                _ => None,
+            },
+            Ok(Segment::Var { name, span, .. })
+                if missing_is_error
+                    && !vars.values.contains_key(name)
+                    && !assigned.contains(name) =>
+            {
+                Some(SubstError::UndefinedVariable {
+                    name: name.to_string(),
+                    position: span.start,
+                    suggestions: varsubst::suggestions(
+                        name,
+                        vars.values.keys().map(String::as_str),
+                    ),
+                })
+            }
+            Ok(_) => None,
+        })
+        .collect();
+    if !errors.contains(&first) {
+        let index = errors.partition_point(|e| e.position() <= first.position());
+        errors.insert(index, first);
+    }
+    errors
+}
+
+/// Write a rendered input to the destination, returning what happened to it
+fn write_one(
+    input: &Input,
+    rendered: &Rendered,
+    destination: &Destination,
+    vars: &Variables,
+    options: &SubstOptions,
+    reporting: Reporting,
+    output: OutputSettings,
+) -> Result<Outcome, Failure> {
+    let Rendered {
+        content, result, ..
+    } = rendered;
+    if reporting.verbosity > 0 {
+        verbose::file(input, content, vars, &options.syntax, reporting.verbosity);
+    }
+    if reporting.dry_run {
+        return Ok(report_dry_run(
+            input,
+            destination,
+            content,
+            result,
+            vars,
+            options,
+        ));
+    }
+    if reporting.diff {
+        return show_diff(
+            input,
+            destination,
+            content,
+            result,
+            vars,
+            reporting.diff_color,
+        );
+    }
+    if reporting.if_changed && is_unchanged(destination, input, result) {
+        if reporting.verbosity > 0 {
+            eprintln!("{}: unchanged", input.display);
+        }
+        return Ok(Outcome::Unchanged);
+    }
+
+    match destination {
+        Destination::Stdout(_) => write_output(&None, result)
+            .map_err(|e| Failure::new(Status::Io, format!("Error writing output: {}", e))),
+        Destination::File(path) => write_file(Path::new(path), input, result, output.mode),
+        Destination::Dir(dir) => {
+            let target = prepare_target(dir, input)?;
+            write_file(&target, input, result, output.mode)
+        }
+        Destination::InPlace(suffix) => {
+            // Inputs are always files here: stdin is rejected with --in-place
+            let path = input.path.as_deref().unwrap_or_default();
+            write_in_place(path, suffix, result, output.mode).map_err(|e| write_error(&path, e))
+        }
+    }?;
+    let outcome = match destination {
+        Destination::Stdout(_) => Outcome::Printed,
+        _ => Outcome::Changed,
+    };
+    Ok(outcome)
+}
+
+/// Whether the file `input` is written to already holds `content`
+fn is_unchanged(destination: &Destination, input: &Input, content: &[u8]) -> bool {
+    target_path(destination, input)
+        .is_some_and(|target| fs::read(target).is_ok_and(|existing| existing == content))
+}
+
+/// Atomically write the output for `input` to `target`
+fn write_file(
+    target: &Path,
+    input: &Input,
+    content: &[u8],
+    mode: OutputMode,
+) -> Result<(), Failure> {
+    output_permissions(target, input.path.as_deref(), mode)
+        .and_then(|permissions| write_atomic(target, content, permissions))
+        .map_err(|e| write_error(&target.display(), e))
+}
+
+/// Describe on stderr what rendering `input` would do to its destination
+fn report_dry_run(
+    input: &Input,
+    destination: &Destination,
+    content: &str,
+    result: &[u8],
+    vars: &Variables,
+    options: &SubstOptions,
+) -> Outcome {
+    let (action, outcome) = match target_path(destination, input) {
+        None => ("would write to stdout".to_string(), Outcome::Printed),
+        Some(target) => match fs::read(&target) {
+            Ok(existing) if existing == result => (
+                format!("would leave '{}' unchanged", target.display()),
+                Outcome::Unchanged,
+            ),
+            Ok(_) => (
+                format!("would change '{}'", target.display()),
+                Outcome::Changed,
+            ),
+            Err(_) => (
+                format!("would create '{}'", target.display()),
+                Outcome::Changed,
+            ),
+        },
+    };
+    let backup = match destination {
+        Destination::InPlace(suffix) if !suffix.is_empty() => {
+            format!(" (backup '{}{}')", input.display, suffix)
+        }
+        _ => String::new(),
+    };
+    eprintln!("{}: {}{}", input.display, action, backup);
+
+    let stats = Stats::collect(content, vars, &options.syntax);
+    let list = |names: &[String]| match names {
+        [] => "none".to_string(),
+        names => names.join(", "),
+    };
+    let defined: Vec<String> = stats.defined.iter().map(|name| vars.label(name)).collect();
+    eprintln!("  substituted: {}", list(&defined));
+    let undefined: Vec<String> = stats
+        .undefined
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    eprintln!("  undefined: {}", list(&undefined));
+    outcome
+}
+
+/// Print to stdout how rendering `input` would change its destination, or
+/// for stdout, how it differs from the template
+fn show_diff(
+    input: &Input,
+    destination: &Destination,
+    content: &str,
+    result: &[u8],
+    vars: &Variables,
+    color: bool,
+) -> Result<Outcome, Failure> {
+    let (old, label) = match target_path(destination, input) {
+        // A file that does not exist yet is compared as empty
+        Some(target) => (
+            fs::read(&target).map_or(String::new(), |existing| {
+                String::from_utf8_lossy(&existing).into_owned()
+            }),
+            target.display().to_string(),
+        ),
+        None => (content.to_string(), input.display.clone()),
+    };
+    let new = String::from_utf8_lossy(result);
+    if old == new {
+        return Ok(Outcome::Unchanged);
+    }
+    // A change to a secret alone still counts, but shows no lines
+    let rendered = format!("{} (rendered)", label);
+    let (old, new) = (vars.redact(&old), vars.redact(&new));
+    if let Some(diff) = diff::unified(&old, &new, (&label, &rendered), color) {
+        write_output(&None, diff.as_bytes())
+            .map_err(|e| Failure::new(Status::Io, format!("Error writing output: {}", e)))?;
+    }
+    Ok(Outcome::Changed)
+}
+
+/// Where `input` is written, or `None` for stdout
+fn target_path(destination: &Destination, input: &Input) -> Option<PathBuf> {
+    match destination {
+        Destination::Stdout(_) => None,
+        Destination::File(path) => Some(PathBuf::from(path)),
+        Destination::Dir(dir) => Some(dir.join(&input.relative)),
+        Destination::InPlace(_) => input.path.as_ref().map(PathBuf::from),
+    }
+}
+
+/// Copy an input verbatim; only directory destinations receive copies
+/// (with their permissions, unless `--mode` is given)
+fn copy_one(
+    input: &Input,
+    destination: &Destination,
+    reporting: Reporting,
+    mode: OutputMode,
+) -> Result<Outcome, Failure> {
+    let (Destination::Dir(dir), Some(path)) = (destination, &input.path) else {
+        return Ok(Outcome::Printed);
+    };
+    if reporting.dry_run {
+        eprintln!(
+            "{}: would copy to '{}'",
+            input.display,
+            dir.join(&input.relative).display()
+        );
+        return Ok(Outcome::Changed);
+    }
+    let mode = match mode {
+        OutputMode::Set(_) => mode,
+        _ => OutputMode::Preserve,
+    };
+    let content = fs::read(path).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error reading input '{}': {}", input.display, e),
+        )
+    })?;
+    if reporting.if_changed && is_unchanged(destination, input, &content) {
+        if reporting.verbosity > 0 {
+            eprintln!("{}: unchanged", input.display);
+        }
+        return Ok(Outcome::Unchanged);
+    }
+    let target = prepare_target(dir, input)?;
+    output_permissions(&target, Some(path), mode)
+        .and_then(|permissions| write_atomic(&target, content, permissions))
+        .map_err(|e| write_error(&target.display(), e))?;
+    Ok(Outcome::Changed)
+}
+
+/// Output path for `input` under `dir`, with its parent directories created
+fn prepare_target(dir: &Path, input: &Input) -> Result<PathBuf, Failure> {
+    let target = dir.join(&input.relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|e| write_error(&parent.display(), e))?;
+    }
+    Ok(target)
+}
+
+fn write_error(path: &dyn std::fmt::Display, e: std::io::Error) -> Failure {
+    Failure::new(
+        Status::Io,
+        format!("Error writing output '{}': {}", path, e),
+    )
+    .with(Diagnostic::new("io", format!("error writing output: {}", e)).file(path.to_string()))
+}
@@ -0,0 +1,189 @@
+//! `--report`: a JSON summary of the whole run, written to a file.
+//!
+//! Like `--verbose`, a report never includes variable values, only names, so
+//! it can be archived as build evidence. The fields are listed in [`SCHEMA`]
+//! and are part of the command-line interface; they must not change.
+
+use crate::cli::args::Args;
+use crate::cli::files::write_atomic;
+use crate::cli::render::Summary;
+use crate::cli::status::{Failure, Status};
+use crate::cli::variables::{Variables, REDACTED};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use varsubst::{MissingAction, Segment, SubstOptions};
+
+/// Description of the report shown in `--help`
+pub const SCHEMA: &str = "\
+Write a JSON summary of the run to PATH once every input is processed
+(with --keep-going, also after failures). Values are never included.
+
+The document has these fields:
+  version          varsubst version
+  options          missing (keep, empty, error, default or marker), open, close,
+                   short_syntax, escape, recursive_values, max_depth,
+                   newline, escape_values, dry_run
+  sources          [{source, variables}]: variable sources in load order
+  files            one object per input, in input order:
+    input          path of the input, or \"<stdin>\"
+    output         path written to, or null for stdout
+    outcome        changed, unchanged, printed or failed (with --dry-run,
+                   what would happen)
+    copied         whether the file was copied verbatim
+    substituted    [{name, count}]: defined names, in first-appearance order;
+                   those of --secret variables also have value \"<redacted>\",
+                   those whose --default was used have defaulted true
+    undefined      [{name, count}]: undefined names
+    unused         provided names (not from the environment) the template
+                   does not reference; empty for copied files
+    input_bytes    size of the template, or null if it could not be read
+    output_bytes   size of the output, or null if it failed
+    elapsed_ms     time spent on the input
+    error          error message if it failed, else null
+  summary          rendered, copied, changed, unchanged and failed counts
+  elapsed_ms       time of the whole run";
+
+/// What the report records about one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub input: String,
+    pub output: Option<String>,
+    pub outcome: &'static str,
+    pub copied: bool,
+    /// Referenced names, in first-appearance order
+    pub references: Vec<Reference>,
+    pub unused: Vec<String>,
+    pub input_bytes: Option<usize>,
+    pub output_bytes: Option<usize>,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+/// A name referenced by a template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub name: String,
+    pub count: usize,
+    pub defined: bool,
+    /// Whether the value is a `--secret`
+    pub secret: bool,
+    /// Whether the value is the variable's `--default`
+    pub defaulted: bool,
+}
+
+impl FileReport {
+    /// Count the references in `content`, a template rendered with `vars`
+    pub fn references(content: &str, vars: &Variables, options: &SubstOptions) -> Vec<Reference> {
+        let mut references: Vec<Reference> = Vec::new();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for segment in varsubst::segments_with(content, &options.syntax).flatten() {
+            if let Segment::Var { name, .. } = segment {
+                match index.get(name) {
+                    Some(&i) => references[i].count += 1,
+                    None => {
+                        index.insert(name, references.len());
+                        references.push(Reference {
+                            name: name.to_string(),
+                            count: 1,
+                            defined: vars.values.contains_key(name),
+                            secret: vars.is_secret(name),
+                            defaulted: vars.is_defaulted(name),
+                        });
+                    }
+                }
+            }
+        }
+        references
+    }
+
+    fn to_json(&self) -> Value {
+        let names = |defined: bool| -> Vec<Value> {
+            (self.references.iter())
+                .filter(|reference| reference.defined == defined)
+                .map(|reference| {
+                    let mut entry = json!({ "name": reference.name, "count": reference.count });
+                    if reference.secret {
+                        entry["value"] = json!(REDACTED);
+                    }
+                    if reference.defaulted {
+                        entry["defaulted"] = json!(true);
+                    }
+                    entry
+                })
+                .collect()
+        };
+        json!({
+            "input": self.input,
+            "output": self.output,
+            "outcome": self.outcome,
+            "copied": self.copied,
+            "substituted": names(true),
+            "undefined": names(false),
+            "unused": self.unused,
+            "input_bytes": self.input_bytes,
+            "output_bytes": self.output_bytes,
+            "elapsed_ms": millis(self.elapsed),
+            "error": self.error,
+        })
+    }
+}
+
+/// Write the report of a finished run to `path`
+pub fn write(
+    path: &str,
+    args: &Args,
+    options: &SubstOptions,
+    vars: &Variables,
+    summary: &Summary,
+    elapsed: Duration,
+) -> Result<(), Failure> {
+    let missing = match &options.missing {
+        MissingAction::Keep => "keep",
+        MissingAction::Empty => "empty",
+        MissingAction::Error => "error",
+        MissingAction::UseDefault(_) => "default",
+        MissingAction::Marker(_) => "marker",
+    };
+    let sources: Vec<Value> = (vars.sources.iter())
+        .map(|(source, count)| json!({ "source": source, "variables": count }))
+        .collect();
+    let report = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "options": {
+            "missing": missing,
+            "open": options.syntax.open(),
+            "close": options.syntax.close(),
+            "short_syntax": options.syntax.short_syntax,
+            "escape": options.syntax.escape,
+            "recursive_values": options.recursive,
+            "max_depth": options.max_depth,
+            "newline": args.newline.to_possible_value().map(|value| value.get_name().to_string()),
+            "escape_values": args.escape_values.to_possible_value().map(|value| value.get_name().to_string()),
+            "dry_run": args.dry_run,
+        },
+        "sources": sources,
+        "files": summary.files.iter().map(FileReport::to_json).collect::<Vec<_>>(),
+        "summary": {
+            "rendered": summary.rendered,
+            "copied": summary.copied,
+            "changed": summary.changed,
+            "unchanged": summary.unchanged,
+            "failed": summary.failed,
+        },
+        "elapsed_ms": millis(elapsed),
+    });
+    let text = serde_json::to_string_pretty(&report).expect("report is valid JSON") + "\n";
+    write_atomic(Path::new(path), text, None).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error writing report '{}': {}", path, e),
+        )
+    })
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
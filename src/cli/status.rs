@@ -0,0 +1,171 @@
+//! Exit statuses and failures that carry them.
+//!
+//! The numeric codes are part of the command-line interface and are listed in
+//! `--help`; they must not change.
+
+use crate::cli::diagnostic::{self, Diagnostic};
+use std::fmt;
+use std::process;
+use varsubst::SubstError;
+
+/// Exit status of a failed run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Any failure not covered below
+    Failure = 1,
+    /// Invalid command-line usage
+    Usage = 2,
+    /// A file or stream could not be read or written
+    Io = 3,
+    /// A template has a syntax error
+    Syntax = 4,
+    /// A variable is undefined and undefined variables are errors, or a
+    /// `${NAME:?message}` variable is undefined or empty
+    Undefined = 5,
+    /// A provided variable is never referenced, with `--fail-on-unused`
+    Unused = 6,
+    /// A template references a variable rejected by `--allow`/`--deny`/
+    /// `--strict-names`
+    Denied = 7,
+    /// An output file changed, with `--exit-code-on-change`
+    Changed = 8,
+}
+
+/// Exit status table shown at the end of `--help`
+pub const HELP: &str = "\
+Exit status:
+  0  Success
+  1  Other failure (e.g. an invalid variables file), or differences with --diff
+  2  Usage error
+  3  I/O error reading or writing a file
+  4  Template syntax error
+  5  Undefined variable with --missing error or --fail-on-undefined, or
+     undefined or empty variable in ${NAME:?message}
+  6  Unused variable with --fail-on-unused
+  7  Reference rejected by --allow, --deny or --strict-names
+  8  An output file changed, with --exit-code-on-change";
+
+impl Status {
+    /// Status for a substitution error
+    pub fn of(error: &SubstError) -> Self {
+        match error {
+            SubstError::UndefinedVariable { .. } | SubstError::RequiredVariable { .. } => {
+                Status::Undefined
+            }
+            SubstError::CircularReference { .. } | SubstError::DepthLimitExceeded { .. } => {
+                Status::Failure
+            }
+            SubstError::Write { .. } => Status::Io,
+            _ => Status::Syntax,
+        }
+    }
+
+    /// The process exit code
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Rank used to exit with the most severe of several failures: problems
+    /// with the files themselves outrank problems with their contents, which
+    /// outrank problems with the variables
+    pub fn severity(self) -> u8 {
+        match self {
+            Status::Changed => 0,
+            Status::Unused => 1,
+            Status::Undefined => 2,
+            Status::Denied => 3,
+            Status::Failure => 4,
+            Status::Syntax => 5,
+            Status::Io => 6,
+            Status::Usage => 7,
+        }
+    }
+}
+
+/// A failed operation: the message to print and the status to exit with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub status: Status,
+    pub message: String,
+    /// What `--json-errors` reports; derived from the message if empty
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Failure {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Failure {
+            status,
+            message: message.into(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Attach a structured description of the failure
+    #[must_use]
+    pub fn with(mut self, diagnostic: Diagnostic) -> Self {
+        self.diagnostics.push(diagnostic);
+        self
+    }
+
+    /// This failure followed by `other`, reported together with the status of
+    /// the more severe one
+    #[must_use]
+    pub fn and(mut self, other: Failure) -> Self {
+        if other.status.severity() > self.status.severity() {
+            self.status = other.status;
+        }
+        self.message = format!("{}\n{}", self.message, other.message);
+        self.diagnostics.extend(other.diagnostics);
+        self
+    }
+
+    /// Print the message (or the diagnostics as JSON) to stderr.
+    ///
+    /// As text, each located diagnostic is followed by an excerpt of its
+    /// line: after the message line it belongs to when there is one line per
+    /// diagnostic, otherwise after the whole message.
+    pub fn report(&self) {
+        if !diagnostic::json() {
+            let lines: Vec<&str> = self.message.lines().collect();
+            if lines.len() == self.diagnostics.len() {
+                for (line, diagnostic) in lines.iter().zip(&self.diagnostics) {
+                    eprintln!("{}", line);
+                    eprint!("{}", excerpt(line, diagnostic).unwrap_or_default());
+                }
+            } else {
+                eprintln!("{}", self.message);
+                for diagnostic in &self.diagnostics {
+                    eprint!("{}", diagnostic.snippet(true).unwrap_or_default());
+                }
+            }
+        } else if self.diagnostics.is_empty() {
+            Diagnostic::of_status(self.status, self.message.clone()).emit();
+        } else {
+            for diagnostic in &self.diagnostics {
+                diagnostic.emit();
+            }
+        }
+    }
+
+    /// Report the failure and exit with its status
+    pub fn exit(self) -> ! {
+        self.report();
+        process::exit(self.status.code())
+    }
+}
+
+/// Excerpt for `diagnostic`, without the location header if `line` already
+/// starts with `file:line:column`
+fn excerpt(line: &str, diagnostic: &Diagnostic) -> Option<String> {
+    let located = match (&diagnostic.file, diagnostic.location) {
+        (Some(file), Some((l, c))) => line.starts_with(&format!("{}:{}:{}:", file, l, c)),
+        _ => false,
+    };
+    diagnostic.snippet(!located)
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
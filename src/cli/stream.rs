@@ -0,0 +1,300 @@
+//! Line-by-line streaming substitution for large inputs.
+//!
+//! Each line is read, substituted and written before the next one is read,
+//! so memory use is bounded by the longest line rather than the input size.
+//! References cannot span lines in this mode: a `${` left open at the end of
+//! a line is reported as an unclosed brace.
+//!
+//! With `--null-data` the same applies to NUL-terminated records instead of
+//! lines, and every record is written back NUL-terminated.
+
+use crate::cli::bom::{Bom, BOM};
+use crate::cli::diagnostic::Diagnostic;
+use crate::cli::files::{line_col, output_permissions, OutputSettings};
+use crate::cli::newline::Newline;
+use crate::cli::policy::Policy;
+use crate::cli::status::{Failure, Status};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use varsubst::{AtomicFile, SubstOptions};
+
+/// How the input is split into units that are substituted independently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Newline-terminated lines, written back unchanged around substitutions
+    Lines,
+    /// NUL-terminated records (`-0`)
+    Nul,
+}
+
+impl Framing {
+    fn terminator(self) -> u8 {
+        match self {
+            Framing::Lines => b'\n',
+            Framing::Nul => 0,
+        }
+    }
+}
+
+/// Stream `input` (stdin if `None`) to `output` (stdout if `None`)
+pub fn run(
+    input: Option<&String>,
+    output: Option<&String>,
+    framing: Framing,
+    vars: &HashMap<String, String>,
+    options: &SubstOptions,
+    policy: &Policy,
+    output_settings: OutputSettings,
+) -> Result<(), Failure> {
+    let name = input.map_or("<stdin>", String::as_str);
+    let reader: Box<dyn BufRead> = match input {
+        Some(path) => Box::new(BufReader::new(File::open(path).map_err(|e| {
+            Failure::new(Status::Io, format!("Error reading input '{}': {}", path, e))
+                .with(Diagnostic::new("io", format!("error reading input: {}", e)).file(path))
+        })?)),
+        None => Box::new(io::stdin().lock()),
+    };
+    let stream = Stream {
+        name,
+        framing,
+        newline: output_settings.newline,
+        bom: output_settings.bom,
+        vars,
+        options,
+        policy,
+    };
+    let Some(path) = output else {
+        let writer = BufWriter::new(io::stdout().lock());
+        return stream.substitute_records(reader, writer);
+    };
+
+    // An output file only replaces the destination once everything is written
+    let output_error = |e: io::Error| {
+        Failure::new(
+            Status::Io,
+            format!("Error writing output '{}': {}", path, e),
+        )
+        .with(Diagnostic::new("io", format!("error writing output: {}", e)).file(path))
+    };
+    let target = Path::new(path);
+    let permissions = output_permissions(target, input.map(String::as_str), output_settings.mode)
+        .map_err(output_error)?;
+    let file = AtomicFile::create(target, permissions).map_err(output_error)?;
+    let mut writer = BufWriter::new(file);
+    stream.substitute_records(reader, &mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(|e| output_error(e.into_error()))?;
+    file.commit().map_err(output_error)
+}
+
+/// One input being streamed, and how to substitute it
+struct Stream<'a> {
+    /// Input name used in messages
+    name: &'a str,
+    framing: Framing,
+    newline: Newline,
+    bom: Bom,
+    vars: &'a HashMap<String, String>,
+    options: &'a SubstOptions,
+    policy: &'a Policy,
+}
+
+impl Stream<'_> {
+    fn substitute_records(
+        &self,
+        mut reader: impl BufRead,
+        mut writer: impl Write,
+    ) -> Result<(), Failure> {
+        let name = self.name;
+        let read_error = |e: io::Error| {
+            Failure::new(Status::Io, format!("Error reading input: {}", e))
+                .with(Diagnostic::new("io", format!("error reading input: {}", e)).file(name))
+        };
+        if self.bom == Bom::Add {
+            writer.write_all(BOM.as_bytes()).map_err(write_error)?;
+        }
+        let mut buffer = Vec::new();
+        let mut number = 0;
+        let mut offset = 0;
+        loop {
+            buffer.clear();
+            let read = reader
+                .read_until(self.framing.terminator(), &mut buffer)
+                .map_err(read_error)?;
+            if read == 0 {
+                break;
+            }
+            number += 1;
+            if number == 1 {
+                offset = self.bom.strip_bytes(&mut buffer);
+            }
+            let text = std::str::from_utf8(&buffer).map_err(|_| {
+                read_error(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ))
+            })?;
+
+            let result = match self.framing {
+                Framing::Lines => self.substitute_line(text, number, offset)?,
+                Framing::Nul => {
+                    let record = text.strip_suffix('\0').unwrap_or(text);
+                    let mut result = self.substitute_record(record, number, offset)?;
+                    result.push('\0');
+                    result
+                }
+            };
+            let result = self.newline.apply(result);
+            writer.write_all(result.as_bytes()).map_err(write_error)?;
+            offset += buffer.len();
+        }
+        writer.flush().map_err(write_error)
+    }
+
+    /// Substitute line `number`, which starts at byte `offset` of the input
+    fn substitute_line(&self, line: &str, number: usize, offset: usize) -> Result<String, Failure> {
+        let (name, vars, options, policy) = (self.name, self.vars, self.options, self.policy);
+        policy.check(name, line, (number, offset), &options.syntax)?;
+        varsubst::substitute_with_options(line, vars, options).map_err(|e| {
+            let (_, column) = line_col(line, e.position());
+            Failure::new(
+                Status::of(&e),
+                format!(
+                    "Substitution error at line {}, column {}: {}",
+                    number, column, e
+                ),
+            )
+            .with(
+                Diagnostic::of_error(&e, line, &options.syntax)
+                    .file(name)
+                    .offset(number, offset),
+            )
+        })
+    }
+
+    /// Substitute record `number`, which starts at byte `offset` of the input.
+    ///
+    /// Positions are reported as byte offsets within the record, since records
+    /// may contain newlines of their own.
+    fn substitute_record(
+        &self,
+        record: &str,
+        number: usize,
+        offset: usize,
+    ) -> Result<String, Failure> {
+        let (name, vars, options, policy) = (self.name, self.vars, self.options, self.policy);
+        let violations = policy.violations(name, record, &options.syntax);
+        if !violations.is_empty() {
+            let messages: Vec<String> = violations
+                .iter()
+                .map(|d| {
+                    let at = d.span.as_ref().map_or(0, |span| span.start);
+                    format!("{}: record {}, offset {}: {}", name, number, at, d.message)
+                })
+                .collect();
+            let mut failure = Failure::new(Status::Denied, messages.join("\n"));
+            failure.diagnostics = violations
+                .into_iter()
+                .map(|d| d.offset(1, offset).without_location())
+                .collect();
+            return Err(failure);
+        }
+        varsubst::substitute_with_options(record, vars, options).map_err(|e| {
+            Failure::new(
+                Status::of(&e),
+                format!(
+                    "Substitution error in record {} at offset {}: {}",
+                    number,
+                    e.position(),
+                    e
+                ),
+            )
+            .with(
+                Diagnostic::of_error(&e, record, &options.syntax)
+                    .file(name)
+                    .offset(1, offset)
+                    .without_location(),
+            )
+        })
+    }
+}
+
+fn write_error(e: io::Error) -> Failure {
+    Failure::new(Status::Io, format!("Error writing output: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(input: &str, vars: &[(&str, &str)]) -> Result<String, Failure> {
+        stream_records(input, Framing::Lines, vars)
+    }
+
+    fn stream_records(
+        input: &str,
+        framing: Framing,
+        vars: &[(&str, &str)],
+    ) -> Result<String, Failure> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut output = Vec::new();
+        let stream = Stream {
+            name: "<stdin>",
+            framing,
+            newline: Newline::Preserve,
+            bom: Bom::Strip,
+            vars: &vars,
+            options: &SubstOptions::new(),
+            policy: &Policy::default(),
+        };
+        stream.substitute_records(input.as_bytes(), &mut output)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_stream_lines() {
+        let output = stream("a=${A}\nb=${B}\nno newline", &[("A", "1"), ("B", "2")]);
+        assert_eq!(output.unwrap(), "a=1\nb=2\nno newline");
+    }
+
+    #[test]
+    fn test_stream_error_line_number() {
+        let err = stream("ok\nfine ${A\n", &[]).unwrap_err();
+        assert_eq!(err.status, Status::Syntax);
+        assert!(
+            err.message
+                .starts_with("Substitution error at line 2, column 6: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_nul_records() {
+        let output = stream_records(
+            "a=${A}\0multi\nline ${A}\0last",
+            Framing::Nul,
+            &[("A", "1")],
+        );
+        assert_eq!(output.unwrap(), "a=1\0multi\nline 1\0last\0");
+    }
+
+    #[test]
+    fn test_nul_record_error_position() {
+        let err = stream_records("ok\0\nx ${B\0", Framing::Nul, &[]).unwrap_err();
+        assert_eq!(err.status, Status::Syntax);
+        assert!(
+            err.message
+                .starts_with("Substitution error in record 2 at offset 3: "),
+            "{}",
+            err
+        );
+        assert_eq!(err.diagnostics[0].span, Some(6..9));
+    }
+}
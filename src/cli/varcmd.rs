@@ -0,0 +1,118 @@
+//! `--var-cmd`: variables whose values are the output of commands.
+//!
+//! Commands are split into words without a shell, so that quoting in a
+//! template's build script means the same on every platform; `--var-cmd-shell`
+//! hands them to the platform shell instead, for pipes and expansions.
+
+use std::process::{Command, Stdio};
+
+/// Run `command` and return its standard output without one trailing
+/// newline. Standard input is closed and standard error goes to ours.
+pub fn run(command: &str, shell: bool) -> Result<String, String> {
+    let mut process = match shell {
+        true if cfg!(windows) => {
+            let mut process = Command::new("cmd");
+            process.args(["/C", command]);
+            process
+        }
+        true => {
+            let mut process = Command::new("sh");
+            process.args(["-c", command]);
+            process
+        }
+        false => {
+            let words = split(command)?;
+            let (program, args) = words.split_first().ok_or("empty command")?;
+            let mut process = Command::new(program);
+            process.args(args);
+            process
+        }
+    };
+    let output = process
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(match output.status.code() {
+            Some(code) => format!("exited with status {}", code),
+            None => "terminated by a signal".to_string(),
+        });
+    }
+    let mut value = String::from_utf8(output.stdout).map_err(|_| "output is not valid UTF-8")?;
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+/// Split `command` into words at unquoted whitespace.
+///
+/// Single quotes keep everything up to the next single quote; in double
+/// quotes and unquoted text a backslash takes the next character literally.
+fn split(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            ' ' | '\t' | '\n' => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => word.push(ch),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(ch) => word.push(ch),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                let ch = chars.next().ok_or("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(ch);
+            }
+            ch => word.get_or_insert_with(String::new).push(ch),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("git  rev-parse --short HEAD").unwrap(),
+            ["git", "rev-parse", "--short", "HEAD"]
+        );
+        assert_eq!(
+            split(r#"date '+%F %T' "a \"b\"" c\ d '' x"#).unwrap(),
+            ["date", "+%F %T", "a \"b\"", "c d", "", "x"]
+        );
+        assert!(split("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_split_errors() {
+        assert_eq!(split("echo 'x").unwrap_err(), "unterminated single quote");
+        assert_eq!(split("echo \"x").unwrap_err(), "unterminated double quote");
+        assert_eq!(split("echo x\\").unwrap_err(), "trailing backslash");
+    }
+}
@@ -0,0 +1,230 @@
+//! Loaders for structured variable files (JSON, YAML and TOML).
+//!
+//! A variable file is a top-level object whose values are scalars. Strings are
+//! used as-is, numbers and booleans are converted to their textual form, and
+//! anything else is rejected with an error naming the offending key. With
+//! flattening enabled, nested objects are expanded into `PARENT_CHILD` keys
+//! instead.
+
+use serde_json::Value;
+
+/// Format of a variable file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Name of the command-line flag that loads this format
+    pub fn flag(self) -> &'static str {
+        match self {
+            Format::Json => "--vars-json",
+            Format::Yaml => "--vars-yaml",
+            Format::Toml => "--vars-toml",
+        }
+    }
+}
+
+/// Separator used when flattening nested keys
+///
+/// `.` would be the natural choice, but it is not a valid variable name
+/// character, so flattened keys could never be referenced.
+const FLATTEN_SEPARATOR: &str = "_";
+
+/// Parse a variable file into `(key, value)` pairs
+pub fn parse(
+    content: &str,
+    format: Format,
+    flatten: bool,
+) -> Result<Vec<(String, String)>, String> {
+    let value = match format {
+        Format::Json => serde_json::from_str(content).map_err(|e| e.to_string())?,
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string())?,
+        Format::Toml => {
+            let table: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+            from_toml(toml::Value::Table(table))
+        }
+    };
+
+    let Value::Object(map) = value else {
+        return Err(format!(
+            "expected a top-level object, found {}",
+            kind(&value)
+        ));
+    };
+
+    let mut pairs = Vec::new();
+    for (key, value) in map {
+        collect(key, value, flatten, &mut pairs)?;
+    }
+    Ok(pairs)
+}
+
+/// Append the scalar(s) for `key` to `pairs`, flattening objects if requested
+fn collect(
+    key: String,
+    value: Value,
+    flatten: bool,
+    pairs: &mut Vec<(String, String)>,
+) -> Result<(), String> {
+    let text = match value {
+        Value::String(s) => s,
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Object(map) if flatten => {
+            for (child, value) in map {
+                collect(
+                    format!("{}{}{}", key, FLATTEN_SEPARATOR, child),
+                    value,
+                    flatten,
+                    pairs,
+                )?;
+            }
+            return Ok(());
+        }
+        other => {
+            return Err(format!(
+                "key '{}' has {} value; only strings, numbers and booleans are supported",
+                key,
+                kind(&other)
+            ))
+        }
+    };
+    pairs.push((key, text));
+    Ok(())
+}
+
+/// Convert a TOML value into the equivalent JSON value
+///
+/// Datetimes become strings in their TOML representation.
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Describe the kind of a value for error messages
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "a null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(content: &str, format: Format, flatten: bool) -> Vec<(String, String)> {
+        let mut pairs = parse(content, format, flatten).unwrap();
+        pairs.sort();
+        pairs
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_scalar_coercion() {
+        let pairs = sorted(
+            r#"{"S": "x", "N": 8080, "F": 1.5, "B": true}"#,
+            Format::Json,
+            false,
+        );
+        assert_eq!(
+            pairs,
+            vec![
+                pair("B", "true"),
+                pair("F", "1.5"),
+                pair("N", "8080"),
+                pair("S", "x")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_value_rejected() {
+        let err = parse(r#"{"OK": "1", "DB": {"HOST": "x"}}"#, Format::Json, false).unwrap_err();
+        assert!(err.contains("key 'DB' has an object value"), "{}", err);
+    }
+
+    #[test]
+    fn test_null_rejected() {
+        let err = parse(r#"{"X": null}"#, Format::Json, false).unwrap_err();
+        assert!(err.contains("key 'X' has a null value"), "{}", err);
+    }
+
+    #[test]
+    fn test_top_level_must_be_object() {
+        let err = parse("[1, 2]", Format::Json, false).unwrap_err();
+        assert_eq!(err, "expected a top-level object, found an array");
+    }
+
+    #[test]
+    fn test_yaml() {
+        let pairs = sorted(
+            "HOST: example.com\nPORT: 80\nTLS: yes\n",
+            Format::Yaml,
+            false,
+        );
+        assert_eq!(
+            pairs,
+            vec![
+                pair("HOST", "example.com"),
+                pair("PORT", "80"),
+                pair("TLS", "yes")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toml() {
+        let pairs = sorted(
+            "HOST = \"example.com\"\nPORT = 80\nWHEN = 1979-05-27\n",
+            Format::Toml,
+            false,
+        );
+        assert_eq!(
+            pairs,
+            vec![
+                pair("HOST", "example.com"),
+                pair("PORT", "80"),
+                pair("WHEN", "1979-05-27")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let pairs = sorted(
+            "[db]\nhost = \"x\"\n[db.pool]\nsize = 4\n",
+            Format::Toml,
+            true,
+        );
+        assert_eq!(pairs, vec![pair("db_host", "x"), pair("db_pool_size", "4")]);
+    }
+
+    #[test]
+    fn test_flatten_still_rejects_arrays() {
+        let err = parse("list:\n  - 1\n", Format::Yaml, true).unwrap_err();
+        assert!(err.contains("key 'list' has an array value"), "{}", err);
+    }
+}
@@ -0,0 +1,551 @@
+//! Building the variable map from all sources.
+
+use crate::cli::args::{SourceArgs, StdinFormat};
+use crate::cli::diagnostic::Diagnostic;
+use crate::cli::dotenv;
+use crate::cli::files::read_input;
+use crate::cli::prompt::Terminal;
+use crate::cli::status::{Failure, Status};
+use crate::cli::varcmd;
+use crate::cli::varfile::{self, Format};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+/// Variables together with where each one came from
+#[derive(Debug, Default, Clone)]
+pub struct Variables {
+    /// Final values after precedence is applied
+    pub values: HashMap<String, String>,
+    /// Label of the source that provided each final value
+    pub origins: HashMap<String, String>,
+    /// Each loaded source with the number of variables it provided, in load order
+    pub sources: Vec<(String, usize)>,
+    /// Names given with `--secret`, whose values are never shown
+    pub secrets: HashSet<String>,
+    /// Names given with `--default`, in command-line order, whether or not
+    /// the default was used
+    pub defaults: Vec<String>,
+    /// Environment variables renamed by `--strip-prefix`, by short name
+    pub stripped: HashMap<String, Stripped>,
+}
+
+/// An environment variable renamed by `--strip-prefix`
+#[derive(Debug, Clone)]
+pub struct Stripped {
+    /// The name with the prefix
+    pub full: String,
+    /// Whether a real variable of the short name was shadowed
+    pub shadows: bool,
+}
+
+/// Source label of variables taken from the process environment
+const ENVIRONMENT: &str = "environment";
+
+/// Source label of `--default` values that were used
+const DEFAULTS: &str = "--default";
+
+/// Shown instead of the value of a `--secret` variable
+pub const REDACTED: &str = "<redacted>";
+
+impl Variables {
+    /// Names and sources of the variables provided explicitly, i.e. not
+    /// taken from the process environment or `--default`, sorted by name
+    pub fn provided(&self) -> Vec<(&str, &str)> {
+        let mut provided: Vec<(&str, &str)> = self
+            .origins
+            .iter()
+            .filter(|(_, origin)| origin.as_str() != ENVIRONMENT && origin.as_str() != DEFAULTS)
+            .map(|(name, origin)| (name.as_str(), origin.as_str()))
+            .collect();
+        provided.sort();
+        provided
+    }
+
+    /// Where the value of `name` came from, naming the full variable and
+    /// any real one it shadows for a name shortened by `--strip-prefix`
+    pub fn origin(&self, name: &str) -> Option<String> {
+        let origin = self.origins.get(name)?;
+        Some(match self.stripped.get(name) {
+            Some(stripped) if origin == ENVIRONMENT => {
+                let mut origin = format!("{} (--strip-prefix)", stripped.full);
+                if stripped.shadows {
+                    origin.push_str(&format!(", shadows {} {}", ENVIRONMENT, name));
+                }
+                origin
+            }
+            _ => origin.clone(),
+        })
+    }
+
+    /// Whether the value of `name` is its `--default`
+    pub fn is_defaulted(&self, name: &str) -> bool {
+        self.origins
+            .get(name)
+            .is_some_and(|origin| origin == DEFAULTS)
+    }
+
+    /// Names given with `--default` together with the source that overrode
+    /// the default, or `None` if it was used
+    pub fn defaults(&self) -> Vec<(&str, Option<&str>)> {
+        (self.defaults.iter())
+            .map(|name| {
+                let origin = self.origins.get(name).map(String::as_str);
+                (name.as_str(), origin.filter(|&origin| origin != DEFAULTS))
+            })
+            .collect()
+    }
+
+    /// Whether `name` was given with `--secret`
+    pub fn is_secret(&self, name: &str) -> bool {
+        self.secrets.contains(name)
+    }
+
+    /// How `name` is shown in messages: as `NAME=<redacted>` if it is a secret
+    pub fn label(&self, name: &str) -> String {
+        match self.is_secret(name) {
+            true => format!("{}={}", name, REDACTED),
+            false => name.to_string(),
+        }
+    }
+
+    /// `text` with every secret value replaced by [`REDACTED`], longest first
+    /// so that a value containing another is replaced whole
+    pub fn redact(&self, text: &str) -> String {
+        let mut values: Vec<&str> = (self.secrets.iter())
+            .filter_map(|name| self.values.get(name))
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+            .collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        let mut text = text.to_string();
+        for value in values {
+            text = text.replace(value, REDACTED);
+        }
+        text
+    }
+
+    /// A copy with the variables of `source` added below every loaded source
+    /// but above `--default`
+    pub fn under(&self, source: String, pairs: Vec<(String, String)>) -> Variables {
+        let mut vars = Variables::default();
+        vars.add(source, pairs);
+        vars.sources.extend(self.sources.iter().cloned());
+        for (name, value) in &self.values {
+            if !(self.is_defaulted(name) && vars.values.contains_key(name)) {
+                vars.values.insert(name.clone(), value.clone());
+                vars.origins
+                    .insert(name.clone(), self.origins[name].clone());
+            }
+        }
+        vars.secrets = self.secrets.clone();
+        vars.defaults = self.defaults.clone();
+        vars.stripped = self.stripped.clone();
+        vars
+    }
+
+    /// Apply the variables of one source, overriding earlier sources
+    pub fn add(&mut self, source: String, pairs: impl IntoIterator<Item = (String, String)>) {
+        let mut count = 0;
+        for (key, value) in pairs {
+            self.origins.insert(key.clone(), source.clone());
+            self.values.insert(key, value);
+            count += 1;
+        }
+        self.sources.push((source, count));
+    }
+}
+
+/// Build the variable map from all sources.
+///
+/// Precedence, lowest to highest: environment, `--env-file`, variable files
+/// (`--vars-json`, `--vars-yaml`, `--vars-toml`), `--vars-stdin`,
+/// `--var-cmd`, `-v`, `--secret`. Files are applied in command-line order.
+/// A `--default` only applies to names that none of them defines.
+pub fn load(args: &SourceArgs) -> Result<Variables, Failure> {
+    let mut vars = Variables::default();
+
+    // Add environment variables if requested (default behavior unless --no-env is specified)
+    if !args.no_env {
+        let (pairs, stripped) = environment(std::env::vars(), args);
+        vars.add(ENVIRONMENT.to_string(), pairs);
+        vars.stripped = stripped;
+    }
+
+    // Add variables from env files in command-line order (overrides environment)
+    for path in &args.env_files {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Failure::new(
+                Status::Io,
+                format!("Error reading env file '{}': {}", path, e),
+            )
+            .with(Diagnostic::new("io", format!("error reading env file: {}", e)).file(path))
+        })?;
+        let pairs = dotenv::parse(&content).map_err(|e| {
+            Failure::new(
+                Status::Failure,
+                format!("Invalid env file '{}': {}", path, e),
+            )
+            .with(Diagnostic::new("failure", format!("invalid env file: {}", e)).file(path))
+        })?;
+        vars.add(format!("--env-file '{}'", path), pairs);
+    }
+
+    // Add variables from JSON/YAML/TOML files in command-line order (overrides env files)
+    for (format, path) in &args.var_files {
+        let source = (path != "-").then_some(path);
+        let content = read_input(source).map_err(|e| {
+            Failure::new(
+                Status::Io,
+                format!("Error reading variables file '{}': {}", path, e),
+            )
+            .with(Diagnostic::new("io", format!("error reading variables file: {}", e)).file(path))
+        })?;
+        let pairs = varfile::parse(&content, *format, args.flatten).map_err(|e| {
+            Failure::new(
+                Status::Failure,
+                format!("Invalid variables file '{}': {}", path, e),
+            )
+            .with(Diagnostic::new("failure", format!("invalid variables file: {}", e)).file(path))
+        })?;
+        vars.add(format!("{} '{}'", format.flag(), path), pairs);
+    }
+
+    // Add variables piped through stdin (overrides variable files)
+    if let Some(format) = args.vars_stdin {
+        let content = read_input(None).map_err(|e| {
+            Failure::new(
+                Status::Io,
+                format!("Error reading variables from stdin: {}", e),
+            )
+        })?;
+        let pairs = match format {
+            StdinFormat::Lines => dotenv::parse_plain(&content).map_err(|e| e.to_string()),
+            StdinFormat::Json => varfile::parse(&content, Format::Json, args.flatten),
+        }
+        .map_err(|e| {
+            Failure::new(
+                Status::Failure,
+                format!("Invalid variables on stdin: {}", e),
+            )
+        })?;
+        vars.add("--vars-stdin".to_string(), pairs);
+    }
+
+    // Add command outputs, running every command once (overrides --vars-stdin)
+    let mut outputs = Vec::new();
+    for var_cmd in &args.var_cmds {
+        let (key, command) = var_cmd.split_once('=').ok_or_else(|| {
+            Failure::new(
+                Status::Usage,
+                format!(
+                    "Invalid --var-cmd format: '{}' (expected KEY=COMMAND)",
+                    var_cmd
+                ),
+            )
+        })?;
+        let value = varcmd::run(command, args.var_cmd_shell).map_err(|e| {
+            Failure::new(
+                Status::Failure,
+                format!("Command for '{}' failed: {}: {}", key, command, e),
+            )
+            .with(Diagnostic::new("failure", format!("command failed: {}", e)).variable(key))
+        })?;
+        outputs.push((key.to_string(), value));
+    }
+    if !outputs.is_empty() {
+        vars.add("--var-cmd".to_string(), outputs);
+    }
+
+    // Add command-line variables (overrides everything else)
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for var in &args.variables {
+        let (key, value) = var.split_once('=').ok_or_else(|| {
+            Failure::new(
+                Status::Usage,
+                format!("Invalid variable format: '{}' (expected KEY=VALUE)", var),
+            )
+        })?;
+        let (key, append) = match key.strip_suffix('+') {
+            Some(key) => (key, true),
+            None => (key, false),
+        };
+        if key.contains('+') {
+            return Err(Failure::new(
+                Status::Usage,
+                format!(
+                    "Invalid variable name '{}' in '{}' ('+' is not allowed)",
+                    key, var
+                ),
+            ));
+        }
+        // Appending sees earlier -v definitions before the other sources
+        let current = (pairs.iter().rev())
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .or_else(|| vars.values.get(key))
+            .filter(|_| append);
+        let value = value_arg(key, value)?;
+        let value = match current {
+            Some(current) => format!("{}{}{}", current, args.append_sep, value),
+            None => value,
+        };
+        pairs.push((key.to_string(), value));
+    }
+    if !pairs.is_empty() {
+        vars.add("-v".to_string(), pairs);
+    }
+
+    // Add secrets, from the environment or else the terminal (overrides -v)
+    let mut secrets = Vec::new();
+    for name in &args.secrets {
+        let value = match std::env::var(name) {
+            Ok(value) => value,
+            Err(_) => Terminal::open()
+                .and_then(|mut terminal| terminal.read_hidden(&format!("{}: ", name)))
+                .map_err(|e| {
+                    let message = format!("secret '{}' is not set", name);
+                    let diagnostic = Diagnostic::new("undefined-variable", message).variable(name);
+                    Failure::new(
+                        Status::Undefined,
+                        format!(
+                            "Secret '{}' is not in the environment and cannot be read \
+                             from the terminal: {}",
+                            name, e
+                        ),
+                    )
+                    .with(diagnostic)
+                })?,
+        };
+        vars.secrets.insert(name.clone());
+        secrets.push((name.clone(), value));
+    }
+    if !secrets.is_empty() {
+        vars.add("--secret".to_string(), secrets);
+    }
+
+    // Add defaults for the names still undefined (later ones win)
+    let mut defaults: Vec<(String, String)> = Vec::new();
+    for default in &args.defaults {
+        let (key, value) = default.split_once('=').ok_or_else(|| {
+            Failure::new(
+                Status::Usage,
+                format!("Invalid default format: '{}' (expected KEY=VALUE)", default),
+            )
+        })?;
+        if !vars.defaults.iter().any(|name| name == key) {
+            vars.defaults.push(key.to_string());
+        }
+        if !vars.values.contains_key(key) {
+            defaults.retain(|(name, _)| name != key);
+            defaults.push((key.to_string(), value.to_string()));
+        }
+    }
+    if !defaults.is_empty() {
+        vars.add(DEFAULTS.to_string(), defaults);
+    }
+
+    Ok(vars)
+}
+
+/// The value of `-v KEY=VALUE`: the contents of a file for `@PATH`, without
+/// one trailing newline, or exactly for `@@PATH`. `\@` starts a literal `@`.
+fn value_arg(key: &str, value: &str) -> Result<String, Failure> {
+    let Some(path) = value.strip_prefix('@') else {
+        let literal = value
+            .strip_prefix('\\')
+            .filter(|rest| rest.starts_with('@'));
+        return Ok(literal.unwrap_or(value).to_string());
+    };
+    let (path, exact) = match path.strip_prefix('@') {
+        Some(path) => (path, true),
+        None => (path, false),
+    };
+    let mut content = fs::read_to_string(path).map_err(|e| {
+        Failure::new(
+            Status::Io,
+            format!("Error reading value of '{}' from '{}': {}", key, path, e),
+        )
+        .with(
+            Diagnostic::new("io", format!("error reading value of '{}': {}", key, e))
+                .file(path)
+                .variable(key),
+        )
+    })?;
+    if !exact && content.ends_with('\n') {
+        content.pop();
+        if content.ends_with('\r') {
+            content.pop();
+        }
+    }
+    Ok(content)
+}
+
+/// Apply `--prefix` and `--strip-prefix` to the environment, returning the
+/// variables and the ones renamed by `--strip-prefix`.
+///
+/// Stripped names come last so they shadow real variables of the same name.
+fn environment(
+    env: impl Iterator<Item = (String, String)>,
+    args: &SourceArgs,
+) -> (Vec<(String, String)>, HashMap<String, Stripped>) {
+    let mut plain = Vec::new();
+    let mut stripped = Vec::new();
+
+    for (key, value) in env.filter(|(key, _)| env_visible(key, &args.prefixes)) {
+        let short = args
+            .strip_prefixes
+            .iter()
+            .find_map(|prefix| key.strip_prefix(prefix.as_str()))
+            .filter(|short| !short.is_empty());
+
+        match short {
+            Some(short) => {
+                stripped.push((short.to_string(), value.clone(), key.clone()));
+                if args.keep_prefixed {
+                    plain.push((key, value));
+                }
+            }
+            None => plain.push((key, value)),
+        }
+    }
+
+    let real: HashSet<&str> = plain.iter().map(|(key, _)| key.as_str()).collect();
+    let full = (stripped.iter())
+        .map(|(short, _, full)| {
+            let shadows = real.contains(short.as_str());
+            (
+                short.clone(),
+                Stripped {
+                    full: full.clone(),
+                    shadows,
+                },
+            )
+        })
+        .collect();
+    plain.extend(stripped.into_iter().map(|(short, value, _)| (short, value)));
+    (plain, full)
+}
+
+/// Whether an environment variable passes the `--prefix` filter
+fn env_visible(key: &str, prefixes: &[String]) -> bool {
+    prefixes.is_empty()
+        || prefixes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::args::Cli;
+    use clap::Parser;
+
+    fn env_map(pairs: &[(&str, &str)], flags: &[&str]) -> HashMap<String, String> {
+        let cli = Cli::parse_from(std::iter::once("varsubst").chain(flags.iter().copied()));
+        let env = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        environment(env, &cli.render.sources)
+            .0
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_prefix_shadows_real_variable() {
+        let vars = env_map(
+            &[("MYAPP_HOST", "stripped"), ("HOST", "real"), ("OTHER", "x")],
+            &["--strip-prefix", "MYAPP_"],
+        );
+        assert_eq!(vars["HOST"], "stripped");
+        assert_eq!(vars["OTHER"], "x");
+        assert!(!vars.contains_key("MYAPP_HOST"));
+    }
+
+    #[test]
+    fn test_strip_prefix_origin() {
+        let cli = Cli::parse_from(["varsubst", "--strip-prefix", "MYAPP_"]);
+        let env = [("MYAPP_HOST", "a"), ("HOST", "b"), ("MYAPP_PORT", "1")]
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        let (pairs, stripped) = environment(env.into_iter(), &cli.render.sources);
+        let mut vars = Variables::default();
+        vars.add(ENVIRONMENT.to_string(), pairs);
+        vars.stripped = stripped;
+        assert_eq!(
+            vars.origin("HOST").unwrap(),
+            "MYAPP_HOST (--strip-prefix), shadows environment HOST"
+        );
+        assert_eq!(vars.origin("PORT").unwrap(), "MYAPP_PORT (--strip-prefix)");
+
+        vars.add("-v".to_string(), [("HOST".to_string(), "c".to_string())]);
+        assert_eq!(vars.origin("HOST").unwrap(), "-v");
+    }
+
+    #[test]
+    fn test_strip_prefix_keep_prefixed() {
+        let vars = env_map(
+            &[("MYAPP_HOST", "h")],
+            &["--strip-prefix", "MYAPP_", "--keep-prefixed"],
+        );
+        assert_eq!(vars["HOST"], "h");
+        assert_eq!(vars["MYAPP_HOST"], "h");
+    }
+
+    #[test]
+    fn test_strip_prefix_ignores_bare_prefix() {
+        let vars = env_map(&[("MYAPP_", "v")], &["--strip-prefix", "MYAPP_"]);
+        assert_eq!(vars["MYAPP_"], "v");
+        assert!(!vars.contains_key(""));
+    }
+
+    #[test]
+    fn test_redact_longest_secret_first() {
+        let mut vars = Variables::default();
+        vars.add(
+            "--secret".to_string(),
+            [("A", "pass"), ("B", "password"), ("C", "")]
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        vars.secrets.extend(["A", "B", "C"].map(String::from));
+        assert_eq!(vars.redact("password pass x"), "<redacted> <redacted> x");
+        assert_eq!(vars.label("A"), "A=<redacted>");
+        assert_eq!(vars.label("D"), "D");
+    }
+
+    #[test]
+    fn test_frontmatter_overrides_defaults() {
+        let mut vars = Variables::default();
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            (pairs.iter())
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        vars.add("-v".to_string(), pairs(&[("A", "v")]));
+        vars.add(DEFAULTS.to_string(), pairs(&[("B", "default")]));
+        vars.defaults.extend(["A", "B"].map(String::from));
+
+        let local = vars.under(
+            "frontmatter".to_string(),
+            pairs(&[("A", "fm"), ("B", "fm")]),
+        );
+        assert_eq!(local.values["A"], "v");
+        assert_eq!(local.values["B"], "fm");
+        assert_eq!(
+            local.defaults(),
+            vec![("A", Some("-v")), ("B", Some("frontmatter"))]
+        );
+        assert_eq!(vars.defaults(), vec![("A", Some("-v")), ("B", None)]);
+    }
+
+    #[test]
+    fn test_env_visible_without_prefixes() {
+        assert!(env_visible("ANYTHING", &[]));
+    }
+
+    #[test]
+    fn test_env_visible_union_of_prefixes() {
+        let prefixes = vec!["MYAPP_".to_string(), "CI_".to_string()];
+        assert!(env_visible("MYAPP_HOST", &prefixes));
+        assert!(env_visible("CI_JOB", &prefixes));
+        assert!(!env_visible("AWS_SECRET_ACCESS_KEY", &prefixes));
+        assert!(!env_visible("MYAPP", &prefixes));
+    }
+}
@@ -0,0 +1,104 @@
+//! `--verbose`: statistics on stderr.
+//!
+//! Reports never include variable values, so they are safe to keep in CI
+//! logs even when secrets are substituted.
+
+use crate::cli::files::line_col;
+use crate::cli::inputs::Input;
+use crate::cli::variables::Variables;
+use varsubst::{Segment, SyntaxConfig};
+
+/// Print the variable sources that were loaded, with their variable counts
+pub fn sources(vars: &Variables) {
+    let loaded: Vec<String> = vars
+        .sources
+        .iter()
+        .map(|(source, count)| format!("{} ({})", source, count))
+        .collect();
+    if loaded.is_empty() {
+        eprintln!("variables: none loaded");
+    } else {
+        eprintln!("variables: {}", loaded.join(", "));
+    }
+}
+
+/// Reference statistics of one template
+#[derive(Debug, Default)]
+pub struct Stats<'a> {
+    /// Number of references
+    pub references: usize,
+    /// Number of references with a defined value
+    pub substituted: usize,
+    /// Distinct defined names, in first-appearance order
+    pub defined: Vec<&'a str>,
+    /// Distinct undefined names, in first-appearance order
+    pub undefined: Vec<&'a str>,
+    /// Distinct defined names whose value is their `--default`
+    pub defaulted: Vec<&'a str>,
+}
+
+impl<'a> Stats<'a> {
+    /// Count the references in `content`
+    pub fn collect(content: &'a str, vars: &Variables, syntax: &'a SyntaxConfig) -> Self {
+        let mut stats = Stats::default();
+        for segment in varsubst::segments_with(content, syntax).flatten() {
+            if let Segment::Var { name, .. } = segment {
+                stats.references += 1;
+                let names = if vars.values.contains_key(name) {
+                    stats.substituted += 1;
+                    &mut stats.defined
+                } else {
+                    &mut stats.undefined
+                };
+                if !names.contains(&name) {
+                    names.push(name);
+                    if vars.is_defaulted(name) {
+                        stats.defaulted.push(name);
+                    }
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// Print the reference statistics of one input.
+///
+/// At level 2 and above every reference is listed with its position and the
+/// source of its value.
+pub fn file(input: &Input, content: &str, vars: &Variables, syntax: &SyntaxConfig, level: u8) {
+    let stats = Stats::collect(content, vars, syntax);
+    let mut summary = format!(
+        "{}: {} reference(s), {} substituted, {} undefined",
+        input.display,
+        stats.references,
+        stats.substituted,
+        stats.references - stats.substituted
+    );
+    if !stats.undefined.is_empty() {
+        summary.push_str(&format!(" ({})", stats.undefined.join(", ")));
+    }
+    if !stats.defaulted.is_empty() {
+        summary.push_str(&format!(
+            ", {} defaulted ({})",
+            stats.defaulted.len(),
+            stats.defaulted.join(", ")
+        ));
+    }
+    eprintln!("{}", summary);
+
+    if level < 2 {
+        return;
+    }
+    for segment in varsubst::segments_with(content, syntax).flatten() {
+        if let Segment::Var { name, span, .. } = segment {
+            let (line, column) = line_col(content, span.start);
+            let origin = match vars.origin(name) {
+                _ if vars.is_defaulted(name) => "defaulted".to_string(),
+                Some(origin) => format!("from {}", origin),
+                None => "undefined".to_string(),
+            };
+            eprintln!("  {}:{}: {} {}", line, column, vars.label(name), origin);
+        }
+    }
+}
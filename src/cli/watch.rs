@@ -0,0 +1,202 @@
+//! `--watch`: re-render whenever a template or variables file changes.
+//!
+//! The parent directory of each watched file is observed rather than the file
+//! itself, so editors that save by replacing the file are still noticed.
+//! Events for other files in those directories, such as the rendered output,
+//! are ignored.
+
+use crate::cli::diagnostic;
+use crate::cli::status::{Failure, Status};
+use notify::{Event, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long events must stop arriving before a render starts
+pub const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Render once, then again after every change to `files`, until interrupted
+pub fn run(files: &[&str], mut render: impl FnMut() -> Result<(), Failure>) -> Result<(), Failure> {
+    let watched = files
+        .iter()
+        .map(|file| {
+            fs::canonicalize(file)
+                .map_err(|e| Failure::new(Status::Io, format!("Error watching '{}': {}", file, e)))
+        })
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // The receiver only goes away when the loop ends
+        let _ = sender.send(event);
+    })
+    .map_err(|e| Failure::new(Status::Io, format!("Error starting watcher: {}", e)))?;
+    let dirs: HashSet<&Path> = watched.iter().filter_map(|path| path.parent()).collect();
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| {
+                Failure::new(
+                    Status::Io,
+                    format!("Error watching '{}': {}", dir.display(), e),
+                )
+            })?;
+    }
+
+    report(render());
+    watch_loop(&events, &watched, DEBOUNCE, || report(render()));
+    Ok(())
+}
+
+/// Call `render` after each burst of events touching `watched`.
+///
+/// A burst ends once no event has arrived for `debounce`. The loop returns
+/// when the sending side of `events` is dropped.
+pub fn watch_loop(
+    events: &Receiver<notify::Result<Event>>,
+    watched: &HashSet<PathBuf>,
+    debounce: Duration,
+    mut render: impl FnMut(),
+) {
+    let relevant = |event: &notify::Result<Event>| match event {
+        Ok(event) => {
+            (event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove())
+                && event.paths.iter().any(|path| watched.contains(path))
+        }
+        Err(_) => false,
+    };
+
+    while let Ok(event) = events.recv() {
+        if !relevant(&event) {
+            continue;
+        }
+        // Unrelated events, such as reads of the output, do not extend the wait
+        let mut deadline = Instant::now() + debounce;
+        loop {
+            match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) if relevant(&event) => deadline = Instant::now() + debounce,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    render();
+                    return;
+                }
+            }
+        }
+        render();
+    }
+}
+
+/// Print a timestamped status line for one render
+fn report(result: Result<(), Failure>) {
+    match result {
+        Ok(()) if !diagnostic::json() => eprintln!("[{}] rendered", timestamp()),
+        Ok(()) => {}
+        Err(failure) => {
+            if !diagnostic::json() {
+                eprint!("[{}] ", timestamp());
+            }
+            failure.report();
+        }
+    }
+}
+
+/// The current UTC time as `HH:MM:SS`
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, EventKind, ModifyKind};
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    fn modify(path: &str) -> notify::Result<Event> {
+        Ok(Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path)))
+    }
+
+    /// Run the loop over the events sent by `send`, returning the render count
+    fn renders(send: impl FnOnce(&Sender<notify::Result<Event>>) + Send + 'static) -> usize {
+        let (sender, events) = mpsc::channel();
+        let producer = thread::spawn(move || send(&sender));
+        let watched = HashSet::from([PathBuf::from("/t/template")]);
+        let mut count = 0;
+        watch_loop(&events, &watched, Duration::from_millis(50), || count += 1);
+        producer.join().unwrap();
+        count
+    }
+
+    #[test]
+    fn test_burst_renders_once() {
+        let count = renders(|sender| {
+            for _ in 0..5 {
+                sender.send(modify("/t/template")).unwrap();
+            }
+        });
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_separate_changes_render_separately() {
+        let count = renders(|sender| {
+            sender.send(modify("/t/template")).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            sender.send(modify("/t/template")).unwrap();
+        });
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_unrelated_events_do_not_delay_render() {
+        let (sender, events) = mpsc::channel();
+        let producer = thread::spawn(move || {
+            sender.send(modify("/t/template")).unwrap();
+            for _ in 0..20 {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(modify("/t/output")).unwrap();
+            }
+        });
+        let watched = HashSet::from([PathBuf::from("/t/template")]);
+        let started = Instant::now();
+        let mut rendered_after = None;
+        watch_loop(&events, &watched, Duration::from_millis(100), || {
+            rendered_after.get_or_insert(started.elapsed());
+        });
+        producer.join().unwrap();
+        assert!(rendered_after.unwrap() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_unrelated_events_are_ignored() {
+        let count = renders(|sender| {
+            sender.send(modify("/t/output")).unwrap();
+            let access = Event::new(EventKind::Access(AccessKind::Any))
+                .add_path(PathBuf::from("/t/template"));
+            sender.send(Ok(access)).unwrap();
+            sender
+                .send(Err(notify::Error::generic("watch failed")))
+                .unwrap();
+        });
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_timestamp_format() {
+        let stamp = timestamp();
+        assert_eq!(stamp.len(), 8);
+        assert_eq!(&stamp[2..3], ":");
+        assert_eq!(&stamp[5..6], ":");
+    }
+}
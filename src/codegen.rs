@@ -0,0 +1,221 @@
+//! Compiling template files into Rust code from a build script.
+//!
+//! [`generate`] parses every `*.tmpl` file in a directory and writes a
+//! module with a static [`Template`] for each of them, so that a program
+//! can embed its templates and neither read nor parse them at startup.
+//! Syntax errors fail the build with the file, line and column.
+//!
+//! In `build.rs`:
+//!
+//! ```no_run
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("templates.rs");
+//! println!("cargo:rerun-if-changed=templates");
+//! if let Err(error) = varsubst::codegen::generate("templates", &out) {
+//!     panic!("{}", error);
+//! }
+//! ```
+//!
+//! And in the crate, for a `templates/greeting.tmpl`:
+//!
+//! ```ignore
+//! mod templates {
+//!     include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//! }
+//!
+//! let output = templates::GREETING.render(&vars)?;
+//! ```
+//!
+//! See `examples/codegen` for a complete project.
+
+use crate::atomic::write_atomic;
+use crate::{FilePhase, Snippet, SubstFileError, Template};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Error returned by [`generate`]
+#[derive(Debug)]
+pub enum CodegenError {
+    /// A template could not be read or has a syntax error, or the module
+    /// could not be written
+    File(SubstFileError),
+    /// Two templates get the same static name
+    DuplicateName {
+        /// The name of the static
+        name: String,
+        /// The templates it would be generated from
+        paths: [PathBuf; 2],
+    },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::File(error) => error.fmt(f),
+            CodegenError::DuplicateName { name, paths } => write!(
+                f,
+                "{} and {} would both be compiled to {}",
+                paths[0].display(),
+                paths[1].display(),
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodegenError::File(error) => Some(error),
+            CodegenError::DuplicateName { .. } => None,
+        }
+    }
+}
+
+impl From<SubstFileError> for CodegenError {
+    fn from(error: SubstFileError) -> Self {
+        CodegenError::File(error)
+    }
+}
+
+/// Parse each `*.tmpl` file directly in `templates` with the default syntax
+/// and write a module to `out` defining a
+/// `pub static NAME: LazyLock<Template>` for it.
+///
+/// `NAME` is the file stem in upper case, with characters that cannot be in
+/// an identifier replaced by `_`: `db-config.tmpl` becomes `DB_CONFIG`. The
+/// statics hold the templates serialized by [`Template::to_bytes`] and
+/// render exactly like [`Template::parse`] of the files.
+///
+/// `out` is only rewritten when its contents change, so that cargo does not
+/// rebuild the crate including it for nothing.
+pub fn generate(templates: impl AsRef<Path>, out: impl AsRef<Path>) -> Result<(), CodegenError> {
+    let (templates, out) = (templates.as_ref(), out.as_ref());
+    let read_error = |path: &Path, source| SubstFileError::Io {
+        path: path.to_path_buf(),
+        phase: FilePhase::Read,
+        source,
+    };
+
+    let mut files = BTreeMap::new();
+    let entries = fs::read_dir(templates).map_err(|e| read_error(templates, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| read_error(templates, e))?.path();
+        let Some(stem) = path.file_stem().filter(|_| is_template(&path)) else {
+            continue;
+        };
+        let name = static_name(&stem.to_string_lossy());
+        if let Some(other) = files.insert(name.clone(), path.clone()) {
+            let mut paths = [other, path];
+            paths.sort();
+            return Err(CodegenError::DuplicateName { name, paths });
+        }
+    }
+
+    let mut module = format!(
+        "// @generated by varsubst::codegen from {}\n",
+        templates.display()
+    );
+    for (name, path) in &files {
+        let source = fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+        let template = Template::parse(&source).map_err(|error| {
+            let position = error.position();
+            let snippet = Snippet::new(&source, position..position);
+            SubstFileError::Subst {
+                path: path.clone(),
+                line: snippet.line(),
+                column: snippet.column(),
+                error,
+            }
+        })?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let bytes = template.to_bytes().escape_ascii().to_string();
+        write!(
+            module,
+            "\n/// `{file}`\n\
+             pub static {name}: ::std::sync::LazyLock<::varsubst::Template> =\n    \
+             ::std::sync::LazyLock::new(|| {{\n        \
+             ::varsubst::Template::from_bytes(b\"{bytes}\")\n            \
+             .expect(\"{file} was compiled by another version of varsubst\")\n    \
+             }});\n",
+            file = file_name.escape_default(),
+        )
+        .expect("writing to a String cannot fail");
+    }
+
+    if fs::read(out).is_ok_and(|existing| existing == module.as_bytes()) {
+        return Ok(());
+    }
+    write_atomic(out, module.as_bytes(), None).map_err(|source| {
+        CodegenError::File(SubstFileError::Io {
+            path: out.to_path_buf(),
+            phase: FilePhase::Write,
+            source,
+        })
+    })
+}
+
+/// Whether `path` is named `*.tmpl`, ignoring hidden files
+fn is_template(path: &Path) -> bool {
+    let hidden = (path.file_name()).is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden && path.extension().is_some_and(|ext| ext == "tmpl") && path.is_file()
+}
+
+/// The name of the static for a template with the file stem `stem`
+fn static_name(stem: &str) -> String {
+    let mut name: String = stem
+        .chars()
+        .map(|ch| match ch.is_ascii_alphanumeric() {
+            true => ch.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    if name == "_" || !name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_static_name() {
+        assert_eq!(static_name("greeting"), "GREETING");
+        assert_eq!(static_name("db-config.local"), "DB_CONFIG_LOCAL");
+        assert_eq!(static_name("404"), "_404");
+        assert_eq!(static_name("_x"), "_X");
+        assert_eq!(static_name("-"), "__");
+        assert_eq!(static_name("ünï"), "_N_");
+    }
+
+    #[test]
+    fn test_syntax_error_location() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("bad.tmpl");
+        fs::write(&path, "line one\n  ${OPEN").unwrap();
+        let out = dir.path().join("out.rs");
+        let error = generate(dir.path(), &out).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("{}:2:3: unclosed brace", path.display())
+        );
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a-b.tmpl"), "").unwrap();
+        fs::write(dir.path().join("a_b.tmpl"), "").unwrap();
+        let error = generate(dir.path(), dir.path().join("out.rs")).unwrap_err();
+        assert!(matches!(
+            error,
+            CodegenError::DuplicateName { ref name, .. } if name == "A_B"
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+//! Substitution that borrows the template when it comes out unchanged.
+
+use crate::{segments_with, Expander, Reference, Segment, SubstOptions, SubstResult};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Substitute variables like [`substitute`](crate::substitute), borrowing
+/// the template instead of allocating when the output would be identical.
+///
+/// The result is [`Cow::Borrowed`] if every reference produced exactly its
+/// own text, as undefined variables kept verbatim do, and there were no
+/// escape sequences; otherwise it is [`Cow::Owned`].
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute_cow;
+/// use std::borrow::Cow;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("NAME", "World")]);
+/// assert!(matches!(substitute_cow("plain text ${OTHER}", &vars).unwrap(), Cow::Borrowed(_)));
+/// assert_eq!(substitute_cow("Hello ${NAME}", &vars).unwrap(), "Hello World");
+/// ```
+pub fn substitute_cow<'t, K, V, S>(
+    template: &'t str,
+    variables: &HashMap<K, V, S>,
+) -> SubstResult<Cow<'t, str>>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    substitute_cow_with_options(template, variables, &SubstOptions::default())
+}
+
+/// Substitute variables with explicit options, borrowing the template when
+/// the output would be identical.
+///
+/// Behaves like [`substitute_cow`], with undefined variables, syntax and
+/// recursion handled according to `options` as in
+/// [`substitute_with_options`](crate::substitute_with_options).
+pub fn substitute_cow_with_options<'t, K, V, S>(
+    template: &'t str,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> SubstResult<Cow<'t, str>>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    if !options.syntax.may_need_processing(template) {
+        return Ok(Cow::Borrowed(template));
+    }
+
+    let mut expander = Expander::new(variables, options);
+    // Only allocated once the output differs from the template
+    let mut output: Option<String> = None;
+    let mut value = String::new();
+    let mut segments = segments_with(template, &options.syntax);
+    loop {
+        let start = segments.offset();
+        let Some(segment) = segments.next() else {
+            break;
+        };
+        match segment? {
+            Segment::Text(text) => {
+                if let Some(output) = &mut output {
+                    output.push_str(text);
+                }
+            }
+            Segment::Escaped(ch) => owned(&mut output, template, start).push(ch),
+            Segment::Var {
+                name,
+                span,
+                quoted,
+                length,
+                transform,
+                operator,
+                ..
+            } => {
+                let reference = Reference {
+                    name,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                    text: &template[span.clone()],
+                };
+                if let Some(output) = &mut output {
+                    expander.var(&reference, span.start, output)?;
+                    continue;
+                }
+                value.clear();
+                expander.var(&reference, span.start, &mut value)?;
+                if value != reference.text {
+                    owned(&mut output, template, start).push_str(&value);
+                }
+            }
+        }
+    }
+    Ok(output.map_or(Cow::Borrowed(template), Cow::Owned))
+}
+
+/// The output, starting it as a copy of `template` up to `at` if the output
+/// has been the template so far
+fn owned<'o>(output: &'o mut Option<String>, template: &str, at: usize) -> &'o mut String {
+    output.get_or_insert_with(|| {
+        let mut owned = String::with_capacity(template.len());
+        owned.push_str(&template[..at]);
+        owned
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MissingAction, SyntaxConfig};
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("A", "a"), ("SELF", "${SELF}")])
+    }
+
+    #[test]
+    fn test_borrows_unchanged_templates() {
+        for template in ["", "no references", "$5 and ${MISSING} and $", "${SELF}"] {
+            let result = substitute_cow(template, &vars()).unwrap();
+            assert!(
+                matches!(result, Cow::Borrowed(t) if t == template),
+                "{}",
+                template
+            );
+        }
+        // A value that reproduces the reference verbatim counts as unchanged
+        let options = SubstOptions::new().missing(MissingAction::UseDefault("${X}".to_string()));
+        let result = substitute_cow_with_options("x ${X}", &vars(), &options).unwrap();
+        assert!(matches!(result, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_owns_changed_templates() {
+        let options = SubstOptions::new().syntax(SyntaxConfig::new().escape(true));
+        let cases = [
+            ("${A}", "a"),
+            ("ü ${MISSING} ${A}!", "ü ${MISSING} a!"),
+            (r"cost \$5 ${MISSING}", "cost $5 ${MISSING}"),
+            (r"${A}\\", r"a\"),
+        ];
+        for (template, expected) in cases {
+            let result = substitute_cow_with_options(template, &vars(), &options).unwrap();
+            assert!(matches!(&result, Cow::Owned(_)), "{}", template);
+            assert_eq!(result, expected);
+        }
+
+        let options = SubstOptions::new().missing(MissingAction::Empty);
+        let result = substitute_cow_with_options("[${MISSING}]", &vars(), &options).unwrap();
+        assert!(matches!(result, Cow::Owned(ref s) if s == "[]"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_cow_with_options("${A} ${B}", &vars(), &options);
+        assert!(matches!(
+            result,
+            Err(crate::SubstError::UndefinedVariable { position: 5, .. })
+        ));
+    }
+}
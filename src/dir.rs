@@ -0,0 +1,435 @@
+//! Substitution of directory trees, with a callback choosing what to do with
+//! each file.
+
+use crate::atomic::write_atomic;
+use crate::file::{render, FilePhase, SubstFileError};
+use crate::SubstOptions;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What [`substitute_dir`] does with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAction {
+    /// Substitute variables in the file
+    Render,
+    /// Copy the file unchanged
+    CopyVerbatim,
+    /// Leave the file out of the destination
+    Skip,
+}
+
+/// Options of [`substitute_dir`]
+///
+/// # Examples
+///
+/// ```no_run
+/// use varsubst::{substitute_dir, DirOptions, FileAction};
+/// use std::collections::HashMap;
+/// use std::path::Path;
+///
+/// let vars = HashMap::from([("PORT", "8080")]);
+/// let options = DirOptions::new()
+///     .filter(|path| match path.extension() {
+///         Some(ext) if ext == "tmpl" => FileAction::Render,
+///         _ => FileAction::CopyVerbatim,
+///     })
+///     .strip_ext("tmpl");
+/// let summary = substitute_dir(Path::new("templates"), Path::new("out"), &vars, options);
+/// assert!(summary.failed.is_empty());
+/// ```
+pub struct DirOptions<'a> {
+    /// Chooses the action for each file, given its path relative to the
+    /// source directory
+    pub filter: Box<dyn Fn(&Path) -> FileAction + 'a>,
+    /// Follow symlinks to files and directories instead of skipping them
+    pub follow_symlinks: bool,
+    /// Extension removed from the names of rendered files, without the dot
+    pub strip_ext: Option<String>,
+    /// Keep going after a file fails instead of stopping
+    pub continue_on_error: bool,
+    /// How rendered files are substituted
+    pub subst: SubstOptions,
+}
+
+impl Default for DirOptions<'_> {
+    fn default() -> Self {
+        DirOptions {
+            filter: Box::new(|_| FileAction::Render),
+            follow_symlinks: false,
+            strip_ext: None,
+            continue_on_error: false,
+            subst: SubstOptions::default(),
+        }
+    }
+}
+
+impl fmt::Debug for DirOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirOptions")
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("strip_ext", &self.strip_ext)
+            .field("continue_on_error", &self.continue_on_error)
+            .field("subst", &self.subst)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> DirOptions<'a> {
+    /// Create options that render every file and skip symlinks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose the action for each file with `filter`, which is given the
+    /// file's path relative to the source directory
+    #[must_use]
+    pub fn filter(mut self, filter: impl Fn(&Path) -> FileAction + 'a) -> Self {
+        self.filter = Box::new(filter);
+        self
+    }
+
+    /// Follow symlinks instead of skipping them
+    #[must_use]
+    pub fn follow_symlinks(mut self, enabled: bool) -> Self {
+        self.follow_symlinks = enabled;
+        self
+    }
+
+    /// Remove the extension `ext` (with or without its dot) from the names
+    /// of rendered files
+    #[must_use]
+    pub fn strip_ext(mut self, ext: impl Into<String>) -> Self {
+        let ext = ext.into();
+        self.strip_ext = Some(ext.trim_start_matches('.').to_string());
+        self
+    }
+
+    /// Keep going after a file fails instead of stopping
+    #[must_use]
+    pub fn continue_on_error(mut self, enabled: bool) -> Self {
+        self.continue_on_error = enabled;
+        self
+    }
+
+    /// Set how rendered files are substituted
+    #[must_use]
+    pub fn subst(mut self, options: SubstOptions) -> Self {
+        self.subst = options;
+        self
+    }
+}
+
+/// What [`substitute_dir`] did, with paths relative to the source directory
+#[derive(Debug, Default)]
+pub struct DirSummary {
+    /// Files substituted, by source path
+    pub rendered: Vec<PathBuf>,
+    /// Files copied unchanged
+    pub copied: Vec<PathBuf>,
+    /// Files the filter skipped, and symlinks unless they are followed
+    pub skipped: Vec<PathBuf>,
+    /// Files and directories that failed, with their errors
+    pub failed: Vec<(PathBuf, SubstFileError)>,
+}
+
+/// Render the directory tree `src` into `dst`.
+///
+/// Every file under `src` is rendered, copied or skipped as the filter of
+/// `options` decides, and written to the same relative path under `dst`,
+/// whose directories are created as needed. Outputs are written atomically
+/// and get the permissions of their source. Entries are visited in name
+/// order. Unless [`DirOptions::continue_on_error`] is set, the walk stops at
+/// the first failure.
+pub fn substitute_dir<K, V, S>(
+    src: &Path,
+    dst: &Path,
+    variables: &HashMap<K, V, S>,
+    options: DirOptions,
+) -> DirSummary
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let mut walk = Walk {
+        src,
+        dst,
+        variables,
+        options: &options,
+        summary: DirSummary::default(),
+        ancestors: Vec::new(),
+    };
+    // Errors are recorded in the summary
+    let _ = walk.dir(Path::new(""));
+    walk.summary
+}
+
+/// State of a [`substitute_dir`] run
+struct Walk<'a, K, V, S> {
+    src: &'a Path,
+    dst: &'a Path,
+    variables: &'a HashMap<K, V, S>,
+    options: &'a DirOptions<'a>,
+    summary: DirSummary,
+    /// Canonical paths of the directories being visited, so that followed
+    /// symlinks cannot loop
+    ancestors: Vec<PathBuf>,
+}
+
+/// Returned once the walk has to stop
+struct Stop;
+
+impl<K, V, S> Walk<'_, K, V, S>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    fn dir(&mut self, relative: &Path) -> Result<(), Stop> {
+        let dir = self.src.join(relative);
+        let entries = fs::read_dir(&dir).and_then(|entries| {
+            let mut entries = entries.collect::<io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            Ok((fs::canonicalize(&dir)?, entries))
+        });
+        let (canonical, entries) = match entries {
+            Ok(entries) => entries,
+            Err(e) => return self.fail(relative, io_error(dir, FilePhase::Read, e)),
+        };
+        let target = self.dst.join(relative);
+        if let Err(e) = fs::create_dir_all(&target) {
+            return self.fail(relative, io_error(target, FilePhase::Write, e));
+        }
+        self.ancestors.push(canonical);
+
+        for entry in entries {
+            let relative = relative.join(entry.file_name());
+            let path = entry.path();
+            // file_type() does not follow symlinks
+            let file_type = match entry.file_type() {
+                Ok(file_type) if file_type.is_symlink() && self.options.follow_symlinks => {
+                    fs::metadata(&path).map(|meta| meta.file_type())
+                }
+                file_type => file_type,
+            };
+            let file_type = match file_type {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    self.fail(&relative, io_error(path, FilePhase::Read, e))?;
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                let looped = fs::canonicalize(&path)
+                    .is_ok_and(|canonical| self.ancestors.contains(&canonical));
+                match looped {
+                    true => self.summary.skipped.push(relative),
+                    false => self.dir(&relative)?,
+                }
+            } else if file_type.is_file() {
+                self.file(relative, &path)?;
+            } else {
+                self.summary.skipped.push(relative);
+            }
+        }
+        self.ancestors.pop();
+        Ok(())
+    }
+
+    fn file(&mut self, relative: PathBuf, path: &Path) -> Result<(), Stop> {
+        let action = (self.options.filter)(&relative);
+        let permissions = fs::metadata(path).ok().map(|meta| meta.permissions());
+        let result = match action {
+            FileAction::Skip => {
+                self.summary.skipped.push(relative);
+                return Ok(());
+            }
+            FileAction::Render => {
+                let target = self.dst.join(self.target_name(&relative));
+                render(
+                    path,
+                    &target,
+                    self.variables,
+                    &self.options.subst,
+                    permissions,
+                )
+            }
+            FileAction::CopyVerbatim => {
+                let target = self.dst.join(&relative);
+                fs::read(path)
+                    .map_err(|e| io_error(path.to_path_buf(), FilePhase::Read, e))
+                    .and_then(|content| {
+                        write_atomic(&target, &content, permissions)
+                            .map_err(|e| io_error(target, FilePhase::Write, e))
+                    })
+            }
+        };
+        match (result, action) {
+            (Ok(()), FileAction::Render) => self.summary.rendered.push(relative),
+            (Ok(()), _) => self.summary.copied.push(relative),
+            (Err(error), _) => self.fail(&relative, error)?,
+        }
+        Ok(())
+    }
+
+    /// The output path of the rendered file `relative`
+    fn target_name(&self, relative: &Path) -> PathBuf {
+        let stripped = (self.options.strip_ext.as_deref())
+            .zip(relative.file_name().and_then(|name| name.to_str()))
+            .and_then(|(ext, name)| name.strip_suffix(ext)?.strip_suffix('.'))
+            .filter(|stem| !stem.is_empty());
+        match stripped {
+            Some(stem) => relative.with_file_name(stem),
+            None => relative.to_path_buf(),
+        }
+    }
+
+    /// Record a failure, and stop unless the walk continues on errors
+    fn fail(&mut self, relative: &Path, error: SubstFileError) -> Result<(), Stop> {
+        self.summary.failed.push((relative.to_path_buf(), error));
+        match self.options.continue_on_error {
+            true => Ok(()),
+            false => Err(Stop),
+        }
+    }
+}
+
+fn io_error(path: PathBuf, phase: FilePhase, source: io::Error) -> SubstFileError {
+    SubstFileError::Io {
+        path,
+        phase,
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("NAME", "World")])
+    }
+
+    /// A source tree and an empty destination
+    fn fixture(files: &[(&str, &str)]) -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        for (path, content) in files {
+            let path = src.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        (dir, src, dst)
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_file_actions() {
+        let (_dir, src, dst) = fixture(&[
+            ("a.conf.tmpl", "hello ${NAME}"),
+            ("sub/b.txt", "${NAME}"),
+            ("sub/deep/skip.bak", "x"),
+        ]);
+        let options = DirOptions::new()
+            .filter(|path| match path.extension().and_then(|ext| ext.to_str()) {
+                Some("tmpl") => FileAction::Render,
+                Some("bak") => FileAction::Skip,
+                _ => FileAction::CopyVerbatim,
+            })
+            .strip_ext(".tmpl");
+        let summary = substitute_dir(&src, &dst, &vars(), options);
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        assert_eq!(summary.rendered, paths(&["a.conf.tmpl"]));
+        assert_eq!(summary.copied, paths(&["sub/b.txt"]));
+        assert_eq!(summary.skipped, paths(&["sub/deep/skip.bak"]));
+
+        assert_eq!(
+            fs::read_to_string(dst.join("a.conf")).unwrap(),
+            "hello World"
+        );
+        assert_eq!(
+            fs::read_to_string(dst.join("sub/b.txt")).unwrap(),
+            "${NAME}"
+        );
+        assert!(dst.join("sub/deep").is_dir());
+        assert!(!dst.join("sub/deep/skip.bak").exists());
+    }
+
+    #[test]
+    fn test_failure_mid_tree() {
+        let files = [("a", "${NAME}"), ("b", "${OPEN"), ("c", "${NAME}")];
+        let (_dir, src, dst) = fixture(&files);
+        let summary = substitute_dir(&src, &dst, &vars(), DirOptions::new());
+        assert_eq!(summary.rendered, paths(&["a"]));
+        assert_eq!(summary.failed.len(), 1);
+        assert!(!dst.join("b").exists());
+        assert!(!dst.join("c").exists());
+
+        let options = DirOptions::new().continue_on_error(true);
+        let summary = substitute_dir(&src, &dst, &vars(), options);
+        assert_eq!(summary.rendered, paths(&["a", "c"]));
+        let (path, error) = &summary.failed[0];
+        assert_eq!(path, Path::new("b"));
+        assert_eq!(
+            error.to_string(),
+            format!("{}:1:1: unclosed brace", src.join("b").display())
+        );
+        assert!(!dst.join("b").exists());
+        assert_eq!(fs::read_to_string(dst.join("c")).unwrap(), "World");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_not_followed_by_default() {
+        let (_dir, src, dst) = fixture(&[("real/a", "${NAME}")]);
+        std::os::unix::fs::symlink(src.join("real"), src.join("linked")).unwrap();
+        std::os::unix::fs::symlink(src.join("real/a"), src.join("b")).unwrap();
+        // A link back up would loop if followed blindly
+        std::os::unix::fs::symlink(&src, src.join("real/up")).unwrap();
+
+        let summary = substitute_dir(&src, &dst, &vars(), DirOptions::new());
+        assert_eq!(summary.rendered, paths(&["real/a"]));
+        assert_eq!(summary.skipped, paths(&["b", "linked", "real/up"]));
+        assert!(!dst.join("b").exists());
+        assert!(!dst.join("linked").exists());
+
+        let options = DirOptions::new().follow_symlinks(true);
+        let summary = substitute_dir(&src, &dst, &vars(), options);
+        assert_eq!(summary.rendered, paths(&["b", "linked/a", "real/a"]));
+        assert_eq!(summary.skipped, paths(&["linked/up", "real/up"]));
+        assert_eq!(fs::read_to_string(dst.join("linked/a")).unwrap(), "World");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_keeps_mode_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, src, dst) = fixture(&[("secret", "${NAME}"), ("config", "${NAME}")]);
+        fs::set_permissions(src.join("secret"), fs::Permissions::from_mode(0o600)).unwrap();
+        let shared = dir.path().join("shared.conf");
+        fs::write(&shared, "old").unwrap();
+        fs::create_dir(&dst).unwrap();
+        std::os::unix::fs::symlink(&shared, dst.join("config")).unwrap();
+
+        let summary = substitute_dir(&src, &dst, &vars(), DirOptions::new());
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        let mode = fs::metadata(dst.join("secret"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(dst.join("secret")).unwrap(), "World");
+
+        let link = fs::symlink_metadata(dst.join("config")).unwrap();
+        assert!(link.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&shared).unwrap(), "World");
+    }
+}
@@ -0,0 +1,258 @@
+//! Substitution of whole files: read, substitute, write atomically.
+
+use crate::atomic::write_atomic;
+use crate::{substitute_with_options, Snippet, SubstError, SubstOptions};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which step of [`substitute_file`] an I/O error happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilePhase {
+    /// Reading the template
+    Read,
+    /// Writing the output
+    Write,
+}
+
+/// Error returned by [`substitute_file`] and [`substitute_file_in_place`]
+#[derive(Debug)]
+pub enum SubstFileError {
+    /// A file could not be read or written
+    Io {
+        /// The file being read or written
+        path: PathBuf,
+        /// Whether it was being read or written
+        phase: FilePhase,
+        /// The underlying error
+        source: io::Error,
+    },
+    /// The template could not be substituted
+    Subst {
+        /// The template file
+        path: PathBuf,
+        /// 1-based line of the error
+        line: usize,
+        /// 1-based column of the error, in characters
+        column: usize,
+        /// The underlying error, whose position is a byte offset in the file
+        error: SubstError,
+    },
+}
+
+impl SubstFileError {
+    /// The file the error is about
+    pub fn path(&self) -> &Path {
+        match self {
+            SubstFileError::Io { path, .. } | SubstFileError::Subst { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for SubstFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubstFileError::Io {
+                path,
+                phase,
+                source,
+            } => {
+                let action = match phase {
+                    FilePhase::Read => "reading",
+                    FilePhase::Write => "writing",
+                };
+                write!(f, "{}: error {}: {}", path.display(), action, source)
+            }
+            SubstFileError::Subst {
+                path,
+                line,
+                column,
+                error,
+            } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path.display(),
+                line,
+                column,
+                error.description()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SubstFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SubstFileError::Io { source, .. } => Some(source),
+            SubstFileError::Subst { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Substitute variables in the file `input` and write the result to `output`.
+///
+/// The output is written to a temporary file next to it and renamed into
+/// place, so it is either completely written or, on any error, not touched
+/// at all. Undefined variables and syntax are handled according to
+/// `options` as in [`substitute_with_options`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use varsubst::{substitute_file, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("PORT", "8080")]);
+/// substitute_file("config.tmpl", "config.conf", &vars, &SubstOptions::new())?;
+/// # Ok::<(), varsubst::SubstFileError>(())
+/// ```
+pub fn substitute_file<K, V, S>(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> Result<(), SubstFileError>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    render(input.as_ref(), output.as_ref(), variables, options, None)
+}
+
+/// Substitute `input` into `output`, which gets `permissions` if given and
+/// otherwise keeps those of an existing file
+pub(crate) fn render<K, V, S>(
+    input: &Path,
+    output: &Path,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+    permissions: Option<fs::Permissions>,
+) -> Result<(), SubstFileError>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let template = fs::read_to_string(input).map_err(|source| SubstFileError::Io {
+        path: input.to_path_buf(),
+        phase: FilePhase::Read,
+        source,
+    })?;
+    let result = substitute_with_options(&template, variables, options).map_err(|error| {
+        let position = error.position();
+        let snippet = Snippet::new(&template, position..position);
+        SubstFileError::Subst {
+            path: input.to_path_buf(),
+            line: snippet.line(),
+            column: snippet.column(),
+            error,
+        }
+    })?;
+    write_atomic(output, result.as_bytes(), permissions).map_err(|source| SubstFileError::Io {
+        path: output.to_path_buf(),
+        phase: FilePhase::Write,
+        source,
+    })
+}
+
+/// Substitute variables in the file at `path`, replacing its contents.
+///
+/// Like [`substitute_file`] with the same path for input and output; the
+/// file keeps its permissions and is left unchanged on error.
+pub fn substitute_file_in_place<K, V, S>(
+    path: impl AsRef<Path>,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> Result<(), SubstFileError>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let path = path.as_ref();
+    substitute_file(path, path, variables, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MissingAction;
+    use tempfile::TempDir;
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("NAME", "World")])
+    }
+
+    #[test]
+    fn test_substitute_file() {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.path().join("in.tmpl"), dir.path().join("out.txt"));
+        fs::write(&input, "Hello ${NAME}!\n").unwrap();
+        substitute_file(&input, &output, &vars(), &SubstOptions::new()).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "Hello World!\n");
+        // Only the output is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        substitute_file_in_place(&input, &vars(), &SubstOptions::new()).unwrap();
+        assert_eq!(fs::read_to_string(&input).unwrap(), "Hello World!\n");
+    }
+
+    #[test]
+    fn test_read_failure() {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.path().join("nope.tmpl"), dir.path().join("out.txt"));
+        let error = substitute_file(&input, &output, &vars(), &SubstOptions::new()).unwrap_err();
+        assert!(matches!(
+            &error,
+            SubstFileError::Io { phase: FilePhase::Read, path, .. } if *path == input
+        ));
+        assert!(error
+            .to_string()
+            .starts_with(&format!("{}: error reading: ", input.display())));
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_syntax_failure_leaves_output_uncreated() {
+        let dir = TempDir::new().unwrap();
+        let (input, output) = (dir.path().join("config.tmpl"), dir.path().join("out.txt"));
+        fs::write(&input, "a\nü ${NAME}\nkey = ${OPEN").unwrap();
+        let error = substitute_file(&input, &output, &vars(), &SubstOptions::new()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("{}:3:7: unclosed brace", input.display())
+        );
+        assert!(matches!(
+            error,
+            SubstFileError::Subst {
+                line: 3,
+                column: 7,
+                error: SubstError::UnclosedBrace { position: 19 },
+                ..
+            }
+        ));
+        assert!(!output.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_options_apply_and_in_place_error_keeps_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("t.tmpl");
+        fs::write(&path, "${NAME} ${MISSING}").unwrap();
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let error = substitute_file_in_place(&path, &vars(), &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("{}:1:9: undefined variable 'MISSING'", path.display())
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "${NAME} ${MISSING}");
+
+        let options = SubstOptions::new().missing(MissingAction::Empty);
+        substitute_file_in_place(&path, &vars(), &options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "World ");
+    }
+}
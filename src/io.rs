@@ -0,0 +1,196 @@
+//! Substitution into an `io::Write` sink.
+
+use crate::{substitute_to_with_options, SubstError, SubstOptions};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+/// Error returned by [`substitute_io`]
+#[derive(Debug)]
+pub enum SubstIoError {
+    /// The template could not be substituted
+    Subst(SubstError),
+    /// Writing the output failed
+    Io(io::Error),
+}
+
+impl fmt::Display for SubstIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubstIoError::Subst(error) => error.fmt(f),
+            SubstIoError::Io(error) => write!(f, "Error writing output: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for SubstIoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SubstIoError::Subst(error) => Some(error),
+            SubstIoError::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<SubstError> for SubstIoError {
+    fn from(error: SubstError) -> Self {
+        SubstIoError::Subst(error)
+    }
+}
+
+impl From<io::Error> for SubstIoError {
+    fn from(error: io::Error) -> Self {
+        SubstIoError::Io(error)
+    }
+}
+
+/// Substitute variables like [`substitute`](crate::substitute), writing the
+/// output to `output` as it is produced instead of building a `String`.
+///
+/// Each run of literal text and each value is passed to `output` with one
+/// `write_all` call; wrap unbuffered sinks such as a `File` or `TcpStream`
+/// in an [`io::BufWriter`]. On error, everything before the failing
+/// reference has been written.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute_io;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("PORT", "8080")]);
+/// let mut output = Vec::new();
+/// substitute_io("listen ${PORT}\n", &vars, &mut output)?;
+/// assert_eq!(output, b"listen 8080\n");
+/// # Ok::<(), varsubst::SubstIoError>(())
+/// ```
+pub fn substitute_io<K, V, S, W>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+    output: &mut W,
+) -> Result<(), SubstIoError>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+    W: io::Write + ?Sized,
+{
+    substitute_io_with_options(template, variables, &SubstOptions::default(), output)
+}
+
+/// Substitute variables with explicit options into an `io::Write` sink.
+///
+/// Behaves like [`substitute_io`], with undefined variables, syntax and
+/// recursion handled according to `options` as in
+/// [`substitute_with_options`](crate::substitute_with_options).
+pub fn substitute_io_with_options<K, V, S, W>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+    output: &mut W,
+) -> Result<(), SubstIoError>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+    W: io::Write + ?Sized,
+{
+    let mut adapter = Adapter {
+        inner: output,
+        error: None,
+    };
+    substitute_to_with_options(template, variables, options, &mut adapter).map_err(|error| {
+        match (error, adapter.error.take()) {
+            (SubstError::Write { .. }, Some(error)) => SubstIoError::Io(error),
+            (error, _) => SubstIoError::Subst(error),
+        }
+    })
+}
+
+/// An `io::Write` seen as a `fmt::Write`, keeping the error that `fmt::Error`
+/// cannot carry
+struct Adapter<'w, W: ?Sized> {
+    inner: &'w mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write + ?Sized> fmt::Write for Adapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MissingAction;
+
+    /// Accepts `capacity` bytes in `write_all` calls, then fails
+    struct Limited {
+        written: Vec<u8>,
+        writes: usize,
+        capacity: usize,
+    }
+
+    impl io::Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.capacity {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            self.written.extend_from_slice(buf);
+            self.writes += 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn limited(capacity: usize) -> Limited {
+        Limited {
+            written: Vec::new(),
+            writes: 0,
+            capacity,
+        }
+    }
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("NAME", "World"), ("PORT", "8080")])
+    }
+
+    #[test]
+    fn test_writes_runs_and_values_whole() {
+        let mut output = limited(usize::MAX);
+        substitute_io("Hello ${NAME}, port ${PORT}!", &vars(), &mut output).unwrap();
+        assert_eq!(output.written, b"Hello World, port 8080!");
+        assert_eq!(output.writes, 5);
+    }
+
+    #[test]
+    fn test_failing_writer_mid_way() {
+        let mut output = limited(10);
+        let error =
+            substitute_io("Hello ${NAME}, port ${PORT}!", &vars(), &mut output).unwrap_err();
+        assert!(matches!(&error, SubstIoError::Io(e) if e.kind() == io::ErrorKind::WriteZero));
+        assert_eq!(error.to_string(), "Error writing output: disk full");
+        // The reference that did not fit was not written at all
+        assert_eq!(output.written, b"Hello ");
+    }
+
+    #[test]
+    fn test_substitution_error_is_not_io() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let mut output = limited(usize::MAX);
+        let error = substitute_io_with_options("${NAME} ${USER}", &vars(), &options, &mut output)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SubstIoError::Subst(SubstError::UndefinedVariable { position: 8, .. })
+        ));
+        assert_eq!(output.written, b"World ");
+    }
+}
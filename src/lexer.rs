@@ -0,0 +1,288 @@
+//! Token-level view of a template, for editors and linters.
+//!
+//! Where [`Segments`] yields one item per reference, [`tokens`] splits each
+//! reference into its delimiters, name, transform and operator, so that
+//! every byte of a well-formed template belongs to exactly one token. Like
+//! the segment iterator it is lazy and does not allocate.
+
+use crate::{Segment, Segments, SubstResult, SyntaxConfig};
+use std::ops::Range;
+
+/// What a [`Token`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// Literal text, including dollar signs that do not start a reference
+    LiteralRun,
+    /// An escape sequence such as `\$`
+    Escape,
+    /// The opening delimiter of a reference, `${` by default
+    VarStart,
+    /// The `#` before the name of a `${#NAME}` length reference
+    Length,
+    /// The name of a delimited reference, with its quotes if quoted
+    VarName,
+    /// A transform such as `@Q` after the name
+    Transform,
+    /// An operator such as `:-` after the name
+    Operator,
+    /// The word after an operator, up to the closing delimiter; absent if
+    /// empty
+    Word,
+    /// The closing delimiter of a reference, `}` by default
+    VarEnd,
+    /// A whole `$NAME` reference
+    ShortVar,
+}
+
+/// A piece of a template, borrowed from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    /// What the token is
+    pub kind: TokenKind,
+    /// The text of the token, `&template[span]`
+    pub text: &'a str,
+    /// Byte range of the token in the template
+    pub span: Range<usize>,
+}
+
+/// Iterator over the [`Token`]s of a template, created by [`tokens`]
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    template: &'a str,
+    syntax: &'a SyntaxConfig,
+    segments: Segments<'a>,
+    /// The rest of the current reference: length, name, transform or
+    /// operator, word and end
+    pending: [Option<Token<'a>>; 5],
+}
+
+/// Split a template into tokens using `syntax`.
+///
+/// A malformed reference yields an `Err` at its position, and scanning
+/// resumes after it as with [`segments_with`](crate::segments_with); the
+/// bytes it covers are not part of any token.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::lexer::{tokens, TokenKind};
+/// use varsubst::SyntaxConfig;
+///
+/// let syntax = SyntaxConfig::new();
+/// let kinds: Vec<TokenKind> = tokens("Hi ${NAME}!", &syntax).map(|t| t.unwrap().kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [
+///         TokenKind::LiteralRun,
+///         TokenKind::VarStart,
+///         TokenKind::VarName,
+///         TokenKind::VarEnd,
+///         TokenKind::LiteralRun,
+///     ]
+/// );
+/// ```
+pub fn tokens<'a>(template: &'a str, syntax: &'a SyntaxConfig) -> Tokens<'a> {
+    Tokens {
+        template,
+        syntax,
+        segments: crate::segments_with(template, syntax),
+        pending: [None, None, None, None, None],
+    }
+}
+
+impl<'a> Tokens<'a> {
+    fn token(&self, kind: TokenKind, span: Range<usize>) -> Token<'a> {
+        Token {
+            kind,
+            text: &self.template[span.clone()],
+            span,
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = SubstResult<Token<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.iter_mut().find_map(Option::take) {
+            return Some(Ok(token));
+        }
+
+        let start = self.segments.offset();
+        let segment = match self.segments.next()? {
+            Ok(segment) => segment,
+            Err(error) => return Some(Err(error)),
+        };
+        let end = self.segments.offset();
+        let token = match segment {
+            Segment::Text(_) => self.token(TokenKind::LiteralRun, start..end),
+            Segment::Escaped(_) => self.token(TokenKind::Escape, start..end),
+            Segment::Var { braced: false, .. } => self.token(TokenKind::ShortVar, start..end),
+            Segment::Var {
+                name,
+                span,
+                quoted,
+                length,
+                transform,
+                operator,
+                ..
+            } => {
+                let open_end = span.start + self.syntax.open().len();
+                let name_start = open_end + usize::from(length);
+                let name_end = name_start + name.len() + 2 * usize::from(quoted);
+                let close_start = span.end - self.syntax.close().len();
+                let suffix = match (transform, operator) {
+                    (Some(_), _) => Some(self.token(TokenKind::Transform, name_end..close_start)),
+                    (_, Some(operator)) => {
+                        let word_start = name_end + operator.symbol_len();
+                        Some(self.token(TokenKind::Operator, name_end..word_start))
+                    }
+                    (None, None) => None,
+                };
+                let word = operator
+                    .filter(|operator| !operator.word.is_empty())
+                    .map(|operator| {
+                        self.token(
+                            TokenKind::Word,
+                            close_start - operator.word.len()..close_start,
+                        )
+                    });
+                self.pending = [
+                    length.then(|| self.token(TokenKind::Length, open_end..name_start)),
+                    Some(self.token(TokenKind::VarName, name_start..name_end)),
+                    suffix,
+                    word,
+                    Some(self.token(TokenKind::VarEnd, close_start..span.end)),
+                ];
+                self.token(TokenKind::VarStart, span.start..open_end)
+            }
+        };
+        Some(Ok(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SubstError;
+
+    fn kinds<'a>(
+        template: &'a str,
+        syntax: &'a SyntaxConfig,
+    ) -> Vec<SubstResult<(TokenKind, &'a str)>> {
+        tokens(template, syntax)
+            .map(|token| token.map(|t| (t.kind, t.text)))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens_reassemble_template() {
+        let syntax = SyntaxConfig::new()
+            .short_syntax(true)
+            .escape(true)
+            .quoted_names(true);
+        let corpus = [
+            "",
+            "plain text",
+            "${A}",
+            "Hello ${NAME}! $$5 ${A}${B}",
+            "$SHORT and ${LONG} and \\${ESCAPED} \\\\",
+            "ü ${NAME@Q} ${'Display Name'@U} ${\"x\\\"y\"}",
+            "trailing $",
+            "line\n${A}\nline\n",
+            "${A:-} ${B:-x y/z} ${'Q'@L} ${'R':-a\\}b} \\}",
+            "${A-x}${B+} ${'C'?m}",
+            "${#A} ${#'B C'}",
+        ];
+        for template in corpus {
+            let tokens: Vec<Token> = tokens(template, &syntax).map(Result::unwrap).collect();
+            let mut offset = 0;
+            for token in &tokens {
+                assert_eq!(token.span.start, offset, "{:?} in {:?}", token, template);
+                assert_eq!(&template[token.span.clone()], token.text);
+                offset = token.span.end;
+            }
+            assert_eq!(offset, template.len(), "{:?}", template);
+            let reassembled: String = tokens.iter().map(|t| t.text).collect();
+            assert_eq!(reassembled, template);
+        }
+    }
+
+    #[test]
+    fn test_token_kinds() {
+        use TokenKind::*;
+        let syntax = SyntaxConfig::new().short_syntax(true).escape(true);
+        assert_eq!(
+            kinds("a\\$ $B ${C@Q}", &syntax),
+            vec![
+                Ok((LiteralRun, "a")),
+                Ok((Escape, "\\$")),
+                Ok((LiteralRun, " ")),
+                Ok((ShortVar, "$B")),
+                Ok((LiteralRun, " ")),
+                Ok((VarStart, "${")),
+                Ok((VarName, "C")),
+                Ok((Transform, "@Q")),
+                Ok((VarEnd, "}")),
+            ]
+        );
+        assert_eq!(
+            kinds(r"${D:-a\}b}${E:-}", &syntax),
+            vec![
+                Ok((VarStart, "${")),
+                Ok((VarName, "D")),
+                Ok((Operator, ":-")),
+                Ok((Word, r"a\}b")),
+                Ok((VarEnd, "}")),
+                Ok((VarStart, "${")),
+                Ok((VarName, "E")),
+                Ok((Operator, ":-")),
+                Ok((VarEnd, "}")),
+            ]
+        );
+        let syntax = SyntaxConfig::new().delimiters("{{", "}}").unwrap();
+        assert_eq!(
+            kinds("{{X}}", &syntax),
+            vec![Ok((VarStart, "{{")), Ok((VarName, "X")), Ok((VarEnd, "}}"))]
+        );
+    }
+
+    #[test]
+    fn test_errors_in_stream() {
+        use TokenKind::*;
+        let syntax = SyntaxConfig::new().short_syntax(false);
+        assert_eq!(
+            kinds("a ${B.C} ${} ${D@Z} ${E", &syntax),
+            vec![
+                Ok((LiteralRun, "a ")),
+                Err(SubstError::InvalidVarName {
+                    name: "B".to_string(),
+                    position: 2,
+                }),
+                Ok((LiteralRun, ".C} ")),
+                Err(SubstError::InvalidVarName {
+                    name: String::new(),
+                    position: 9,
+                }),
+                Ok((LiteralRun, " ")),
+                Err(SubstError::UnknownTransform {
+                    directive: 'Z',
+                    position: 13,
+                }),
+                Ok((LiteralRun, " ")),
+                Err(SubstError::UnclosedBrace { position: 20 }),
+            ]
+        );
+        let syntax = SyntaxConfig::new().strict_dollar(true);
+        assert_eq!(
+            kinds("$ ${A}", &syntax),
+            vec![
+                Err(SubstError::StrayDollar { position: 0 }),
+                Ok((LiteralRun, " ")),
+                Ok((VarStart, "${")),
+                Ok((VarName, "A")),
+                Ok((VarEnd, "}")),
+            ]
+        );
+    }
+}
@@ -35,35 +35,234 @@
 //! assert_eq!(result, "Price: ${PRICE}");
 //! ```
 
+mod atomic;
+mod bytes;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+mod cow;
+mod dir;
+mod file;
+mod io;
+pub mod lexer;
+mod operator;
+mod provider;
+mod reader;
+mod report;
+mod segment;
+mod snippet;
+mod stream;
+mod suggest;
+mod syntax;
+mod template;
+mod trace;
+mod transform;
+mod writer;
+
+// Shared with the binary, not part of the public API
+#[doc(hidden)]
+pub use atomic::AtomicFile;
+pub use bytes::{substitute_bytes, substitute_bytes_with_options};
+pub use cow::{substitute_cow, substitute_cow_with_options};
+pub use dir::{substitute_dir, DirOptions, DirSummary, FileAction};
+pub use file::{substitute_file, substitute_file_in_place, FilePhase, SubstFileError};
+pub use io::{substitute_io, substitute_io_with_options, SubstIoError};
+pub use operator::{Operator, OperatorKind};
+pub use provider::VarProvider;
+pub use reader::SubstReader;
+pub use report::{substitute_with_report, SubstReport};
+pub use segment::{segments, segments_with, unquote, Segment, Segments};
+pub use snippet::Snippet;
+pub use stream::{substitute_stream, StreamError, SubstStats};
+pub use suggest::suggestions;
+pub use syntax::{DelimiterError, SyntaxConfig};
+#[cfg(feature = "serialize")]
+pub use template::TemplateDecodeError;
+pub use template::{RenderState, Template};
+pub use trace::{substitute_traced, EventOrigin, SubstEvent};
+pub use transform::Transform;
+pub use writer::SubstWriter;
+
+use report::Usage;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Hasher of the maps the crate builds for itself, whose keys are trusted
+/// names; with the `fast-hash` feature it is ahash instead of SipHash
+#[cfg(feature = "fast-hash")]
+type FastState = ahash::RandomState;
+#[cfg(not(feature = "fast-hash"))]
+type FastState = std::collections::hash_map::RandomState;
+
+/// A map built internally, never exposed in the public API
+pub(crate) type FastMap<K, V> = HashMap<K, V, FastState>;
 
 /// Error types for variable substitution
+///
+/// Positions are byte offsets into the template, which differ from
+/// character indices after non-ASCII text: the unclosed reference in
+/// `é ${X` is at position 3. Versions up to 0.0.1 reported character
+/// indices.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SubstError {
     /// Unclosed variable reference (missing closing brace)
     UnclosedBrace {
-        /// Position where the unclosed brace was detected
+        /// Byte offset of the `$` starting the unclosed reference
         position: usize,
     },
     /// Invalid variable name (empty or contains invalid characters)
     InvalidVarName {
         /// The invalid variable name
         name: String,
-        /// Position where the invalid name was detected
+        /// Byte offset of the `$` starting the invalid reference
+        position: usize,
+    },
+    /// A `$` that does not start a reference, with
+    /// [`SyntaxConfig::strict_dollar`]
+    StrayDollar {
+        /// Byte offset of the `$`
+        position: usize,
+    },
+    /// A `${NAME@X}` reference with a letter `X` that is not a [`Transform`]
+    UnknownTransform {
+        /// The letter after `@`
+        directive: char,
+        /// Byte offset of the `$` starting the reference
+        position: usize,
+    },
+    /// Variable is not defined and [`MissingAction::Error`] is in effect
+    UndefinedVariable {
+        /// The undefined variable name
+        name: String,
+        /// Byte offset of the `$` starting the reference
+        position: usize,
+        /// Defined names that `name` may be a misspelling of, best first;
+        /// see [`suggestions`]
+        suggestions: Vec<String>,
+    },
+    /// A `${NAME?message}` reference to a variable that is undefined, or a
+    /// `${NAME:?message}` one to a variable that is undefined or empty
+    RequiredVariable {
+        /// The required variable name
+        name: String,
+        /// The text after `:?`, unescaped; empty if none was given
+        message: String,
+        /// Byte offset of the `$` starting the reference
+        position: usize,
+    },
+    /// A variable's value refers back to the variable, with
+    /// [`SubstOptions::recursive`]
+    CircularReference {
+        /// The variables being expanded, outermost first, ending with the
+        /// one that repeats
+        chain: Vec<String>,
+        /// Byte offset of the template reference whose expansion looped
+        position: usize,
+    },
+    /// The writer given to [`substitute_to`] failed
+    Write {
+        /// Byte offset in the template of the text being written
+        position: usize,
+    },
+    /// Values nest references deeper than [`SubstOptions::max_depth`]
+    DepthLimitExceeded {
+        /// The depth limit in effect
+        depth: usize,
+        /// The variable whose value still contained references at the limit
+        variable: String,
+        /// Byte offset of the template reference being expanded
         position: usize,
     },
 }
 
 impl fmt::Display for SubstError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = self.description();
+        let mut chars = description.chars();
+        let first = chars.next().map(|ch| ch.to_ascii_uppercase());
+        write!(
+            f,
+            "{}{} at position {}",
+            first.unwrap_or_default(),
+            chars.as_str(),
+            self.position()
+        )?;
+        let suggestions = match self {
+            SubstError::UndefinedVariable { suggestions, .. } => &suggestions[..],
+            _ => &[],
+        };
+        let names: Vec<String> = suggestions.iter().map(|n| format!("'{}'", n)).collect();
+        match names.split_last() {
+            None => Ok(()),
+            Some((last, [])) => write!(f, " (did you mean {}?)", last),
+            Some((last, rest)) => write!(f, " (did you mean {} or {}?)", rest.join(", "), last),
+        }
+    }
+}
+
+impl SubstError {
+    /// The same error reported at `position`
+    fn at(mut self, position: usize) -> Self {
+        match &mut self {
+            SubstError::UnclosedBrace { position: p }
+            | SubstError::InvalidVarName { position: p, .. }
+            | SubstError::StrayDollar { position: p }
+            | SubstError::UnknownTransform { position: p, .. }
+            | SubstError::UndefinedVariable { position: p, .. }
+            | SubstError::RequiredVariable { position: p, .. }
+            | SubstError::CircularReference { position: p, .. }
+            | SubstError::Write { position: p }
+            | SubstError::DepthLimitExceeded { position: p, .. } => *p = position,
+        }
+        self
+    }
+
+    /// What went wrong, without the position
+    fn description(&self) -> String {
         match self {
-            SubstError::UnclosedBrace { position } => {
-                write!(f, "Unclosed brace at position {}", position)
+            SubstError::UnclosedBrace { .. } => "unclosed brace".to_string(),
+            SubstError::InvalidVarName { name, .. } => {
+                format!("invalid variable name '{}'", name)
+            }
+            SubstError::StrayDollar { .. } => "'$' does not start a reference".to_string(),
+            SubstError::UnknownTransform { directive, .. } => {
+                format!("unknown transform '@{}'", directive)
+            }
+            SubstError::UndefinedVariable { name, .. } => {
+                format!("undefined variable '{}'", name)
+            }
+            SubstError::RequiredVariable { name, message, .. } if message.is_empty() => {
+                format!("required variable '{}' is not set", name)
             }
-            SubstError::InvalidVarName { name, position } => {
-                write!(f, "Invalid variable name '{}' at position {}", name, position)
+            SubstError::RequiredVariable { name, message, .. } => {
+                format!("required variable '{}': {}", name, message)
             }
+            SubstError::CircularReference { chain, .. } => {
+                format!("circular reference {}", chain.join(" -> "))
+            }
+            SubstError::Write { .. } => "writing the output failed".to_string(),
+            SubstError::DepthLimitExceeded {
+                depth, variable, ..
+            } => format!(
+                "expanding '{}' exceeds the maximum depth of {}",
+                variable, depth
+            ),
+        }
+    }
+
+    /// Byte offset in the template where the error occurred
+    pub fn position(&self) -> usize {
+        match self {
+            SubstError::UnclosedBrace { position }
+            | SubstError::InvalidVarName { position, .. }
+            | SubstError::StrayDollar { position }
+            | SubstError::UnknownTransform { position, .. }
+            | SubstError::UndefinedVariable { position, .. }
+            | SubstError::RequiredVariable { position, .. }
+            | SubstError::CircularReference { position, .. }
+            | SubstError::Write { position }
+            | SubstError::DepthLimitExceeded { position, .. } => *position,
         }
     }
 }
@@ -73,21 +272,228 @@ impl std::error::Error for SubstError {}
 /// Result type for substitution operations
 pub type SubstResult<T> = Result<T, SubstError>;
 
-/// Parser state during variable substitution
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum State {
-    /// Normal text parsing
-    Normal,
-    /// Just encountered a backslash (escape character)
-    #[cfg(feature = "escape")]
-    Escape,
-    /// Just encountered a dollar sign
-    Dollar,
-    /// Inside ${...} collecting variable name
-    BraceVar,
-    /// After $ collecting short variable name (with short_syntax feature)
-    #[cfg(feature = "short_syntax")]
-    ShortVar,
+/// What to do when a referenced variable is not defined
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MissingAction {
+    /// Keep the original reference (`${VAR}` or `$VAR`) in the output
+    #[default]
+    Keep,
+    /// Replace the reference with an empty string (like GNU `envsubst`)
+    Empty,
+    /// Fail with [`SubstError::UndefinedVariable`]
+    Error,
+    /// Replace the reference with the given text
+    UseDefault(String),
+    /// Replace the reference with the given pattern, in which `{name}` is
+    /// replaced by the variable name and `{position}` by the byte offset of
+    /// the reference, e.g. `⟦MISSING:{name}⟧`. The result is not scanned for
+    /// references.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute_with_options, MissingAction, SubstOptions};
+    /// use std::collections::HashMap;
+    ///
+    /// let vars: HashMap<&str, &str> = HashMap::new();
+    /// let marker = MissingAction::Marker("<MISSING:{name}@{position}>".to_string());
+    /// let options = SubstOptions::new().missing(marker);
+    /// let result = substitute_with_options("host=${HOST}", &vars, &options).unwrap();
+    /// assert_eq!(result, "host=<MISSING:HOST@5>");
+    /// ```
+    Marker(String),
+}
+
+/// A callback deciding what to do with each undefined variable, set with
+/// [`SubstOptions::on_missing`]
+///
+/// Clones share the same callback. Two hooks are equal if they are clones.
+#[derive(Clone)]
+pub struct MissingHook(Arc<Mutex<MissingFn>>);
+
+type MissingFn = dyn FnMut(&str, usize) -> MissingAction + Send;
+
+impl MissingHook {
+    fn call(&self, name: &str, position: usize) -> MissingAction {
+        // A callback that panicked once is still called
+        let mut hook = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        hook(name, position)
+    }
+}
+
+impl fmt::Debug for MissingHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MissingHook(..)")
+    }
+}
+
+impl PartialEq for MissingHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MissingHook {}
+
+/// Options controlling substitution behavior
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_options, MissingAction, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars: HashMap<&str, &str> = HashMap::new();
+/// let options = SubstOptions::new().missing(MissingAction::Empty);
+/// let result = substitute_with_options("[${NAME}]", &vars, &options).unwrap();
+/// assert_eq!(result, "[]");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstOptions {
+    /// How undefined variables are handled
+    pub missing: MissingAction,
+    /// Which template syntax is recognized
+    pub syntax: SyntaxConfig,
+    /// Expand references found inside substituted values
+    pub recursive: bool,
+    /// How many levels of values may be expanded with `recursive`; 0
+    /// disables recursion
+    pub max_depth: usize,
+    /// Called for each undefined variable to decide what to do with it,
+    /// instead of applying `missing`
+    pub on_missing: Option<MissingHook>,
+    /// Count the length of values for `${#NAME}` in bytes of UTF-8 rather
+    /// than in characters
+    pub length_in_bytes: bool,
+}
+
+/// Default [`SubstOptions::max_depth`]
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+impl Default for SubstOptions {
+    fn default() -> Self {
+        SubstOptions {
+            missing: MissingAction::default(),
+            syntax: SyntaxConfig::default(),
+            recursive: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            on_missing: None,
+            length_in_bytes: false,
+        }
+    }
+}
+
+impl SubstOptions {
+    /// Create options with the default behavior
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expand references inside substituted values, with the same syntax
+    /// and undefined-variable handling as the template.
+    ///
+    /// A value that refers back to a variable being expanded fails with
+    /// [`SubstError::CircularReference`]. Errors inside a value are reported
+    /// at the position of the template reference that led to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute_with_options, SubstOptions};
+    /// use std::collections::HashMap;
+    ///
+    /// let vars = HashMap::from([("URL", "https://${HOST}"), ("HOST", "example.com")]);
+    /// let options = SubstOptions::new().recursive(true);
+    /// let result = substitute_with_options("${URL}/", &vars, &options).unwrap();
+    /// assert_eq!(result, "https://example.com/");
+    /// ```
+    #[must_use]
+    pub fn recursive(mut self, enabled: bool) -> Self {
+        self.recursive = enabled;
+        self
+    }
+
+    /// Limit recursive expansion to `depth` levels of values (the value of a
+    /// template reference is level 1); deeper references fail with
+    /// [`SubstError::DepthLimitExceeded`]. 0 disables recursion.
+    #[must_use]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Count the length that `${#NAME}` expands to in bytes of UTF-8 instead
+    /// of in characters (Unicode scalar values).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute, substitute_with_options, SubstOptions};
+    /// use std::collections::HashMap;
+    ///
+    /// let vars = HashMap::from([("CITY", "Zürich")]);
+    /// assert_eq!(substitute("${#CITY}", &vars).unwrap(), "6");
+    /// let options = SubstOptions::new().length_in_bytes(true);
+    /// assert_eq!(substitute_with_options("${#CITY}", &vars, &options).unwrap(), "7");
+    /// ```
+    #[must_use]
+    pub fn length_in_bytes(mut self, enabled: bool) -> Self {
+        self.length_in_bytes = enabled;
+        self
+    }
+
+    /// Set how undefined variables are handled
+    #[must_use]
+    pub fn missing(mut self, action: MissingAction) -> Self {
+        self.missing = action;
+        self
+    }
+
+    /// Set which template syntax is recognized
+    #[must_use]
+    pub fn syntax(mut self, syntax: SyntaxConfig) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Call `hook` with the name and byte position of each undefined
+    /// variable, and handle that reference as the [`MissingAction`] it
+    /// returns. This replaces [`SubstOptions::missing`], so the hook can
+    /// log references, prompt for values or collect warnings while deciding
+    /// per reference. Escaped references are text and never reach it.
+    ///
+    /// The hook must not substitute with these same options, which would
+    /// call it from inside itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute_with_options, MissingAction, SubstOptions};
+    /// use std::collections::HashMap;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    /// let log = Arc::clone(&warnings);
+    /// let options = SubstOptions::new().on_missing(move |name, position| {
+    ///     log.lock().unwrap().push(format!("{} at {}", name, position));
+    ///     match name {
+    ///         "PORT" => MissingAction::UseDefault("80".to_string()),
+    ///         _ => MissingAction::Keep,
+    ///     }
+    /// });
+    /// let vars = HashMap::from([("HOST", "example.com")]);
+    /// let result = substitute_with_options("${HOST}:${PORT}${PATH}", &vars, &options).unwrap();
+    /// assert_eq!(result, "example.com:80${PATH}");
+    /// assert_eq!(*warnings.lock().unwrap(), ["PORT at 8", "PATH at 15"]);
+    /// ```
+    #[must_use]
+    pub fn on_missing<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&str, usize) -> MissingAction + Send + 'static,
+    {
+        self.on_missing = Some(MissingHook(Arc::new(Mutex::new(hook))));
+        self
+    }
 }
 
 /// Substitute variables in the input string.
@@ -124,217 +530,522 @@ enum State {
 /// let result = substitute("User: ${USER}, Home: ${HOME}", &vars).unwrap();
 /// assert_eq!(result, "User: alice, Home: /home/alice");
 /// ```
-pub fn substitute<K, V>(template: &str, variables: &HashMap<K, V>) -> SubstResult<String>
+pub fn substitute<K, V, S>(template: &str, variables: &HashMap<K, V, S>) -> SubstResult<String>
 where
     K: AsRef<str> + std::hash::Hash + Eq,
     V: AsRef<str>,
+    S: std::hash::BuildHasher,
 {
-    // Fast path: if no $ signs and no escape sequences needed, return as-is
-    #[cfg(feature = "escape")]
-    let needs_processing = template.contains('$') || template.contains('\\');
+    substitute_with_options(template, variables, &SubstOptions::default())
+}
+
+/// Substitute variables in the input string with explicit options.
+///
+/// Behaves like [`substitute`], except that undefined variables are handled
+/// according to [`SubstOptions::missing`] and the template syntax follows
+/// [`SubstOptions::syntax`].
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_options, MissingAction, SubstError, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars: HashMap<&str, &str> = HashMap::new();
+/// let options = SubstOptions::new().missing(MissingAction::Error);
+/// let result = substitute_with_options("Hello ${NAME}", &vars, &options);
+/// assert!(matches!(result, Err(SubstError::UndefinedVariable { .. })));
+/// ```
+pub fn substitute_with_options<K, V, S>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> SubstResult<String>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    // Pre-allocate with template size as a reasonable starting point
+    let mut output = String::with_capacity(template.len());
+    substitute_to_with_options(template, variables, options, &mut output)?;
+    Ok(output)
+}
 
-    #[cfg(not(feature = "escape"))]
-    let needs_processing = template.contains('$');
+/// Substitute variables like [`substitute`], failing on the first undefined
+/// variable instead of keeping its reference.
+///
+/// Shorthand for [`substitute_with_options`] with [`MissingAction::Error`].
+/// Escaped references such as `\${NAME}` are text, not references, so they
+/// never fail.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_strict, SubstError};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("USER", "alice")]);
+/// assert_eq!(substitute_strict("${USER}", &vars).unwrap(), "alice");
+/// assert!(matches!(
+///     substitute_strict("${USER}@${HOST}", &vars),
+///     Err(SubstError::UndefinedVariable { position: 8, .. })
+/// ));
+/// ```
+pub fn substitute_strict<K, V, S>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+) -> SubstResult<String>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let options = SubstOptions::new().missing(MissingAction::Error);
+    substitute_with_options(template, variables, &options)
+}
 
-    if !needs_processing {
-        return Ok(template.to_string());
+/// Substitute variables like [`substitute`], writing the output to `output`
+/// instead of returning a new `String`.
+///
+/// Text and values are written as they are produced, so on error `output`
+/// holds everything before the failing reference. A failure of the writer
+/// itself is reported as [`SubstError::Write`].
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute_to;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("NAME", "World")]);
+/// let mut output = String::from("> ");
+/// substitute_to("Hello ${NAME}!", &vars, &mut output).unwrap();
+/// assert_eq!(output, "> Hello World!");
+/// ```
+pub fn substitute_to<K, V, S, W>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+    output: &mut W,
+) -> SubstResult<()>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+    W: fmt::Write + ?Sized,
+{
+    substitute_to_with_options(template, variables, &SubstOptions::default(), output)
+}
+
+/// Substitute variables with explicit options into `output`.
+///
+/// Behaves like [`substitute_to`], with undefined variables, syntax and
+/// recursion handled according to `options` as in [`substitute_with_options`].
+///
+/// # Examples
+///
+/// Rendering inside a `Display` implementation:
+///
+/// ```
+/// use varsubst::{substitute_to_with_options, MissingAction, SubstOptions};
+/// use std::collections::HashMap;
+/// use std::fmt;
+///
+/// struct Greeting<'a>(&'a HashMap<&'a str, &'a str>);
+///
+/// impl fmt::Display for Greeting<'_> {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         let options = SubstOptions::new().missing(MissingAction::Empty);
+///         substitute_to_with_options("Hi ${NAME}${X}", self.0, &options, f).map_err(|_| fmt::Error)
+///     }
+/// }
+///
+/// let vars = HashMap::from([("NAME", "Ann")]);
+/// assert_eq!(Greeting(&vars).to_string(), "Hi Ann");
+/// ```
+pub fn substitute_to_with_options<K, V, S, W>(
+    template: &str,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+    output: &mut W,
+) -> SubstResult<()>
+where
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+    W: fmt::Write + ?Sized,
+{
+    // Fast path: if no references and no escape sequences are possible, copy as-is
+    if !options.syntax.may_need_processing(template) {
+        return write(output, template, 0);
     }
 
-    // Optimization: Build a fast lookup table
-    // This converts O(k·m) variable lookups into O(m + k)
-    let lookup: HashMap<&str, &str> = variables
-        .iter()
-        .map(|(k, v)| (k.as_ref(), v.as_ref()))
-        .collect();
+    Expander::new(variables, options).expand(template, output, None)
+}
+
+/// Substitute variables from any [`VarProvider`] instead of a `HashMap`.
+///
+/// Behaves like [`substitute_with_options`]; values are requested from
+/// `provider` once per reference.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_provider, SubstOptions};
+/// use std::collections::BTreeMap;
+///
+/// let config = BTreeMap::from([("HOST".to_string(), "example.com".to_string())]);
+/// let options = SubstOptions::new();
+/// let result = substitute_with_provider("https://${HOST}/", &config, &options).unwrap();
+/// assert_eq!(result, "https://example.com/");
+///
+/// let pairs = [("A", "1"), ("B", "2")];
+/// assert_eq!(substitute_with_provider("${A}${B}", &pairs, &options).unwrap(), "12");
+/// ```
+pub fn substitute_with_provider<P>(
+    template: &str,
+    provider: &P,
+    options: &SubstOptions,
+) -> SubstResult<String>
+where
+    P: VarProvider + ?Sized,
+{
+    if !options.syntax.may_need_processing(template) {
+        return Ok(template.to_string());
+    }
 
-    // Pre-allocate with template size as a reasonable starting point
     let mut output = String::with_capacity(template.len());
-    let mut state = State::Normal;
-    let mut var_name = String::new();
-    let mut var_start_pos = 0;
-
-    let chars: Vec<char> = template.chars().collect();
-    let mut i = 0;
-
-    while i < chars.len() {
-        let ch = chars[i];
-
-        match state {
-            State::Normal => {
-                #[cfg(feature = "escape")]
-                if ch == '\\' {
-                    state = State::Escape;
-                    i += 1;
-                    continue;
-                }
+    Expander::with_provider(&provider, options).expand(template, &mut output, None)?;
 
-                if ch == '$' {
-                    state = State::Dollar;
-                    var_start_pos = i;
-                } else {
-                    output.push(ch);
-                }
-            }
+    Ok(output)
+}
 
-            #[cfg(feature = "escape")]
-            State::Escape => {
-                // Escape special characters: $, {, }
-                match ch {
-                    '$' | '{' | '}' => output.push(ch),
-                    '\\' => output.push('\\'),
-                    // For any other character after \, keep the backslash
-                    _ => {
-                        output.push('\\');
-                        output.push(ch);
-                    }
-                }
-                state = State::Normal;
-            }
+/// Where an [`Expander`] looks up values
+enum Lookup<'a> {
+    /// A caller's `HashMap`, indexed for fast lookup
+    Map(FastMap<&'a str, &'a str>),
+    /// A caller's provider
+    Provider(&'a dyn VarProvider),
+}
 
-            State::Dollar => {
-                if ch == '{' {
-                    state = State::BraceVar;
-                    var_name.clear();
-                } else if is_var_char_start(ch) {
-                    #[cfg(feature = "short_syntax")]
-                    {
-                        state = State::ShortVar;
-                        var_name.clear();
-                        var_name.push(ch);
-                    }
-                    #[cfg(not(feature = "short_syntax"))]
-                    {
-                        // Without short_syntax feature, $ followed by non-{ is literal
-                        output.push('$');
-                        output.push(ch);
-                        state = State::Normal;
-                    }
-                } else {
-                    // Dollar sign followed by something else, treat as literal
-                    output.push('$');
-                    output.push(ch);
-                    state = State::Normal;
-                }
-            }
+impl<'a> Lookup<'a> {
+    fn get(&self, name: &str) -> Option<Cow<'a, str>> {
+        match *self {
+            Lookup::Map(ref map) => map.get(name).map(|&value| Cow::Borrowed(value)),
+            Lookup::Provider(provider) => provider.get(name),
+        }
+    }
 
-            State::BraceVar => {
-                if ch == '}' {
-                    // End of variable reference
-                    if var_name.is_empty() {
-                        return Err(SubstError::InvalidVarName {
-                            name: String::new(),
-                            position: var_start_pos,
-                        });
-                    }
-
-                    // Look up and substitute the variable (O(1) with lookup table)
-                    if let Some(&value) = lookup.get(var_name.as_str()) {
-                        output.push_str(value);
-                    } else {
-                        // Variable not found, keep original syntax
-                        output.push_str("${");
-                        output.push_str(&var_name);
-                        output.push('}');
-                    }
-
-                    var_name.clear();
-                    state = State::Normal;
-                } else if is_var_char(ch) {
-                    var_name.push(ch);
-                } else {
-                    // Invalid character in variable name
-                    return Err(SubstError::InvalidVarName {
-                        name: var_name.clone(),
-                        position: var_start_pos,
-                    });
-                }
-            }
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Lookup::Map(map) => map.keys().copied().collect(),
+            Lookup::Provider(provider) => provider.names(),
+        }
+    }
+}
 
-            #[cfg(feature = "short_syntax")]
-            State::ShortVar => {
-                if is_var_char(ch) {
-                    var_name.push(ch);
-                } else {
-                    // End of short variable name (O(1) with lookup table)
-                    if let Some(&value) = lookup.get(var_name.as_str()) {
-                        output.push_str(value);
-                    } else {
-                        // Variable not found, keep original syntax
-                        output.push('$');
-                        output.push_str(&var_name);
-                    }
-
-                    var_name.clear();
-                    state = State::Normal;
-
-                    // Process current character in Normal state
-                    #[cfg(feature = "escape")]
-                    if ch == '\\' {
-                        state = State::Escape;
-                        i += 1;
-                        continue;
-                    }
-
-                    if ch == '$' {
-                        state = State::Dollar;
-                        var_start_pos = i;
-                    } else {
-                        output.push(ch);
-                    }
-                }
-            }
+/// A reference as [`Expander::var`] substitutes it
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reference<'r> {
+    /// The name as written, between the quotes if `quoted`
+    pub(crate) name: &'r str,
+    pub(crate) quoted: bool,
+    pub(crate) length: bool,
+    pub(crate) transform: Option<Transform>,
+    pub(crate) operator: Option<Operator<'r>>,
+    /// The whole reference as written
+    pub(crate) text: &'r str,
+}
+
+impl Reference<'_> {
+    /// The name to look up: unquoted if it was quoted
+    pub(crate) fn key(&self) -> Cow<'_, str> {
+        match self.quoted {
+            true => unquote(self.name),
+            false => Cow::Borrowed(self.name),
         }
+    }
+}
 
-        i += 1;
+/// Substitution state shared by a template and, when recursive, the values
+/// expanded inside it
+pub(crate) struct Expander<'a> {
+    lookup: Lookup<'a>,
+    options: &'a SubstOptions,
+    /// Variables whose values are being expanded, outermost first
+    chain: Vec<String>,
+    /// Values assigned by `${NAME:=word}`, in assignment order, which take
+    /// precedence over `lookup`
+    assigned: Vec<(String, String)>,
+    /// The names referenced so far, when a report asks for them
+    usage: Option<Usage>,
+}
+
+impl<'a> Expander<'a> {
+    pub(crate) fn new<K, V, S>(variables: &'a HashMap<K, V, S>, options: &'a SubstOptions) -> Self
+    where
+        K: AsRef<str> + std::hash::Hash + Eq,
+        V: AsRef<str>,
+        S: std::hash::BuildHasher,
+    {
+        // Optimization: Build a fast lookup table
+        // This converts O(k·m) variable lookups into O(m + k)
+        let lookup = variables
+            .iter()
+            .map(|(k, v)| (k.as_ref(), v.as_ref()))
+            .collect();
+        Expander {
+            lookup: Lookup::Map(lookup),
+            options,
+            chain: Vec::new(),
+            assigned: Vec::new(),
+            usage: None,
+        }
     }
 
-    // Handle end of string
-    match state {
-        State::Normal => {}
+    pub(crate) fn with_provider(provider: &'a dyn VarProvider, options: &'a SubstOptions) -> Self {
+        Expander {
+            lookup: Lookup::Provider(provider),
+            options,
+            chain: Vec::new(),
+            assigned: Vec::new(),
+            usage: None,
+        }
+    }
 
-        #[cfg(feature = "escape")]
-        State::Escape => {
-            // Trailing backslash, keep it
-            output.push('\\');
+    /// The value of `name`, as assigned or from the lookup
+    fn get(&self, name: &str) -> Option<Cow<'a, str>> {
+        match self.assigned.iter().find(|(assigned, _)| assigned == name) {
+            Some((_, value)) => Some(Cow::Owned(value.clone())),
+            None => self.lookup.get(name),
         }
+    }
 
-        State::Dollar => {
-            // Trailing dollar sign
-            output.push('$');
+    /// Assign `value` to `name` for the rest of the substitution
+    fn assign(&mut self, name: &str, value: &str) {
+        match self
+            .assigned
+            .iter_mut()
+            .find(|(assigned, _)| assigned == name)
+        {
+            Some((_, assigned)) => *assigned = value.to_string(),
+            None => self.assigned.push((name.to_string(), value.to_string())),
         }
+    }
 
-        State::BraceVar => {
-            // Unclosed brace
-            return Err(SubstError::UnclosedBrace {
-                position: var_start_pos,
-            });
+    /// Substitute `text` into `output`. `origin` is the position of the
+    /// template reference when `text` is a variable's value.
+    pub(crate) fn expand<W: fmt::Write + ?Sized>(
+        &mut self,
+        text: &str,
+        output: &mut W,
+        origin: Option<usize>,
+    ) -> SubstResult<()> {
+        let options = self.options;
+        let mut segments = segments_with(text, &options.syntax);
+        loop {
+            let position = origin.unwrap_or(segments.offset());
+            let Some(segment) = segments.next() else {
+                return Ok(());
+            };
+            let segment = segment.map_err(|e| match origin {
+                Some(position) => e.at(position),
+                None => e,
+            })?;
+            match segment {
+                Segment::Text(text) => write(output, text, position)?,
+                Segment::Escaped(ch) => write(output, ch.encode_utf8(&mut [0; 4]), position)?,
+                Segment::Var {
+                    name,
+                    span,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                    ..
+                } => {
+                    let position = origin.unwrap_or(span.start);
+                    let reference = Reference {
+                        name,
+                        quoted,
+                        length,
+                        transform,
+                        operator,
+                        text: &text[span],
+                    };
+                    self.var(&reference, position, output)?;
+                }
+            }
         }
+    }
 
-        #[cfg(feature = "short_syntax")]
-        State::ShortVar => {
-            // End of string in short var, substitute if found (O(1) with lookup table)
-            if let Some(&value) = lookup.get(var_name.as_str()) {
-                output.push_str(value);
-            } else {
-                output.push('$');
-                output.push_str(&var_name);
+    /// Substitute `reference`, reported at `position`, into `output`,
+    /// returning where the output came from
+    pub(crate) fn var<W: fmt::Write + ?Sized>(
+        &mut self,
+        reference: &Reference<'_>,
+        position: usize,
+        output: &mut W,
+    ) -> SubstResult<EventOrigin> {
+        let resolved = |()| EventOrigin::Resolved;
+        let default = |()| EventOrigin::Default;
+        let options = self.options;
+        let name = reference.name;
+        // The chain is only non-empty inside values
+        if self.chain.len() > options.max_depth {
+            return Err(SubstError::DepthLimitExceeded {
+                depth: options.max_depth,
+                variable: self.chain.last().map_or(name, String::as_str).to_string(),
+                position,
+            });
+        }
+        let key = reference.key();
+        // Look up and substitute the variable (O(1) with lookup table)
+        let value = self.get(&key);
+        if let Some(usage) = &mut self.usage {
+            usage.record(&key, value.is_none());
+        }
+        if let Some(operator) = reference.operator {
+            let set = operator.is_set(value.as_deref());
+            match (operator.kind, value) {
+                (OperatorKind::Alternate, _) if set => {
+                    let word = operator.unescaped_word(&options.syntax);
+                    return write(output, &word, position).map(default);
+                }
+                (OperatorKind::Alternate, _) => return Ok(EventOrigin::Default),
+                (_, Some(value)) if set => {
+                    return self.value(name, &value, position, output).map(resolved)
+                }
+                (OperatorKind::Default, _) => {
+                    let word = operator.unescaped_word(&options.syntax);
+                    return write(output, &word, position).map(default);
+                }
+                (OperatorKind::Assign, _) => {
+                    let word = operator.unescaped_word(&options.syntax);
+                    self.assign(&key, &word);
+                    return write(output, &word, position).map(default);
+                }
+                (OperatorKind::Required, _) => {
+                    return Err(SubstError::RequiredVariable {
+                        name: key.into_owned(),
+                        message: operator.unescaped_word(&options.syntax).into_owned(),
+                        position,
+                    });
+                }
+            }
+        }
+        let Some(value) = value else {
+            let text = reference.text;
+            return handle_missing(output, &key, text, position, options, &self.lookup);
+        };
+        if reference.length {
+            let mut expanded = String::new();
+            self.value(name, &value, position, &mut expanded)?;
+            let length = match options.length_in_bytes {
+                true => expanded.len(),
+                false => expanded.chars().count(),
+            };
+            return write(output, &length.to_string(), position).map(resolved);
+        }
+        match reference.transform {
+            None => self.value(name, &value, position, output).map(resolved),
+            Some(transform) => {
+                let mut expanded = String::new();
+                self.value(name, &value, position, &mut expanded)?;
+                let mut transformed = String::with_capacity(expanded.len());
+                transform.apply(&expanded, &mut transformed);
+                write(output, &transformed, position).map(resolved)
             }
         }
     }
 
-    Ok(output)
+    /// Substitute the value of `name` into `output`, expanding it if
+    /// recursive
+    fn value<W: fmt::Write + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &str,
+        position: usize,
+        output: &mut W,
+    ) -> SubstResult<()> {
+        let options = self.options;
+        if !options.recursive || options.max_depth == 0 {
+            return write(output, value, position);
+        }
+        if self.chain.iter().any(|outer| outer == name) {
+            let mut chain = self.chain.clone();
+            chain.push(name.to_string());
+            return Err(SubstError::CircularReference { chain, position });
+        }
+        self.chain.push(name.to_string());
+        self.expand(value, output, Some(position))?;
+        self.chain.pop();
+        Ok(())
+    }
 }
 
-/// Check if a character can start a variable name
-#[inline]
-fn is_var_char_start(ch: char) -> bool {
-    ch.is_ascii_alphabetic() || ch == '_'
+/// Write `text` to `output`, reporting a failure at `position`
+fn write<W: fmt::Write + ?Sized>(output: &mut W, text: &str, position: usize) -> SubstResult<()> {
+    output
+        .write_str(text)
+        .map_err(|_| SubstError::Write { position })
 }
 
-/// Check if a character can be part of a variable name
-#[inline]
-fn is_var_char(ch: char) -> bool {
-    ch.is_ascii_alphanumeric() || ch == '_'
+/// Apply the configured [`MissingAction`], or the one the
+/// [`SubstOptions::on_missing`] hook returns, for an undefined variable,
+/// returning whether the reference was kept or replaced
+fn handle_missing<W: fmt::Write + ?Sized>(
+    output: &mut W,
+    name: &str,
+    reference: &str,
+    position: usize,
+    options: &SubstOptions,
+    known: &Lookup<'_>,
+) -> SubstResult<EventOrigin> {
+    let action = match &options.on_missing {
+        Some(hook) => Cow::Owned(hook.call(name, position)),
+        None => Cow::Borrowed(&options.missing),
+    };
+    let replaced = match &*action {
+        // Keep original syntax
+        MissingAction::Keep => {
+            return write(output, reference, position).map(|()| EventOrigin::Kept)
+        }
+        MissingAction::Empty => Ok(()),
+        MissingAction::Error => Err(SubstError::UndefinedVariable {
+            name: name.to_string(),
+            position,
+            suggestions: suggestions(name, known.names()),
+        }),
+        MissingAction::UseDefault(text) => write(output, text, position),
+        MissingAction::Marker(pattern) => push_marker(output, pattern, name, position),
+    };
+    replaced.map(|()| EventOrigin::Default)
+}
+
+/// Expand a [`MissingAction::Marker`] pattern into `output`
+fn push_marker<W: fmt::Write + ?Sized>(
+    output: &mut W,
+    pattern: &str,
+    name: &str,
+    position: usize,
+) -> SubstResult<()> {
+    let mut rest = pattern;
+    while let Some(i) = rest.find('{') {
+        write(output, &rest[..i], position)?;
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("{name}") {
+            write(output, name, position)?;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{position}") {
+            write(output, &position.to_string(), position)?;
+            rest = after;
+        } else {
+            write(output, "{", position)?;
+            rest = &rest[1..];
+        }
+    }
+    write(output, rest, position)
 }
 
 /// Substitute variables from environment variables.
@@ -352,7 +1063,7 @@ fn is_var_char(ch: char) -> bool {
 /// assert_eq!(result, "Value: test");
 /// ```
 pub fn substitute_from_env(template: &str) -> SubstResult<String> {
-    let env_vars: HashMap<String, String> = std::env::vars().collect();
+    let env_vars: FastMap<String, String> = std::env::vars().collect();
     substitute(template, &env_vars)
 }
 
@@ -417,7 +1128,13 @@ mod tests {
     fn test_unclosed_brace() {
         let vars: HashMap<&str, &str> = HashMap::new();
         let result = substitute("Hello ${NAME", &vars);
-        assert!(matches!(result, Err(SubstError::UnclosedBrace { position: 6 })));
+        assert!(matches!(
+            result,
+            Err(SubstError::UnclosedBrace { position: 6 })
+        ));
+        // Positions are byte offsets, not character indices
+        let result = substitute("é ${X", &vars);
+        assert_eq!(result, Err(SubstError::UnclosedBrace { position: 3 }));
     }
 
     #[test]
@@ -430,7 +1147,7 @@ mod tests {
     #[test]
     fn test_invalid_var_name() {
         let vars: HashMap<&str, &str> = HashMap::new();
-        let result = substitute("${NA-ME}", &vars);
+        let result = substitute("${NA.ME}", &vars);
         assert!(matches!(result, Err(SubstError::InvalidVarName { .. })));
     }
 
@@ -550,6 +1267,523 @@ mod tests {
         assert_eq!(result, "$VAR value");
     }
 
+    #[test]
+    fn test_missing_keep() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().missing(MissingAction::Keep);
+        let result = substitute_with_options("a ${X} b", &vars, &options).unwrap();
+        assert_eq!(result, "a ${X} b");
+    }
+
+    #[test]
+    fn test_missing_empty() {
+        let vars = make_vars(&[("A", "1")]);
+        let options = SubstOptions::new().missing(MissingAction::Empty);
+        let result = substitute_with_options("${A}-${X}-", &vars, &options).unwrap();
+        assert_eq!(result, "1--");
+    }
+
+    #[test]
+    fn test_missing_error() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_with_options("ab ${X}", &vars, &options);
+        assert_eq!(
+            result,
+            Err(SubstError::UndefinedVariable {
+                name: "X".to_string(),
+                position: 3,
+                suggestions: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_missing_error_suggestions() {
+        let vars = make_vars(&[("DB_HOST", "h"), ("db_port", "1"), ("USER", "u")]);
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let error = substitute_with_options("${DB_HSOT}", &vars, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Undefined variable 'DB_HSOT' at position 0 (did you mean 'DB_HOST'?)"
+        );
+        let error = substitute_with_options("${DB_PORT}", &vars, &options).unwrap_err();
+        assert!(matches!(
+            error,
+            SubstError::UndefinedVariable { suggestions, .. } if suggestions == ["db_port", "DB_HOST"]
+        ));
+        let error = substitute_with_options("${HOME}", &vars, &options).unwrap_err();
+        assert_eq!(error.to_string(), "Undefined variable 'HOME' at position 0");
+    }
+
+    #[test]
+    fn test_quoted_names() {
+        let vars = make_vars(&[("build:number", "42"), ("it's", "yes"), ("A", "a")]);
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        let options = SubstOptions::new().syntax(syntax);
+        let template = r#"${"build:number"} ${'it\'s'} ${"A"} ${'no such key'} ${"x\"y"}"#;
+        let result = substitute_with_options(template, &vars, &options).unwrap();
+        assert_eq!(result, r#"42 yes a ${'no such key'} ${"x\"y"}"#);
+
+        let options = options.missing(MissingAction::Error);
+        let result = substitute_with_options(r"${'a\'b'}", &vars, &options);
+        assert!(matches!(
+            result,
+            Err(SubstError::UndefinedVariable { name, .. }) if name == "a'b"
+        ));
+    }
+
+    #[test]
+    fn test_transforms() {
+        let vars = make_vars(&[
+            ("FILE", "it's a file"),
+            ("TEXT", "two\nlines"),
+            ("EMPTY", ""),
+            ("NAME", "Straße"),
+            ("NESTED", "${NAME}'s"),
+        ]);
+        let result = substitute("${FILE@Q} ${TEXT@Q} ${EMPTY@Q} ${NAME@U}", &vars).unwrap();
+        assert_eq!(result, "'it'\\''s a file' 'two\nlines' '' STRASSE");
+
+        // Undefined variables follow the policy, and kept ones keep the transform
+        let result = substitute("${NOPE@Q}", &vars).unwrap();
+        assert_eq!(result, "${NOPE@Q}");
+        let options = SubstOptions::new().missing(MissingAction::UseDefault("a b".to_string()));
+        let result = substitute_with_options("${NOPE@Q}", &vars, &options).unwrap();
+        assert_eq!(result, "a b");
+
+        // The transform applies to the whole expanded value
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("${NESTED@Q}", &vars, &options).unwrap();
+        assert_eq!(result, "'Straße'\\''s'");
+
+        let result = substitute("x ${FILE@q}", &vars);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown transform '@q' at position 2"
+        );
+    }
+
+    #[test]
+    fn test_default_operator() {
+        let vars = make_vars(&[("HOST", "example.com"), ("EMPTY", ""), ("DIR", "/srv")]);
+        let result = substitute(
+            "${HOST:-localhost} ${EMPTY:-empty} ${NOPE:-/usr/local bin} ${NOPE:-}|",
+            &vars,
+        )
+        .unwrap();
+        assert_eq!(result, "example.com empty /usr/local bin |");
+
+        // The word is literal, up to the first unescaped closing brace
+        let result = substitute(r"${NOPE:-${DIR}/x} ${NOPE:-{a\}b\\}", &vars).unwrap();
+        assert_eq!(result, r"${DIR/x} {a}b\");
+        // Braces in the word do not pair up
+        let result = substitute("${NOPE:-a{b}c}", &vars).unwrap();
+        assert_eq!(result, "a{bc}");
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("${NOPE:-$${DIR\\}}", &vars, &options).unwrap();
+        assert_eq!(result, "$${DIR}");
+
+        // A default means the variable is never missing
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_with_options("${NOPE:-x}", &vars, &options).unwrap();
+        assert_eq!(result, "x");
+        let result = substitute_strict("${EMPTY:-x}${HOST:-x}", &vars).unwrap();
+        assert_eq!(result, "xexample.com");
+    }
+
+    #[test]
+    fn test_required_operator() {
+        let vars = make_vars(&[("HOST", "example.com"), ("EMPTY", "")]);
+        let result = substitute("${HOST:?host is required}", &vars).unwrap();
+        assert_eq!(result, "example.com");
+
+        let result = substitute(r"db: ${EMPTY:?a {password\} is required: see /docs}", &vars);
+        assert_eq!(
+            result,
+            Err(SubstError::RequiredVariable {
+                name: "EMPTY".to_string(),
+                message: "a {password} is required: see /docs".to_string(),
+                position: 4,
+            })
+        );
+        let options = SubstOptions::new().missing(MissingAction::Empty);
+        let error = substitute_with_options("${NOPE:?}", &vars, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Required variable 'NOPE' is not set at position 0"
+        );
+        let error = substitute("${NOPE:?set NOPE}", &vars).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Required variable 'NOPE': set NOPE at position 0"
+        );
+    }
+
+    #[test]
+    fn test_alternate_operator() {
+        let vars = make_vars(&[("TLS", "1"), ("EMPTY", ""), ("DIR", "/srv")]);
+        let result = substitute(
+            "serve ${TLS:+--tls-enabled --cert=/etc/cert}${EMPTY:+--x}${NOPE:+--y} .",
+            &vars,
+        )
+        .unwrap();
+        assert_eq!(result, "serve --tls-enabled --cert=/etc/cert .");
+
+        // An empty alternate is empty output, and the rest of the word is
+        // literal even if it looks like another operator
+        let result = substitute("[${TLS:+}] [${TLS:+a:-b}] [${TLS:+${DIR}]", &vars).unwrap();
+        assert_eq!(result, "[] [a:-b] [${DIR]");
+
+        // Never the value, so never missing either
+        let options = SubstOptions::new()
+            .recursive(true)
+            .missing(MissingAction::Error);
+        let result = substitute_with_options("${NOPE:+x}${DIR:+y}", &vars, &options).unwrap();
+        assert_eq!(result, "y");
+    }
+
+    #[test]
+    fn test_length() {
+        let vars = make_vars(&[
+            ("NAME", "Zürich"),
+            ("EMPTY", ""),
+            ("EMOJI", "👍🏽"),
+            ("NESTED", "${NAME}!"),
+        ]);
+        let result = substitute("${#NAME} ${#EMPTY} ${#EMOJI} ${#NESTED}", &vars).unwrap();
+        assert_eq!(result, "6 0 2 8");
+        let options = SubstOptions::new().length_in_bytes(true);
+        let result = substitute_with_options("${#NAME} ${#EMOJI}", &vars, &options).unwrap();
+        assert_eq!(result, "7 8");
+        // The length of the expanded value when recursive
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("${#NESTED}", &vars, &options).unwrap();
+        assert_eq!(result, "7");
+
+        // Undefined variables follow the missing-variable policy, whose
+        // output is inserted as is rather than measured
+        let result = substitute("[${#NOPE}]", &vars).unwrap();
+        assert_eq!(result, "[${#NOPE}]");
+        let options = SubstOptions::new().missing(MissingAction::UseDefault("n/a".to_string()));
+        let result = substitute_with_options("[${#NOPE}]", &vars, &options).unwrap();
+        assert_eq!(result, "[n/a]");
+        let error = substitute_strict("${#NOPE}", &vars).unwrap_err();
+        assert!(matches!(error, SubstError::UndefinedVariable { ref name, .. } if name == "NOPE"));
+
+        let error = substitute("${#}", &vars).unwrap_err();
+        assert_eq!(
+            error,
+            SubstError::InvalidVarName {
+                name: String::new(),
+                position: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_operators_unset_empty_and_set() {
+        let vars = make_vars(&[("EMPTY", ""), ("SET", "v")]);
+        // Output for an undefined, an empty and a non-empty variable, with
+        // `!` for a RequiredVariable error
+        let table = [
+            (":-", ["w", "w", "v"]),
+            ("-", ["w", "", "v"]),
+            (":=", ["w", "w", "v"]),
+            ("=", ["w", "", "v"]),
+            (":?", ["!", "!", "v"]),
+            ("?", ["!", "", "v"]),
+            (":+", ["", "", "w"]),
+            ("+", ["", "w", "w"]),
+        ];
+        for (operator, expected) in table {
+            for (name, expected) in ["NOPE", "EMPTY", "SET"].into_iter().zip(expected) {
+                let template = format!("${{{}{}w}}", name, operator);
+                let result = match substitute_strict(&template, &vars) {
+                    Err(SubstError::RequiredVariable { .. }) => "!".to_string(),
+                    result => result.unwrap(),
+                };
+                assert_eq!(result, expected, "{}", template);
+            }
+        }
+
+        // What `=` assigns is seen by the references after it
+        let mut vars = vars;
+        vars.remove("SET");
+        let template = "${EMPTY=w}[${EMPTY}] ${SET=w}[${SET}] ${EMPTY:=x}[${EMPTY}]";
+        let result = substitute(template, &vars).unwrap();
+        assert_eq!(result, "[] w[w] x[x]");
+    }
+
+    #[test]
+    fn test_assign_operator() {
+        let vars = make_vars(&[("HOST", "example.com"), ("EMPTY", ""), ("DIR", "/srv")]);
+        // Before the assignment a reference is undefined, after it is not
+        let result = substitute("${PORT}|${PORT:=8080}|${PORT}|${PORT:-x}", &vars).unwrap();
+        assert_eq!(result, "${PORT}|8080|8080|8080");
+        let result = substitute_strict("${EMPTY:=e} ${EMPTY}", &vars).unwrap();
+        assert_eq!(result, "e e");
+
+        // Like `:-` for defined variables
+        let template = "${HOST:=x} ${DIR:=/tmp} ${HOST}";
+        let default = template.replace(":=", ":-");
+        assert_eq!(
+            substitute(template, &vars).unwrap(),
+            substitute(&default, &vars).unwrap()
+        );
+
+        // Assigned values are values, so they are expanded when recursive
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options(r"${U:=${DIR\}/x} ${U}", &vars, &options).unwrap();
+        assert_eq!(result, "${DIR}/x /srv/x");
+    }
+
+    #[test]
+    fn test_provider_with_owned_values() {
+        // Computes every value, so nothing outlives a single lookup
+        struct Computed;
+        impl VarProvider for Computed {
+            fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+                match name {
+                    "URL" => Some(Cow::Owned("https://${HOST}".to_string())),
+                    "HOST" => Some(Cow::Owned("example.com".to_string())),
+                    "LOOP" => Some(Cow::Owned("${LOOP}".to_string())),
+                    _ => None,
+                }
+            }
+
+            fn names(&self) -> Vec<&str> {
+                vec!["URL", "HOST", "LOOP"]
+            }
+        }
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_provider("${URL}/", &Computed, &options).unwrap();
+        assert_eq!(result, "https://example.com/");
+        assert!(matches!(
+            substitute_with_provider("${LOOP}", &Computed, &options),
+            Err(SubstError::CircularReference { .. })
+        ));
+
+        let options = options.missing(MissingAction::Error);
+        let result = substitute_with_provider("${HSOT}", &Computed, &options);
+        assert!(matches!(
+            result,
+            Err(SubstError::UndefinedVariable { suggestions, .. }) if suggestions == ["HOST"]
+        ));
+    }
+
+    #[test]
+    fn test_substitute_to() {
+        let vars = make_vars(&[("A", "alpha"), ("B", "${A}")]);
+        let mut output = String::from("start:");
+        substitute_to("${A} ${C}", &vars, &mut output).unwrap();
+        assert_eq!(output, "start:alpha ${C}");
+        #[cfg(feature = "escape")]
+        {
+            substitute_to(" \\$", &vars, &mut output).unwrap();
+            assert_eq!(output, "start:alpha ${C} $");
+        }
+
+        // Output before the failing reference is kept
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let mut output = String::new();
+        let result = substitute_to_with_options("${A}, ${C}", &vars, &options, &mut output);
+        assert!(matches!(result, Err(SubstError::UndefinedVariable { .. })));
+        assert_eq!(output, "alpha, ");
+    }
+
+    #[test]
+    fn test_substitute_to_failing_writer() {
+        /// Accepts `capacity` bytes, then fails
+        struct Limited {
+            written: String,
+            capacity: usize,
+        }
+        impl fmt::Write for Limited {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                if self.written.len() + s.len() > self.capacity {
+                    return Err(fmt::Error);
+                }
+                self.written.push_str(s);
+                Ok(())
+            }
+        }
+        let vars = make_vars(&[("A", "alpha"), ("B", "${A}")]);
+        let options = SubstOptions::new().recursive(true);
+        for (capacity, position, written) in [(0, 0, ""), (5, 5, "text "), (13, 10, "text alpha ")]
+        {
+            let mut output = Limited {
+                written: String::new(),
+                capacity,
+            };
+            let result = substitute_to_with_options("text ${A} ${B}", &vars, &options, &mut output);
+            assert_eq!(result, Err(SubstError::Write { position }), "{}", capacity);
+            assert_eq!(output.written, written);
+        }
+        let mut output = Limited {
+            written: String::new(),
+            capacity: 0,
+        };
+        let result = substitute_to("plain", &vars, &mut output);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Writing the output failed at position 0"
+        );
+    }
+
+    #[test]
+    fn test_missing_use_default() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().missing(MissingAction::UseDefault("?".to_string()));
+        let result = substitute_with_options("${X}${Y}", &vars, &options).unwrap();
+        assert_eq!(result, "??");
+    }
+
+    #[test]
+    fn test_missing_marker() {
+        let vars = make_vars(&[("A", "${B}")]);
+        let marker = MissingAction::Marker("⟦MISSING:{name}⟧{x}{".to_string());
+        let options = SubstOptions::new().missing(marker);
+        let result = substitute_with_options("${A} ü${X}", &vars, &options).unwrap();
+        assert_eq!(result, "${B} ü⟦MISSING:X⟧{x}{");
+
+        // The marker is not scanned, even when values are
+        let marker = MissingAction::Marker("${{name}}@{position}".to_string());
+        let options = SubstOptions::new().missing(marker).recursive(true);
+        let result = substitute_with_options("-${A}", &vars, &options).unwrap();
+        assert_eq!(result, "-${B}@1");
+
+        let syntax = SyntaxConfig::new().short_syntax(true).escape(true);
+        let marker = MissingAction::Marker("<{name}>".to_string());
+        let options = SubstOptions::new().missing(marker).syntax(syntax);
+        let result = substitute_with_options(r"$X \$Y \${Z} ${W}", &vars, &options).unwrap();
+        assert_eq!(result, "<X> $Y ${Z} <W>");
+    }
+
+    #[cfg(feature = "short_syntax")]
+    #[test]
+    fn test_missing_short_syntax() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().missing(MissingAction::Empty);
+        let result = substitute_with_options("[$X] [$Y", &vars, &options).unwrap();
+        assert_eq!(result, "[] [");
+
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_with_options("$X", &vars, &options);
+        assert!(matches!(result, Err(SubstError::UndefinedVariable { .. })));
+    }
+
+    #[test]
+    fn test_missing_at_start_middle_end() {
+        let vars = make_vars(&[("A", "1")]);
+        let syntax = SyntaxConfig::new().short_syntax(true);
+        for template in ["${X} ${A} ${Y} ${A} ${Z}", "$X $A $Y $A $Z"] {
+            let kept = template.replace("${A}", "1").replace("$A", "1");
+            let cases = [
+                (MissingAction::Keep, kept.as_str()),
+                (MissingAction::Empty, " 1  1 "),
+                (MissingAction::UseDefault("-".to_string()), "- 1 - 1 -"),
+            ];
+            for (missing, expected) in cases {
+                let options = SubstOptions::new().syntax(syntax.clone()).missing(missing);
+                let result = substitute_with_options(template, &vars, &options).unwrap();
+                assert_eq!(result, expected, "{}", template);
+            }
+        }
+
+        let options = SubstOptions::new()
+            .syntax(syntax)
+            .missing(MissingAction::Error);
+        let cases = [
+            ("${X} ${A}", "X", 0),
+            ("${A} ${Y} ${A}", "Y", 5),
+            ("${A} ${Z}", "Z", 5),
+            ("$X $A", "X", 0),
+            ("$A $Y $A", "Y", 3),
+            ("$A $Z", "Z", 3),
+        ];
+        for (template, name, position) in cases {
+            assert_eq!(
+                substitute_with_options(template, &vars, &options),
+                Err(SubstError::UndefinedVariable {
+                    name: name.to_string(),
+                    position,
+                    suggestions: Vec::new(),
+                }),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn test_substitute_strict() {
+        let vars = make_vars(&[("A", "1")]);
+        assert_eq!(substitute_strict("${A}-${A}", &vars).unwrap(), "1-1");
+        assert_eq!(
+            substitute_strict("${A} ${X} ${Y}", &vars),
+            Err(SubstError::UndefinedVariable {
+                name: "X".to_string(),
+                position: 5,
+                suggestions: Vec::new(),
+            })
+        );
+        #[cfg(feature = "escape")]
+        assert_eq!(substitute_strict(r"\${X} ${A}", &vars).unwrap(), "${X} 1");
+        #[cfg(feature = "short_syntax")]
+        assert!(matches!(
+            substitute_strict("${A} $X", &vars),
+            Err(SubstError::UndefinedVariable { position: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_on_missing() {
+        let vars = make_vars(&[("A", "${B}")]);
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&calls);
+        let options = SubstOptions::new()
+            .missing(MissingAction::Error)
+            .syntax(SyntaxConfig::new().escape(true).short_syntax(true))
+            .recursive(true)
+            .on_missing(move |name, position| {
+                let mut seen = seen.lock().unwrap();
+                seen.push((name.to_string(), position));
+                match seen.len() {
+                    1 => MissingAction::Keep,
+                    2 => MissingAction::Empty,
+                    3 => MissingAction::UseDefault(format!("<{}>", name)),
+                    4 => MissingAction::Keep,
+                    _ => MissingAction::Error,
+                }
+            });
+        let template = r"${X} \${Y} $Z ${A} ${X} ${W}";
+        let result = substitute_with_options(template, &vars, &options);
+        assert!(matches!(
+            result,
+            Err(SubstError::UndefinedVariable { ref name, position: 24, .. }) if name == "W"
+        ));
+        let calls = calls.lock().unwrap();
+        let expected = [("X", 0), ("Z", 11), ("B", 14), ("X", 19), ("W", 24)];
+        let expected: Vec<(String, usize)> =
+            expected.iter().map(|&(n, p)| (n.to_string(), p)).collect();
+        assert_eq!(*calls, expected);
+
+        let options = options.clone().on_missing(|_, _| MissingAction::Empty);
+        let result = substitute_with_options(template, &vars, &options).unwrap();
+        assert_eq!(result, r" ${Y}    ");
+        assert_eq!(options.clone(), options);
+    }
+
+    #[cfg(feature = "escape")]
+    #[test]
+    fn test_missing_error_ignores_escaped() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let result = substitute_with_options(r"\${X\}", &vars, &options).unwrap();
+        assert_eq!(result, "${X}");
+    }
+
     #[test]
     fn test_performance_single_pass() {
         // This test verifies that we only scan the string once
@@ -577,4 +1811,191 @@ mod tests {
         let result = substitute("${KEY}", &vars).unwrap();
         assert_eq!(result, "value");
     }
+
+    #[test]
+    fn test_recursive_chain() {
+        let vars = make_vars(&[
+            ("URL", "${SCHEME}://${HOST}"),
+            ("SCHEME", "https"),
+            ("HOST", "${NAME}.example.com"),
+            ("NAME", "www"),
+        ]);
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("<${URL}>", &vars, &options).unwrap();
+        assert_eq!(result, "<https://www.example.com>");
+
+        // Non-recursive by default
+        let result = substitute("${URL}", &vars).unwrap();
+        assert_eq!(result, "${SCHEME}://${HOST}");
+    }
+
+    #[test]
+    fn test_recursive_cycle() {
+        let vars = make_vars(&[("A", "x${B}"), ("B", "${C}"), ("C", "${A}"), ("S", "-${S}")]);
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("ok ${A}", &vars, &options);
+        assert_eq!(
+            result,
+            Err(SubstError::CircularReference {
+                chain: vec!["A".into(), "B".into(), "C".into(), "A".into()],
+                position: 3,
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Circular reference A -> B -> C -> A at position 3"
+        );
+        let result = substitute_with_options("${S}", &vars, &options);
+        assert!(
+            matches!(result, Err(SubstError::CircularReference { chain, .. }) if chain == ["S", "S"])
+        );
+    }
+
+    #[test]
+    fn test_recursive_values_use_missing_policy() {
+        let vars = make_vars(&[("A", "[${UNSET}]")]);
+        let keep = SubstOptions::new().recursive(true);
+        assert_eq!(
+            substitute_with_options("${A}", &vars, &keep).unwrap(),
+            "[${UNSET}]"
+        );
+        let error = keep.clone().missing(MissingAction::Error);
+        assert_eq!(
+            substitute_with_options("x ${A}", &vars, &error),
+            Err(SubstError::UndefinedVariable {
+                name: "UNSET".to_string(),
+                position: 2,
+                suggestions: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let vars: HashMap<String, String> = (1..=10)
+            .map(|i| (format!("V{}", i), format!("${{V{}}}", i + 1)))
+            .chain([("V11".to_string(), "end".to_string())])
+            .collect();
+        let options = SubstOptions::new().recursive(true);
+        let result = substitute_with_options("${V1}", &vars, &options.clone().max_depth(20));
+        assert_eq!(result.unwrap(), "end");
+        assert_eq!(
+            substitute_with_options("${V1}", &vars, &options.clone().max_depth(5)),
+            Err(SubstError::DepthLimitExceeded {
+                depth: 5,
+                variable: "V6".to_string(),
+                position: 0,
+            })
+        );
+        // Depth 0 inserts values verbatim
+        let result = substitute_with_options("${V1}", &vars, &options.max_depth(0));
+        assert_eq!(result.unwrap(), "${V2}");
+    }
+
+    #[test]
+    fn test_custom_hashers() {
+        let mut vars: HashMap<String, String, ahash::RandomState> = HashMap::default();
+        vars.insert("NAME".to_string(), "World".to_string());
+        assert_eq!(substitute("Hello ${NAME}", &vars).unwrap(), "Hello World");
+
+        /// An FxHasher-style hasher, chosen with `BuildHasherDefault`
+        #[derive(Default)]
+        struct XorHasher(u64);
+
+        impl std::hash::Hasher for XorHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.0 = self.0.rotate_left(5) ^ u64::from(byte);
+                }
+            }
+        }
+
+        let vars: HashMap<&str, &str, std::hash::BuildHasherDefault<XorHasher>> =
+            [("A", "1"), ("B", "${A}")].into_iter().collect();
+        let options = SubstOptions::new().recursive(true);
+        assert_eq!(
+            substitute_with_options("${A}${B}", &vars, &options).unwrap(),
+            "11"
+        );
+        assert_eq!(substitute_bytes(b"\xff${A}", &vars).unwrap(), b"\xff1");
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_public_types_are_send_sync() {
+        assert_send_sync::<SubstOptions>();
+        assert_send_sync::<SyntaxConfig>();
+        assert_send_sync::<MissingAction>();
+        assert_send_sync::<SubstError>();
+        assert_send_sync::<Segment<'static>>();
+        assert_send_sync::<Segments<'static>>();
+        assert_send_sync::<Snippet<'static>>();
+        assert_send_sync::<Template>();
+        assert_send_sync::<RenderState>();
+    }
+
+    #[test]
+    fn test_concurrent_rendering_shares_template() {
+        let template = std::sync::Arc::new(Template::parse("${GREETING}, worker ${ID}!").unwrap());
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let handles: Vec<_> = (0..12)
+            .map(|id| {
+                let template = std::sync::Arc::clone(&template);
+                let options = options.clone();
+                std::thread::spawn(move || {
+                    let vars = HashMap::from([
+                        ("GREETING".to_string(), "hello".to_string()),
+                        ("ID".to_string(), id.to_string()),
+                    ]);
+                    let outputs: Vec<String> = (0..100)
+                        .map(|_| template.render_with_options(&vars, &options).unwrap())
+                        .collect();
+                    assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]));
+                    outputs.into_iter().next().unwrap()
+                })
+            })
+            .collect();
+        for (id, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), format!("hello, worker {}!", id));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_substitution_shares_options() {
+        let options = std::sync::Arc::new(
+            SubstOptions::new()
+                .missing(MissingAction::Error)
+                .recursive(true),
+        );
+        let template = std::sync::Arc::new("${GREETING}, worker ${ID}!".to_string());
+        let handles: Vec<_> = (0..12)
+            .map(|id| {
+                let options = std::sync::Arc::clone(&options);
+                let template = std::sync::Arc::clone(&template);
+                std::thread::spawn(move || {
+                    let vars = HashMap::from([
+                        ("GREETING".to_string(), "hello ${NAME}".to_string()),
+                        ("NAME".to_string(), format!("n{}", id)),
+                        ("ID".to_string(), id.to_string()),
+                    ]);
+                    let outputs: Vec<String> = (0..100)
+                        .map(|_| substitute_with_options(&template, &vars, &options).unwrap())
+                        .collect();
+                    assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]));
+                    outputs.into_iter().next().unwrap()
+                })
+            })
+            .collect();
+        for (id, handle) in handles.into_iter().enumerate() {
+            assert_eq!(
+                handle.join().unwrap(),
+                format!("hello n{}, worker {}!", id, id)
+            );
+        }
+    }
 }
@@ -1,113 +1,407 @@
 #[cfg(not(feature = "cli"))]
 compile_error!("The binary requires the 'cli' feature. Use: cargo build --features cli");
 
-use clap::Parser;
-use std::collections::HashMap;
-use std::fs;
-use std::io::{self, Read};
-use std::process;
+mod cli;
 
-/// High-performance variable substitution tool with single-pass parsing
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Input file (or stdin if not specified)
-    #[arg(value_name = "FILE")]
-    input: Option<String>,
-
-    /// Output file (or stdout if not specified)
-    #[arg(short, long, value_name = "FILE")]
-    output: Option<String>,
-
-    /// Define variables (format: KEY=VALUE)
-    #[arg(short = 'v', long = "var", value_name = "KEY=VALUE")]
-    variables: Vec<String>,
-
-    /// Don't use environment variables (by default, environment variables are used)
-    #[arg(long = "no-env")]
-    no_env: bool,
-
-    /// Fail if undefined variables are found
-    #[arg(short = 'f', long = "fail-on-undefined")]
-    fail_on_undefined: bool,
-}
+use clap::error::ErrorKind;
+use clap::CommandFactory;
+use cli::args::{usage_exit, Args, Cli};
+use cli::diagnostic::Diagnostic;
+use cli::files::{read_input, read_input_bytes, write_output};
+use cli::inputs::Input;
+use cli::policy::Policy;
+use cli::render::{Destination, Reporting};
+use cli::status::{Failure, Status};
+use cli::stream::Framing;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+use varsubst::{MissingAction, SubstOptions};
 
 fn main() {
-    let args = Args::parse();
-
-    // Read input
-    let input = match read_input(&args.input) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("Error reading input: {}", e);
-            process::exit(1);
+    let started = Instant::now();
+    let mut args = Args::parse_args();
+
+    if args.envsubst_compat {
+        envsubst_compat(&args);
+        return;
+    }
+
+    if let Some(list) = &args.files_from {
+        if list == "-" {
+            let other = match args.sources.var_files.iter().find(|(_, path)| path == "-") {
+                Some((format, _)) => Some(format.flag()),
+                None => args.sources.vars_stdin.is_some().then_some("--vars-stdin"),
+            };
+            if let Some(other) = other {
+                usage_exit(Cli::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!("cannot read both --files-from and {} from stdin", other),
+                ));
+            }
         }
+        let files =
+            cli::inputs::files_from(list, args.files_from_null).unwrap_or_else(|f| f.exit());
+        args.inputs.extend(files);
+        // An empty list means nothing to do, not a template on stdin
+        if args.inputs.is_empty() {
+            return;
+        }
+    }
+
+    let ignore = cli::ignore::Ignore::new(&args.ignore).unwrap_or_else(|f| f.exit());
+    if !args.globs.is_empty() {
+        let files =
+            cli::inputs::expand_globs(&args.globs, args.hidden, args.allow_empty_glob, &ignore)
+                .unwrap_or_else(|f| f.exit());
+        args.inputs.extend(files);
+        // Patterns that matched nothing must not fall back to stdin
+        if args.inputs.is_empty() {
+            return;
+        }
+    }
+
+    let syntax = match args.syntax.resolve() {
+        Ok(syntax) => syntax,
+        Err(e) => usage_exit(Cli::command().error(ErrorKind::InvalidValue, e)),
     };
 
-    // Build variable map
-    let mut vars: HashMap<String, String> = HashMap::new();
+    if args.check {
+        let policy = Policy {
+            names: args.strict_names.clone(),
+            ..Policy::default()
+        };
+        process::exit(cli::check::run(&args.inputs, &syntax, &policy));
+    }
 
-    // Add environment variables if requested (default behavior unless --no-env is specified)
-    if !args.no_env {
-        for (key, value) in std::env::vars() {
-            vars.insert(key, value);
+    for (used, flag) in [
+        (args.list_vars.is_some(), "--list-vars"),
+        (args.stream, "--stream"),
+        (args.null_data, "--null-data"),
+    ] {
+        if used && args.inputs.len() > 1 {
+            usage_exit(Cli::command().error(
+                ErrorKind::TooManyValues,
+                format!("{} accepts a single input file", flag),
+            ));
         }
     }
 
-    // Add command-line variables (overrides environment)
-    for var in &args.variables {
-        match var.split_once('=') {
-            Some((key, value)) => {
-                vars.insert(key.to_string(), value.to_string());
-            }
-            None => {
-                eprintln!("Invalid variable format: '{}' (expected KEY=VALUE)", var);
-                process::exit(1);
+    if args.watch
+        && (args.inputs.is_empty() || args.sources.var_files.iter().any(|(_, p)| p == "-"))
+    {
+        usage_exit(Cli::command().error(
+            ErrorKind::ArgumentConflict,
+            "--watch requires files; stdin cannot be watched",
+        ));
+    }
+
+    if args.in_place.is_some() && args.inputs.is_empty() {
+        usage_exit(Cli::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "--in-place requires an input file, not stdin",
+        ));
+    }
+
+    // stdin can only be consumed once
+    let mut stdin_users: Vec<&str> = Vec::new();
+    if args.inputs.is_empty() && args.recursive.is_none() && args.exprs.is_empty() {
+        stdin_users.push("the template");
+    }
+    if args.sources.vars_stdin.is_some() {
+        stdin_users.push("--vars-stdin");
+    }
+    if let Some((format, _)) = args.sources.var_files.iter().find(|(_, path)| path == "-") {
+        stdin_users.push(format.flag());
+    }
+    if let [first, second, ..] = stdin_users[..] {
+        usage_exit(Cli::command().error(
+            ErrorKind::ArgumentConflict,
+            format!("cannot read both {} and {} from stdin", first, second),
+        ));
+    }
+
+    let mut inputs: Vec<Input> = args.inputs.iter().map(|path| Input::file(path)).collect();
+    let mut skipped = 0;
+    let mut ignored = 0;
+    if let Some(root) = &args.recursive {
+        let walk = cli::inputs::walk(
+            Path::new(root),
+            args.ext.as_deref(),
+            args.copy_others,
+            &ignore,
+            !args.no_ignore_file,
+        )
+        .unwrap_or_else(|f| f.exit());
+        if args.verbose > 0 {
+            for path in &walk.ignored {
+                eprintln!("{}: ignored", path.display());
             }
         }
+        inputs = walk.inputs;
+        skipped = walk.skipped;
+        ignored = walk.ignored.len();
+    } else if !args.exprs.is_empty() {
+        let newline = if args.no_newline { "" } else { "\n" };
+        inputs = (args.exprs.iter().enumerate())
+            .map(|(i, expr)| Input::expr(i + 1, format!("{}{}", expr, newline)))
+            .collect();
+    } else if inputs.is_empty() {
+        inputs.push(Input::stdin());
+    }
+    if let Some(base) = &args.base_dir {
+        cli::inputs::rebase(&mut inputs, base).unwrap_or_else(|f| f.exit());
+    }
+    if let (true, Some(ext)) = (args.strip_ext, &args.ext) {
+        cli::inputs::strip_ext(&mut inputs, ext);
     }
 
-    // Perform substitution
-    let result = match varsubst::substitute(&input, &vars) {
-        Ok(output) => output,
-        Err(e) => {
-            eprintln!("Substitution error: {}", e);
-            process::exit(1);
-        }
+    let destination = match Destination::from_args(&args, &inputs) {
+        Ok(destination) => destination,
+        Err(message) => usage_exit(Cli::command().error(ErrorKind::ArgumentConflict, message)),
     };
 
-    // Check for undefined variables if requested
-    if args.fail_on_undefined && result.contains("${") {
-        eprintln!("Error: Undefined variables found in output");
-        process::exit(1);
+    // Listing variables needs no values
+    if let Some(format) = args.list_vars {
+        let name = &inputs[0].display;
+        let input = read_input(args.inputs.first()).unwrap_or_else(|e| {
+            Failure::new(Status::Io, format!("Error reading input: {}", e))
+                .with(Diagnostic::new("io", format!("error reading input: {}", e)).file(name))
+                .exit()
+        });
+        if let Err(e) = cli::list::run(&input, format, &syntax) {
+            Failure::new(Status::of(&e), format!("Substitution error: {}", e))
+                .with(Diagnostic::of_error(&e, &input, &syntax).file(name))
+                .exit();
+        }
+        return;
     }
 
-    // Write output
-    if let Err(e) = write_output(&args.output, &result) {
-        eprintln!("Error writing output: {}", e);
-        process::exit(1);
+    let missing = if args.fail_on_undefined {
+        MissingAction::Error
+    } else {
+        args.missing.clone()
+    };
+    let options = SubstOptions::new()
+        .missing(missing)
+        .syntax(syntax)
+        .recursive(args.recursive_values)
+        .max_depth(args.max_depth);
+
+    let reporting = Reporting {
+        verbosity: args.verbose,
+        dry_run: args.dry_run,
+        track_references: args.fail_on_unused,
+        if_changed: args.if_changed,
+        jobs: args.jobs(),
+        keep_going: args.keep_going,
+        diff: args.diff,
+        diff_color: args.color(io::stdout().is_terminal()),
+        report: args.report.is_some(),
+        fail_fast: args.fail_fast,
+    };
+
+    let output_settings = args.output_settings();
+    let policy = Policy {
+        allow: args.allow.clone(),
+        deny: args.deny.clone(),
+        names: args.strict_names.clone(),
+    };
+
+    if args.watch {
+        // Variables are reloaded on every render so edits to them apply
+        let mut watched: Vec<&str> = args.inputs.iter().map(String::as_str).collect();
+        watched.extend(args.sources.env_files.iter().map(String::as_str));
+        watched.extend(args.sources.var_files.iter().map(|(_, path)| path.as_str()));
+        cli::watch::run(&watched, || {
+            let mut vars = cli::variables::load(&args.sources)?;
+            args.escape_values.apply_all(&mut vars.values);
+            if args.verbose > 0 {
+                cli::verbose::sources(&vars);
+            }
+            cli::render::run(
+                &inputs,
+                &destination,
+                &vars,
+                &options,
+                &policy,
+                reporting,
+                output_settings,
+            )
+            .map(|_| ())
+        })
+        .unwrap_or_else(|f| f.exit());
+        return;
     }
-}
 
-/// Read input from file or stdin
-fn read_input(path: &Option<String>) -> io::Result<String> {
-    match path {
-        Some(file_path) => fs::read_to_string(file_path),
-        None => {
-            let mut buffer = String::new();
-            io::stdin().read_to_string(&mut buffer)?;
-            Ok(buffer)
+    let mut vars = cli::variables::load(&args.sources).unwrap_or_else(|f| f.exit());
+    if args.sources.var_prompt {
+        // The templates are scanned before rendering, so stdin is read now
+        for input in
+            (inputs.iter_mut()).filter(|input| input.path.is_none() && input.text.is_none())
+        {
+            let text = read_input(None).unwrap_or_else(|e| {
+                Failure::new(Status::Io, format!("Error reading input: {}", e)).exit()
+            });
+            input.text = Some(text);
+        }
+        let templates: Vec<String> = (inputs.iter())
+            .filter(|input| !input.copy)
+            .filter_map(|input| match &input.text {
+                Some(text) => Some(text.clone()),
+                None => read_input(input.path.as_ref()).ok(),
+            })
+            .collect();
+        let names = cli::prompt::undefined(
+            templates.iter().map(String::as_str),
+            &vars.values,
+            &options.syntax,
+        );
+        let values =
+            cli::prompt::ask(&names, &args.sources.var_prompt_hidden).unwrap_or_else(|f| f.exit());
+        if !values.is_empty() {
+            vars.add("--var-prompt".to_string(), values);
         }
     }
-}
+    args.escape_values.apply_all(&mut vars.values);
+    if args.verbose > 0 {
+        cli::verbose::sources(&vars);
+    }
 
-/// Write output to file or stdout
-fn write_output(path: &Option<String>, content: &str) -> io::Result<()> {
-    match path {
-        Some(file_path) => fs::write(file_path, content),
-        None => {
-            print!("{}", content);
-            Ok(())
+    let mut changed = false;
+    if args.stream || args.null_data {
+        let framing = if args.null_data {
+            Framing::Nul
+        } else {
+            Framing::Lines
+        };
+        cli::stream::run(
+            args.inputs.first(),
+            args.output.as_ref(),
+            framing,
+            &vars.values,
+            &options,
+            &policy,
+            output_settings,
+        )
+        .unwrap_or_else(|f| f.exit());
+    } else {
+        let summary = cli::render::run(
+            &inputs,
+            &destination,
+            &vars,
+            &options,
+            &policy,
+            reporting,
+            output_settings,
+        )
+        .unwrap_or_else(|f| f.exit());
+        let changes = format!(
+            "{} changed, {} unchanged",
+            summary.changed, summary.unchanged
+        );
+        let failed = match args.keep_going {
+            true => format!(", {} failed", summary.failed),
+            false => String::new(),
+        };
+        if args.recursive.is_some() && !args.json_errors {
+            let changes = match args.if_changed {
+                true => format!(" ({})", changes),
+                false => String::new(),
+            };
+            let ignored = match ignored {
+                0 => String::new(),
+                n => format!(", {} ignored", n),
+            };
+            eprintln!(
+                "{} rendered, {} copied, {} skipped{}{}{}",
+                summary.rendered, summary.copied, skipped, ignored, failed, changes
+            );
+        } else if args.keep_going && !args.json_errors {
+            let changes = match args.if_changed {
+                true => format!(" ({})", changes),
+                false => String::new(),
+            };
+            eprintln!("{} rendered{}{}", summary.rendered, failed, changes);
+        } else if args.if_changed && inputs.len() > 1 && !args.json_errors {
+            eprintln!("{}", changes);
         }
+        if let Some(path) = &args.report {
+            cli::report::write(path, &args, &options, &vars, &summary, started.elapsed())
+                .unwrap_or_else(|f| f.exit());
+        }
+        // Unused names cannot be told apart from those of failed inputs
+        if let Some(status) = summary.failure {
+            process::exit(status.code());
+        }
+        if args.fail_on_unused {
+            let allowed = |name: &str| args.allow_unused.iter().any(|a| a == name);
+            let mut unused: Vec<Diagnostic> = vars
+                .provided()
+                .into_iter()
+                .filter(|(name, _)| !summary.referenced.contains(*name) && !allowed(name))
+                .map(|(name, origin)| {
+                    let message = format!("Unused variable '{}' (from {})", name, origin);
+                    Diagnostic::new("unused-variable", message).variable(name)
+                })
+                .collect();
+            if args.fail_on_unused_defaults {
+                for (name, overridden) in vars.defaults() {
+                    let message = match overridden {
+                        Some(origin) => {
+                            format!("Unused default for '{}' (overridden by {})", name, origin)
+                        }
+                        None if !summary.referenced.contains(name) => {
+                            format!("Unused default for '{}'", name)
+                        }
+                        None => continue,
+                    };
+                    if !allowed(name) {
+                        unused.push(Diagnostic::new("unused-variable", message).variable(name));
+                    }
+                }
+            }
+            if !unused.is_empty() {
+                let messages: Vec<&str> = unused.iter().map(|d| d.message.as_str()).collect();
+                let mut failure = Failure::new(Status::Unused, messages.join("\n"));
+                failure.diagnostics = unused;
+                failure.exit();
+            }
+        }
+        changed = summary.changed > 0;
+    }
+
+    if args.verbose > 0 {
+        eprintln!("elapsed: {:.2?}", started.elapsed());
     }
+    if changed && args.diff {
+        process::exit(1);
+    }
+    if changed && args.exit_code_on_change {
+        process::exit(Status::Changed.code());
+    }
+}
+
+/// Run as GNU envsubst would, for `--envsubst-compat`
+fn envsubst_compat(args: &Args) {
+    let only = match &args.inputs[..] {
+        [] => None,
+        [shell_format] => Some(cli::envsubst::names(shell_format)),
+        [_, extra, ..] => usage_exit(Cli::command().error(
+            ErrorKind::TooManyValues,
+            format!(
+                "--envsubst-compat takes a single SHELL-FORMAT argument and reads the \
+                 template from stdin; unexpected '{}'",
+                extra
+            ),
+        )),
+    };
+    let vars = cli::variables::load(&args.sources).unwrap_or_else(|f| f.exit());
+    let template = read_input_bytes(None)
+        .unwrap_or_else(|e| Failure::new(Status::Io, format!("Error reading input: {}", e)).exit());
+    let output = cli::envsubst::substitute(&template, &vars.values, only.as_ref());
+    write_output(&args.output, &output).unwrap_or_else(|e| {
+        Failure::new(Status::Io, format!("Error writing output: {}", e)).exit()
+    });
 }
@@ -0,0 +1,193 @@
+//! POSIX-style `${NAME:-word}` and `${NAME-word}` operators between a name
+//! and its closing delimiter.
+
+use crate::SyntaxConfig;
+use std::borrow::Cow;
+
+/// An operator and its word written after the name in a delimited
+/// reference, as in `${PORT:-8080}`
+///
+/// As in POSIX shells, an operator with a colon treats a variable that is
+/// defined but empty like an undefined one, and one without a colon, as in
+/// `${PORT-8080}`, only acts on undefined variables.
+///
+/// The word runs to the first closing delimiter that is not escaped, so it
+/// may contain characters that are not valid in names, such as spaces and
+/// `/`. Unlike in shells, braces in it do not pair up: `${X:-a{b}c}` ends at
+/// the first `}`, and a `}` in the word is written `\}`. It is used
+/// literally, without expanding references in it; see
+/// [`Operator::unescaped_word`].
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("HOST", "example.com"), ("PORT", "")]);
+/// let result = substitute("${HOST:-localhost}:${PORT:-8080}${PATH:-/}", &vars).unwrap();
+/// assert_eq!(result, "example.com:8080/");
+/// let result = substitute("[${PORT-8080}] [${PATH-/}]", &vars).unwrap();
+/// assert_eq!(result, "[] [/]");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operator<'a> {
+    /// Which operator it is
+    pub kind: OperatorKind,
+    /// Whether it is written with a colon, as in `:-`, so that an empty
+    /// value counts as unset
+    pub colon: bool,
+    /// The text between the operator and the closing delimiter, with its
+    /// escapes still in place
+    pub word: &'a str,
+}
+
+/// What an [`Operator`] does with a value that is unset: undefined, or
+/// with [`Operator::colon`] also empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperatorKind {
+    /// `-`: the word instead of an unset value
+    Default,
+    /// `=`: like [`Default`](Self::Default), also assigning the word to the
+    /// variable so that the references after it resolve to it
+    Assign,
+    /// `?`: fail with [`SubstError::RequiredVariable`](crate::SubstError::RequiredVariable),
+    /// whose message is the word, if the value is unset
+    Required,
+    /// `+`: the word instead of a value that is set, and nothing otherwise
+    Alternate,
+}
+
+impl OperatorKind {
+    /// The operator character, after the colon if there is one
+    pub fn symbol(self) -> char {
+        match self {
+            OperatorKind::Default => '-',
+            OperatorKind::Assign => '=',
+            OperatorKind::Required => '?',
+            OperatorKind::Alternate => '+',
+        }
+    }
+
+    /// The operator that `text` starts with, if any, and whether it has a
+    /// colon
+    pub(crate) fn at_start_of(text: &str) -> Option<(Self, bool)> {
+        let (colon, rest) = match text.strip_prefix(':') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let symbol = rest.chars().next()?;
+        [
+            OperatorKind::Default,
+            OperatorKind::Assign,
+            OperatorKind::Required,
+            OperatorKind::Alternate,
+        ]
+        .into_iter()
+        .find(|kind| kind.symbol() == symbol)
+        .map(|kind| (kind, colon))
+    }
+}
+
+impl<'a> Operator<'a> {
+    /// Length in bytes of the operator as written, with its colon
+    pub fn symbol_len(&self) -> usize {
+        usize::from(self.colon) + 1
+    }
+
+    /// Whether `value` counts as set: defined, and with a colon also not
+    /// empty
+    pub fn is_set(&self, value: Option<&str>) -> bool {
+        value.is_some_and(|value| !(self.colon && value.is_empty()))
+    }
+
+    /// The word as it is inserted: a backslash followed by another backslash,
+    /// `$` or a character of the delimiters of `syntax` stands for that
+    /// character, whether or not [`SyntaxConfig::escape`] is enabled, and any
+    /// other backslash is literal
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{segments, Segment, SyntaxConfig};
+    ///
+    /// let Some(Ok(Segment::Var { operator: Some(operator), .. })) =
+    ///     segments(r"${NAME:-{\}\n}").next()
+    /// else {
+    ///     unreachable!()
+    /// };
+    /// assert_eq!(operator.word, r"{\}\n");
+    /// assert_eq!(operator.unescaped_word(&SyntaxConfig::new()), r"{}\n");
+    /// ```
+    pub fn unescaped_word(&self, syntax: &SyntaxConfig) -> Cow<'a, str> {
+        let word = self.word;
+        if !word.contains('\\') {
+            return Cow::Borrowed(word);
+        }
+        let mut unescaped = String::with_capacity(word.len());
+        let mut chars = word.chars();
+        while let Some(ch) = chars.next() {
+            match (ch, chars.clone().next()) {
+                ('\\', Some(next)) if next == '$' || syntax.is_escapable(next) => {
+                    unescaped.push(next);
+                    chars.next();
+                }
+                _ => unescaped.push(ch),
+            }
+        }
+        Cow::Owned(unescaped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str) -> Operator<'_> {
+        Operator {
+            kind: OperatorKind::Default,
+            colon: true,
+            word,
+        }
+    }
+
+    #[test]
+    fn test_unescaped_word() {
+        let syntax = SyntaxConfig::new();
+        assert!(matches!(
+            word("a b/c").unescaped_word(&syntax),
+            Cow::Borrowed("a b/c")
+        ));
+        assert_eq!(word(r"\}\{\$\\").unescaped_word(&syntax), r"}{$\");
+        assert_eq!(word(r"C:\Users\x\").unescaped_word(&syntax), r"C:\Users\x\");
+        let syntax = SyntaxConfig::new().delimiters("<<", ">>").unwrap();
+        assert_eq!(word(r"\>\}").unescaped_word(&syntax), r">\}");
+    }
+
+    #[test]
+    fn test_at_start_of() {
+        use OperatorKind::*;
+        assert_eq!(OperatorKind::at_start_of(":-x}"), Some((Default, true)));
+        assert_eq!(OperatorKind::at_start_of(":=x}"), Some((Assign, true)));
+        assert_eq!(OperatorKind::at_start_of("?}"), Some((Required, false)));
+        assert_eq!(OperatorKind::at_start_of(":+-x}"), Some((Alternate, true)));
+        assert_eq!(OperatorKind::at_start_of("+:-x}"), Some((Alternate, false)));
+        assert_eq!(OperatorKind::at_start_of(":x}"), None);
+        assert_eq!(OperatorKind::at_start_of(":"), None);
+        assert_eq!(OperatorKind::at_start_of("}"), None);
+    }
+
+    #[test]
+    fn test_is_set() {
+        let colon = word("");
+        let plain = Operator {
+            colon: false,
+            ..colon
+        };
+        assert_eq!(colon.symbol_len(), 2);
+        assert_eq!(plain.symbol_len(), 1);
+        assert!(colon.is_set(Some("x")) && plain.is_set(Some("x")));
+        assert!(!colon.is_set(Some("")) && plain.is_set(Some("")));
+        assert!(!colon.is_set(None) && !plain.is_set(None));
+    }
+}
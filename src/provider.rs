@@ -0,0 +1,177 @@
+//! Variable sources other than a `HashMap`.
+
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, HashMap};
+
+/// A source of variable values for [`substitute_with_provider`]
+///
+/// Implemented for `HashMap` and `BTreeMap` (and `IndexMap` with the
+/// `indexmap` feature) with string keys, for slices of `(name, value)` pairs
+/// (where a later pair wins) and for references to any provider. Implement
+/// it for a configuration store to substitute from it without copying its
+/// values into a map first.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_provider, SubstOptions, VarProvider};
+/// use std::borrow::Cow;
+///
+/// struct Numbers;
+///
+/// impl VarProvider for Numbers {
+///     fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+///         let n: u32 = name.strip_prefix('N')?.parse().ok()?;
+///         Some(Cow::Owned((n * n).to_string()))
+///     }
+/// }
+///
+/// let result = substitute_with_provider("${N3} ${N12}", &Numbers, &SubstOptions::new()).unwrap();
+/// assert_eq!(result, "9 144");
+/// ```
+///
+/// [`substitute_with_provider`]: crate::substitute_with_provider
+pub trait VarProvider {
+    /// The value of the variable `name`, or `None` if it is undefined
+    fn get(&self, name: &str) -> Option<Cow<'_, str>>;
+
+    /// The defined names, used to suggest a name for a misspelled one in
+    /// [`SubstError::UndefinedVariable`](crate::SubstError::UndefinedVariable).
+    /// Providers that cannot list their names keep the default, which
+    /// suggests nothing.
+    fn names(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+impl<P: VarProvider + ?Sized> VarProvider for &P {
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        (**self).get(name)
+    }
+
+    fn names(&self) -> Vec<&str> {
+        (**self).names()
+    }
+}
+
+impl<K, V, S> VarProvider for HashMap<K, V, S>
+where
+    K: Borrow<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        HashMap::get(self, name).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.keys().map(Borrow::borrow).collect()
+    }
+}
+
+impl<K, V> VarProvider for BTreeMap<K, V>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        BTreeMap::get(self, name).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.keys().map(Borrow::borrow).collect()
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<K, V, S> VarProvider for indexmap::IndexMap<K, V, S>
+where
+    K: Borrow<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        indexmap::IndexMap::get(self, name).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    /// The names in definition order
+    fn names(&self) -> Vec<&str> {
+        self.keys().map(Borrow::borrow).collect()
+    }
+}
+
+impl<K, V> VarProvider for [(K, V)]
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        (self.iter().rev())
+            .find(|(key, _)| key.as_ref() == name)
+            .map(|(_, value)| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.iter().map(|(key, _)| key.as_ref()).collect()
+    }
+}
+
+impl<K, V, const N: usize> VarProvider for [(K, V); N]
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        VarProvider::get(&self[..], name)
+    }
+
+    fn names(&self) -> Vec<&str> {
+        VarProvider::names(&self[..])
+    }
+}
+
+impl<K, V> VarProvider for Vec<(K, V)>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        VarProvider::get(&self[..], name)
+    }
+
+    fn names(&self) -> Vec<&str> {
+        VarProvider::names(&self[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_and_pairs_agree() {
+        let pairs = [("A", "1"), ("B", "2"), ("A", "3")];
+        let hash: HashMap<&str, &str> = HashMap::from(pairs);
+        let btree: BTreeMap<String, String> =
+            pairs.map(|(k, v)| (k.to_string(), v.to_string())).into();
+        let providers: [&dyn VarProvider; 4] = [&hash, &btree, &pairs, &&pairs[..]];
+        for provider in providers {
+            assert_eq!(provider.get("A").as_deref(), Some("3"));
+            assert_eq!(provider.get("B").as_deref(), Some("2"));
+            assert_eq!(provider.get("C"), None);
+            assert!(provider.names().contains(&"B"));
+        }
+    }
+
+    #[test]
+    fn test_default_names_is_empty() {
+        struct Empty;
+        impl VarProvider for Empty {
+            fn get(&self, _: &str) -> Option<Cow<'_, str>> {
+                None
+            }
+        }
+        assert!(Empty.names().is_empty());
+        assert!((&&Empty).get("X").is_none());
+    }
+}
@@ -0,0 +1,239 @@
+//! A reader that substitutes variables in what it reads.
+
+use crate::stream::{Pending, CHUNK_SIZE};
+use crate::{Expander, SubstOptions, VarProvider};
+use std::io::{self, Read};
+
+/// An [`io::Read`] adapter that substitutes variables in the text read from
+/// an inner reader, for code that only accepts `impl Read`
+///
+/// The inner reader is read in chunks of a few kilobytes whatever the size
+/// of the caller's buffer, so reading one byte at a time does not read the
+/// inner reader one byte at a time, and a value longer than the buffer is
+/// handed out over several calls. Text that could still be the start of a
+/// reference is held back until the rest of it has been read.
+///
+/// A syntax or substitution error, or input that is not valid UTF-8, is
+/// returned as an [`io::Error`] of kind [`io::ErrorKind::InvalidData`];
+/// for the former its inner error is the [`SubstError`](crate::SubstError),
+/// with positions counted from the start of the input. Input that ends in
+/// the middle of `${NAME` fails with
+/// [`SubstError::UnclosedBrace`](crate::SubstError::UnclosedBrace). The
+/// output before the error is returned first, then the error once, and after
+/// that the reader is at its end.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::SubstReader;
+/// use std::collections::HashMap;
+/// use std::io::Read;
+///
+/// let vars = HashMap::from([("HOST", "db.internal")]);
+/// let mut reader = SubstReader::new("host: ${HOST}\n".as_bytes(), &vars);
+/// let mut config = String::new();
+/// reader.read_to_string(&mut config)?;
+/// assert_eq!(config, "host: db.internal\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct SubstReader<R, P> {
+    inner: R,
+    provider: P,
+    options: SubstOptions,
+    pending: Pending,
+    chunk: Vec<u8>,
+    /// Substituted output not returned by `read` yet, from `consumed` on
+    output: String,
+    consumed: usize,
+    /// Whether the inner reader has ended, or substituting it failed
+    done: bool,
+    /// The error to return once `output` has been returned
+    error: Option<io::Error>,
+}
+
+impl<R: Read, P: VarProvider> SubstReader<R, P> {
+    /// Substitute variables from `provider` with default options
+    pub fn new(inner: R, provider: P) -> Self {
+        Self::with_options(inner, provider, SubstOptions::default())
+    }
+
+    /// Substitute variables from `provider` with undefined variables, syntax
+    /// and recursion handled according to `options`
+    pub fn with_options(inner: R, provider: P, options: SubstOptions) -> Self {
+        SubstReader {
+            inner,
+            provider,
+            options,
+            pending: Pending::default(),
+            chunk: vec![0; CHUNK_SIZE],
+            output: String::new(),
+            consumed: 0,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// The inner reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// The inner reader, discarding anything read from it but not returned
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read the next chunk of the inner reader and substitute what it
+    /// completes
+    fn fill(&mut self) -> io::Result<()> {
+        let read = self.inner.read(&mut self.chunk)?;
+        self.output.clear();
+        self.consumed = 0;
+        if let Err(error) = self.substitute(read) {
+            // The text taken so far cannot be resumed
+            self.error = Some(error);
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    /// Substitute what the `read` bytes at the start of `chunk` complete
+    /// into `output`
+    fn substitute(&mut self, read: usize) -> io::Result<()> {
+        let eof = read == 0;
+        match eof {
+            true => self.pending.close()?,
+            false => self.pending.push(&self.chunk[..read])?,
+        }
+        let mut expander = Expander::with_provider(&self.provider, &self.options);
+        (self.pending)
+            .substitute(&mut expander, eof, &mut self.output)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.done = eof;
+        Ok(())
+    }
+}
+
+impl<R: Read, P: VarProvider> Read for SubstReader<R, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.consumed == self.output.len() && !self.done {
+            self.fill()?;
+        }
+        if self.consumed == self.output.len() {
+            if let Some(error) = self.error.take() {
+                return Err(error);
+            }
+        }
+        let available = &self.output.as_bytes()[self.consumed..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{substitute_with_options, MissingAction, SubstError, SyntaxConfig};
+    use std::collections::HashMap;
+
+    /// Returns one byte per read
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&byte, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("NAME", "World"), ("LONG", "a value longer than one byte")])
+    }
+
+    /// Read everything, `size` bytes at a time
+    fn read_all(mut reader: impl Read, size: usize) -> io::Result<String> {
+        let mut output = Vec::new();
+        let mut buf = vec![0; size];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(String::from_utf8(output).unwrap()),
+                len => output.extend_from_slice(&buf[..len]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_one_byte_at_a_time() {
+        let syntax = SyntaxConfig::new().escape(true).short_syntax(true);
+        let options = SubstOptions::new().syntax(syntax);
+        let template = r"Hello ${NAME}, ü \${NAME} $NAME ${LONG} ${MISSING}$";
+        let vars = vars();
+        let expected = substitute_with_options(template, &vars, &options).unwrap();
+        let input = Trickle(template.as_bytes());
+        let reader = SubstReader::with_options(input, &vars, options.clone());
+        assert_eq!(read_all(reader, 1).unwrap(), expected);
+        let reader = SubstReader::with_options(template.as_bytes(), &vars, options);
+        assert_eq!(read_all(reader, 1).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_unclosed_brace_at_end() {
+        let vars = vars();
+        let reader = SubstReader::new(Trickle(b"Hello ${NAME} ${NAME"), &vars);
+        let error = read_all(reader, 4).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = error
+            .into_inner()
+            .unwrap()
+            .downcast::<SubstError>()
+            .unwrap();
+        assert_eq!(*error, SubstError::UnclosedBrace { position: 14 });
+    }
+
+    #[test]
+    fn test_errors() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let reader = SubstReader::with_options(&b"${NAME} ${USER}"[..], vars(), options);
+        let error = read_all(reader, 64).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Undefined variable 'USER' at position 8"));
+
+        let reader = SubstReader::new(Trickle(b"ok \xc3"), vars());
+        let error = read_all(reader, 64).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_after_error() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let vars = HashMap::from([("A", "a")]);
+        let input = Trickle(b"hello ${A} ${X} and more");
+        let mut reader = SubstReader::with_options(input, &vars, options);
+        let mut output = Vec::new();
+        let mut buf = [0; 4];
+        let error = loop {
+            match reader.read(&mut buf) {
+                Ok(len) => output.extend_from_slice(&buf[..len]),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(output, b"hello a ");
+        let rest = reader.get_ref().0;
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        // Nothing more is read from the inner reader
+        assert_eq!(reader.get_ref().0, rest);
+    }
+}
@@ -0,0 +1,201 @@
+//! Substitution that also reports what the template referenced and assigned.
+
+use crate::{Expander, FastMap, SubstOptions, SubstResult, VarProvider};
+use std::collections::HashSet;
+
+/// What a substitution did besides producing its output, returned by
+/// [`substitute_with_report`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubstReport {
+    /// The names and values assigned by `${NAME:=word}` references, in the
+    /// order they were first assigned. A variable assigned again, after it
+    /// was assigned an empty word, has its last value.
+    pub assignments: Vec<(String, String)>,
+    /// The referenced names, unquoted, in order of first reference. With
+    /// [`SubstOptions::recursive`] this includes the names referenced by
+    /// values.
+    pub references: Vec<String>,
+    /// The referenced names that were undefined at some reference, in order
+    /// of first reference, including the ones an operator such as
+    /// `${NAME:-default}` supplied output for
+    pub undefined: Vec<String>,
+    /// The provided names that nothing referenced, in the order
+    /// [`VarProvider::names`] lists them: definition order for an `IndexMap`
+    /// (with the `indexmap` feature) or a list of pairs, sorted for a
+    /// `BTreeMap` and arbitrary for a `HashMap`
+    pub unused: Vec<String>,
+}
+
+/// The names an [`Expander`] looked up, in order of first reference
+#[derive(Default)]
+pub(crate) struct Usage {
+    /// Each name, and whether it was undefined at some reference
+    names: Vec<(String, bool)>,
+    /// Index of each name in `names`
+    index: FastMap<String, usize>,
+}
+
+impl Usage {
+    pub(crate) fn record(&mut self, name: &str, undefined: bool) {
+        match self.index.get(name) {
+            Some(&i) => self.names[i].1 |= undefined,
+            None => {
+                self.index.insert(name.to_string(), self.names.len());
+                self.names.push((name.to_string(), undefined));
+            }
+        }
+    }
+}
+
+/// Substitute variables like [`substitute_with_provider`](crate::substitute_with_provider),
+/// also returning a report of the substitution.
+///
+/// A `${NAME:=word}` reference to a variable that is undefined or empty
+/// produces `word` and assigns it for the references after it, so later
+/// `${NAME}` references resolve to `word` too. `variables` is left
+/// unchanged; the assignments are listed in the report for callers that
+/// want to keep them.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_report, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("HOST", "example.com")]);
+/// let template = "${HOST:=localhost}:${PORT:=8080} port=${PORT}";
+/// let (output, report) = substitute_with_report(template, &vars, &SubstOptions::new()).unwrap();
+/// assert_eq!(output, "example.com:8080 port=8080");
+/// assert_eq!(report.assignments, [("PORT".to_string(), "8080".to_string())]);
+/// assert_eq!(report.references, ["HOST", "PORT"]);
+/// assert_eq!(report.undefined, ["PORT"]);
+/// ```
+pub fn substitute_with_report<P>(
+    template: &str,
+    variables: &P,
+    options: &SubstOptions,
+) -> SubstResult<(String, SubstReport)>
+where
+    P: VarProvider + ?Sized,
+{
+    let mut expander = Expander::with_provider(&variables, options);
+    expander.usage = Some(Usage::default());
+    let mut output = String::with_capacity(template.len());
+    expander.expand(template, &mut output, None)?;
+
+    let usage = expander.usage.take().unwrap_or_default();
+    let mut seen = HashSet::new();
+    let unused = (variables.names().into_iter())
+        .filter(|name| !usage.index.contains_key(*name) && seen.insert(*name))
+        .map(str::to_string)
+        .collect();
+    let undefined = (usage.names.iter())
+        .filter(|(_, undefined)| *undefined)
+        .map(|(name, _)| name.clone())
+        .collect();
+    let report = SubstReport {
+        assignments: expander.assigned,
+        references: usage.names.into_iter().map(|(name, _)| name).collect(),
+        undefined,
+        unused,
+    };
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyntaxConfig;
+    use std::collections::HashMap;
+
+    fn assignments(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        (pairs.iter())
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_assignments_in_order() {
+        let vars = HashMap::from([("SET", "x"), ("EMPTY", "")]);
+        let template = "${B:=2}${SET:=y}${A:=1}${EMPTY:=e}${B:=3} ${A}${B}${EMPTY}${SET}";
+        let (output, report) =
+            substitute_with_report(template, &vars, &SubstOptions::new()).unwrap();
+        assert_eq!(output, "2x1e2 12ex");
+        assert_eq!(
+            report.assignments,
+            assignments(&[("B", "2"), ("A", "1"), ("EMPTY", "e")])
+        );
+    }
+
+    #[test]
+    fn test_assigned_empty_word() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let template = "[${A:=}][${A}][${A:=a}][${A}]";
+        let (output, report) =
+            substitute_with_report(template, &vars, &SubstOptions::new()).unwrap();
+        assert_eq!(output, "[][][a][a]");
+        assert_eq!(report.assignments, assignments(&[("A", "a")]));
+    }
+
+    #[test]
+    fn test_assignments_use_unescaped_unquoted_names() {
+        let vars: HashMap<&str, &str> = HashMap::new();
+        let options = SubstOptions::new().syntax(SyntaxConfig::new().quoted_names(true));
+        let template = r"${'a b':=x\}y} ${'a b'}";
+        let (output, report) = substitute_with_report(template, &vars, &options).unwrap();
+        assert_eq!(output, "x}y x}y");
+        assert_eq!(report.assignments, assignments(&[("a b", "x}y")]));
+    }
+
+    #[test]
+    fn test_references_in_template_order() {
+        let vars = [("UNUSED", "u"), ("B", "b"), ("A", "a"), ("ALSO_UNUSED", "")];
+        let template = "${B} ${C:-c} ${A} ${B} ${D:=d} ${C} ${D}";
+        let (output, report) =
+            substitute_with_report(template, &vars, &SubstOptions::new()).unwrap();
+        assert_eq!(output, "b c a b d ${C} d");
+        assert_eq!(report.references, ["B", "C", "A", "D"]);
+        assert_eq!(report.undefined, ["C", "D"]);
+        assert_eq!(report.unused, ["UNUSED", "ALSO_UNUSED"]);
+
+        let options = SubstOptions::new().recursive(true);
+        let vars = [("OUTER", "<${INNER}>"), ("INNER", "i")];
+        let (_, report) = substitute_with_report("${OUTER}", &vars, &options).unwrap();
+        assert_eq!(report.references, ["OUTER", "INNER"]);
+        assert!(report.unused.is_empty());
+    }
+
+    #[cfg(feature = "indexmap")]
+    #[test]
+    fn test_indexmap_keeps_definition_order() {
+        use crate::substitute_traced;
+        use indexmap::IndexMap;
+
+        let vars: IndexMap<&str, &str> = [
+            ("ZETA", "z"),
+            ("PORT", "80"),
+            ("ALPHA", "a"),
+            ("HOST", "h"),
+            ("MIDDLE", "m"),
+        ]
+        .into_iter()
+        .collect();
+        let template = "${HOST}:${PORT} ${MISSING} ${HOST} ${ALPHA:-x} ${NOPE:-y} ${PORT}";
+        let (output, report) =
+            substitute_with_report(template, &vars, &SubstOptions::new()).unwrap();
+        assert_eq!(output, "h:80 ${MISSING} h a y 80");
+        assert_eq!(
+            report.references,
+            ["HOST", "PORT", "MISSING", "ALPHA", "NOPE"]
+        );
+        assert_eq!(report.undefined, ["MISSING", "NOPE"]);
+        assert_eq!(report.unused, ["ZETA", "MIDDLE"]);
+
+        let (_, events) = substitute_traced(template, &vars, &SubstOptions::new()).unwrap();
+        let names: Vec<&str> = events.iter().map(|event| event.name).collect();
+        assert_eq!(
+            names,
+            ["HOST", "PORT", "MISSING", "HOST", "ALPHA", "NOPE", "PORT"]
+        );
+    }
+}
@@ -0,0 +1,815 @@
+//! Segment iterator: the single-pass parser shared by all substitution APIs.
+
+use crate::syntax::DEFAULT_SYNTAX;
+use crate::{Operator, OperatorKind, SubstError, SubstResult, SyntaxConfig, Transform};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// A piece of a parsed template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// Literal text, copied to the output unchanged
+    Text(&'a str),
+    /// An escape sequence such as `\$`, producing a single character
+    Escaped(char),
+    /// A variable reference
+    Var {
+        /// The variable name; for a quoted name, the text between the quotes
+        /// with its escapes still in place (see [`unquote`])
+        name: &'a str,
+        /// Byte range of the whole reference (`${NAME}` or `$NAME`) in the template
+        span: Range<usize>,
+        /// Whether the reference used the delimited `${NAME}` form
+        braced: bool,
+        /// Whether the name was quoted, as in `${'Display Name'}`
+        quoted: bool,
+        /// Whether the name was preceded by `#`, as in `${#NAME}`, for the
+        /// length of the value instead of the value; such a reference has no
+        /// transform or operator
+        length: bool,
+        /// The transform written after the name, as in `${NAME@Q}`
+        transform: Option<Transform>,
+        /// The operator and word written after the name, as in
+        /// `${NAME:-default}`
+        operator: Option<Operator<'a>>,
+    },
+}
+
+/// Iterator over the [`Segment`]s of a template, created by [`segments`]
+#[derive(Debug, Clone)]
+pub struct Segments<'a> {
+    template: &'a str,
+    syntax: &'a SyntaxConfig,
+    pos: usize,
+}
+
+/// Parse a template into segments.
+///
+/// The iterator yields an `Err` for each malformed reference and then resumes
+/// scanning after it, so all syntax errors in a template can be collected in
+/// one pass.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{segments, Segment};
+///
+/// let parsed: Vec<_> = segments("Hi ${NAME}!").collect::<Result<_, _>>().unwrap();
+/// assert_eq!(
+///     parsed,
+///     vec![
+///         Segment::Text("Hi "),
+///         Segment::Var {
+///             name: "NAME",
+///             span: 3..10,
+///             braced: true,
+///             quoted: false,
+///             length: false,
+///             transform: None,
+///             operator: None,
+///         },
+///         Segment::Text("!"),
+///     ]
+/// );
+/// ```
+pub fn segments(template: &str) -> Segments<'_> {
+    segments_with(template, &DEFAULT_SYNTAX)
+}
+
+/// Parse a template into segments using the given syntax.
+///
+/// Like [`segments`], but recognizes the delimiters, short syntax and escapes
+/// configured in `syntax` instead of the compiled-in defaults.
+pub fn segments_with<'a>(template: &'a str, syntax: &'a SyntaxConfig) -> Segments<'a> {
+    Segments {
+        template,
+        syntax,
+        pos: 0,
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = SubstResult<Segment<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.template.as_bytes();
+        let start = self.pos;
+        if start >= bytes.len() {
+            return None;
+        }
+
+        let syntax = self.syntax;
+        let open = syntax.open().as_bytes();
+        let close = syntax.close().as_bytes();
+        let dollar_pairs = syntax.dollar_pairs();
+
+        // Scan a run of literal text up to the next reference or escape
+        let mut i = start;
+        while i < bytes.len() {
+            if bytes[i] == open[0] && bytes[i..].starts_with(open) {
+                break;
+            }
+            match bytes[i] {
+                b'$' => match bytes.get(i + 1) {
+                    Some(&b) if syntax.short_syntax && is_var_char_start(b) => break,
+                    _ if syntax.strict_dollar => break,
+                    // Dollar sign followed by something else: both are literal
+                    Some(_) if dollar_pairs => i += 2,
+                    _ => i += 1,
+                },
+                b'\\' if syntax.escape && self.escapable_at(i + 1).is_some() => break,
+                _ => i += 1,
+            }
+        }
+
+        if i > start {
+            self.pos = i;
+            return Some(Ok(Segment::Text(&self.template[start..i])));
+        }
+
+        if bytes[i] == b'\\' {
+            let ch = self.escapable_at(i + 1)?;
+            self.pos = i + 1 + ch.len_utf8();
+            return Some(Ok(Segment::Escaped(ch)));
+        }
+
+        let short = syntax.short_syntax && bytes.get(i + 1).is_some_and(|&b| is_var_char_start(b));
+        if bytes[i] == b'$' && !short && !bytes[i..].starts_with(open) {
+            // Only reached with strict_dollar
+            self.pos = i + 1;
+            return Some(Err(SubstError::StrayDollar { position: i }));
+        }
+
+        if bytes[i..].starts_with(open) {
+            let length = bytes.get(i + open.len()) == Some(&b'#');
+            let name_start = i + open.len() + usize::from(length);
+            if syntax.quoted_names && matches!(bytes.get(name_start), Some(b'\'' | b'"')) {
+                return Some(self.quoted(i, name_start, length));
+            }
+            let mut j = name_start;
+            while j < bytes.len() && is_var_char(bytes[j]) {
+                j += 1;
+            }
+            let name = &self.template[name_start..j];
+            let (operator, j) = match (!length).then(|| self.operator_at(i, j)).flatten() {
+                Some(Ok((operator, close_at))) => (Some(operator), close_at),
+                Some(Err(error)) => return Some(Err(error)),
+                None => (None, j),
+            };
+            let (transform, j) = match (!length).then(|| self.transform_at(i, j)).flatten() {
+                Some(Ok((transform, after))) => (Some(transform), after),
+                Some(Err(error)) => return Some(Err(error)),
+                None => (None, j),
+            };
+
+            if j == bytes.len() {
+                self.pos = j;
+                return Some(Err(SubstError::UnclosedBrace { position: i }));
+            }
+            if !bytes[j..].starts_with(close) {
+                // Invalid character in variable name; resume at it
+                self.pos = j;
+                return Some(Err(SubstError::InvalidVarName {
+                    name: name.to_string(),
+                    position: i,
+                }));
+            }
+
+            let end = j + close.len();
+            self.pos = end;
+            if name.is_empty() {
+                return Some(Err(SubstError::InvalidVarName {
+                    name: String::new(),
+                    position: i,
+                }));
+            }
+            return Some(Ok(Segment::Var {
+                name,
+                span: i..end,
+                braced: true,
+                quoted: false,
+                length,
+                transform,
+                operator,
+            }));
+        }
+
+        // bytes[i] is '$' starting a short reference
+        let mut j = i + 1;
+        while j < bytes.len() && is_var_char(bytes[j]) {
+            j += 1;
+        }
+        self.pos = j;
+        Some(Ok(Segment::Var {
+            name: &self.template[i + 1..j],
+            span: i..j,
+            braced: false,
+            quoted: false,
+            length: false,
+            transform: None,
+            operator: None,
+        }))
+    }
+}
+
+impl<'a> Segments<'a> {
+    /// Byte offset where the next segment starts
+    pub(crate) fn offset(&self) -> usize {
+        self.pos
+    }
+
+    /// Parse the reference starting at `start` whose name is quoted, with the
+    /// opening quote at `quote_at`, and after a `#` if `length`
+    fn quoted(&mut self, start: usize, quote_at: usize, length: bool) -> SubstResult<Segment<'a>> {
+        let bytes = self.template.as_bytes();
+        let quote = bytes[quote_at];
+        let mut j = quote_at + 1;
+        while j < bytes.len() && bytes[j] != quote {
+            j += if bytes[j] == b'\\' { 2 } else { 1 };
+        }
+        if j >= bytes.len() {
+            self.pos = bytes.len();
+            return Err(SubstError::UnclosedBrace { position: start });
+        }
+        let name = &self.template[quote_at + 1..j];
+        let (operator, j) = match (!length).then(|| self.operator_at(start, j + 1)).flatten() {
+            Some(result) => result.map(|(operator, close_at)| (Some(operator), close_at))?,
+            None => (None, j + 1),
+        };
+        let (transform, j) = match (!length).then(|| self.transform_at(start, j)).flatten() {
+            Some(result) => result.map(|(transform, after)| (Some(transform), after))?,
+            None => (None, j),
+        };
+        let close = self.syntax.close().as_bytes();
+        if !bytes[j..].starts_with(close) {
+            self.pos = j;
+            return Err(SubstError::InvalidVarName {
+                name: unquote(name).into_owned(),
+                position: start,
+            });
+        }
+        let end = j + close.len();
+        self.pos = end;
+        if name.is_empty() {
+            return Err(SubstError::InvalidVarName {
+                name: String::new(),
+                position: start,
+            });
+        }
+        Ok(Segment::Var {
+            name,
+            span: start..end,
+            braced: true,
+            quoted: true,
+            length,
+            transform,
+            operator,
+        })
+    }
+
+    /// Parse an operator such as `:-` and its word at byte offset `at`, right
+    /// after the name of the reference starting at `start`, returning it
+    /// with the offset of the closing delimiter after the word. `None` if
+    /// there is no operator.
+    fn operator_at(
+        &mut self,
+        start: usize,
+        at: usize,
+    ) -> Option<SubstResult<(Operator<'a>, usize)>> {
+        let (kind, colon) = OperatorKind::at_start_of(self.template.get(at..)?)?;
+        let bytes = self.template.as_bytes();
+        let close = self.syntax.close().as_bytes();
+        let word_start = at + usize::from(colon) + 1;
+        let mut j = word_start;
+        // An escaped character never ends the word
+        while j < bytes.len() && !bytes[j..].starts_with(close) {
+            j += match bytes[j] {
+                b'\\' => {
+                    1 + self.template[j + 1..]
+                        .chars()
+                        .next()
+                        .map_or(0, char::len_utf8)
+                }
+                _ => 1,
+            };
+        }
+        if j >= bytes.len() {
+            self.pos = bytes.len();
+            return Some(Err(SubstError::UnclosedBrace { position: start }));
+        }
+        let word = &self.template[word_start..j];
+        Some(Ok((Operator { kind, colon, word }, j)))
+    }
+
+    /// Parse a transform such as `@Q` at byte offset `at`, right after the
+    /// name of the reference starting at `start`, returning it with the
+    /// offset after it. `None` if there is no `@` followed by one character
+    /// and the closing delimiter.
+    fn transform_at(&mut self, start: usize, at: usize) -> Option<SubstResult<(Transform, usize)>> {
+        let rest = self.template.get(at..)?.strip_prefix('@')?;
+        let directive = rest.chars().next()?;
+        if !rest[directive.len_utf8()..].starts_with(self.syntax.close()) {
+            return None;
+        }
+        let after = at + 1 + directive.len_utf8();
+        match Transform::from_directive(directive) {
+            Some(transform) => Some(Ok((transform, after))),
+            None => {
+                self.pos = after + self.syntax.close().len();
+                Some(Err(SubstError::UnknownTransform {
+                    directive,
+                    position: start,
+                }))
+            }
+        }
+    }
+
+    /// The character at byte offset `i` if it can be escaped
+    fn escapable_at(&self, i: usize) -> Option<char> {
+        let ch = self.template.get(i..)?.chars().next()?;
+        self.syntax.is_escapable(ch).then_some(ch)
+    }
+}
+
+/// The lookup key of a quoted name as written between the quotes: `\'`,
+/// `\"` and `\\` stand for the character after the backslash, and any other
+/// backslash is literal.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::unquote;
+///
+/// assert_eq!(unquote(r"it\'s \\ \n"), r"it's \ \n");
+/// ```
+pub fn unquote(name: &str) -> Cow<'_, str> {
+    if !name.contains('\\') {
+        return Cow::Borrowed(name);
+    }
+    let mut key = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some(next @ ('\'' | '"' | '\\'))) => {
+                key.push(next);
+                chars.next();
+            }
+            _ => key.push(ch),
+        }
+    }
+    Cow::Owned(key)
+}
+
+/// Check if a byte can start a variable name
+#[inline]
+fn is_var_char_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+/// Check if a byte can be part of a variable name
+#[inline]
+fn is_var_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(template: &str) -> Vec<SubstResult<Segment<'_>>> {
+        segments(template).collect()
+    }
+
+    #[test]
+    fn test_segments_spans() {
+        let template = "a ${X} b ${LONG_NAME}";
+        for segment in segments(template) {
+            if let Segment::Var { name, span, .. } = segment.unwrap() {
+                assert_eq!(&template[span.start + 2..span.end - 1], name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_segments_byte_positions() {
+        let result = collect("ü ${X");
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Text("ü ")),
+                Err(SubstError::UnclosedBrace { position: 3 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_resume_after_error() {
+        let result = collect("${A.B} ${} ${OK}");
+        assert_eq!(
+            result,
+            vec![
+                Err(SubstError::InvalidVarName {
+                    name: "A".to_string(),
+                    position: 0,
+                }),
+                Ok(Segment::Text(".B} ")),
+                Err(SubstError::InvalidVarName {
+                    name: String::new(),
+                    position: 7,
+                }),
+                Ok(Segment::Text(" ")),
+                Ok(Segment::Var {
+                    name: "OK",
+                    span: 11..16,
+                    braced: true,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_literal_dollars() {
+        let result = collect("$5 $$ $");
+        assert_eq!(result, vec![Ok(Segment::Text("$5 $$ $"))]);
+    }
+
+    #[test]
+    fn test_segments_strict_dollar() {
+        let syntax = SyntaxConfig::new()
+            .strict_dollar(true)
+            .short_syntax(false)
+            .escape(true);
+        let stray = |position| Err(SubstError::StrayDollar { position });
+        let collect = |template| segments_with(template, &syntax).collect::<Vec<_>>();
+        assert_eq!(collect("$ {X}"), vec![stray(0), Ok(Segment::Text(" {X}"))]);
+        assert_eq!(
+            collect("a$5 $VAR"),
+            vec![
+                Ok(Segment::Text("a")),
+                stray(1),
+                Ok(Segment::Text("5 ")),
+                stray(4),
+                Ok(Segment::Text("VAR")),
+            ]
+        );
+        assert_eq!(collect("x$"), vec![Ok(Segment::Text("x")), stray(1)]);
+        assert_eq!(collect("$$"), vec![stray(0), stray(1)]);
+        assert_eq!(
+            collect(r"\$ ${X}"),
+            vec![
+                Ok(Segment::Escaped('$')),
+                Ok(Segment::Text(" ")),
+                Ok(Segment::Var {
+                    name: "X",
+                    span: 3..7,
+                    braced: true,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+            ]
+        );
+
+        let short = syntax.clone().short_syntax(true);
+        let result: Vec<_> = segments_with("$X $", &short).collect();
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Var {
+                    name: "X",
+                    span: 0..2,
+                    braced: false,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+                Ok(Segment::Text(" ")),
+                stray(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_quoted_names() {
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        let collect = |template| segments_with(template, &syntax).collect::<Vec<_>>();
+        let var = |name, span| {
+            Ok(Segment::Var {
+                name,
+                span,
+                braced: true,
+                quoted: true,
+                length: false,
+                transform: None,
+                operator: None,
+            })
+        };
+        assert_eq!(
+            collect("${'Display Name'}"),
+            vec![var("Display Name", 0..17)]
+        );
+        assert_eq!(collect(r#"${"a\"b}"}"#), vec![var(r#"a\"b}"#, 0..10)]);
+        assert_eq!(
+            collect("${'x' ${\"\"}"),
+            vec![
+                Err(SubstError::InvalidVarName {
+                    name: "x".to_string(),
+                    position: 0,
+                }),
+                Ok(Segment::Text(" ")),
+                Err(SubstError::InvalidVarName {
+                    name: String::new(),
+                    position: 6,
+                }),
+            ]
+        );
+        assert_eq!(
+            collect(r"a ${'open\'}"),
+            vec![
+                Ok(Segment::Text("a ")),
+                Err(SubstError::UnclosedBrace { position: 2 }),
+            ]
+        );
+        // Without the option a quote is an invalid name character
+        let result: Vec<_> = segments("${'A'}").collect();
+        assert!(matches!(result[0], Err(SubstError::InvalidVarName { .. })));
+    }
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote("plain"), Cow::Borrowed("plain"));
+        assert_eq!(unquote(r#"\'\"\\\x"#), r#"'"\\x"#);
+        assert_eq!(unquote(r"trailing\"), r"trailing\");
+    }
+
+    #[test]
+    fn test_segments_transforms() {
+        let var = |name, span, quoted, transform| {
+            Ok(Segment::Var {
+                name,
+                span,
+                braced: true,
+                quoted,
+                length: false,
+                transform,
+                operator: None,
+            })
+        };
+        assert_eq!(
+            collect("${A@Q}${B@U} ${C@Z} ${D@}"),
+            vec![
+                var("A", 0..6, false, Some(Transform::Quote)),
+                var("B", 6..12, false, Some(Transform::Upper)),
+                Ok(Segment::Text(" ")),
+                Err(SubstError::UnknownTransform {
+                    directive: 'Z',
+                    position: 13,
+                }),
+                Ok(Segment::Text(" ")),
+                Err(SubstError::InvalidVarName {
+                    name: "D".to_string(),
+                    position: 20,
+                }),
+                Ok(Segment::Text("@}")),
+            ]
+        );
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        assert_eq!(
+            segments_with("${'a b'@L}", &syntax).collect::<Vec<_>>(),
+            vec![var("a b", 0..10, true, Some(Transform::Lower))]
+        );
+    }
+
+    #[test]
+    fn test_segments_default_operator() {
+        let var = |name, span, quoted, word| {
+            Ok(Segment::Var {
+                name,
+                span,
+                braced: true,
+                quoted,
+                length: false,
+                transform: None,
+                operator: Some(Operator {
+                    kind: OperatorKind::Default,
+                    colon: true,
+                    word,
+                }),
+            })
+        };
+        assert_eq!(
+            collect(r"${A:-x y/z}${B:-}${C:-a\}b}"),
+            vec![
+                var("A", 0..11, false, "x y/z"),
+                var("B", 11..17, false, ""),
+                var("C", 17..27, false, r"a\}b"),
+            ]
+        );
+        let operator = |kind, colon, word| Some(Operator { kind, colon, word });
+        let operators: Vec<_> = segments("${A-}${B=:x}${C?m}${D+-}")
+            .map(|segment| match segment {
+                Ok(Segment::Var { operator, .. }) => operator,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            operators,
+            vec![
+                operator(OperatorKind::Default, false, ""),
+                operator(OperatorKind::Assign, false, ":x"),
+                operator(OperatorKind::Required, false, "m"),
+                operator(OperatorKind::Alternate, false, "-"),
+            ]
+        );
+        assert_eq!(
+            collect("${:-x} ${A:-x"),
+            vec![
+                Err(SubstError::InvalidVarName {
+                    name: String::new(),
+                    position: 0,
+                }),
+                Ok(Segment::Text(" ")),
+                Err(SubstError::UnclosedBrace { position: 7 }),
+            ]
+        );
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        assert_eq!(
+            segments_with("${'a b':-${X}}", &syntax).collect::<Vec<_>>(),
+            vec![var("a b", 0..13, true, "${X"), Ok(Segment::Text("}")),]
+        );
+    }
+
+    #[test]
+    fn test_segments_length() {
+        let var = |name, span, quoted| {
+            Ok(Segment::Var {
+                name,
+                span,
+                braced: true,
+                quoted,
+                length: true,
+                transform: None,
+                operator: None,
+            })
+        };
+        let invalid = |name: &str, position| {
+            Err(SubstError::InvalidVarName {
+                name: name.to_string(),
+                position,
+            })
+        };
+        assert_eq!(
+            collect("${#A}${#} ${#B@Q} ${#C:-x}"),
+            vec![
+                var("A", 0..5, false),
+                invalid("", 5),
+                Ok(Segment::Text(" ")),
+                invalid("B", 10),
+                Ok(Segment::Text("@Q} ")),
+                invalid("C", 18),
+                Ok(Segment::Text(":-x}")),
+            ]
+        );
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        assert_eq!(
+            segments_with("${#'a b'}", &syntax).collect::<Vec<_>>(),
+            vec![var("a b", 0..9, true)]
+        );
+    }
+
+    #[cfg(feature = "escape")]
+    #[test]
+    fn test_segments_escapes() {
+        let result = collect(r"\${X\} \a");
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Escaped('$')),
+                Ok(Segment::Text("{X")),
+                Ok(Segment::Escaped('}')),
+                Ok(Segment::Text(r" \a")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_custom_delimiters() {
+        let syntax = SyntaxConfig::new().delimiters("{{", "}}").unwrap();
+        let result: Vec<_> = segments_with("${A} {{B}} {C}", &syntax).collect();
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Text("${A} ")),
+                Ok(Segment::Var {
+                    name: "B",
+                    span: 5..10,
+                    braced: true,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+                Ok(Segment::Text(" {C}")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_identical_delimiters() {
+        let syntax = SyntaxConfig::new().delimiters("%", "%").unwrap();
+        let result: Vec<_> = segments_with("%A%%B%", &syntax).collect();
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Var {
+                    name: "A",
+                    span: 0..3,
+                    braced: true,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+                Ok(Segment::Var {
+                    name: "B",
+                    span: 3..6,
+                    braced: true,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_segments_escape_custom_delimiter() {
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .delimiters("{{", "}}")
+            .unwrap();
+        let result: Vec<_> = segments_with(r"\{{A}} \$", &syntax).collect();
+        assert_eq!(
+            result,
+            vec![Ok(Segment::Escaped('{')), Ok(Segment::Text(r"{A}} \$")),]
+        );
+    }
+
+    #[test]
+    fn test_segments_runtime_toggles() {
+        let syntax = SyntaxConfig::new().short_syntax(true).escape(false);
+        let result: Vec<_> = segments_with(r"\$A", &syntax).collect();
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Text(r"\")),
+                Ok(Segment::Var {
+                    name: "A",
+                    span: 1..3,
+                    braced: false,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+            ]
+        );
+    }
+
+    #[cfg(feature = "short_syntax")]
+    #[test]
+    fn test_segments_short_syntax() {
+        let result = collect("$A-$B");
+        assert_eq!(
+            result,
+            vec![
+                Ok(Segment::Var {
+                    name: "A",
+                    span: 0..2,
+                    braced: false,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+                Ok(Segment::Text("-")),
+                Ok(Segment::Var {
+                    name: "B",
+                    span: 3..5,
+                    braced: false,
+                    quoted: false,
+                    length: false,
+                    transform: None,
+                    operator: None,
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Source excerpts with a caret underline, for pointing at errors.
+
+use std::fmt;
+use std::ops::Range;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// The source line containing a byte range, with the range underlined
+///
+/// Displaying a snippet prints the line with its number in a gutter, then a
+/// line of carets under the range. A range that continues past the end of
+/// its first line is underlined to the end of that line; an empty range gets
+/// a single caret. With [`path`](Snippet::path), the block starts with a
+/// `--> path:line:column` header.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::Snippet;
+///
+/// let template = "name: ${NAME}\nport: ${}\n";
+/// let snippet = Snippet::new(template, 20..23);
+/// assert_eq!(snippet.line(), 2);
+/// assert_eq!(snippet.column(), 7);
+/// assert_eq!(
+///     snippet.to_string(),
+///     "  |\n2 | port: ${}\n  |       ^^^\n"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet<'a> {
+    source: &'a str,
+    span: Range<usize>,
+    first_line: usize,
+    path: Option<&'a str>,
+    color: bool,
+}
+
+impl<'a> Snippet<'a> {
+    /// Point at `span`, a byte range of `source`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `span` is out of bounds or not on character boundaries.
+    pub fn new(source: &'a str, span: Range<usize>) -> Self {
+        assert!(
+            span.start <= span.end
+                && source.is_char_boundary(span.start)
+                && source.is_char_boundary(span.end),
+            "span {:?} is not a valid range of the source",
+            span
+        );
+        Snippet {
+            source,
+            span,
+            first_line: 1,
+            path: None,
+            color: false,
+        }
+    }
+
+    /// Number lines from `line` instead of 1, for sources that are an
+    /// excerpt of a larger file
+    #[must_use]
+    pub fn first_line(mut self, line: usize) -> Self {
+        self.first_line = line;
+        self
+    }
+
+    /// Name the file the source comes from in a header line
+    #[must_use]
+    pub fn path(mut self, path: &'a str) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// Color the gutter and the underline with ANSI escape codes
+    #[must_use]
+    pub fn color(mut self, enabled: bool) -> Self {
+        self.color = enabled;
+        self
+    }
+
+    /// 1-based number of the line containing the start of the span
+    pub fn line(&self) -> usize {
+        self.first_line + self.source[..self.span.start].matches('\n').count()
+    }
+
+    /// 1-based column of the start of the span, counted in characters
+    pub fn column(&self) -> usize {
+        self.source[self.line_start()..self.span.start]
+            .chars()
+            .count()
+            + 1
+    }
+
+    fn line_start(&self) -> usize {
+        self.source[..self.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1)
+    }
+}
+
+impl fmt::Display for Snippet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.line_start();
+        let end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let text = self.source[start..end].trim_end_matches('\r');
+        let underlined = &self.source[self.span.start..self.span.end.min(start + text.len())];
+
+        // Keep tabs so the carets line up under the same characters
+        let indent: String = self.source[start..self.span.start]
+            .chars()
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(underlined.chars().count().max(1));
+
+        let number = self.line().to_string();
+        let blank = " ".repeat(number.len());
+        let (red, blue, reset) = match self.color {
+            true => (RED, BLUE, RESET),
+            false => ("", "", ""),
+        };
+        if let Some(path) = self.path {
+            let (line, column) = (self.line(), self.column());
+            writeln!(f, "{blank}{blue}-->{reset} {path}:{line}:{column}")?;
+        }
+        writeln!(f, "{blue}{blank} |{reset}")?;
+        writeln!(f, "{blue}{number} |{reset} {text}")?;
+        writeln!(f, "{blue}{blank} |{reset} {indent}{red}{carets}{reset}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_line() {
+        let snippet = Snippet::new("x = ${A}", 4..8);
+        assert_eq!(snippet.line(), 1);
+        assert_eq!(snippet.column(), 5);
+        assert_eq!(snippet.to_string(), "  |\n1 | x = ${A}\n  |     ^^^^\n");
+    }
+
+    #[test]
+    fn test_multiline_span_is_cut_at_line_end() {
+        let snippet = Snippet::new("a\nb ${OPEN\nc\n", 4..13);
+        assert_eq!(snippet.to_string(), "  |\n2 | b ${OPEN\n  |   ^^^^^^\n");
+    }
+
+    #[test]
+    fn test_empty_span_and_wide_gutter() {
+        let snippet = Snippet::new("ü${", 2..2).first_line(99);
+        assert_eq!(snippet.line(), 99);
+        assert_eq!(snippet.column(), 2);
+        assert_eq!(snippet.to_string(), "   |\n99 | ü${\n   |  ^\n");
+    }
+
+    #[test]
+    fn test_path_header() {
+        let snippet = Snippet::new("x\n ${}", 3..6).first_line(4).path("t.txt");
+        assert_eq!(
+            snippet.to_string(),
+            " --> t.txt:5:2\n  |\n5 |  ${}\n  |  ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_tabs_and_crlf() {
+        let snippet = Snippet::new("\tv=${A}\r\n", 3..7);
+        assert_eq!(snippet.to_string(), "  |\n1 | \tv=${A}\n  | \t  ^^^^\n");
+    }
+
+    #[test]
+    fn test_color() {
+        let snippet = Snippet::new("${A}", 0..4).color(true);
+        let text = snippet.to_string();
+        assert!(text.contains("\x1b[1;31m^^^^\x1b[0m"), "{:?}", text);
+        assert!(text.contains("\x1b[1;34m1 |\x1b[0m ${A}"), "{:?}", text);
+    }
+}
@@ -0,0 +1,464 @@
+//! Substitution from an `io::Read` to an `io::Write` in bounded memory.
+//!
+//! The input is decoded and substituted chunk by chunk. Only the tail of a
+//! chunk that could still parse differently once more input arrives, such as
+//! a reference whose closing delimiter has not been read yet, is carried over
+//! to the next chunk.
+
+use crate::{segments_with, Expander, Reference, Segment, SubstError, SubstOptions, SubstResult};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Bytes requested from the reader at a time
+pub(crate) const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Counts returned by [`substitute_stream`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstStats {
+    /// Bytes read from the input
+    pub bytes_read: u64,
+    /// Bytes written to the output
+    pub bytes_written: u64,
+    /// References substituted, including undefined ones that were kept or
+    /// replaced according to [`SubstOptions::missing`]
+    pub references: usize,
+}
+
+/// Error returned by [`substitute_stream`]
+#[derive(Debug)]
+pub enum StreamError {
+    /// The input could not be substituted; positions are byte offsets into
+    /// the whole input
+    Subst(SubstError),
+    /// Reading the input failed, or it was not valid UTF-8
+    Read(io::Error),
+    /// Writing the output failed
+    Write(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Subst(error) => error.fmt(f),
+            StreamError::Read(error) => write!(f, "Error reading input: {}", error),
+            StreamError::Write(error) => write!(f, "Error writing output: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamError::Subst(error) => Some(error),
+            StreamError::Read(error) | StreamError::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<SubstError> for StreamError {
+    fn from(error: SubstError) -> Self {
+        StreamError::Subst(error)
+    }
+}
+
+/// Substitute variables in everything read from `reader`, writing the output
+/// to `writer` as it is produced.
+///
+/// The result is the same as reading the whole input into a `String` and
+/// calling [`substitute_with_options`](crate::substitute_with_options), but
+/// memory use is bounded by the chunk size and the longest reference rather
+/// than the input size. References, escapes and UTF-8 characters split
+/// between reads are handled. On error, the output up to the last complete
+/// chunk before the failing reference has been written. `writer` is flushed
+/// at the end.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_stream, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("TABLE", "users")]);
+/// let input = "SELECT * FROM ${TABLE};\n".repeat(1000);
+/// let mut output = Vec::new();
+/// let stats = substitute_stream(input.as_bytes(), &mut output, &vars, &SubstOptions::new())?;
+/// assert_eq!(stats.references, 1000);
+/// assert!(output.starts_with(b"SELECT * FROM users;\nSELECT"));
+/// # Ok::<(), varsubst::StreamError>(())
+/// ```
+pub fn substitute_stream<R, W, K, V, S>(
+    reader: R,
+    writer: W,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+) -> Result<SubstStats, StreamError>
+where
+    R: Read,
+    W: Write,
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    stream(reader, writer, variables, options, CHUNK_SIZE)
+}
+
+/// [`substitute_stream`] reading at most `chunk_size` bytes at a time
+fn stream<R, W, K, V, S>(
+    mut reader: R,
+    mut writer: W,
+    variables: &HashMap<K, V, S>,
+    options: &SubstOptions,
+    chunk_size: usize,
+) -> Result<SubstStats, StreamError>
+where
+    R: Read,
+    W: Write,
+    K: AsRef<str> + std::hash::Hash + Eq,
+    V: AsRef<str>,
+    S: std::hash::BuildHasher,
+{
+    let mut expander = Expander::new(variables, options);
+    let mut stats = SubstStats::default();
+    let mut chunk = vec![0; chunk_size];
+    let mut pending = Pending::default();
+    let mut output = String::new();
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(StreamError::Read(e)),
+        };
+        stats.bytes_read += read as u64;
+        let eof = read == 0;
+        match eof {
+            true => pending.close(),
+            false => pending.push(&chunk[..read]),
+        }
+        .map_err(StreamError::Read)?;
+
+        output.clear();
+        stats.references += pending.substitute(&mut expander, eof, &mut output)?;
+        writer
+            .write_all(output.as_bytes())
+            .map_err(StreamError::Write)?;
+        stats.bytes_written += output.len() as u64;
+        if eof {
+            break;
+        }
+    }
+    writer.flush().map_err(StreamError::Write)?;
+    Ok(stats)
+}
+
+/// Input that has arrived in pieces but has not been substituted yet
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    /// Decoded input that could still parse differently with more input
+    text: String,
+    /// Byte offset of `text` in the whole input
+    base: usize,
+    /// The bytes of a character split by the end of the last piece
+    partial: Vec<u8>,
+    /// What `${NAME:=word}` assigned in the input so far, kept for the
+    /// expanders of later pieces
+    assigned: Vec<(String, String)>,
+}
+
+impl Pending {
+    /// Append the next piece of input
+    pub(crate) fn push(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !self.partial.is_empty() {
+            let Some((&byte, rest)) = bytes.split_first() else {
+                return Ok(());
+            };
+            self.partial.push(byte);
+            bytes = rest;
+            match std::str::from_utf8(&self.partial) {
+                Ok(ch) => {
+                    self.text.push_str(ch);
+                    self.partial.clear();
+                }
+                Err(e) if e.error_len().is_some() => return Err(invalid_utf8()),
+                Err(_) => {}
+            }
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(text) => self.text.push_str(text),
+            Err(e) if e.error_len().is_none() => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                self.text
+                    .push_str(std::str::from_utf8(valid).expect("valid up to here"));
+                self.partial.extend_from_slice(rest);
+            }
+            Err(_) => return Err(invalid_utf8()),
+        }
+        Ok(())
+    }
+
+    /// Check that the input did not end inside a character
+    pub(crate) fn close(&self) -> io::Result<()> {
+        match self.partial.is_empty() {
+            true => Ok(()),
+            false => Err(invalid_utf8()),
+        }
+    }
+
+    /// Substitute the segments that more input cannot change into `output`,
+    /// or all of them at `eof`, returning the number of references among
+    /// them. Error positions are byte offsets into the whole input.
+    pub(crate) fn substitute(
+        &mut self,
+        expander: &mut Expander,
+        eof: bool,
+        output: &mut String,
+    ) -> SubstResult<usize> {
+        let mut references = 0;
+        std::mem::swap(&mut expander.assigned, &mut self.assigned);
+        let done = self.complete(expander, eof, output, &mut references);
+        std::mem::swap(&mut expander.assigned, &mut self.assigned);
+        let done = done?;
+        self.text.drain(..done);
+        self.base += done;
+        Ok(references)
+    }
+
+    /// Substitute into `output`, returning how many bytes of `text` are done
+    fn complete(
+        &self,
+        expander: &mut Expander,
+        eof: bool,
+        output: &mut String,
+        references: &mut usize,
+    ) -> SubstResult<usize> {
+        let (pending, base) = (&self.text[..], self.base);
+        let syntax = &expander.options.syntax;
+        // How far past the end of a segment the parser may look to decide it:
+        // a delimiter, or an `@` and a transform letter before the closing one
+        let lookahead = match eof {
+            true => 0,
+            false => syntax.open().len() + syntax.close().len() + 1 + char::MAX.len_utf8(),
+        };
+        let mut segments = segments_with(pending, syntax);
+        loop {
+            let start = segments.offset();
+            let Some(segment) = segments.next() else {
+                return Ok(pending.len());
+            };
+            if segments.offset() + lookahead > pending.len() {
+                // Literal text can be cut short and resumed
+                return Ok(match segment {
+                    Ok(Segment::Text(_)) => {
+                        let limit = pending.len() - lookahead.min(pending.len());
+                        let end = resume_point(pending, start, limit);
+                        output.push_str(&pending[start..end]);
+                        end
+                    }
+                    _ => start,
+                });
+            }
+            match segment.map_err(|e| {
+                let position = base + e.position();
+                e.at(position)
+            })? {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Escaped(ch) => output.push(ch),
+                Segment::Var {
+                    name,
+                    span,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                    ..
+                } => {
+                    *references += 1;
+                    let reference = Reference {
+                        name,
+                        quoted,
+                        length,
+                        transform,
+                        operator,
+                        text: &pending[span.clone()],
+                    };
+                    expander.var(&reference, base + span.start, output)?;
+                }
+            }
+        }
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    )
+}
+
+/// The last offset up to `limit` in the literal text starting at `start`
+/// where scanning it can stop and resume: a character boundary that is not
+/// right after a `$`, which may pair with the next character
+fn resume_point(text: &str, start: usize, limit: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut at = limit.max(start);
+    while at > start && (!text.is_char_boundary(at) || bytes[at - 1] == b'$') {
+        at -= 1;
+    }
+    at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{substitute_with_options, MissingAction, SyntaxConfig};
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("VAR", "value"), ("Ü", "x"), ("NESTED", "<${VAR}>")])
+    }
+
+    fn chunked(
+        input: &[u8],
+        options: &SubstOptions,
+        chunk_size: usize,
+    ) -> (Vec<u8>, Result<SubstStats, StreamError>) {
+        let mut output = Vec::new();
+        let result = stream(input, &mut output, &vars(), options, chunk_size);
+        (output, result)
+    }
+
+    #[test]
+    fn test_reference_split_across_chunks() {
+        let options = SubstOptions::new();
+        let (output, result) = chunked(b"a${VAR}b", &options, 2);
+        assert_eq!(output, b"avalueb");
+        let stats = result.unwrap();
+        assert_eq!(
+            stats,
+            SubstStats {
+                bytes_read: 8,
+                bytes_written: 7,
+                references: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_assignment_seen_by_later_chunks() {
+        let options = SubstOptions::new();
+        let input = b"${PORT:=8080} and later ${PORT} ${VAR:=x}";
+        for chunk_size in 1..=input.len() {
+            let (output, result) = chunked(input, &options, chunk_size);
+            result.unwrap();
+            assert_eq!(
+                output, b"8080 and later 8080 value",
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn test_utf8_split_across_chunks() {
+        let options = SubstOptions::new();
+        let input = "ü€😀 ${VAR} 😀".as_bytes();
+        for chunk_size in 1..=4 {
+            let (output, result) = chunked(input, &options, chunk_size);
+            result.unwrap();
+            assert_eq!(output, "ü€😀 value 😀".as_bytes(), "{}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_matches_substitute_for_every_chunk_size() {
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .short_syntax(true)
+            .quoted_names(true);
+        let templates = [
+            "",
+            "plain text without references",
+            "${VAR}${VAR}$VAR ${MISSING} $$VAR $${VAR} $",
+            r"\${VAR} \\${VAR} \x ${VAR@Q}${VAR@U} ${'VAR'} ${'it\'s'}",
+            "ü${VAR}é $é ${NESTED}",
+            "bad ${VAR-x} ${} ${VAR@Z} ${VAR",
+            "${VAR@",
+        ];
+        let option_sets = [
+            SubstOptions::new().syntax(syntax.clone()),
+            SubstOptions::new()
+                .syntax(syntax.clone())
+                .missing(MissingAction::Error)
+                .recursive(true),
+            SubstOptions::new().syntax(SyntaxConfig::new().delimiters("{{", "}}").unwrap()),
+        ];
+        for options in &option_sets {
+            for template in templates {
+                let expected = substitute_with_options(template, &vars(), options);
+                for chunk_size in 1..=template.len() + 1 {
+                    let (output, result) = chunked(template.as_bytes(), options, chunk_size);
+                    match (&expected, result) {
+                        (Ok(expected), Ok(_)) => {
+                            assert_eq!(output, expected.as_bytes(), "{} / {}", template, chunk_size)
+                        }
+                        (Err(expected), Err(StreamError::Subst(error))) => {
+                            assert_eq!(&error, expected, "{} / {}", template, chunk_size)
+                        }
+                        (expected, result) => panic!(
+                            "{} / {}: expected {:?}, got {:?}",
+                            template, chunk_size, expected, result
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_literal_text_is_not_held_back() {
+        let mut pending = Pending::default();
+        pending.push("x".repeat(100).as_bytes()).unwrap();
+        let mut output = String::new();
+        let options = SubstOptions::new();
+        let vars = vars();
+        let mut expander = Expander::new(&vars, &options);
+        pending
+            .substitute(&mut expander, false, &mut output)
+            .unwrap();
+        let done = pending.base;
+        assert!(done > 80, "{}", done);
+        assert_eq!(output.len(), done);
+        // A `$` at the cut point could still pair with what follows
+        assert_eq!(resume_point("ab$$", 0, 4), 2);
+        assert_eq!(resume_point("aü", 0, 2), 1);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let options = SubstOptions::new();
+        for input in [&b"ok \xff ${VAR}"[..], b"truncated \xc3"] {
+            let (_, result) = chunked(input, &options, 4);
+            assert!(
+                matches!(result, Err(StreamError::Read(ref e)) if e.kind() == io::ErrorKind::InvalidData),
+                "{:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_error() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let error =
+            substitute_stream(&b"${VAR}"[..], Full, &vars(), &SubstOptions::new()).unwrap_err();
+        assert!(matches!(error, StreamError::Write(_)));
+        assert_eq!(error.to_string(), "Error writing output: disk full");
+    }
+}
@@ -0,0 +1,94 @@
+//! "Did you mean" suggestions for undefined variable names.
+
+/// How many suggestions an error carries at most
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The names among `names` that `name` is probably a misspelling of, best
+/// first and at most three.
+///
+/// A name is suggested if it differs from `name` only in case, or by a few
+/// insertions, deletions, substitutions or swaps of adjacent characters: one
+/// per three characters of `name`.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::suggestions;
+///
+/// let names = ["DB_HOST", "DB_PORT", "HOME"];
+/// assert_eq!(suggestions("DB_HSOT", names), vec!["DB_HOST"]);
+/// assert_eq!(suggestions("home", names), vec!["HOME"]);
+/// assert!(suggestions("USER", names).is_empty());
+/// ```
+pub fn suggestions<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let limit = name.chars().count() / 3;
+    let mut candidates: Vec<(usize, &str)> = names
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .filter_map(|candidate| {
+            if candidate.eq_ignore_ascii_case(name) {
+                return Some((0, candidate));
+            }
+            let distance = distance(name, candidate);
+            (distance <= limit).then_some((distance, candidate))
+        })
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+    (candidates.into_iter())
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// Edit distance between `a` and `b`, counting a swap of two adjacent
+/// characters as one edit
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // Three rows of the table: two rows back, the previous one and this one
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("abc", "abc"), 0);
+        assert_eq!(distance("abc", "acb"), 1);
+        assert_eq!(distance("abc", "ab"), 1);
+        assert_eq!(distance("", "ab"), 2);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_transposition_and_case() {
+        let names = ["DB_HOSTS", "DB_HOST", "db_host", "PORT", "P"];
+        assert_eq!(suggestions("DB_HSOT", names), vec!["DB_HOST", "DB_HOSTS"]);
+        assert_eq!(suggestions("db_HOST", names), vec!["DB_HOST", "db_host"]);
+        assert_eq!(suggestions("port", names), vec!["PORT"]);
+    }
+
+    #[test]
+    fn test_no_suggestions() {
+        assert!(suggestions("HOST", ["PATH", "USER"]).is_empty());
+        assert!(suggestions("A", ["A", "B"]).is_empty());
+        assert!(suggestions("X", std::iter::empty()).is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+//! Runtime syntax configuration: delimiters, short syntax and escapes.
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Which template syntax is recognized
+///
+/// The defaults match the crate features: `${NAME}` references, `$NAME` if the
+/// `short_syntax` feature is enabled, and escapes if the `escape` feature is
+/// enabled.
+///
+/// With escapes enabled, a backslash followed by `\` or by any character of
+/// the delimiters produces that character literally, so `\$` and `\{` work
+/// with the default delimiters and `\{` with `{{NAME}}`.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_with_options, SubstOptions, SyntaxConfig};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("NAME", "World")]);
+/// let syntax = SyntaxConfig::new().delimiters("{{", "}}").unwrap();
+/// let options = SubstOptions::new().syntax(syntax);
+/// let result = substitute_with_options("Hello {{NAME}}!", &vars, &options).unwrap();
+/// assert_eq!(result, "Hello World!");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxConfig {
+    /// Whether `$NAME` references are recognized
+    pub short_syntax: bool,
+    /// Whether backslash escape sequences are recognized
+    pub escape: bool,
+    /// Whether a `$` that does not start a reference is an error
+    pub strict_dollar: bool,
+    /// Whether names may be quoted, as in `${'Display Name'}`
+    pub quoted_names: bool,
+    open: Cow<'static, str>,
+    close: Cow<'static, str>,
+}
+
+/// The compiled-in default syntax
+pub(crate) static DEFAULT_SYNTAX: SyntaxConfig = SyntaxConfig::DEFAULT;
+
+impl SyntaxConfig {
+    const DEFAULT: SyntaxConfig = SyntaxConfig {
+        short_syntax: cfg!(feature = "short_syntax"),
+        escape: cfg!(feature = "escape"),
+        strict_dollar: false,
+        quoted_names: false,
+        open: Cow::Borrowed("${"),
+        close: Cow::Borrowed("}"),
+    };
+
+    /// Create the default syntax
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable or disable `$NAME` references
+    #[must_use]
+    pub fn short_syntax(mut self, enabled: bool) -> Self {
+        self.short_syntax = enabled;
+        self
+    }
+
+    /// Enable or disable backslash escape sequences
+    #[must_use]
+    pub fn escape(mut self, enabled: bool) -> Self {
+        self.escape = enabled;
+        self
+    }
+
+    /// Make every `$` that does not start a reference, and is not escaped,
+    /// fail with [`SubstError::StrayDollar`](crate::SubstError::StrayDollar)
+    /// instead of being copied literally.
+    ///
+    /// A `$` starts a reference if it begins the opening delimiter, or a name
+    /// character follows it and short syntax is enabled; so `$ {X}`, `$5`,
+    /// `$$` and a trailing `$` are errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute_with_options, SubstError, SubstOptions, SyntaxConfig};
+    /// use std::collections::HashMap;
+    ///
+    /// let vars = HashMap::from([("X", "1")]);
+    /// let options = SubstOptions::new().syntax(SyntaxConfig::new().strict_dollar(true));
+    /// let result = substitute_with_options("$ {X}", &vars, &options);
+    /// assert_eq!(result, Err(SubstError::StrayDollar { position: 0 }));
+    /// ```
+    #[must_use]
+    pub fn strict_dollar(mut self, enabled: bool) -> Self {
+        self.strict_dollar = enabled;
+        self
+    }
+
+    /// Allow names in single or double quotes between the delimiters, such
+    /// as `${'Display Name'}` or `${"build:number"}`, for keys that are not
+    /// valid names.
+    ///
+    /// The quoted text is the exact lookup key, except that `\'`, `\"` and
+    /// `\\` stand for the character after the backslash (see
+    /// [`unquote`](crate::unquote)). A reference kept because the variable is
+    /// undefined is copied as written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{substitute_with_options, SubstOptions, SyntaxConfig};
+    /// use std::collections::HashMap;
+    ///
+    /// let vars = HashMap::from([("Display Name", "Alice")]);
+    /// let options = SubstOptions::new().syntax(SyntaxConfig::new().quoted_names(true));
+    /// let result = substitute_with_options("Hi ${'Display Name'}", &vars, &options).unwrap();
+    /// assert_eq!(result, "Hi Alice");
+    /// ```
+    #[must_use]
+    pub fn quoted_names(mut self, enabled: bool) -> Self {
+        self.quoted_names = enabled;
+        self
+    }
+
+    /// Use `open` and `close` around variable names instead of `${` and `}`.
+    ///
+    /// Delimiters must be non-empty, must not contain whitespace, backslashes
+    /// or variable name characters, and neither may be a prefix of the other
+    /// unless they are identical (as in `%NAME%`).
+    pub fn delimiters(
+        mut self,
+        open: impl Into<String>,
+        close: impl Into<String>,
+    ) -> Result<Self, DelimiterError> {
+        let (open, close) = (open.into(), close.into());
+        for delimiter in [&open, &close] {
+            if delimiter.is_empty() {
+                return Err(DelimiterError::Empty);
+            }
+            if let Some(ch) = delimiter.chars().find(|&ch| {
+                ch.is_whitespace() || ch == '\\' || ch.is_ascii_alphanumeric() || ch == '_'
+            }) {
+                return Err(DelimiterError::InvalidChar(ch));
+            }
+        }
+        if open != close && (open.starts_with(&close) || close.starts_with(&open)) {
+            return Err(DelimiterError::Overlapping { open, close });
+        }
+        self.open = Cow::Owned(open);
+        self.close = Cow::Owned(close);
+        Ok(self)
+    }
+
+    /// The opening delimiter
+    pub fn open(&self) -> &str {
+        &self.open
+    }
+
+    /// The closing delimiter
+    pub fn close(&self) -> &str {
+        &self.close
+    }
+
+    /// Whether `ch` may follow a backslash to form an escape sequence
+    pub(crate) fn is_escapable(&self, ch: char) -> bool {
+        ch == '\\' || self.open.contains(ch) || self.close.contains(ch)
+    }
+
+    /// Whether a `$` not starting a reference swallows the next character,
+    /// so that `$${X}` stays literal with the default delimiters
+    pub(crate) fn dollar_pairs(&self) -> bool {
+        self.open.starts_with('$')
+    }
+
+    /// Cheap check whether `template` could contain anything but plain text
+    pub(crate) fn may_need_processing(&self, template: &str) -> bool {
+        let open_first = self.open.chars().next().unwrap_or('$');
+        template.contains(open_first)
+            || ((self.short_syntax || self.strict_dollar) && template.contains('$'))
+            || (self.escape && template.contains('\\'))
+    }
+}
+
+impl Default for SyntaxConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Error returned by [`SyntaxConfig::delimiters`] for unusable delimiters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DelimiterError {
+    /// A delimiter is empty
+    Empty,
+    /// A delimiter contains whitespace, a backslash or a variable name character
+    InvalidChar(char),
+    /// One delimiter is a prefix of the other, so references are ambiguous
+    Overlapping {
+        /// The opening delimiter
+        open: String,
+        /// The closing delimiter
+        close: String,
+    },
+}
+
+impl fmt::Display for DelimiterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelimiterError::Empty => write!(f, "delimiters must not be empty"),
+            DelimiterError::InvalidChar(ch) => {
+                write!(f, "delimiters must not contain '{}'", ch.escape_default())
+            }
+            DelimiterError::Overlapping { open, close } => write!(
+                f,
+                "delimiters '{}' and '{}' overlap; neither may be a prefix of the other",
+                open, close
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DelimiterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_features() {
+        let syntax = SyntaxConfig::default();
+        assert_eq!(syntax.open(), "${");
+        assert_eq!(syntax.close(), "}");
+        assert_eq!(syntax.short_syntax, cfg!(feature = "short_syntax"));
+        assert_eq!(syntax.escape, cfg!(feature = "escape"));
+    }
+
+    #[test]
+    fn test_delimiter_validation() {
+        let new = SyntaxConfig::new;
+        assert_eq!(new().delimiters("", "}}"), Err(DelimiterError::Empty));
+        assert_eq!(
+            new().delimiters("{ {", "}"),
+            Err(DelimiterError::InvalidChar(' '))
+        );
+        assert_eq!(
+            new().delimiters("<", "<<"),
+            Err(DelimiterError::Overlapping {
+                open: "<".to_string(),
+                close: "<<".to_string()
+            })
+        );
+        assert!(new().delimiters("%", "%").is_ok());
+        assert!(new().delimiters("{{", "}}").is_ok());
+    }
+}
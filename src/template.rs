@@ -0,0 +1,561 @@
+//! Templates parsed once and rendered many times.
+
+use crate::{segments_with, Expander, Segment, SubstOptions, SubstResult, SyntaxConfig};
+use crate::{unquote, Operator, OperatorKind, Reference, Transform, VarProvider};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+#[cfg(feature = "serialize")]
+mod encoding;
+
+#[cfg(feature = "serialize")]
+pub use encoding::TemplateDecodeError;
+
+/// A parsed template that can be rendered repeatedly without parsing again
+///
+/// Syntax errors are reported by [`Template::parse`], so rendering can only
+/// fail on undefined variables with [`MissingAction::Error`] or while
+/// expanding values with [`SubstOptions::recursive`].
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::Template;
+/// use std::collections::HashMap;
+///
+/// let template = Template::parse("Hello ${NAME}!")?;
+/// for name in ["Alice", "Bob"] {
+///     let vars = HashMap::from([("NAME", name)]);
+///     assert_eq!(template.render(&vars)?, format!("Hello {}!", name));
+/// }
+/// # Ok::<(), varsubst::SubstError>(())
+/// ```
+///
+/// [`MissingAction::Error`]: crate::MissingAction::Error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    /// Literal output of all text and escapes, in order
+    literals: String,
+    parts: Vec<Part>,
+}
+
+/// A piece of a parsed [`Template`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// Literal output: a range of `literals`, merging adjacent text and escapes
+    Text(Range<usize>),
+    /// A reference, with ranges of `source`
+    Var {
+        name: Range<usize>,
+        reference: Range<usize>,
+        quoted: bool,
+        length: bool,
+        transform: Option<Transform>,
+        operator: Option<(OperatorKind, bool, Range<usize>)>,
+    },
+}
+
+impl Template {
+    /// Parse `template` with the default syntax
+    pub fn parse(template: &str) -> SubstResult<Self> {
+        Self::parse_with(template, &SyntaxConfig::default())
+    }
+
+    /// Parse `template` with the delimiters, short syntax and escapes of
+    /// `syntax`, failing on its first syntax error
+    pub fn parse_with(template: &str, syntax: &SyntaxConfig) -> SubstResult<Self> {
+        let mut literals = String::new();
+        let mut parts = Vec::new();
+        for segment in segments_with(template, syntax) {
+            match segment? {
+                Segment::Text(text) => push_literal(&mut parts, &mut literals, text),
+                Segment::Escaped(ch) => {
+                    push_literal(&mut parts, &mut literals, ch.encode_utf8(&mut [0; 4]))
+                }
+                Segment::Var {
+                    name,
+                    span,
+                    braced,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                } => {
+                    // The word ends right before the closing delimiter
+                    let operator = operator.map(|operator| {
+                        let end = span.end - syntax.close().len();
+                        let word = end - operator.word.len()..end;
+                        (operator.kind, operator.colon, word)
+                    });
+                    let start = match braced {
+                        true => {
+                            span.start
+                                + syntax.open().len()
+                                + usize::from(length)
+                                + usize::from(quoted)
+                        }
+                        false => span.start + 1,
+                    };
+                    parts.push(Part::Var {
+                        name: start..start + name.len(),
+                        reference: span,
+                        quoted,
+                        length,
+                        transform,
+                        operator,
+                    });
+                }
+            }
+        }
+        Ok(Template {
+            source: template.to_string(),
+            literals,
+            parts,
+        })
+    }
+
+    /// The template text that was parsed
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The names of the referenced variables, in order of first reference
+    /// and without duplicates. Quoted names are unquoted, so providing a
+    /// value for each name leaves no reference unsubstituted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{SyntaxConfig, Template};
+    ///
+    /// let syntax = SyntaxConfig::new().escape(true);
+    /// let template = Template::parse_with(r"${HOST}:${PORT} \${ESCAPED} ${HOST}", &syntax)?;
+    /// assert_eq!(template.variables(), ["HOST", "PORT"]);
+    /// # Ok::<(), varsubst::SubstError>(())
+    /// ```
+    pub fn variables(&self) -> Vec<Cow<'_, str>> {
+        let mut seen = HashSet::new();
+        (self.parts.iter())
+            .filter_map(|part| self.key(part))
+            .filter(|name| seen.insert(name.clone()))
+            .collect()
+    }
+
+    /// Render with default options: undefined variables are kept verbatim
+    pub fn render<P>(&self, variables: &P) -> SubstResult<String>
+    where
+        P: VarProvider + ?Sized,
+    {
+        self.render_with_options(variables, &SubstOptions::default())
+    }
+
+    /// Render with undefined variables and recursion handled according to
+    /// `options`.
+    ///
+    /// The template's own syntax was fixed when it was parsed;
+    /// [`SubstOptions::syntax`] only applies to values expanded with
+    /// [`SubstOptions::recursive`].
+    pub fn render_with_options<P>(
+        &self,
+        variables: &P,
+        options: &SubstOptions,
+    ) -> SubstResult<String>
+    where
+        P: VarProvider + ?Sized,
+    {
+        let mut expander = Expander::with_provider(&variables, options);
+        let mut output = String::with_capacity(self.source.len());
+        for part in &self.parts {
+            self.write_part(part, &mut expander, &mut output)?;
+        }
+        Ok(output)
+    }
+
+    /// Render like [`Template::render_with_options`], keeping the output,
+    /// where each reference ended up in it and the variables, so that
+    /// [`Template::render_update`] can patch it later
+    pub fn render_state<K, V, S>(
+        &self,
+        variables: &HashMap<K, V, S>,
+        options: &SubstOptions,
+    ) -> SubstResult<RenderState>
+    where
+        K: AsRef<str> + std::hash::Hash + Eq,
+        V: AsRef<str>,
+        S: std::hash::BuildHasher,
+    {
+        let variables: HashMap<String, String> = variables
+            .iter()
+            .map(|(name, value)| (name.as_ref().to_string(), value.as_ref().to_string()))
+            .collect();
+        let mut expander = Expander::with_provider(&variables, options);
+        let mut output = String::with_capacity(self.source.len());
+        let mut spans = Vec::with_capacity(self.parts.len());
+        for part in &self.parts {
+            let start = output.len();
+            self.write_part(part, &mut expander, &mut output)?;
+            spans.push(start..output.len());
+        }
+        Ok(RenderState {
+            output,
+            spans,
+            variables,
+            options: options.clone(),
+        })
+    }
+
+    /// Update the output in `prev` for the new values in `changed`, which
+    /// are merged into its variables.
+    ///
+    /// Only the references to variables whose values differ are substituted
+    /// again, and their output is spliced into the previous output. With
+    /// [`SubstOptions::recursive`] a value may refer to any other variable,
+    /// so every reference is substituted again. On error, `prev` is left as
+    /// it was.
+    ///
+    /// # Panics
+    ///
+    /// `prev` must have been rendered from this template. Otherwise this may
+    /// panic, when the templates have a different number of parts, or return
+    /// meaningless output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::{SubstOptions, Template};
+    /// use std::collections::{HashMap, HashSet};
+    ///
+    /// let template = Template::parse("${HOST} is ${STATUS}")?;
+    /// let vars = HashMap::from([("HOST", "db1"), ("STATUS", "up")]);
+    /// let mut state = template.render_state(&vars, &SubstOptions::new())?;
+    /// assert_eq!(state.output(), "db1 is up");
+    ///
+    /// let changed = HashMap::from([("STATUS", "down")]);
+    /// assert_eq!(template.render_update(&mut state, &changed)?, "db1 is down");
+    /// # Ok::<(), varsubst::SubstError>(())
+    /// ```
+    pub fn render_update<'s, K, V, S>(
+        &self,
+        prev: &'s mut RenderState,
+        changed: &HashMap<K, V, S>,
+    ) -> SubstResult<&'s str>
+    where
+        K: AsRef<str> + std::hash::Hash + Eq,
+        V: AsRef<str>,
+        S: std::hash::BuildHasher,
+    {
+        assert_eq!(
+            prev.spans.len(),
+            self.parts.len(),
+            "render state of another template"
+        );
+        let changed: HashMap<&str, &str> = changed
+            .iter()
+            .map(|(name, value)| (name.as_ref(), value.as_ref()))
+            .filter(|(name, value)| prev.variables.get(*name).map(String::as_str) != Some(value))
+            .collect();
+        if changed.is_empty() {
+            return Ok(&prev.output);
+        }
+
+        // References to an assigned variable are all substituted again when
+        // it changes, in order, so the expander sees the same assignments
+        let variables = Overlay {
+            changed: &changed,
+            variables: &prev.variables,
+        };
+        let mut expander = Expander::with_provider(&variables, &prev.options);
+        let mut updates = Vec::new();
+        for (index, part) in self.parts.iter().enumerate() {
+            let dirty = match self.key(part) {
+                Some(key) => prev.options.recursive || changed.contains_key(&*key),
+                None => false,
+            };
+            if dirty {
+                let mut output = String::new();
+                self.write_part(part, &mut expander, &mut output)?;
+                updates.push((index, output));
+            }
+        }
+
+        for (name, value) in changed {
+            prev.variables.insert(name.to_string(), value.to_string());
+        }
+        prev.splice(updates);
+        Ok(&prev.output)
+    }
+
+    /// The name a reference looks up, or `None` for literal output
+    fn key(&self, part: &Part) -> Option<Cow<'_, str>> {
+        let Part::Var { name, quoted, .. } = part else {
+            return None;
+        };
+        let name = &self.source[name.clone()];
+        Some(match quoted {
+            true => unquote(name),
+            false => Cow::Borrowed(name),
+        })
+    }
+
+    fn write_part(
+        &self,
+        part: &Part,
+        expander: &mut Expander<'_>,
+        output: &mut String,
+    ) -> SubstResult<()> {
+        match part {
+            Part::Text(range) => output.push_str(&self.literals[range.clone()]),
+            Part::Var {
+                name,
+                reference,
+                quoted,
+                length,
+                transform,
+                operator,
+            } => {
+                let operator = operator.as_ref().map(|(kind, colon, word)| Operator {
+                    kind: *kind,
+                    colon: *colon,
+                    word: &self.source[word.clone()],
+                });
+                let var = Reference {
+                    name: &self.source[name.clone()],
+                    quoted: *quoted,
+                    length: *length,
+                    transform: *transform,
+                    operator,
+                    text: &self.source[reference.clone()],
+                };
+                expander.var(&var, reference.start, output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The output of a [`Template`] from [`Template::render_state`], which
+/// [`Template::render_update`] patches when variables change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderState {
+    output: String,
+    /// The range of `output` produced by each part of the template
+    spans: Vec<Range<usize>>,
+    variables: HashMap<String, String>,
+    options: SubstOptions,
+}
+
+impl RenderState {
+    /// The current output
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// The variables the current output was rendered with
+    pub fn variables(&self) -> &HashMap<String, String> {
+        &self.variables
+    }
+
+    /// Take the current output
+    pub fn into_output(self) -> String {
+        self.output
+    }
+
+    /// Replace the output of the parts in `updates`, which are in order,
+    /// shifting the spans after them
+    fn splice(&mut self, updates: Vec<(usize, String)>) {
+        let mut updates = updates.into_iter().peekable();
+        let mut output = String::with_capacity(self.output.len());
+        for (index, span) in self.spans.iter_mut().enumerate() {
+            let start = output.len();
+            match updates.next_if(|(update, _)| *update == index) {
+                Some((_, text)) => output.push_str(&text),
+                None => output.push_str(&self.output[span.clone()]),
+            }
+            *span = start..output.len();
+        }
+        self.output = output;
+    }
+}
+
+/// Changed values on top of the variables of a [`RenderState`]
+struct Overlay<'a> {
+    changed: &'a HashMap<&'a str, &'a str>,
+    variables: &'a HashMap<String, String>,
+}
+
+impl VarProvider for Overlay<'_> {
+    fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        match self.changed.get(name) {
+            Some(value) => Some(Cow::Borrowed(value)),
+            None => VarProvider::get(self.variables, name),
+        }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        let added = self.changed.keys().copied();
+        let added = added.filter(|name| !self.variables.contains_key(*name));
+        self.variables
+            .keys()
+            .map(String::as_str)
+            .chain(added)
+            .collect()
+    }
+}
+
+/// Append literal output, extending the last part if it is literal too
+fn push_literal(parts: &mut Vec<Part>, literals: &mut String, text: &str) {
+    let start = literals.len();
+    literals.push_str(text);
+    match parts.last_mut() {
+        Some(Part::Text(range)) => range.end = literals.len(),
+        _ => parts.push(Part::Text(start..literals.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{substitute_with_options, MissingAction, SubstError};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_render_matches_substitute() {
+        let vars = HashMap::from([("A", "1"), ("B", "${A}"), ("Display Name", "x")]);
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .short_syntax(true)
+            .quoted_names(true);
+        let templates = [
+            "",
+            "plain",
+            "${A} $B ${C} \\$A \\\\${A}",
+            "ü${A@Q}${'Display Name'}$",
+        ];
+        for missing in [MissingAction::Keep, MissingAction::Empty] {
+            for recursive in [false, true] {
+                let options = SubstOptions::new()
+                    .syntax(syntax.clone())
+                    .missing(missing.clone())
+                    .recursive(recursive);
+                for text in templates {
+                    let template = Template::parse_with(text, &syntax).unwrap();
+                    assert_eq!(
+                        template.render_with_options(&vars, &options),
+                        substitute_with_options(text, &vars, &options),
+                        "{}",
+                        text
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_literals_are_merged() {
+        let syntax = SyntaxConfig::new().escape(true);
+        let template = Template::parse_with("a\\$b\\\\c${X}d", &syntax).unwrap();
+        assert_eq!(template.parts.len(), 3);
+        assert_eq!(template.literals, "a$b\\cd");
+        assert_eq!(template.as_str(), "a\\$b\\\\c${X}d");
+    }
+
+    #[test]
+    fn test_variables_round_trip() {
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .short_syntax(true)
+            .quoted_names(true);
+        let text = r"$A ${B} \${NOT_A_VAR} \$NOR_THIS ${A@Q} ${'it\'s'} $C${B}";
+        let template = Template::parse_with(text, &syntax).unwrap();
+        let names = template.variables();
+        assert_eq!(names, ["A", "B", "it's", "C"]);
+
+        let vars: Vec<(&str, &str)> = names.iter().map(|name| (&**name, "v")).collect();
+        let options = SubstOptions::new()
+            .syntax(syntax.clone())
+            .missing(MissingAction::Error);
+        let output = template.render_with_options(&vars, &options).unwrap();
+        assert_eq!(output, r"v v ${NOT_A_VAR} $NOR_THIS 'v' v vv");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Template::parse("ok ${A} ${B"),
+            Err(SubstError::UnclosedBrace { position: 8 })
+        );
+        let template = Template::parse("${A} ${MISSING}").unwrap();
+        let vars = BTreeMap::from([("A".to_string(), "a".to_string())]);
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        assert!(matches!(
+            template.render_with_options(&vars, &options),
+            Err(SubstError::UndefinedVariable { position: 5, .. })
+        ));
+        assert_eq!(template.render(&vars).unwrap(), "a ${MISSING}");
+    }
+
+    /// Apply each change in turn, checking against rendering from scratch
+    fn check_updates(text: &str, options: &SubstOptions, steps: &[&[(&str, &str)]]) {
+        let template = Template::parse_with(text, &options.syntax).unwrap();
+        let mut vars = HashMap::from([("A".to_string(), "a".to_string())]);
+        let mut state = template.render_state(&vars, options).unwrap();
+        for changed in steps {
+            let changed: HashMap<&str, &str> = changed.iter().copied().collect();
+            for (name, value) in &changed {
+                vars.insert(name.to_string(), value.to_string());
+            }
+            let expected = template.render_with_options(&vars, options).unwrap();
+            let output = template.render_update(&mut state, &changed).unwrap();
+            assert_eq!(output, expected, "{} with {:?}", text, changed);
+            assert_eq!(state.variables(), &vars);
+        }
+    }
+
+    #[test]
+    fn test_render_update_matches_render() {
+        let syntax = SyntaxConfig::new().quoted_names(true);
+        let text = "<${A}|${B:-none}|${#A}|${C:=set}${C}|${A@Q}|${'D E'}|${B:+alt}|${A}>";
+        let steps: &[&[(&str, &str)]] = &[
+            &[("A", "a much longer value")],
+            &[("B", "b")],
+            &[("A", ""), ("B", "")],
+            &[("C", "c"), ("D E", "d")],
+            &[("A", "ü"), ("B", "bb"), ("C", "")],
+            &[("UNUSED", "x")],
+            &[("A", "ü")],
+        ];
+        for missing in [MissingAction::Keep, MissingAction::Empty] {
+            let options = SubstOptions::new().syntax(syntax.clone()).missing(missing);
+            check_updates(text, &options, steps);
+        }
+    }
+
+    #[test]
+    fn test_render_update_recursive() {
+        let options = SubstOptions::new().recursive(true);
+        let steps: &[&[(&str, &str)]] = &[
+            &[("B", "[${A}]")],
+            &[("A", "changes B too")],
+            &[("B", "${#A}")],
+        ];
+        check_updates("${A} ${B} ${A}", &options, steps);
+    }
+
+    #[test]
+    fn test_render_update_error_keeps_state() {
+        let template = Template::parse("${A} ${R:?is required}").unwrap();
+        let vars = HashMap::from([("A", "a"), ("R", "r")]);
+        let mut state = template.render_state(&vars, &SubstOptions::new()).unwrap();
+        let before = state.clone();
+
+        let changed = HashMap::from([("A", "new"), ("R", "")]);
+        assert!(matches!(
+            template.render_update(&mut state, &changed),
+            Err(SubstError::RequiredVariable { position: 5, .. })
+        ));
+        assert_eq!(state, before);
+        assert_eq!(state.into_output(), "a r");
+    }
+}
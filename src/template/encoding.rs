@@ -0,0 +1,412 @@
+//! A stable binary form of [`Template`], to parse templates ahead of time.
+//!
+//! Version 1 is laid out as follows, with integers as little-endian `u32`
+//! and strings as their length followed by UTF-8:
+//!
+//! - the magic bytes `VSTP` and the format version as a little-endian `u16`
+//! - the source text and the literal output
+//! - the number of parts, then each part: `0` and the range of the literal
+//!   output for text, or `1` for a reference, followed by the ranges of the
+//!   source holding its name and the whole reference, a flags byte (quoted,
+//!   length, colon), the transform directive or `0`, and the operator symbol
+//!   or `0`, followed by the range of its word if there is one
+//!
+//! Ranges are a start and an end offset.
+
+use super::{Part, Template};
+use crate::{OperatorKind, Transform};
+use std::fmt;
+use std::ops::Range;
+
+const MAGIC: &[u8; 4] = b"VSTP";
+
+/// The format version written by [`Template::to_bytes`]
+const FORMAT_VERSION: u16 = 1;
+
+const QUOTED: u8 = 1;
+const LENGTH: u8 = 2;
+const COLON: u8 = 4;
+
+/// Error returned by [`Template::from_bytes`] for data it cannot read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateDecodeError {
+    /// The data does not start with the magic bytes of a serialized template
+    NotATemplate,
+    /// The data was written in another format version
+    UnsupportedVersion(u16),
+    /// The data ends before the template does
+    Truncated,
+    /// The data is not a valid template
+    Invalid {
+        /// The offset in the data
+        offset: usize,
+        /// What is wrong there
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for TemplateDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateDecodeError::NotATemplate => write!(f, "not a serialized template"),
+            TemplateDecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported template format version {} (expected {})",
+                version, FORMAT_VERSION
+            ),
+            TemplateDecodeError::Truncated => write!(f, "serialized template is truncated"),
+            TemplateDecodeError::Invalid { offset, reason } => {
+                write!(
+                    f,
+                    "invalid serialized template at byte {}: {}",
+                    offset, reason
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateDecodeError {}
+
+impl Template {
+    /// Serialize the parsed template, to be read back by
+    /// [`Template::from_bytes`] without parsing it again.
+    ///
+    /// The format is versioned: data written by a different version of the
+    /// format is rejected rather than misread.
+    ///
+    /// # Panics
+    ///
+    /// If the template is 4 GiB or longer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use varsubst::Template;
+    /// use std::collections::HashMap;
+    ///
+    /// let bytes = Template::parse("Hello ${NAME}!")?.to_bytes();
+    /// let template = Template::from_bytes(&bytes).unwrap();
+    /// let vars = HashMap::from([("NAME", "World")]);
+    /// assert_eq!(template.render(&vars)?, "Hello World!");
+    /// # Ok::<(), varsubst::SubstError>(())
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.source.len() + self.literals.len() + 64);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        put_str(&mut bytes, &self.source);
+        put_str(&mut bytes, &self.literals);
+        put_u32(&mut bytes, self.parts.len());
+        for part in &self.parts {
+            match part {
+                Part::Text(range) => {
+                    bytes.push(0);
+                    put_range(&mut bytes, range);
+                }
+                Part::Var {
+                    name,
+                    reference,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                } => {
+                    bytes.push(1);
+                    put_range(&mut bytes, name);
+                    put_range(&mut bytes, reference);
+                    let colon = operator.as_ref().is_some_and(|(_, colon, _)| *colon);
+                    let flags = [(*quoted, QUOTED), (*length, LENGTH), (colon, COLON)];
+                    bytes.push(
+                        flags
+                            .iter()
+                            .filter(|(set, _)| *set)
+                            .map(|(_, bit)| bit)
+                            .sum(),
+                    );
+                    bytes.push(transform.map_or(0, |transform| transform.directive() as u8));
+                    match operator {
+                        Some((kind, _, word)) => {
+                            bytes.push(kind.symbol() as u8);
+                            put_range(&mut bytes, word);
+                        }
+                        None => bytes.push(0),
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Read a template serialized by [`Template::to_bytes`].
+    ///
+    /// The data is checked as it is read, so untrusted data yields an error
+    /// rather than a panic, and nothing larger than the data is allocated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TemplateDecodeError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(TemplateDecodeError::NotATemplate);
+        }
+        let version = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+        if version != FORMAT_VERSION {
+            return Err(TemplateDecodeError::UnsupportedVersion(version));
+        }
+        let source = reader.str()?;
+        let literals = reader.str()?;
+
+        let count = reader.u32()?;
+        // Every part takes at least 9 bytes, so a larger count is a lie
+        if count > reader.remaining() / 9 {
+            return Err(reader.invalid("more parts than the data holds"));
+        }
+        let mut parts = Vec::with_capacity(count);
+        for _ in 0..count {
+            let part = match reader.byte()? {
+                0 => Part::Text(reader.range(literals)?),
+                1 => {
+                    let name = reader.range(source)?;
+                    let reference = reader.range(source)?;
+                    let flags = reader.byte()?;
+                    if flags & !(QUOTED | LENGTH | COLON) != 0 {
+                        return Err(reader.invalid("unknown reference flags"));
+                    }
+                    let transform = match reader.byte()? {
+                        0 => None,
+                        directive => Some(
+                            Transform::from_directive(char::from(directive))
+                                .ok_or_else(|| reader.invalid("unknown transform"))?,
+                        ),
+                    };
+                    let operator = match reader.byte()? {
+                        0 => None,
+                        symbol => {
+                            let kind = operator_kind(char::from(symbol))
+                                .ok_or_else(|| reader.invalid("unknown operator"))?;
+                            Some((kind, flags & COLON != 0, reader.range(source)?))
+                        }
+                    };
+                    Part::Var {
+                        name,
+                        reference,
+                        quoted: flags & QUOTED != 0,
+                        length: flags & LENGTH != 0,
+                        transform,
+                        operator,
+                    }
+                }
+                _ => return Err(reader.invalid("unknown part")),
+            };
+            parts.push(part);
+        }
+        if reader.remaining() != 0 {
+            return Err(reader.invalid("trailing data"));
+        }
+        Ok(Template {
+            source: source.to_string(),
+            literals: literals.to_string(),
+            parts,
+        })
+    }
+}
+
+fn operator_kind(symbol: char) -> Option<OperatorKind> {
+    [
+        OperatorKind::Default,
+        OperatorKind::Assign,
+        OperatorKind::Required,
+        OperatorKind::Alternate,
+    ]
+    .into_iter()
+    .find(|kind| kind.symbol() == symbol)
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("template too large to serialize");
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, text: &str) {
+    put_u32(bytes, text.len());
+    bytes.extend_from_slice(text.as_bytes());
+}
+
+fn put_range(bytes: &mut Vec<u8>, range: &Range<usize>) {
+    put_u32(bytes, range.start);
+    put_u32(bytes, range.end);
+}
+
+/// Checked reads from serialized data
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    fn invalid(&self, reason: &'static str) -> TemplateDecodeError {
+        TemplateDecodeError::Invalid {
+            offset: self.offset,
+            reason,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], TemplateDecodeError> {
+        if len > self.remaining() {
+            return Err(TemplateDecodeError::Truncated);
+        }
+        let taken = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, TemplateDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, TemplateDecodeError> {
+        let bytes = self.take(4)?;
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        usize::try_from(value).map_err(|_| self.invalid("length too large"))
+    }
+
+    fn str(&mut self) -> Result<&'a str, TemplateDecodeError> {
+        let len = self.u32()?;
+        let start = self.offset;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes).map_err(|_| TemplateDecodeError::Invalid {
+            offset: start,
+            reason: "text is not UTF-8",
+        })
+    }
+
+    /// A range of `text`, which must fall on character boundaries
+    fn range(&mut self, text: &str) -> Result<Range<usize>, TemplateDecodeError> {
+        let start = self.offset;
+        let range = self.u32()?..self.u32()?;
+        match text.get(range.clone()) {
+            Some(_) => Ok(range),
+            None => Err(TemplateDecodeError::Invalid {
+                offset: start,
+                reason: "range outside of its text",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SubstOptions, SyntaxConfig};
+    use std::collections::HashMap;
+
+    const FIXTURE: &str = r"Hello ${NAME}! \${x} ${#A} ${B:-dft} ${C@Q} ${D+alt} ü";
+
+    /// A pseudo-random template built from pieces covering the syntax
+    fn random_template(seed: &mut u64) -> String {
+        const PIECES: [&str; 16] = [
+            "text",
+            " ",
+            "ü",
+            "$ ",
+            r"\$",
+            r"\\",
+            "${A}",
+            "${B}",
+            "$A",
+            "${#A}",
+            "${A@Q}",
+            "${B:-x y}",
+            r"${A=${B\}}",
+            "${'Q N'}",
+            "${C:?gone}",
+            "${B+alt}",
+        ];
+        let mut text = String::new();
+        loop {
+            // xorshift64
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            match (*seed % 20) as usize {
+                piece if piece < PIECES.len() => text.push_str(PIECES[piece]),
+                _ if text.len() > 40 => return text,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .short_syntax(true)
+            .quoted_names(true);
+        let vars = HashMap::from([("A", "a"), ("B", "'b'"), ("Q N", "q")]);
+        let options = SubstOptions::new().syntax(syntax.clone());
+        let mut seed = 0x5eed;
+        for _ in 0..500 {
+            let text = random_template(&mut seed);
+            let template = Template::parse_with(&text, &syntax).unwrap();
+            let decoded = Template::from_bytes(&template.to_bytes()).unwrap();
+            assert_eq!(decoded, template, "{}", text);
+            assert_eq!(
+                decoded.render_with_options(&vars, &options),
+                template.render_with_options(&vars, &options)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fixture_is_stable() {
+        let fixture = include_bytes!("../../tests/fixtures/template_v1.bin");
+        let template = Template::parse(FIXTURE).unwrap();
+        assert_eq!(template.to_bytes(), fixture);
+        assert_eq!(Template::from_bytes(fixture), Ok(template));
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let mut bytes = Template::parse("${A}").unwrap().to_bytes();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(
+            Template::from_bytes(&bytes),
+            Err(TemplateDecodeError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            Template::from_bytes(b"{\"source\": \"${A}\"}"),
+            Err(TemplateDecodeError::NotATemplate)
+        );
+    }
+
+    #[test]
+    fn test_corrupt_data_is_rejected() {
+        let bytes = Template::parse(FIXTURE).unwrap().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(Template::from_bytes(&bytes[..len]).is_err(), "{}", len);
+        }
+        // Any single corrupted byte either fails to decode or decodes to
+        // something that renders without panicking
+        let vars = HashMap::from([("NAME", "x")]);
+        for index in 0..bytes.len() {
+            for value in [0x00, 0x01, 0x7f, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[index] = value;
+                if let Ok(template) = Template::from_bytes(&corrupt) {
+                    let _ = template.render(&vars);
+                }
+            }
+        }
+
+        // A huge part count must not allocate
+        let mut huge = Template::parse("").unwrap().to_bytes();
+        huge.truncate(huge.len() - 4);
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Template::from_bytes(&huge),
+            Err(TemplateDecodeError::Invalid { .. })
+        ));
+    }
+}
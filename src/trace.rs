@@ -0,0 +1,182 @@
+//! Substitution that also reports where each reference ended up in the
+//! output, for highlighting rendered text.
+
+use crate::{segments_with, Expander, Reference, Segment, SubstOptions, SubstResult, VarProvider};
+use std::ops::Range;
+
+/// Where the output of a reference came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOrigin {
+    /// The variable's value
+    Resolved,
+    /// Text produced for an undefined variable by [`SubstOptions::missing`],
+    /// or the word of an operator such as `${NAME:-default}` or
+    /// `${NAME:+alternate}`, possibly empty
+    Default,
+    /// The reference itself, kept because the variable is undefined
+    Kept,
+}
+
+/// A template reference and the output it produced, reported by
+/// [`substitute_traced`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstEvent<'a> {
+    /// The variable name, as written between the quotes if quoted
+    pub name: &'a str,
+    /// Byte range of the reference in the template
+    pub template_span: Range<usize>,
+    /// Byte range of what the reference produced in the output
+    pub output_span: Range<usize>,
+    /// Where that output came from
+    pub origin: EventOrigin,
+}
+
+/// Substitute variables like [`substitute_with_provider`](crate::substitute_with_provider),
+/// also returning one event per template reference in output order.
+///
+/// Output spans are byte offsets into the returned string, so
+/// `&output[event.output_span]` is exactly what the reference produced. With
+/// [`SubstOptions::recursive`], an event covers the whole expanded value.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::{substitute_traced, EventOrigin, SubstOptions};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("NAME", "World")]);
+/// let (output, events) =
+///     substitute_traced("Hi ${NAME}, ${X}", &vars, &SubstOptions::new()).unwrap();
+/// assert_eq!(output, "Hi World, ${X}");
+/// assert_eq!(&output[events[0].output_span.clone()], "World");
+/// assert_eq!(events[1].origin, EventOrigin::Kept);
+/// ```
+pub fn substitute_traced<'a, P>(
+    template: &'a str,
+    variables: &P,
+    options: &SubstOptions,
+) -> SubstResult<(String, Vec<SubstEvent<'a>>)>
+where
+    P: VarProvider + ?Sized,
+{
+    let mut expander = Expander::with_provider(&variables, options);
+    let mut output = String::with_capacity(template.len());
+    let mut events = Vec::new();
+    for segment in segments_with(template, &options.syntax) {
+        match segment? {
+            Segment::Text(text) => output.push_str(text),
+            Segment::Escaped(ch) => output.push(ch),
+            Segment::Var {
+                name,
+                span,
+                braced,
+                quoted,
+                length,
+                transform,
+                operator,
+            } => {
+                let reference = Reference {
+                    name,
+                    quoted,
+                    length,
+                    transform,
+                    operator,
+                    text: &template[span.clone()],
+                };
+                let start = output.len();
+                let origin = expander.var(&reference, span.start, &mut output)?;
+                // The name as a slice of the template, which outlives `options`
+                let name_start = match braced {
+                    true => {
+                        let open = options.syntax.open().len();
+                        span.start + open + usize::from(length) + usize::from(quoted)
+                    }
+                    false => span.start + 1,
+                };
+                events.push(SubstEvent {
+                    name: &template[name_start..name_start + name.len()],
+                    template_span: span,
+                    output_span: start..output.len(),
+                    origin,
+                });
+            }
+        }
+    }
+    Ok((output, events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MissingAction, SyntaxConfig};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_output_spans_recover_values() {
+        let vars = HashMap::from([("A", "ü-long value"), ("B", ""), ("C", "x")]);
+        let options = SubstOptions::new().syntax(SyntaxConfig::new().escape(true));
+        let template = r"\${A} ${A}ü${B}\\${C} ${D}";
+        let (output, events) = substitute_traced(template, &vars, &options).unwrap();
+        assert_eq!(output, r"${A} ü-long valueü\x ${D}");
+
+        let recovered: Vec<(&str, &str, EventOrigin)> = (events.iter())
+            .map(|e| (e.name, &output[e.output_span.clone()], e.origin))
+            .collect();
+        assert_eq!(
+            recovered,
+            vec![
+                ("A", "ü-long value", EventOrigin::Resolved),
+                ("B", "", EventOrigin::Resolved),
+                ("C", "x", EventOrigin::Resolved),
+                ("D", "${D}", EventOrigin::Kept),
+            ]
+        );
+        for event in &events {
+            let reference = &template[event.template_span.clone()];
+            assert_eq!(reference, format!("${{{}}}", event.name));
+        }
+    }
+
+    #[test]
+    fn test_default_and_recursive_origins() {
+        let vars = HashMap::from([("URL", "https://${HOST}"), ("HOST", "example.com")]);
+        let options = SubstOptions::new()
+            .recursive(true)
+            .missing(MissingAction::UseDefault("?".to_string()));
+        let (output, events) = substitute_traced("${URL}/${PATH}", &vars, &options).unwrap();
+        assert_eq!(output, "https://example.com/?");
+        assert_eq!(
+            &output[events[0].output_span.clone()],
+            "https://example.com"
+        );
+        assert_eq!(events[1].origin, EventOrigin::Default);
+        assert_eq!(events[1].output_span, 20..21);
+    }
+
+    #[test]
+    fn test_hook_origins() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let vars = HashMap::from([("NAME", "World")]);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let options = SubstOptions::new().on_missing(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            MissingAction::Empty
+        });
+        let (output, events) = substitute_traced("${NAME} ${X}", &vars, &options).unwrap();
+        assert_eq!(output, "World ");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(events[1].origin, EventOrigin::Default);
+        assert_eq!(events[1].output_span, 6..6);
+
+        let options = SubstOptions::new()
+            .missing(MissingAction::Empty)
+            .on_missing(|_, _| MissingAction::Keep);
+        let (output, events) = substitute_traced("${NAME} ${X}", &vars, &options).unwrap();
+        assert_eq!(output, "World ${X}");
+        assert_eq!(events[1].origin, EventOrigin::Kept);
+        assert_eq!(&output[events[1].output_span.clone()], "${X}");
+    }
+}
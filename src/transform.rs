@@ -0,0 +1,100 @@
+//! Bash-style `${NAME@X}` transforms applied to a variable's value.
+
+/// A transform written as `@X` after the name in a delimited reference, as
+/// in `${FILE@Q}`
+///
+/// Transforms apply to the value of a defined variable (after recursive
+/// expansion); what [`MissingAction`](crate::MissingAction) produces for an
+/// undefined one is used as-is, and a kept reference keeps its transform.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::substitute;
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("FILE", "my file's.txt")]);
+/// let result = substitute("rm -- ${FILE@Q}", &vars).unwrap();
+/// assert_eq!(result, r"rm -- 'my file'\''s.txt'");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    /// `@Q`: the value in single quotes, safe to reuse as one POSIX shell
+    /// word
+    Quote,
+    /// `@U`: the value in upper case
+    Upper,
+    /// `@L`: the value in lower case
+    Lower,
+}
+
+impl Transform {
+    /// The transform written as `@directive`, if there is one
+    pub fn from_directive(directive: char) -> Option<Self> {
+        match directive {
+            'Q' => Some(Transform::Quote),
+            'U' => Some(Transform::Upper),
+            'L' => Some(Transform::Lower),
+            _ => None,
+        }
+    }
+
+    /// The letter written after `@`
+    pub fn directive(self) -> char {
+        match self {
+            Transform::Quote => 'Q',
+            Transform::Upper => 'U',
+            Transform::Lower => 'L',
+        }
+    }
+
+    /// Append `value` transformed to `output`
+    pub(crate) fn apply(self, value: &str, output: &mut String) {
+        match self {
+            Transform::Quote => {
+                output.push('\'');
+                output.push_str(&value.replace('\'', r"'\''"));
+                output.push('\'');
+            }
+            Transform::Upper => output.push_str(&value.to_uppercase()),
+            Transform::Lower => output.push_str(&value.to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(transform: Transform, value: &str) -> String {
+        let mut output = String::new();
+        transform.apply(value, &mut output);
+        output
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(apply(Transform::Quote, "plain"), "'plain'");
+        assert_eq!(apply(Transform::Quote, ""), "''");
+        assert_eq!(apply(Transform::Quote, "it's"), r"'it'\''s'");
+        assert_eq!(apply(Transform::Quote, "''"), r"''\'''\'''");
+        assert_eq!(apply(Transform::Quote, "a\nb $c"), "'a\nb $c'");
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(apply(Transform::Upper, "straße"), "STRASSE");
+        assert_eq!(apply(Transform::Lower, "ÄB"), "äb");
+    }
+
+    #[test]
+    fn test_directives_round_trip() {
+        for transform in [Transform::Quote, Transform::Upper, Transform::Lower] {
+            assert_eq!(
+                Transform::from_directive(transform.directive()),
+                Some(transform)
+            );
+        }
+        assert_eq!(Transform::from_directive('q'), None);
+    }
+}
@@ -0,0 +1,261 @@
+//! A writer that substitutes variables in what is written to it.
+
+use crate::stream::Pending;
+use crate::{Expander, SubstIoError, SubstOptions, VarProvider};
+use std::io::{self, Write};
+
+/// An [`io::Write`] adapter that substitutes variables in the text written
+/// to it before passing it on to an inner writer
+///
+/// References, escapes and UTF-8 characters may be split between `write`
+/// calls. Everything written is substituted and passed on at once, except
+/// the last few bytes when they could still be the start of a reference,
+/// which are held back until more is written. [`flush`](Write::flush)
+/// flushes the inner writer but keeps holding them back, since the next
+/// write could complete them; [`finish`](SubstWriter::finish) writes them
+/// out as the end of the text, as dropping the writer does while ignoring
+/// errors.
+///
+/// A syntax or substitution error, or text that is not valid UTF-8, is
+/// returned by `write` as an [`io::Error`] of kind
+/// [`io::ErrorKind::InvalidData`]; for the former its inner error is the
+/// [`SubstError`](crate::SubstError), with positions counted from the start
+/// of everything written. The output before the error has been written to
+/// the inner writer by then, and the writer is done: later writes fail
+/// without taking any bytes, [`finish`](SubstWriter::finish) fails too, and
+/// dropping it writes nothing more.
+///
+/// # Examples
+///
+/// ```
+/// use varsubst::SubstWriter;
+/// use std::collections::HashMap;
+/// use std::io::Write;
+///
+/// let vars = HashMap::from([("USER", "ann")]);
+/// let mut writer = SubstWriter::new(Vec::new(), &vars);
+/// write!(writer, "home: /home/${{US")?;
+/// write!(writer, "ER}}\n")?;
+/// assert_eq!(writer.finish()?, b"home: /home/ann\n");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct SubstWriter<W: Write, P: VarProvider> {
+    /// `None` once finished
+    inner: Option<W>,
+    provider: P,
+    options: SubstOptions,
+    pending: Pending,
+    output: String,
+    /// Whether a write failed, after which nothing more is written
+    failed: bool,
+}
+
+impl<W: Write, P: VarProvider> SubstWriter<W, P> {
+    /// Substitute variables from `provider` with default options
+    pub fn new(inner: W, provider: P) -> Self {
+        Self::with_options(inner, provider, SubstOptions::default())
+    }
+
+    /// Substitute variables from `provider` with undefined variables, syntax
+    /// and recursion handled according to `options`
+    pub fn with_options(inner: W, provider: P, options: SubstOptions) -> Self {
+        SubstWriter {
+            inner: Some(inner),
+            provider,
+            options,
+            pending: Pending::default(),
+            output: String::new(),
+            failed: false,
+        }
+    }
+
+    /// The inner writer
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("not finished")
+    }
+
+    /// The inner writer. Writing to it directly puts the text after the
+    /// output of everything substituted so far, which may not include the
+    /// last few bytes written.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("not finished")
+    }
+
+    /// Substitute and write out the rest of the text, flush the inner writer
+    /// and return it. Fails with
+    /// [`SubstError::UnclosedBrace`](crate::SubstError::UnclosedBrace) if the
+    /// text ended inside a reference.
+    pub fn finish(mut self) -> Result<W, SubstIoError> {
+        self.end()?;
+        Ok(self.inner.take().expect("not finished"))
+    }
+
+    /// Substitute what more text cannot change, or everything at `eof`, and
+    /// write it to the inner writer
+    fn substitute(&mut self, eof: bool) -> Result<(), SubstIoError> {
+        if self.failed {
+            return Err(SubstIoError::Io(failed()));
+        }
+        // Set again on success; the text taken so far cannot be resumed
+        self.failed = true;
+        self.output.clear();
+        let mut expander = Expander::with_provider(&self.provider, &self.options);
+        let result = (self.pending).substitute(&mut expander, eof, &mut self.output);
+        // The output before an error is written too
+        let inner = self.inner.as_mut().expect("not finished");
+        inner.write_all(self.output.as_bytes())?;
+        result?;
+        self.failed = false;
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), SubstIoError> {
+        if self.failed {
+            return Err(SubstIoError::Io(failed()));
+        }
+        if let Err(e) = self.pending.close() {
+            self.failed = true;
+            return Err(e.into());
+        }
+        self.substitute(true)?;
+        self.get_mut().flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write, P: VarProvider> Write for SubstWriter<W, P> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(failed());
+        }
+        if let Err(e) = self.pending.push(buf) {
+            self.failed = true;
+            return Err(e);
+        }
+        self.substitute(false).map_err(|e| match e {
+            SubstIoError::Subst(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            SubstIoError::Io(e) => e,
+        })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write, P: VarProvider> Drop for SubstWriter<W, P> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && !self.failed {
+            // Errors are ignored, as by `BufWriter`; call `finish` to see them
+            let _ = self.end();
+        }
+    }
+}
+
+fn failed() -> io::Error {
+    io::Error::other("an earlier write to the SubstWriter failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{substitute_with_options, MissingAction, SubstError, SyntaxConfig};
+    use std::collections::HashMap;
+
+    fn vars() -> HashMap<&'static str, &'static str> {
+        HashMap::from([("NAME", "World"), ("Q", "it's")])
+    }
+
+    #[test]
+    fn test_writes_split_at_every_offset() {
+        let syntax = SyntaxConfig::new()
+            .escape(true)
+            .short_syntax(true)
+            .quoted_names(true);
+        let options = SubstOptions::new().syntax(syntax);
+        let template = r"Hi ${NAME}! \${NAME} \\$NAME ü${Q@Q} $$5 ${'NAME'}${MISSING} $";
+        let vars = vars();
+        let expected = substitute_with_options(template, &vars, &options).unwrap();
+        let bytes = template.as_bytes();
+        for first in 0..=bytes.len() {
+            for second in first..=bytes.len() {
+                let mut writer = SubstWriter::with_options(Vec::new(), &vars, options.clone());
+                writer.write_all(&bytes[..first]).unwrap();
+                writer.write_all(&bytes[first..second]).unwrap();
+                writer.write_all(&bytes[second..]).unwrap();
+                let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+                assert_eq!(output, expected, "split at {} and {}", first, second);
+            }
+        }
+    }
+
+    #[test]
+    fn test_finish_inside_reference() {
+        let mut writer = SubstWriter::new(Vec::new(), vars());
+        writer.write_all(b"Hello ${NAME}, ${NA").unwrap();
+        let error = writer.finish().unwrap_err();
+        assert!(
+            matches!(
+                error,
+                SubstIoError::Subst(SubstError::UnclosedBrace { position: 15 })
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_flush_and_drop() {
+        let mut output = Vec::new();
+        {
+            let mut writer = SubstWriter::new(&mut output, vars());
+            writer.write_all(b"${NAME} costs 5$").unwrap();
+            writer.flush().unwrap();
+            // The `$` could still start a reference
+            assert!(!writer.get_ref().ends_with(b"$"));
+        }
+        assert_eq!(output, b"World costs 5$");
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let mut writer = SubstWriter::with_options(Vec::new(), vars(), options);
+        let error = writer
+            .write_all(b"${NAME} ${USER} and more text")
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .starts_with("Undefined variable 'USER' at position 8"));
+        assert_eq!(writer.get_ref(), b"World ");
+
+        let mut writer = SubstWriter::new(Vec::new(), vars());
+        writer.write_all(b"ok \xc3").unwrap();
+        assert!(
+            matches!(writer.finish(), Err(SubstIoError::Io(e)) if e.kind() == io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_write_after_error() {
+        let options = SubstOptions::new().missing(MissingAction::Error);
+        let vars = HashMap::from([("A", "a")]);
+        let mut output = Vec::new();
+        {
+            let mut writer = SubstWriter::with_options(&mut output, &vars, options);
+            let error = writer.write(b"hello ${A} ${X} and more").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(writer.write(b" text").is_err());
+            assert_eq!(writer.get_ref().as_slice(), b"hello a ");
+        }
+        assert_eq!(output, b"hello a ");
+
+        let mut writer = SubstWriter::new(Vec::new(), &vars);
+        assert!(writer.write(b"ok \xff").is_err());
+        assert!(writer.write(b"more").is_err());
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,4389 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Path to a file in `tests/fixtures`
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// The varsubst binary with environment variables disabled
+fn varsubst() -> Command {
+    let mut cmd = cargo_bin_cmd!("varsubst");
+    cmd.arg("--no-env");
+    cmd
+}
+
+/// The varsubst binary with a controlled environment
+fn varsubst_with_env(vars: &[(&str, &str)]) -> Command {
+    let mut cmd = cargo_bin_cmd!("varsubst");
+    cmd.env_clear().envs(vars.iter().copied());
+    cmd
+}
+
+#[test]
+fn test_missing_keep_is_default() {
+    varsubst()
+        .args(["-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=example.com port=${PORT}\n");
+}
+
+#[test]
+fn test_missing_keep() {
+    varsubst()
+        .args(["--missing", "keep", "-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=example.com port=${PORT}\n");
+}
+
+#[test]
+fn test_missing_empty() {
+    varsubst()
+        .args(["--missing", "empty", "-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=example.com port=\n");
+}
+
+#[test]
+fn test_missing_error() {
+    varsubst()
+        .args(["--missing", "error", "-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Undefined variable 'PORT'"));
+}
+
+#[test]
+fn test_default_operator_is_not_undefined() {
+    varsubst()
+        .args(["--missing", "error"])
+        .write_stdin("${A} ${B:-b} ${C}\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Undefined variable 'A'"))
+        .stderr(predicate::str::contains("Undefined variable 'C'"))
+        .stderr(predicate::str::contains("'B'").not());
+    varsubst()
+        .args(["--missing", "error"])
+        .write_stdin("${A} ${B:=b} ${B} ${C}\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Undefined variable 'C'"))
+        .stderr(predicate::str::contains("'B'").not());
+    varsubst()
+        .args(["--missing", "error", "-v", "B="])
+        .write_stdin("${B:-/usr/local bin}\n")
+        .assert()
+        .success()
+        .stdout("/usr/local bin\n");
+}
+
+#[test]
+fn test_required_variable() {
+    varsubst()
+        .args(["--json-errors", "-v", "DB_USER=app", "-v", "DB_PASSWORD="])
+        .write_stdin("${DB_USER:?user required} ${DB_PASSWORD:?password required}\n")
+        .assert()
+        .code(5)
+        .stdout("")
+        .stderr(predicate::str::contains(r#""code":"required-variable""#))
+        .stderr(predicate::str::contains("password required"));
+    varsubst()
+        .args(["-v", "DB_PASSWORD=secret"])
+        .write_stdin("${DB_PASSWORD:?password required}\n")
+        .assert()
+        .success()
+        .stdout("secret\n");
+}
+
+#[test]
+fn test_length_reference() {
+    varsubst()
+        .args(["-v", "USER=zoë"])
+        .write_stdin("${#USER} ${#NOPE}\n")
+        .assert()
+        .success()
+        .stdout("3 ${#NOPE}\n");
+    varsubst()
+        .write_stdin("${#}\n")
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Invalid variable name ''"));
+}
+
+#[test]
+fn test_missing_default() {
+    varsubst()
+        .args(["--missing", "default=N/A", "-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=example.com port=N/A\n");
+}
+
+#[test]
+fn test_missing_marker() {
+    varsubst()
+        .args(["--missing", "marker=<MISSING:{name}@{position}>"])
+        .args(["-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=example.com port=<MISSING:PORT@18>\n");
+}
+
+#[test]
+fn test_missing_invalid_mode() {
+    varsubst()
+        .args(["--missing", "drop"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid mode 'drop'"));
+}
+
+#[test]
+fn test_fail_on_undefined() {
+    varsubst()
+        .args(["-f", "-v", "HOST=example.com"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Undefined variable 'PORT'"));
+}
+
+#[cfg(feature = "short_syntax")]
+#[test]
+fn test_missing_short_syntax() {
+    varsubst()
+        .args(["--missing", "empty"])
+        .write_stdin("[$HOST]")
+        .assert()
+        .success()
+        .stdout("[]");
+}
+
+#[cfg(feature = "escape")]
+#[test]
+fn test_list_vars() {
+    varsubst()
+        .arg("--list-vars")
+        .arg(fixture("list_vars.txt"))
+        .assert()
+        .success()
+        .stdout("HOST\nPORT\nUSER\n");
+}
+
+#[cfg(feature = "escape")]
+#[test]
+fn test_list_vars_json() {
+    let output = varsubst()
+        .arg("--list-vars=json")
+        .arg(fixture("list_vars.txt"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!([
+            {
+                "name": "HOST",
+                "count": 2,
+                "positions": [
+                    { "offset": 0, "line": 1, "column": 1 },
+                    { "offset": 28, "line": 2, "column": 13 },
+                ],
+            },
+            {
+                "name": "PORT",
+                "count": 1,
+                "positions": [{ "offset": 8, "line": 1, "column": 9 }],
+            },
+            {
+                "name": "USER",
+                "count": 1,
+                "positions": [{ "offset": 41, "line": 3, "column": 6 }],
+            },
+        ])
+    );
+}
+
+#[test]
+fn test_list_vars_stdin() {
+    varsubst()
+        .arg("--list-vars")
+        .write_stdin("${B} ${A} ${B}")
+        .assert()
+        .success()
+        .stdout("B\nA\n");
+}
+
+#[test]
+fn test_list_vars_empty() {
+    varsubst()
+        .arg("--list-vars")
+        .write_stdin("no references")
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn test_list_vars_syntax_error() {
+    varsubst()
+        .arg("--list-vars")
+        .write_stdin("${OK} ${BROKEN")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Unclosed brace"));
+}
+
+#[test]
+fn test_check_good_file() {
+    varsubst()
+        .arg("--check")
+        .arg(fixture("check_good.txt"))
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("1 file(s) checked, no errors\n");
+}
+
+#[test]
+fn test_check_reports_all_errors() {
+    let bad = fixture("check_bad.txt");
+    varsubst()
+        .arg("--check")
+        .arg(fixture("check_good.txt"))
+        .arg(&bad)
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(format!(
+            "{}:2:5: Invalid variable name 'NA'",
+            bad
+        )))
+        .stderr(predicate::str::contains(format!(
+            "{}:3:5: Invalid variable name ''",
+            bad
+        )))
+        .stderr(predicate::str::ends_with("2 error(s) in 1 of 2 file(s)\n"));
+}
+
+#[test]
+fn test_check_ignores_fail_on_undefined() {
+    varsubst()
+        .args(["--check", "--fail-on-undefined"])
+        .arg(fixture("check_good.txt"))
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_check_stdin() {
+    varsubst()
+        .arg("--check")
+        .write_stdin("${UNCLOSED")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("<stdin>:1:1: Unclosed brace"));
+}
+
+#[test]
+fn test_check_missing_file() {
+    varsubst()
+        .arg("--check")
+        .arg(fixture("does_not_exist.txt"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "does_not_exist.txt: error reading input",
+        ));
+}
+
+#[test]
+fn test_env_file() {
+    varsubst()
+        .args(["--env-file", &fixture("app.env")])
+        .arg(fixture("env_file.txt"))
+        .assert()
+        .success()
+        .stdout("S=db.internal:5432/app \"prod\" p@ss # not a comment\n");
+}
+
+#[test]
+fn test_env_file_overrides_environment() {
+    varsubst_with_env(&[("DB_HOST", "from-env"), ("DB_USER", "admin")])
+        .args(["--env-file", &fixture("app.env")])
+        .write_stdin("${DB_HOST} ${DB_USER}")
+        .assert()
+        .success()
+        .stdout("db.internal admin");
+}
+
+#[test]
+fn test_var_overrides_env_file() {
+    varsubst_with_env(&[("DB_HOST", "from-env")])
+        .args(["--env-file", &fixture("app.env"), "-v", "DB_HOST=from-flag"])
+        .write_stdin("${DB_HOST}")
+        .assert()
+        .success()
+        .stdout("from-flag");
+}
+
+#[test]
+fn test_env_file_missing() {
+    let path = fixture("no_such.env");
+    varsubst()
+        .args(["--env-file", &path])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Error reading env file '{}'",
+            path
+        )));
+}
+
+#[test]
+fn test_env_file_malformed_line() {
+    let path = fixture("broken.env");
+    varsubst()
+        .args(["--env-file", &path])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Invalid env file '{}': line 2",
+            path
+        )));
+}
+
+#[test]
+fn test_multiple_env_files_layer_in_order() {
+    varsubst_with_env(&[("NAME", "from-env"), ("TOKEN", "secret")])
+        .args(["--env-file", &fixture("layered/.env")])
+        .args(["--env-file", &fixture("layered/.env.local")])
+        .args(["--env-file", &fixture("layered/.env.production")])
+        .args(["-v", "HOST=from-flag"])
+        .write_stdin("${LEVEL} ${HOST} ${PORT} ${NAME} ${TOKEN}")
+        .assert()
+        .success()
+        .stdout("production from-flag 8443 app secret");
+}
+
+#[test]
+fn test_multiple_env_files_order_matters() {
+    varsubst()
+        .args(["--env-file", &fixture("layered/.env.production")])
+        .args(["--env-file", &fixture("layered/.env")])
+        .write_stdin("${LEVEL} ${PORT}")
+        .assert()
+        .success()
+        .stdout("base 80");
+}
+
+#[test]
+fn test_vars_json_coercion() {
+    varsubst()
+        .args(["--vars-json", &fixture("vars.json")])
+        .write_stdin("${HOST}:${PORT} ratio=${RATIO} debug=${DEBUG}")
+        .assert()
+        .success()
+        .stdout("api.example.com:8443 ratio=0.75 debug=false");
+}
+
+#[test]
+fn test_vars_json_layering_and_precedence() {
+    varsubst()
+        .args(["--env-file", &fixture("app.env")])
+        .args(["--vars-json", &fixture("vars.json")])
+        .args(["--vars-json", &fixture("override.json")])
+        .args(["-v", "HOST=from-flag"])
+        .write_stdin("${HOST}:${PORT} ${DB_HOST}")
+        .assert()
+        .success()
+        .stdout("from-flag:9000 db.internal");
+}
+
+#[test]
+fn test_vars_json_nested_value_error() {
+    varsubst()
+        .args(["--vars-json", &fixture("nested.json")])
+        .write_stdin("${HOST}")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "key 'DATABASE' has an object value",
+        ));
+}
+
+#[test]
+fn test_vars_json_from_stdin() {
+    varsubst()
+        .args(["--vars-json", "-"])
+        .arg(fixture("missing.txt"))
+        .write_stdin(r#"{"HOST": "h", "PORT": 1}"#)
+        .assert()
+        .success()
+        .stdout("host=h port=1\n");
+}
+
+#[test]
+fn test_vars_json_stdin_conflicts_with_stdin_template() {
+    varsubst()
+        .args(["--vars-json", "-"])
+        .write_stdin("{}")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "cannot read both the template and --vars-json from stdin",
+        ));
+}
+
+#[test]
+fn test_vars_yaml() {
+    varsubst()
+        .args(["--vars-yaml", &fixture("vars.yaml")])
+        .write_stdin("${HOST}:${PORT} ${DEBUG}")
+        .assert()
+        .success()
+        .stdout("yaml.example.com:8080 true");
+}
+
+#[test]
+fn test_vars_toml() {
+    varsubst()
+        .args(["--vars-toml", &fixture("vars.toml")])
+        .write_stdin("${HOST}:${PORT} ${DEBUG}")
+        .assert()
+        .success()
+        .stdout("toml.example.com:9090 false");
+}
+
+#[test]
+fn test_vars_yaml_nested_error_names_file_and_key() {
+    let path = fixture("nested.yaml");
+    varsubst()
+        .args(["--vars-yaml", &path])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Invalid variables file '{}': key 'database' has an object value",
+            path
+        )));
+}
+
+#[test]
+fn test_vars_toml_nested_error_names_file_and_key() {
+    let path = fixture("nested.toml");
+    varsubst()
+        .args(["--vars-toml", &path])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Invalid variables file '{}': key 'database' has an object value",
+            path
+        )));
+}
+
+#[test]
+fn test_vars_flatten() {
+    varsubst()
+        .args(["--flatten", "--vars-yaml", &fixture("nested.yaml")])
+        .write_stdin("${database_host}:${database_port}")
+        .assert()
+        .success()
+        .stdout("db.internal:5432");
+
+    varsubst()
+        .args(["--flatten", "--vars-toml", &fixture("nested.toml")])
+        .write_stdin("${database_host}")
+        .assert()
+        .success()
+        .stdout("db.internal");
+}
+
+#[test]
+fn test_var_files_apply_in_command_line_order() {
+    varsubst()
+        .args(["--vars-toml", &fixture("vars.toml")])
+        .args(["--vars-yaml", &fixture("vars.yaml")])
+        .write_stdin("${HOST}")
+        .assert()
+        .success()
+        .stdout("yaml.example.com");
+
+    varsubst()
+        .args(["--vars-yaml", &fixture("vars.yaml")])
+        .args(["--vars-toml", &fixture("vars.toml")])
+        .write_stdin("${HOST}")
+        .assert()
+        .success()
+        .stdout("toml.example.com");
+}
+
+#[test]
+fn test_vars_stdin_lines() {
+    varsubst()
+        .arg("--vars-stdin")
+        .arg(fixture("missing.txt"))
+        .write_stdin("# generated\n\nHOST=piped.example.com\nPORT= 80 \n")
+        .assert()
+        .success()
+        .stdout("host=piped.example.com port= 80 \n");
+}
+
+#[test]
+fn test_vars_stdin_json() {
+    varsubst()
+        .arg("--vars-stdin=json")
+        .arg(fixture("missing.txt"))
+        .write_stdin(r#"{"HOST": "json.example.com", "PORT": 443}"#)
+        .assert()
+        .success()
+        .stdout("host=json.example.com port=443\n");
+}
+
+#[test]
+fn test_vars_stdin_overridden_by_var() {
+    varsubst()
+        .args(["--vars-stdin", "-v", "PORT=1"])
+        .arg(fixture("missing.txt"))
+        .write_stdin("HOST=h\nPORT=2\n")
+        .assert()
+        .success()
+        .stdout("host=h port=1\n");
+}
+
+#[test]
+fn test_vars_stdin_line_without_equals() {
+    varsubst()
+        .arg("--vars-stdin")
+        .arg(fixture("missing.txt"))
+        .write_stdin("HOST=h\noops\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid variables on stdin: line 2: expected KEY=VALUE",
+        ));
+}
+
+#[test]
+fn test_vars_stdin_conflicts_with_stdin_template() {
+    varsubst()
+        .arg("--vars-stdin")
+        .write_stdin("HOST=h\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "cannot read both the template and --vars-stdin from stdin",
+        ));
+}
+
+#[test]
+fn test_prefix_restricts_environment() {
+    varsubst_with_env(&[
+        ("MYAPP_HOST", "app.local"),
+        ("AWS_SECRET_ACCESS_KEY", "hunter2"),
+    ])
+    .args(["--prefix", "MYAPP_"])
+    .write_stdin("${MYAPP_HOST} ${AWS_SECRET_ACCESS_KEY}")
+    .assert()
+    .success()
+    .stdout("app.local ${AWS_SECRET_ACCESS_KEY}");
+}
+
+#[test]
+fn test_prefix_multiple_flags_union() {
+    varsubst_with_env(&[("MYAPP_HOST", "h"), ("CI_JOB", "42"), ("OTHER", "x")])
+        .args(["--prefix", "MYAPP_", "--prefix", "CI_"])
+        .write_stdin("${MYAPP_HOST} ${CI_JOB} ${OTHER}")
+        .assert()
+        .success()
+        .stdout("h 42 ${OTHER}");
+}
+
+#[test]
+fn test_prefix_does_not_filter_explicit_variables() {
+    varsubst_with_env(&[("OTHER", "from-env")])
+        .args(["--prefix", "MYAPP_", "-v", "OTHER=from-flag"])
+        .args(["--env-file", &fixture("app.env")])
+        .write_stdin("${OTHER} ${DB_HOST}")
+        .assert()
+        .success()
+        .stdout("from-flag db.internal");
+}
+
+#[test]
+fn test_strip_prefix_shadows_real_variable() {
+    varsubst_with_env(&[("MYAPP_DB_HOST", "db.internal"), ("DB_HOST", "real")])
+        .args(["--strip-prefix", "MYAPP_"])
+        .write_stdin("${DB_HOST} ${MYAPP_DB_HOST}")
+        .assert()
+        .success()
+        .stdout("db.internal ${MYAPP_DB_HOST}");
+}
+
+#[test]
+fn test_strip_prefix_shadowing_is_verbose() {
+    varsubst_with_env(&[("MYAPP_HOST", "a"), ("HOST", "b"), ("MYAPP_PORT", "1")])
+        .args(["--strip-prefix", "MYAPP_", "--verbose", "--verbose"])
+        .args(["-e", "${HOST}:${PORT}"])
+        .assert()
+        .success()
+        .stdout("a:1\n")
+        .stderr(predicate::str::contains(
+            "HOST from MYAPP_HOST (--strip-prefix), shadows environment HOST",
+        ))
+        .stderr(predicate::str::contains(
+            "PORT from MYAPP_PORT (--strip-prefix)\n",
+        ));
+}
+
+#[test]
+fn test_strip_prefix_keep_prefixed() {
+    varsubst_with_env(&[("MYAPP_DB_HOST", "db.internal")])
+        .args(["--strip-prefix", "MYAPP_", "--keep-prefixed"])
+        .write_stdin("${DB_HOST} ${MYAPP_DB_HOST}")
+        .assert()
+        .success()
+        .stdout("db.internal db.internal");
+}
+
+#[test]
+fn test_strip_prefix_with_prefix_filter() {
+    varsubst_with_env(&[
+        ("MYAPP_DB_HOST", "db.internal"),
+        ("DB_PORT", "5432"),
+        ("HOME", "/root"),
+    ])
+    .args(["--prefix", "MYAPP_", "--strip-prefix", "MYAPP_"])
+    .write_stdin("${DB_HOST} ${DB_PORT} ${HOME}")
+    .assert()
+    .success()
+    .stdout("db.internal ${DB_PORT} ${HOME}");
+}
+
+/// Create a temporary directory containing `name` with `content`
+fn temp_file(name: &str, content: &str) -> (TempDir, String) {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join(name);
+    fs::write(&path, content).unwrap();
+    (dir, path.to_str().unwrap().to_string())
+}
+
+#[test]
+fn test_in_place_without_backup() {
+    let (dir, path) = temp_file("app.conf", "host=${HOST}\n");
+    varsubst()
+        .args(["-i", "-v", "HOST=example.com"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "host=example.com\n");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_in_place_with_backup() {
+    let (_dir, path) = temp_file("app.conf", "host=${HOST}\n");
+    varsubst()
+        .args(["-i=.bak", "-v", "HOST=example.com"])
+        .arg(&path)
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "host=example.com\n");
+    assert_eq!(
+        fs::read_to_string(format!("{}.bak", path)).unwrap(),
+        "host=${HOST}\n"
+    );
+}
+
+#[test]
+fn test_in_place_long_form() {
+    let (_dir, path) = temp_file("app.conf", "${A}");
+    varsubst()
+        .args(["--in-place=.orig", "-v", "A=1"])
+        .arg(&path)
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "1");
+    assert_eq!(
+        fs::read_to_string(format!("{}.orig", path)).unwrap(),
+        "${A}"
+    );
+}
+
+#[test]
+fn test_in_place_error_leaves_original_untouched() {
+    let (dir, path) = temp_file("app.conf", "host=${HOST} ${BROKEN");
+    varsubst()
+        .args(["-i=.bak", "-v", "HOST=example.com"])
+        .arg(&path)
+        .assert()
+        .failure();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "host=${HOST} ${BROKEN");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_in_place_preserves_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (_dir, path) = temp_file("run.sh", "echo ${MSG}\n");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+    varsubst()
+        .args(["-i", "-v", "MSG=hi"])
+        .arg(&path)
+        .assert()
+        .success();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o750);
+}
+
+#[test]
+fn test_in_place_conflicts_with_output() {
+    let (_dir, path) = temp_file("app.conf", "");
+    varsubst()
+        .args(["-i", "-o", "out.conf"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_in_place_requires_file() {
+    varsubst()
+        .arg("-i")
+        .write_stdin("${A}")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--in-place requires an input file",
+        ));
+}
+
+#[test]
+fn test_in_place_sed_style_suffix() {
+    for args in [&["-i", ".bak"][..], &["-i.bak"]] {
+        let (_dir, path) = temp_file("config.conf", "${A}");
+        varsubst()
+            .args(args)
+            .arg(&path)
+            .args(["-v", "A=x"])
+            .assert()
+            .success();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x");
+        assert_eq!(fs::read_to_string(format!("{}.bak", path)).unwrap(), "${A}");
+    }
+
+    // An existing file after -i is an input, not a suffix
+    let (dir, path) = temp_file(".env", "${A}");
+    varsubst()
+        .args(["-i", ".env"])
+        .current_dir(dir.path())
+        .args(["-v", "A=x"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "x");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_multiple_inputs_concatenated_to_stdout() {
+    let dir = TempDir::new().unwrap();
+    let mut paths = Vec::new();
+    for (name, content) in [
+        ("a.txt", "a=${X}\n"),
+        ("b.txt", "b=${X}\n"),
+        ("c.txt", "c\n"),
+    ] {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        paths.push(path);
+    }
+
+    varsubst()
+        .args(["-v", "X=1"])
+        .args(&paths)
+        .assert()
+        .success()
+        .stdout("a=1\nb=1\nc\n");
+}
+
+#[test]
+fn test_print_filenames_between_inputs() {
+    let dir = temp_tree(&[("a.txt", "a=${X}\n"), ("b.txt", "b=${X}\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--print-filenames", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout("==> a.txt <==\na=1\n==> b.txt <==\nb=1\n");
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--print-filenames", "a.txt", "b.txt"])
+        .args(["--filename-format", "# {}", "--separator", "\n"])
+        .assert()
+        .success()
+        .stdout("# a.txt\na=1\n\n# b.txt\nb=1\n");
+}
+
+#[test]
+fn test_separator_without_filenames() {
+    let dir = temp_tree(&[("a.txt", "a=${X}\n"), ("b.txt", "b=${X}\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--separator", "---\n", "a.txt", "b.txt"])
+        .assert()
+        .success()
+        .stdout("a=1\n---\nb=1\n");
+}
+
+#[test]
+fn test_print_filenames_single_input() {
+    let dir = temp_tree(&[("a.txt", "a=${X}\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-v",
+            "X=1",
+            "--print-filenames",
+            "--separator",
+            "--\n",
+            "a.txt",
+        ])
+        .assert()
+        .success()
+        .stdout("a=1\n");
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--always-print-filenames", "a.txt"])
+        .assert()
+        .success()
+        .stdout("==> a.txt <==\na=1\n");
+
+    varsubst()
+        .args(["-v", "X=1", "--always-print-filenames"])
+        .write_stdin("a=${X}\n")
+        .assert()
+        .success()
+        .stdout("==> <stdin> <==\na=1\n");
+}
+
+#[test]
+fn test_print_filenames_requires_stdout() {
+    let dir = temp_tree(&[("a.txt", "a\n"), ("b.txt", "b\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--print-filenames", "--output-dir", "out", "a.txt", "b.txt"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_multiple_inputs_to_output_dir() {
+    let (src, a) = temp_file("a.conf", "${X}-a");
+    let b = src.path().join("b.conf");
+    fs::write(&b, "${X}-b").unwrap();
+    let out = TempDir::new().unwrap();
+
+    varsubst()
+        .args(["-v", "X=1", "-o"])
+        .arg(out.path())
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(
+        fs::read_to_string(out.path().join("a.conf")).unwrap(),
+        "1-a"
+    );
+    assert_eq!(
+        fs::read_to_string(out.path().join("b.conf")).unwrap(),
+        "1-b"
+    );
+}
+
+#[test]
+fn test_multiple_inputs_in_place() {
+    let (dir, a) = temp_file("a.conf", "${X}");
+    let b = dir.path().join("b.conf");
+    fs::write(&b, "${X}${X}").unwrap();
+
+    varsubst()
+        .args(["-i", "-v", "X=1"])
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(&a).unwrap(), "1");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "11");
+}
+
+#[test]
+fn test_multiple_inputs_need_output_dir() {
+    let (dir, a) = temp_file("a.conf", "");
+    varsubst()
+        .arg("-o")
+        .arg(dir.path().join("out.conf"))
+        .arg(&a)
+        .arg(&a)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "-o must be an existing directory when multiple inputs are given",
+        ));
+}
+
+#[test]
+fn test_multiple_inputs_stop_at_first_error() {
+    let (dir, a) = temp_file("a.conf", "${BROKEN");
+    let b = dir.path().join("b.conf");
+    fs::write(&b, "${X}").unwrap();
+    let out = TempDir::new().unwrap();
+
+    varsubst()
+        .args(["-v", "X=1", "-o"])
+        .arg(out.path())
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Substitution error in '{}'",
+            a
+        )));
+
+    assert!(!out.path().join("b.conf").exists());
+}
+
+/// Create files (with parent directories) under a fresh temporary directory
+fn temp_tree(files: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_glob_recursive_sorted() {
+    let dir = temp_tree(&[
+        ("configs/b.tmpl", "b=${X}\n"),
+        ("configs/a.tmpl", "a=${X}\n"),
+        ("configs/nested/c.tmpl", "c=${X}\n"),
+        ("configs/skip.txt", "skip\n"),
+    ]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--glob", "configs/**/*.tmpl"])
+        .assert()
+        .success()
+        .stdout("a=1\nb=1\nc=1\n");
+}
+
+#[test]
+fn test_glob_output_dir() {
+    let dir = temp_tree(&[
+        ("configs/app.tmpl", "${X}"),
+        ("configs/deep/db.tmpl", "${X}${X}"),
+    ]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-v",
+            "X=1",
+            "--glob",
+            "configs/**/*.tmpl",
+            "--output-dir",
+            "out",
+        ])
+        .assert()
+        .success()
+        .stdout("");
+
+    let out = dir.path().join("out");
+    assert_eq!(fs::read_to_string(out.join("app.tmpl")).unwrap(), "1");
+    assert_eq!(fs::read_to_string(out.join("db.tmpl")).unwrap(), "11");
+}
+
+#[test]
+fn test_glob_excludes_hidden_by_default() {
+    let dir = temp_tree(&[("t/a.tmpl", "a\n"), ("t/.hidden.tmpl", "hidden\n")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--glob", "t/*.tmpl"])
+        .assert()
+        .success()
+        .stdout("a\n");
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--glob", "t/*.tmpl", "--hidden"])
+        .assert()
+        .success()
+        .stdout("hidden\na\n");
+}
+
+#[test]
+fn test_glob_no_matches() {
+    let dir = TempDir::new().unwrap();
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--glob", "*.tmpl"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Glob pattern '*.tmpl' matched no files",
+        ));
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--glob", "*.tmpl", "--allow-empty-glob"])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn test_files_from_nul_separated_stdin() {
+    let dir = temp_tree(&[("t/a.tmpl", "a=${X}\n"), ("t/deep/b.tmpl", "b=${X}\n")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-v",
+            "X=1",
+            "--files-from",
+            "-",
+            "-0",
+            "--output-dir",
+            "out",
+        ])
+        .write_stdin("t/a.tmpl\0\0t/deep/b.tmpl\0")
+        .assert()
+        .success()
+        .stdout("");
+
+    let out = dir.path().join("out");
+    assert_eq!(tree_files(&out), ["a.tmpl", "b.tmpl"]);
+    assert_eq!(fs::read_to_string(out.join("a.tmpl")).unwrap(), "a=1\n");
+    assert_eq!(fs::read_to_string(out.join("b.tmpl")).unwrap(), "b=1\n");
+}
+
+#[test]
+fn test_files_from_lines_in_a_file() {
+    let dir = temp_tree(&[
+        ("list", "b.tmpl\n\na.tmpl\n"),
+        ("a.tmpl", "a=${X}\n"),
+        ("b.tmpl", "b=${X}\n"),
+    ]);
+
+    // The list's order is kept, and -0 elsewhere in argv is a value
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=-0", "--files-from", "list"])
+        .assert()
+        .success()
+        .stdout("b=-0\na=-0\n");
+}
+
+#[test]
+fn test_files_from_missing_file_is_named() {
+    let dir = temp_tree(&[("a.tmpl", "a\n")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--files-from", "-", "--keep-going", "--output-dir", "out"])
+        .write_stdin("nope.tmpl\na.tmpl\n")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Error reading input 'nope.tmpl'"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/a.tmpl")).unwrap(),
+        "a\n"
+    );
+}
+
+#[test]
+fn test_files_from_usage_errors() {
+    varsubst()
+        .args(["--files-from", "-", "input.txt"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    varsubst()
+        .args(["--files-from", "-"])
+        .write_stdin("a.tmpl\n-\n")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "a template cannot be read from stdin with --files-from",
+        ));
+
+    varsubst()
+        .args(["--files-from", "-", "--vars-stdin"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "cannot read both --files-from and --vars-stdin from stdin",
+        ));
+}
+
+#[test]
+fn test_files_from_empty_list_reads_no_template() {
+    varsubst()
+        .args(["--files-from", "-", "-0"])
+        .write_stdin("\0")
+        .assert()
+        .success()
+        .stdout("");
+}
+
+/// Relative paths of all files under `root`, sorted
+fn tree_files(root: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let relative = path.strip_prefix(root).unwrap();
+                files.push(relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn test_recursive_mirrors_tree() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "-v", "DB_HOST=db", "-v", "PORT=80"])
+        .args([
+            "--recursive",
+            &fixture("tree"),
+            "--ext",
+            ".tmpl",
+            "--strip-ext",
+        ])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr("3 rendered, 0 copied, 1 skipped\n");
+
+    assert_eq!(
+        tree_files(out.path()),
+        vec!["app.conf", "nested/db.env", "nested/deep/server"]
+    );
+    let db = fs::read_to_string(out.path().join("nested/db.env")).unwrap();
+    assert_eq!(db, "DB_HOST=db\n");
+}
+
+#[test]
+fn test_recursive_copy_others() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "-v", "DB_HOST=db", "-v", "PORT=80"])
+        .args([
+            "--recursive",
+            &fixture("tree"),
+            "--ext",
+            "tmpl",
+            "--copy-others",
+        ])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr("3 rendered, 1 copied, 0 skipped\n");
+
+    assert_eq!(
+        tree_files(out.path()),
+        vec![
+            "README.md",
+            "app.conf.tmpl",
+            "nested/db.env.tmpl",
+            "nested/deep/server.tmpl"
+        ]
+    );
+    // Copied files are not substituted
+    let readme = fs::read_to_string(out.path().join("README.md")).unwrap();
+    assert_eq!(readme, "# ${APP} templates\n");
+}
+
+#[test]
+fn test_recursive_error_names_relative_path() {
+    let src = temp_tree(&[("ok.tmpl", "${X}"), ("sub/bad.tmpl", "${BROKEN")]);
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "X=1", "--recursive"])
+        .arg(src.path())
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Substitution error in '{}'",
+            Path::new("sub").join("bad.tmpl").display()
+        )));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursive_skips_symlinks() {
+    let src = temp_tree(&[("a.tmpl", "${X}"), ("elsewhere/b.tmpl", "${X}")]);
+    std::os::unix::fs::symlink(src.path().join("elsewhere"), src.path().join("link")).unwrap();
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "X=1", "--recursive"])
+        .arg(src.path())
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr("2 rendered, 0 copied, 1 skipped\n");
+
+    assert_eq!(tree_files(out.path()), vec!["a.tmpl", "elsewhere/b.tmpl"]);
+}
+
+#[test]
+fn test_output_dir_with_base_dir_mirrors_paths() {
+    let dir = temp_tree(&[
+        ("src/app.conf.tmpl", "${X}"),
+        ("src/nested/deep/db.conf.tmpl", "${X}${X}"),
+    ]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "X=1", "--output-dir", "out/new", "--base-dir", "src"])
+        .args(["--ext", ".tmpl", "--strip-ext"])
+        .args(["./src/app.conf.tmpl", "src/nested/deep/db.conf.tmpl"])
+        .assert()
+        .success();
+
+    let out = dir.path().join("out/new");
+    assert_eq!(tree_files(&out), vec!["app.conf", "nested/deep/db.conf"]);
+    assert_eq!(
+        fs::read_to_string(out.join("nested/deep/db.conf")).unwrap(),
+        "11"
+    );
+}
+
+#[test]
+fn test_output_dir_input_outside_base_dir() {
+    let dir = temp_tree(&[("src/a", ""), ("other/b", "")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "--output-dir",
+            "out",
+            "--base-dir",
+            "src",
+            "src/a",
+            "other/b",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Input 'other/b' is not inside --base-dir 'src'",
+        ));
+}
+
+#[test]
+fn test_output_dir_conflict_detected_before_writing() {
+    let dir = temp_tree(&[("one/app.conf", "${X}"), ("two/app.conf", "${X}")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-v",
+            "X=1",
+            "--output-dir",
+            "out",
+            "one/app.conf",
+            "two/app.conf",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Inputs 'one/app.conf' and 'two/app.conf' would both be written to",
+        ));
+
+    assert!(!dir.path().join("out").exists());
+}
+
+#[test]
+fn test_output_dir_strip_ext_conflict() {
+    let dir = temp_tree(&[("t/app.conf", "plain"), ("t/app.conf.tmpl", "${X}")]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--recursive", "t", "--output-dir", "out"])
+        .args(["--ext", ".tmpl", "--strip-ext", "--copy-others"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("would both be written to"));
+
+    assert!(!dir.path().join("out").exists());
+}
+
+#[test]
+fn test_stream_stdin() {
+    varsubst()
+        .args(["--stream", "-v", "A=1"])
+        .write_stdin("x=${A}\ny=${B}\n")
+        .assert()
+        .success()
+        .stdout("x=1\ny=${B}\n");
+}
+
+#[test]
+fn test_stream_error_reports_line() {
+    varsubst()
+        .arg("--stream")
+        .write_stdin("fine\nstill fine\nbroken ${A\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(
+            "Substitution error at line 3, column 8: ",
+        ));
+}
+
+/// Pipe 100 MB through `--stream` under a 64 MB address-space limit, which
+/// buffering the whole input would exceed
+#[cfg(unix)]
+#[test]
+#[ignore = "slow: streams 100 MB"]
+fn test_stream_large_input_bounded_memory() {
+    use std::io::{Read, Write};
+    use std::process::Stdio;
+
+    const LINE: &str = "value=${A} padding padding padding padding padding\n";
+    const TOTAL: usize = 100 * 1024 * 1024;
+
+    let bin = assert_cmd::cargo::cargo_bin!("varsubst");
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(r#"ulimit -v 65536 && exec "$0" --no-env --stream -v A=1"#)
+        .arg(bin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || {
+        let chunk = LINE.repeat(1024);
+        let mut written = 0;
+        while written < TOTAL {
+            stdin.write_all(chunk.as_bytes()).unwrap();
+            written += chunk.len();
+        }
+        written / LINE.len()
+    });
+
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut output_len = 0;
+    loop {
+        let n = stdout.read(&mut buffer).unwrap();
+        if n == 0 {
+            break;
+        }
+        output_len += n;
+    }
+
+    let lines = writer.join().unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(output_len, lines * (LINE.len() - "${A}".len() + 1));
+}
+
+#[test]
+fn test_dialects_render_identically() {
+    let expected = "server example.com:80 # example.com\n";
+    for flags in [
+        vec![],
+        vec!["--style", "handlebars"],
+        vec!["--delim-open", "{{", "--delim-close", "}}"],
+    ] {
+        let file = if flags.is_empty() {
+            "dialect_shell.txt"
+        } else {
+            "dialect_handlebars.txt"
+        };
+        varsubst()
+            .args(["-v", "HOST=example.com", "-v", "PORT=80"])
+            .args(&flags)
+            .arg(fixture(file))
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    varsubst()
+        .args([
+            "-v",
+            "HOST=example.com",
+            "-v",
+            "PORT=80",
+            "--style",
+            "percent",
+        ])
+        .arg(fixture("dialect_percent.txt"))
+        .assert()
+        .success()
+        .stdout(expected);
+}
+
+#[test]
+fn test_custom_delimiters_leave_shell_syntax_alone() {
+    varsubst()
+        .args(["-v", "A=1", "--style", "handlebars"])
+        .write_stdin("{{A}} ${A} \\{{A}}")
+        .assert()
+        .success()
+        .stdout("1 ${A} {{A}}");
+}
+
+#[test]
+fn test_invalid_delimiters() {
+    varsubst()
+        .args(["--delim-open", ""])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("delimiters must not be empty"));
+
+    varsubst()
+        .args(["--delim-open", "<", "--delim-close", "<<"])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("delimiters '<' and '<<' overlap"));
+}
+
+#[test]
+fn test_short_syntax_flags() {
+    let run = |flag: &str| {
+        let mut cmd = varsubst();
+        cmd.args(["-v", "USER=alice", "-v", "HOME=/home/alice", flag])
+            .arg(fixture("short_syntax.txt"));
+        cmd
+    };
+
+    run("--short-syntax")
+        .assert()
+        .success()
+        .stdout("user=alice home=/home/alice\n");
+    run("--no-short-syntax")
+        .assert()
+        .success()
+        .stdout("user=$USER home=/home/alice\n");
+}
+
+#[test]
+fn test_escape_flags() {
+    let run = |flag: &str| {
+        let mut cmd = varsubst();
+        cmd.args(["-v", "USER=alice", flag])
+            .arg(fixture("windows_paths.txt"));
+        cmd
+    };
+
+    run("--escape")
+        .assert()
+        .success()
+        .stdout("path=C:\\Users${USER}\\app\n");
+    run("--no-escape")
+        .assert()
+        .success()
+        .stdout("path=C:\\Users\\alice\\app\n");
+}
+
+#[test]
+fn test_last_syntax_flag_wins() {
+    varsubst()
+        .args(["-v", "A=1", "--short-syntax", "--no-short-syntax"])
+        .write_stdin("$A")
+        .assert()
+        .success()
+        .stdout("$A");
+}
+
+#[test]
+fn test_exit_code_usage() {
+    varsubst().arg("--no-such-flag").assert().code(2);
+    varsubst()
+        .args(["-v", "NOEQUALS"])
+        .write_stdin("")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_exit_code_io() {
+    varsubst()
+        .arg("does/not/exist.txt")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Error reading input"));
+    varsubst()
+        .args(["--env-file", "does/not/exist.env"])
+        .write_stdin("")
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_exit_code_syntax() {
+    varsubst().write_stdin("${BROKEN").assert().code(4);
+    varsubst()
+        .arg("--check")
+        .arg(fixture("check_bad.txt"))
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn test_exit_code_undefined() {
+    varsubst()
+        .args(["--missing", "error"])
+        .write_stdin("${UNDEFINED}")
+        .assert()
+        .code(5);
+    varsubst()
+        .arg("-f")
+        .write_stdin("${UNDEFINED}")
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn test_exit_code_other_failure() {
+    varsubst()
+        .arg("--env-file")
+        .arg(fixture("broken.env"))
+        .write_stdin("")
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn test_exit_codes_listed_in_help() {
+    varsubst()
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Exit status:"))
+        .stdout(predicate::str::contains("5  Undefined variable"));
+}
+
+#[test]
+fn test_verbose_summary() {
+    varsubst()
+        .arg("--verbose")
+        .arg("--env-file")
+        .arg(fixture("app.env"))
+        .args(["-v", "PORT=80"])
+        .arg(fixture("missing.txt"))
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout("host=${HOST} port=80\n")
+        .stderr(predicate::str::contains(format!(
+            "variables: --env-file '{}' (",
+            fixture("app.env")
+        )))
+        .stderr(predicate::str::contains("-v (1)"))
+        .stderr(predicate::str::contains(format!(
+            "{}: 2 reference(s), 1 substituted, 1 undefined (HOST)",
+            fixture("missing.txt")
+        )))
+        .stderr(predicate::str::is_match("(?m)^elapsed: ").unwrap());
+}
+
+#[test]
+fn test_verbose_twice_lists_references_without_values() {
+    varsubst()
+        .args(["--verbose", "--verbose", "-v", "PORT=secret-value"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("  1:6: HOST undefined"))
+        .stderr(predicate::str::contains("  1:19: PORT from -v"))
+        .stderr(predicate::str::contains("secret-value").not());
+}
+
+#[test]
+fn test_dry_run_in_place_leaves_file_untouched() {
+    let (dir, path) = temp_file("app.conf", "host=${HOST} port=${PORT}\n");
+    varsubst()
+        .args(["--dry-run", "-i=.bak", "-v", "HOST=example.com"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains(format!(
+            "{}: would change '{}' (backup '{}.bak')",
+            path, path, path
+        )))
+        .stderr(predicate::str::contains("  substituted: HOST\n"))
+        .stderr(predicate::str::contains("  undefined: PORT\n"));
+
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "host=${HOST} port=${PORT}\n"
+    );
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_dry_run_detects_unchanged_output() {
+    let (src, template) = temp_file("app.conf", "${X}\n");
+    let out = TempDir::new().unwrap();
+    fs::write(out.path().join("app.conf"), "1\n").unwrap();
+
+    varsubst()
+        .args(["--dry-run", "-v", "X=1", "--output-dir"])
+        .arg(out.path())
+        .arg(&template)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("would leave"))
+        .stderr(predicate::str::contains("unchanged"));
+
+    let new_out = src.path().join("fresh");
+    varsubst()
+        .args(["--dry-run", "-v", "X=1", "--output-dir"])
+        .arg(&new_out)
+        .arg(&template)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("would create"));
+    assert!(!new_out.exists());
+}
+
+#[test]
+fn test_dry_run_exit_code_matches_real_run() {
+    let (_dir, path) = temp_file("app.conf", "${UNDEFINED}");
+    varsubst()
+        .args(["--dry-run", "-i", "--missing", "error"])
+        .arg(&path)
+        .assert()
+        .code(5);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "${UNDEFINED}");
+}
+
+#[test]
+fn test_fail_on_unused_var() {
+    varsubst()
+        .args(["--fail-on-unused", "-v", "HOST=h", "-v", "DB_PASSWORD=x"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .code(6)
+        .stdout("host=h port=${PORT}\n")
+        .stderr("Unused variable 'DB_PASSWORD' (from -v)\n");
+}
+
+#[test]
+fn test_fail_on_unused_env_file() {
+    varsubst()
+        .args(["--fail-on-unused", "--env-file"])
+        .arg(fixture("app.env"))
+        .write_stdin("${DB_HOST}:${DB_PORT}")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(format!(
+            "Unused variable 'DB_NAME' (from --env-file '{}')",
+            fixture("app.env")
+        )))
+        .stderr(predicate::str::contains("'DB_PASS'"))
+        .stderr(predicate::str::contains("'DB_HOST'").not());
+}
+
+#[test]
+fn test_fail_on_unused_ignores_environment() {
+    varsubst_with_env(&[("UNRELATED", "1"), ("HOST", "h")])
+        .args(["--fail-on-unused", "-v", "PORT=80"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=h port=80\n");
+}
+
+#[test]
+fn test_allow_unused() {
+    varsubst()
+        .args([
+            "--fail-on-unused",
+            "--allow-unused",
+            "EXTRA",
+            "-v",
+            "EXTRA=1",
+        ])
+        .write_stdin("plain")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_deny_pattern_rejects_reference() {
+    varsubst()
+        .args(["--deny", "AWS_*", "--deny", "*_SECRET*", "-v", "AWS_KEY=k"])
+        .write_stdin("ok ${HOST}\nkey=${AWS_KEY}\n  db=${DB_SECRET}\n")
+        .assert()
+        .code(7)
+        .stdout("")
+        .stderr(
+            "<stdin>:2:5: reference to 'AWS_KEY' is denied by pattern 'AWS_*'
+  |
+2 | key=${AWS_KEY}
+  |     ^^^^^^^^^^
+<stdin>:3:6: reference to 'DB_SECRET' is denied by pattern '*_SECRET*'
+  |
+3 |   db=${DB_SECRET}
+  |      ^^^^^^^^^^^^
+",
+        );
+}
+
+#[test]
+fn test_allow_mode_rejects_unlisted_names() {
+    varsubst()
+        .args(["--allow", "APP_*", "-v", "APP_NAME=demo"])
+        .write_stdin("${APP_NAME} ${HOME}")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "reference to 'HOME' is not matched by any --allow pattern",
+        ));
+
+    varsubst()
+        .args(["--allow", "APP_*", "-v", "APP_NAME=demo"])
+        .write_stdin("${APP_NAME}")
+        .assert()
+        .success()
+        .stdout("demo");
+}
+
+#[test]
+fn test_bare_star_patterns() {
+    // Allowing everything is the same as no allow list
+    varsubst()
+        .args(["--allow", "*", "-v", "A=1"])
+        .write_stdin("${A}")
+        .assert()
+        .success()
+        .stdout("1");
+
+    // Deny wins over allow, and a bare * deny rejects every reference
+    varsubst()
+        .args(["--allow", "*", "--deny", "*"])
+        .write_stdin("plain text is fine")
+        .assert()
+        .success();
+    varsubst()
+        .args(["--allow", "*", "--deny", "*"])
+        .write_stdin("${A}")
+        .assert()
+        .code(7);
+}
+
+#[test]
+fn test_deny_applies_to_defined_and_undefined_references() {
+    varsubst()
+        .args(["--deny", "SECRET", "--stream"])
+        .write_stdin("a\n${SECRET}\n")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "<stdin>:2:1: reference to 'SECRET'",
+        ));
+}
+
+#[test]
+fn test_completions_bash_and_zsh() {
+    for shell in ["bash", "zsh"] {
+        // No stdin is provided: the subcommand must not wait for a template
+        cargo_bin_cmd!("varsubst")
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("varsubst"))
+            .stdout(predicate::str::contains("--missing"))
+            .stdout(predicate::str::contains("--output-dir"))
+            .stdout(predicate::str::contains("--fail-on-undefined"));
+    }
+}
+
+#[test]
+fn test_man_page() {
+    let output = cargo_bin_cmd!("varsubst")
+        .arg("man")
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    let page = String::from_utf8(output.stdout).unwrap();
+    for section in [
+        ".TH varsubst 1",
+        ".SH SYNOPSIS",
+        ".SH OPTIONS",
+        ".SH SUBCOMMANDS",
+        ".SH \"TEMPLATE SYNTAX\"",
+        ".SH \"EXIT STATUS\"",
+        ".TH varsubst-check 1",
+        ".TH varsubst-vars 1",
+    ] {
+        assert!(page.contains(section), "missing {}", section);
+    }
+    // Every long flag listed by --help, and those only subcommands have; roff
+    // escapes each '-'
+    let roff = page.replace("\\-", "-");
+    let help = cargo_bin_cmd!("varsubst")
+        .args(["render", "--help"])
+        .output()
+        .unwrap();
+    assert!(help.status.success());
+    let mut flags = vec!["--json".to_string(), "--out-dir".to_string()];
+    flags.extend(
+        String::from_utf8(help.stdout)
+            .unwrap()
+            .split_whitespace()
+            .filter(|word| word.starts_with("--") && word.len() > 2)
+            .map(|word| word.trim_end_matches([',', '.']).to_string()),
+    );
+    for flag in flags {
+        let flag = flag.split(['=', '[', '<']).next().unwrap();
+        assert!(roff.contains(flag), "missing {}", flag);
+    }
+    assert!(roff.contains("The value of NAME"));
+    assert!(roff.contains("Template syntax error"));
+}
+
+#[test]
+fn test_man_out_dir() {
+    let dir = TempDir::new().unwrap();
+    let out = dir.path().join("man/man1");
+    cargo_bin_cmd!("varsubst")
+        .args(["man", "--out-dir"])
+        .arg(&out)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        tree_files(&out),
+        vec![
+            "varsubst-check.1",
+            "varsubst-completions.1",
+            "varsubst-man.1",
+            "varsubst-render.1",
+            "varsubst-vars.1",
+            "varsubst.1"
+        ]
+    );
+}
+
+#[test]
+fn test_help_shows_template_syntax() {
+    cargo_bin_cmd!("varsubst")
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Template syntax:"))
+        .stdout(predicate::str::contains("Exit status:"));
+}
+
+#[test]
+fn test_completions_offer_missing_modes() {
+    cargo_bin_cmd!("varsubst")
+        .args(["completions", "zsh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("keep"))
+        .stdout(predicate::str::contains("default="));
+}
+
+#[test]
+fn test_completions_rejects_unknown_shell() {
+    cargo_bin_cmd!("varsubst")
+        .args(["completions", "tcsh"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid value 'tcsh'"));
+}
+
+#[test]
+fn test_file_named_like_subcommand_renders_with_path() {
+    let dir = temp_tree(&[("completions", "${A}")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "./completions"])
+        .assert()
+        .success()
+        .stdout("1");
+}
+
+/// Parse each stderr line as a JSON diagnostic
+fn json_diagnostics(stderr: &[u8]) -> Vec<serde_json::Value> {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("stderr line is not JSON"))
+        .collect()
+}
+
+#[test]
+fn test_json_errors_check_reports_each_error() {
+    let output = varsubst()
+        .args(["--json-errors", "--check", "tests/fixtures/json_errors.txt"])
+        .assert()
+        .code(4)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+
+    let invalid = &diagnostics[0];
+    assert_eq!(invalid["severity"], "error");
+    assert_eq!(invalid["code"], "invalid-name");
+    assert_eq!(invalid["file"], "tests/fixtures/json_errors.txt");
+    assert_eq!(invalid["line"], 2);
+    assert_eq!(invalid["column"], 7);
+    assert_eq!(invalid["span"], serde_json::json!([20, 23]));
+    assert_eq!(invalid["variable"], "");
+    assert!(invalid["message"].is_string());
+
+    let unclosed = &diagnostics[1];
+    assert_eq!(unclosed["code"], "unclosed-brace");
+    assert_eq!(unclosed["line"], 3);
+    assert_eq!(unclosed["column"], 7);
+    assert_eq!(unclosed["span"], serde_json::json!([30, 36]));
+    assert!(unclosed["variable"].is_null());
+}
+
+#[test]
+fn test_json_errors_undefined_and_io() {
+    let output = varsubst()
+        .args(["--json-errors", "--missing", "error", "-v", "NAME=x"])
+        .write_stdin("a: ${A}\nb: ${B}\n")
+        .assert()
+        .code(5)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["code"], "undefined-variable");
+    assert_eq!(diagnostics[0]["variable"], "A");
+    assert_eq!(diagnostics[1]["variable"], "B");
+    assert_eq!(diagnostics[1]["line"], 2);
+    assert_eq!(diagnostics[0]["file"], "<stdin>");
+    assert_eq!(diagnostics[0]["line"], 1);
+    assert_eq!(diagnostics[0]["column"], 4);
+    assert_eq!(diagnostics[0]["span"], serde_json::json!([3, 7]));
+
+    let output = varsubst()
+        .args(["--json-errors", "tests/fixtures/does_not_exist.txt"])
+        .assert()
+        .code(3)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    assert_eq!(diagnostics[0]["code"], "io");
+    assert_eq!(diagnostics[0]["file"], "tests/fixtures/does_not_exist.txt");
+    assert!(diagnostics[0]["line"].is_null());
+}
+
+#[test]
+fn test_json_errors_stream_locations_are_file_relative() {
+    let output = varsubst()
+        .args(["--json-errors", "--stream", "--deny", "SECRET"])
+        .write_stdin("first\nkey=${SECRET}\n")
+        .assert()
+        .code(7)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    assert_eq!(diagnostics[0]["code"], "denied-variable");
+    assert_eq!(diagnostics[0]["line"], 2);
+    assert_eq!(diagnostics[0]["column"], 5);
+    assert_eq!(diagnostics[0]["span"], serde_json::json!([10, 19]));
+}
+
+#[test]
+fn test_json_errors_usage_error() {
+    let output = varsubst()
+        .args(["--json-errors", "--no-such-flag"])
+        .assert()
+        .code(2)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    assert_eq!(diagnostics[0]["code"], "usage");
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("--no-such-flag"));
+}
+
+#[test]
+fn test_json_errors_schema_in_help() {
+    varsubst()
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--json-errors"))
+        .stdout(predicate::str::contains("undefined-variable"))
+        .stdout(predicate::str::contains("span"));
+}
+
+#[test]
+fn test_error_excerpt_substitution() {
+    varsubst()
+        .args(["--color", "never", "--fail-on-undefined", "--fail-fast"])
+        .arg("tests/fixtures/json_errors.txt")
+        .assert()
+        .code(5)
+        .stderr(
+            "Substitution error in 'tests/fixtures/json_errors.txt': \
+             Undefined variable 'NAME' at position 6\n \
+             --> tests/fixtures/json_errors.txt:1:7\n  \
+             |\n\
+             1 | name: ${NAME}\n  \
+             |       ^^^^^^^\n",
+        );
+}
+
+#[test]
+fn test_error_excerpt_check_prints_one_block_per_error() {
+    varsubst()
+        .args([
+            "--color",
+            "never",
+            "--check",
+            "tests/fixtures/json_errors.txt",
+        ])
+        .assert()
+        .code(4)
+        .stderr(
+            "tests/fixtures/json_errors.txt:2:7: Invalid variable name '' at position 20
+  |
+2 | port: ${}
+  |       ^^^
+tests/fixtures/json_errors.txt:3:7: Unclosed brace at position 30
+  |
+3 | host: ${HOST
+  |       ^^^^^^
+2 error(s) in 1 of 1 file(s)
+",
+        );
+}
+
+#[test]
+fn test_error_excerpt_policy_stream_line() {
+    varsubst()
+        .args(["--color", "never", "--stream", "--deny", "SECRET"])
+        .write_stdin("ok\nkey: ${SECRET}\n")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "<stdin>:2:6: reference to 'SECRET' is denied by pattern 'SECRET'\n  \
+             |\n\
+             2 | key: ${SECRET}\n  \
+             |      ^^^^^^^^^\n",
+        ));
+}
+
+#[test]
+fn test_error_excerpt_color() {
+    varsubst()
+        .args(["--color", "always", "--fail-on-undefined"])
+        .write_stdin("${NAME}")
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("\x1b[1;31m^^^^^^^\x1b[0m"));
+
+    // auto never colors a captured stderr
+    varsubst()
+        .arg("--fail-on-undefined")
+        .write_stdin("${NAME}")
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("^^^^^^^"))
+        .stderr(predicate::str::contains("\x1b[").not());
+}
+
+/// Poll until `path` holds `expected`, failing after a few seconds
+fn wait_for_content(path: &Path, expected: &str) {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(path).ok().as_deref() != Some(expected) {
+        assert!(
+            Instant::now() < deadline,
+            "'{}' never became {:?}",
+            path.display(),
+            expected
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_watch_rerenders_on_change() {
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::Duration;
+
+    let dir = temp_tree(&[("template.conf", "v=${A}\n"), ("vars.env", "A=1\n")]);
+    let template = dir.path().join("template.conf");
+    let out = dir.path().join("out.conf");
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("varsubst"))
+        .current_dir(dir.path())
+        .args(["--no-env", "--watch", "--env-file", "vars.env"])
+        .args(["template.conf", "-o", "out.conf"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    wait_for_content(&out, "v=1\n");
+    // Give the watcher time to register before changing anything
+    thread::sleep(Duration::from_millis(300));
+    fs::write(&template, "v=${A}!\n").unwrap();
+    wait_for_content(&out, "v=1!\n");
+    fs::write(dir.path().join("vars.env"), "A=2\n").unwrap();
+    wait_for_content(&out, "v=2!\n");
+
+    // A broken template is reported without ending the watch
+    fs::write(&template, "v=${A\n").unwrap();
+    thread::sleep(Duration::from_millis(600));
+    fs::write(&template, "v=${A}?\n").unwrap();
+    wait_for_content(&out, "v=2?\n");
+
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("] rendered"), "{}", stderr);
+    assert!(
+        stderr.contains("] Substitution error in 'template.conf'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_watch_rejects_stdin() {
+    varsubst()
+        .arg("--watch")
+        .write_stdin("${A}")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("stdin cannot be watched"));
+}
+
+#[test]
+fn test_null_data_records() {
+    let input = "name=${NAME}\0plain\nwith newline\0price \\${5}\0";
+    varsubst()
+        .args(["-0", "-v", "NAME=alice"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("name=alice\0plain\nwith newline\0price ${5}\0");
+
+    // Without escapes the literal "${" in the third record is an error
+    varsubst()
+        .args(["--null-data", "--no-escape", "-v", "NAME=alice"])
+        .write_stdin("name=${NAME}\0plain\0price ${5\0")
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "Substitution error in record 3 at offset 6: ",
+        ))
+        // Lines of a record are not lines of the input
+        .stderr(predicate::str::contains("<stdin>:").not())
+        .stderr(predicate::str::contains("1 | price ${5"));
+}
+
+#[test]
+fn test_null_data_terminates_last_record() {
+    varsubst()
+        .args(["-0", "--stream", "-v", "A=1"])
+        .write_stdin("${A}\0${A}")
+        .assert()
+        .success()
+        .stdout("1\x001\0");
+}
+
+#[test]
+fn test_null_data_deny_reports_record() {
+    varsubst()
+        .args(["-0", "--deny", "SECRET"])
+        .write_stdin("ok\0x ${SECRET}\0")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "<stdin>: record 2, offset 2: reference to 'SECRET' is denied",
+        ))
+        .stderr(predicate::str::contains("<stdin>:1").not());
+
+    // Excerpts are numbered by line within the record
+    varsubst()
+        .args(["-0", "--deny", "SECRET"])
+        .write_stdin("ok\0first\nx ${SECRET}\0")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "<stdin>: record 2, offset 8: reference to 'SECRET' is denied",
+        ))
+        .stderr(predicate::str::contains("2 | x ${SECRET}"));
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_output_keeps_input_mode() {
+    let dir = temp_tree(&[("run.sh.tmpl", "echo ${A}\n"), ("unit.tmpl", "${A}\n")]);
+    set_file_mode(&dir.path().join("run.sh.tmpl"), 0o755);
+    set_file_mode(&dir.path().join("unit.tmpl"), 0o600);
+
+    for (input, output, mode) in [
+        ("run.sh.tmpl", "run.sh", 0o755),
+        ("unit.tmpl", "unit", 0o600),
+    ] {
+        varsubst()
+            .current_dir(dir.path())
+            .args(["-v", "A=1", input, "-o", output])
+            .assert()
+            .success();
+        assert_eq!(file_mode(&dir.path().join(output)), mode, "{}", output);
+    }
+
+    // Streaming and directory outputs keep it too
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--stream", "-v", "A=1", "run.sh.tmpl", "-o", "streamed.sh"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("streamed.sh")), 0o755);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "--output-dir", "out", "run.sh.tmpl"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("out/run.sh.tmpl")), 0o755);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mode_overrides_and_no_preserve_mode() {
+    let dir = temp_tree(&[("run.sh.tmpl", "echo ${A}\n")]);
+    set_file_mode(&dir.path().join("run.sh.tmpl"), 0o755);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--mode", "0640", "run.sh.tmpl", "-o", "explicit"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("explicit")), 0o640);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--no-preserve-mode", "run.sh.tmpl", "-o", "plain"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("plain")) & 0o111, 0);
+
+    // In-place editing keeps the mode, unless --mode is given
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-i", "run.sh.tmpl"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("run.sh.tmpl")), 0o755);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-i", "--mode", "700", "run.sh.tmpl"])
+        .assert()
+        .success();
+    assert_eq!(file_mode(&dir.path().join("run.sh.tmpl")), 0o700);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mode_for_new_and_streamed_outputs() {
+    let dir = temp_tree(&[("secret.tmpl", "key=${KEY}\n")]);
+    for flags in [&[][..], &["--stream"][..]] {
+        let out = dir.path().join("secret.conf");
+        varsubst()
+            .current_dir(dir.path())
+            .args(flags)
+            .args(["-v", "KEY=k", "--mode", "0600", "secret.tmpl", "-o"])
+            .arg(&out)
+            .assert()
+            .success();
+        assert_eq!(file_mode(&out), 0o600, "{:?}", flags);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "key=k\n");
+        fs::remove_file(&out).unwrap();
+    }
+}
+
+#[test]
+fn test_invalid_mode() {
+    varsubst()
+        .args(["--mode", "0999"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid mode '0999'"));
+}
+
+#[test]
+fn test_stream_error_after_output_began_leaves_destination() {
+    let dir = temp_tree(&[("out.conf", "previous\n")]);
+    let out = dir.path().join("out.conf");
+
+    // The first lines are substituted and written before the error
+    let input = "a=${A}\n".repeat(10_000) + "broken ${A\n";
+    varsubst()
+        .args(["--stream", "-v", "A=1", "-o"])
+        .arg(&out)
+        .write_stdin(input)
+        .assert()
+        .code(4);
+    assert_eq!(fs::read_to_string(&out).unwrap(), "previous\n");
+    assert_eq!(tree_files(dir.path()), ["out.conf"]);
+}
+
+#[test]
+fn test_output_error_leaves_destination_and_no_temp_files() {
+    let dir = temp_tree(&[
+        ("good.tmpl", "${A}"),
+        ("bad.tmpl", "${A"),
+        ("out", "previous"),
+    ]);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "bad.tmpl", "-o", "out"])
+        .assert()
+        .code(4);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out")).unwrap(),
+        "previous"
+    );
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "good.tmpl", "-o", "out"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(dir.path().join("out")).unwrap(), "1");
+    assert_eq!(tree_files(dir.path()), ["bad.tmpl", "good.tmpl", "out"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_output_through_symlink_replaces_target() {
+    let dir = temp_tree(&[("real.conf", "old"), ("t", "${A}")]);
+    std::os::unix::fs::symlink("real.conf", dir.path().join("link.conf")).unwrap();
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=new", "t", "-o", "link.conf"])
+        .assert()
+        .success();
+    assert!(fs::symlink_metadata(dir.path().join("link.conf"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("real.conf")).unwrap(),
+        "new"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_output_to_device_is_written_directly() {
+    varsubst()
+        .args(["-v", "A=1", "-o", "/dev/null"])
+        .write_stdin("${A}")
+        .assert()
+        .success();
+    assert!(!fs::metadata("/dev/null").unwrap().is_file());
+}
+
+fn set_old_mtime(path: &Path) -> std::time::SystemTime {
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    old
+}
+
+fn mtime(path: &Path) -> std::time::SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+#[test]
+fn test_if_changed_skips_identical_output() {
+    let dir = temp_tree(&[("t", "v=${A}\n"), ("out", "v=1\n")]);
+    let out = dir.path().join("out");
+    let old = set_old_mtime(&out);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--if-changed", "--verbose", "-v", "A=1", "t", "-o", "out"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("t: unchanged"));
+    assert_eq!(mtime(&out), old);
+
+    // Without the flag the file is rewritten
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "t", "-o", "out"])
+        .assert()
+        .success();
+    assert_ne!(mtime(&out), old);
+}
+
+#[test]
+fn test_if_changed_rewrites_different_output() {
+    let dir = temp_tree(&[("t", "v=${A}\n"), ("out", "v=1\n")]);
+    let out = dir.path().join("out");
+    let old = set_old_mtime(&out);
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--if-changed", "-v", "A=2", "t", "-o", "out"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&out).unwrap(), "v=2\n");
+    assert_ne!(mtime(&out), old);
+}
+
+#[test]
+fn test_if_changed_summary_and_exit_code() {
+    let dir = temp_tree(&[
+        ("src/a.tmpl", "a=${A}\n"),
+        ("src/b.tmpl", "b=${A}\n"),
+        ("out/a.tmpl", "a=1\n"),
+    ]);
+    let run = |extra: &[&str]| {
+        let mut cmd = varsubst();
+        cmd.current_dir(dir.path())
+            .args(["--if-changed", "-v", "A=1", "--output-dir", "out"])
+            .args(extra)
+            .args(["src/a.tmpl", "src/b.tmpl"]);
+        cmd
+    };
+
+    run(&["--exit-code-on-change"])
+        .assert()
+        .code(8)
+        .stderr(predicate::str::contains("1 changed, 1 unchanged"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/b.tmpl")).unwrap(),
+        "b=1\n"
+    );
+
+    // Nothing left to change
+    run(&["--exit-code-on-change"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("0 changed, 2 unchanged"));
+
+    // Recursive mode reports the counts in its summary
+    fs::write(dir.path().join("src/a.tmpl"), "a=${A}!\n").unwrap();
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "--if-changed",
+            "-v",
+            "A=1",
+            "--output-dir",
+            "out",
+            "--recursive",
+            "src",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "2 rendered, 0 copied, 0 skipped (1 changed, 1 unchanged)",
+        ));
+}
+
+#[test]
+fn test_exit_code_on_change_with_dry_run() {
+    let dir = temp_tree(&[("t", "v=${A}\n"), ("out", "v=1\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--if-changed", "--exit-code-on-change", "--dry-run"])
+        .args(["-v", "A=2", "t", "-o", "out"])
+        .assert()
+        .code(8);
+    assert_eq!(fs::read_to_string(dir.path().join("out")).unwrap(), "v=1\n");
+}
+
+#[test]
+fn test_expr_repeated() {
+    varsubst()
+        .args(["-v", "USER=ana", "-v", "DATE=today"])
+        .args(["-e", "Hello ${USER}", "--expr", "today is ${DATE}"])
+        .assert()
+        .success()
+        .stdout("Hello ana\ntoday is today\n");
+}
+
+#[test]
+fn test_expr_no_newline() {
+    varsubst()
+        .args(["-v", "A=1", "-e", "a=${A}", "-e", ";b", "--no-newline"])
+        .assert()
+        .success()
+        .stdout("a=1;b");
+}
+
+#[test]
+fn test_expr_does_not_read_stdin() {
+    varsubst()
+        .args(["--vars-stdin=lines", "-e", "${NAME}"])
+        .write_stdin("NAME=from-stdin\n")
+        .assert()
+        .success()
+        .stdout("from-stdin\n");
+}
+
+#[test]
+fn test_expr_conflicts_with_input_file() {
+    varsubst()
+        .args(["-e", "x", "tests/fixtures/json_errors.txt"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "'--expr <TEMPLATE>' cannot be used",
+        ));
+}
+
+#[test]
+fn test_expr_missing_error() {
+    varsubst()
+        .args(["--color", "never", "--missing", "error"])
+        .args(["-v", "A=1", "-e", "${A}", "-e", "b ${NOPE}"])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "Substitution error in '<expr 2>': Undefined variable 'NOPE' at position 2\n \
+             --> <expr 2>:1:3",
+        ));
+}
+
+#[test]
+fn test_recursive_values_from_env_file() {
+    let dir = temp_tree(&[(
+        ".env",
+        "URL=${SCHEME}://${HOST}\nHOST=${NAME}.example.com\nSCHEME=https\nNAME=api\n",
+    )]);
+    let env = dir.path().join(".env");
+    varsubst()
+        .args(["--recursive-values", "--env-file", env.to_str().unwrap()])
+        .write_stdin("url: ${URL}\n")
+        .assert()
+        .success()
+        .stdout("url: https://api.example.com\n");
+
+    // Values are inserted verbatim by default
+    varsubst()
+        .args(["--env-file", env.to_str().unwrap()])
+        .write_stdin("url: ${URL}\n")
+        .assert()
+        .success()
+        .stdout("url: ${SCHEME}://${HOST}\n");
+}
+
+#[test]
+fn test_recursive_values_cycle() {
+    varsubst()
+        .args(["--color", "never", "--recursive-values"])
+        .args(["-v", "A=${B}", "-v", "B=x${C}", "-v", "C=${A}"])
+        .write_stdin("a: ${A}\n")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::starts_with(
+            "Substitution error: Circular reference A -> B -> C -> A at position 3\n",
+        ));
+}
+
+#[test]
+fn test_recursive_values_max_depth() {
+    let args = ["-v", "A=${B}", "-v", "B=${C}", "-v", "C=done"];
+    varsubst()
+        .args(["--recursive-values", "--max-depth", "2"])
+        .args(args)
+        .write_stdin("${A}")
+        .assert()
+        .success()
+        .stdout("done");
+    varsubst()
+        .args(["--recursive-values", "--max-depth", "1"])
+        .args(args)
+        .write_stdin("${A}")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Expanding 'B' exceeds the maximum depth of 1",
+        ));
+}
+
+#[test]
+fn test_recursive_values_count_as_used() {
+    varsubst()
+        .args(["--recursive-values", "--fail-on-unused"])
+        .args(["-v", "URL=${HOST}/", "-v", "HOST=h"])
+        .write_stdin("${URL}")
+        .assert()
+        .success()
+        .stdout("h/");
+}
+
+#[test]
+fn test_newline_lf_to_crlf() {
+    varsubst()
+        .args(["--newline", "crlf", "-v", "A=1"])
+        .write_stdin("a=${A}\nb\n")
+        .assert()
+        .success()
+        .stdout("a=1\r\nb\r\n");
+}
+
+#[test]
+fn test_newline_crlf_to_lf_normalizes_mixed_input() {
+    varsubst()
+        .args(["--newline", "lf", "-v", "A=1"])
+        .write_stdin("a=${A}\r\nb\nc\r\n")
+        .assert()
+        .success()
+        .stdout("a=1\nb\nc\n");
+}
+
+#[test]
+fn test_newline_applies_inside_values() {
+    varsubst()
+        .args(["--newline", "crlf", "-v", "CERT=line1\nline2"])
+        .write_stdin("cert: ${CERT}\n")
+        .assert()
+        .success()
+        .stdout("cert: line1\r\nline2\r\n");
+}
+
+#[test]
+fn test_newline_preserve_is_default() {
+    varsubst()
+        .write_stdin("a\r\nb\n")
+        .assert()
+        .success()
+        .stdout("a\r\nb\n");
+}
+
+#[test]
+fn test_newline_stream_and_file_output() {
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("out.txt");
+    varsubst()
+        .args(["--stream", "--newline", "crlf", "-v", "A=x\ny", "-o"])
+        .arg(&output)
+        .write_stdin("1 ${A}\n2\n")
+        .assert()
+        .success();
+    assert_eq!(fs::read(&output).unwrap(), b"1 x\r\ny\r\n2\r\n");
+}
+
+#[test]
+fn test_binary_safe_preserves_bytes() {
+    let output = varsubst()
+        .args(["--binary-safe", "-v", "NAME=ok"])
+        .arg(fixture("binary.bin"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        output,
+        b"\x89PNG\r\n\x1a\n\xff\xfe name=ok \xc3\x28 end\x00\x01"
+    );
+}
+
+#[test]
+fn test_binary_safe_output_file_and_stdin() {
+    let dir = TempDir::new().unwrap();
+    let output = dir.path().join("out.bin");
+    varsubst()
+        .args(["--binary-safe", "-v", "A=1", "-o"])
+        .arg(&output)
+        .write_stdin(&b"\xff${A}\xfe"[..])
+        .assert()
+        .success();
+    assert_eq!(fs::read(&output).unwrap(), b"\xff1\xfe");
+}
+
+#[test]
+fn test_binary_safe_error_position_is_byte_offset() {
+    varsubst()
+        .args(["--binary-safe", "--missing", "error"])
+        .write_stdin(&b"\xff\xfe ${X}"[..])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "Undefined variable 'X' at position 3",
+        ));
+}
+
+#[test]
+fn test_invalid_utf8_rejected_without_binary_safe() {
+    varsubst()
+        .arg(fixture("binary.bin"))
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("did not contain valid UTF-8"));
+}
+
+#[test]
+fn test_config_discovered_from_parent_directory() {
+    let dir = temp_tree(&[
+        (
+            "varsubst.toml",
+            "missing = \"error\"\nenv-file = \"vars/app.env\"\nnewline = \"crlf\"\n",
+        ),
+        ("vars/app.env", "HOST=example.com\n"),
+        ("sub/deep/t.txt", "host=${HOST}\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path().join("sub/deep"))
+        .arg("t.txt")
+        .assert()
+        .success()
+        .stdout("host=example.com\r\n");
+    varsubst()
+        .current_dir(dir.path().join("sub"))
+        .write_stdin("${NOPE}")
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("Undefined variable 'NOPE'"));
+}
+
+#[test]
+fn test_config_overridden_by_command_line() {
+    let dir = temp_tree(&[
+        (
+            "varsubst.toml",
+            "missing = \"error\"\nstyle = \"handlebars\"\nescape = true\nenv-file = \"a.env\"\n",
+        ),
+        ("a.env", "A=from-config\n"),
+        ("b.env", "A=from-cli\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .write_stdin("{{A}} {{B}} \\{{A}}")
+        .assert()
+        .code(5);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--missing", "keep", "--env-file", "b.env", "--no-escape"])
+        .write_stdin("{{A}} {{B}} \\{{A}}")
+        .assert()
+        .success()
+        .stdout("from-cli {{B}} \\from-cli");
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--no-config", "-v", "A=1"])
+        .write_stdin("{{A}} ${A}")
+        .assert()
+        .success()
+        .stdout("{{A}} 1");
+}
+
+#[test]
+fn test_config_path_resolves_relative_to_file() {
+    let dir = temp_tree(&[
+        (
+            "conf/custom.toml",
+            "env-file = [\"one.env\", \"two.env\"]\n",
+        ),
+        ("conf/one.env", "A=1\nB=1\n"),
+        ("conf/two.env", "B=2\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--config", "conf/custom.toml"])
+        .write_stdin("${A}${B}")
+        .assert()
+        .success()
+        .stdout("12");
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--config", "missing.toml"])
+        .write_stdin("")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "Error reading config file 'missing.toml'",
+        ));
+}
+
+#[test]
+fn test_config_unknown_keys_warn_and_bad_values_fail() {
+    let dir = temp_tree(&[("varsubst.toml", "deny = \"*_SECRET\"\nfuture = 1\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1"])
+        .write_stdin("${A}")
+        .assert()
+        .success()
+        .stdout("1")
+        .stderr(predicate::str::contains("unknown key 'future' ignored"));
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "DB_SECRET=x"])
+        .write_stdin("${DB_SECRET}")
+        .assert()
+        .code(7);
+
+    let dir = temp_tree(&[("varsubst.toml", "newline = \"dos\"\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .write_stdin("")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "invalid value 'dos' for 'newline' (expected preserve, lf, crlf, native)",
+        ));
+}
+
+#[test]
+fn test_render_subcommand_and_legacy_form_agree() {
+    let dir = temp_tree(&[("t.tmpl", "host=${HOST}\n")]);
+    for form in [&[][..], &["render"][..]] {
+        let out = dir.path().join("out.conf");
+        cargo_bin_cmd!("varsubst")
+            .current_dir(dir.path())
+            .args(form)
+            .args(["--no-env", "-v", "HOST=example.com", "t.tmpl", "-o"])
+            .arg(&out)
+            .assert()
+            .success()
+            .stdout("");
+        assert_eq!(fs::read_to_string(&out).unwrap(), "host=example.com\n");
+        fs::remove_file(&out).unwrap();
+    }
+}
+
+#[test]
+fn test_render_subcommand_accepts_shared_flags() {
+    cargo_bin_cmd!("varsubst")
+        .args(["render", "--no-env", "--style", "handlebars", "-v", "A=1"])
+        .write_stdin("{{A}} ${A}")
+        .assert()
+        .success()
+        .stdout("1 ${A}");
+}
+
+#[test]
+fn test_check_subcommand() {
+    let dir = temp_tree(&[("ok.txt", "{{A}}"), ("bad.txt", "x\n{{B")]);
+    cargo_bin_cmd!("varsubst")
+        .current_dir(dir.path())
+        .args(["check", "--style", "handlebars", "ok.txt", "bad.txt"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("bad.txt:2:1: Unclosed brace"))
+        .stderr(predicate::str::contains("1 error(s) in 1 of 2 file(s)"));
+    cargo_bin_cmd!("varsubst")
+        .current_dir(dir.path())
+        .args(["check", "ok.txt"])
+        .assert()
+        .success();
+    cargo_bin_cmd!("varsubst")
+        .args(["check", "-v", "A=1"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_vars_subcommand() {
+    cargo_bin_cmd!("varsubst")
+        .arg("vars")
+        .write_stdin("${B} ${A} ${B}")
+        .assert()
+        .success()
+        .stdout("B\nA\n");
+    cargo_bin_cmd!("varsubst")
+        .args(["vars", "--json", "--style", "percent"])
+        .write_stdin("%A%")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("[{\"count\":1,\"name\":\"A\""));
+}
+
+#[test]
+fn test_subcommands_use_config_syntax() {
+    let dir = temp_tree(&[
+        ("varsubst.toml", "style = \"handlebars\"\n"),
+        ("t.txt", "{{A}} ${B}"),
+    ]);
+    cargo_bin_cmd!("varsubst")
+        .current_dir(dir.path())
+        .args(["vars", "t.txt"])
+        .assert()
+        .success()
+        .stdout("A\n");
+}
+
+#[test]
+fn test_file_named_like_subcommand() {
+    let dir = temp_tree(&[("check", "${A}")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "./check"])
+        .assert()
+        .success()
+        .stdout("1");
+}
+
+#[test]
+fn test_leading_bom_is_stripped_by_default() {
+    let output = varsubst()
+        .args(["-v", "NAME=x"])
+        .arg(fixture("bom.txt"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(output, b"name=x\n\xef\xbb\xbfinner\n");
+    for flags in [&["--stream"][..], &["--binary-safe"][..]] {
+        let output = varsubst()
+            .args(flags)
+            .args(["-v", "NAME=x"])
+            .write_stdin(&b"\xef\xbb\xbf${NAME}\n\xef\xbb\xbf"[..])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        assert_eq!(output, b"x\n\xef\xbb\xbf", "{:?}", flags);
+    }
+}
+
+#[test]
+fn test_keep_bom() {
+    for flags in [&["--keep-bom"][..], &["--keep-bom", "--stream"][..]] {
+        let output = varsubst()
+            .args(flags)
+            .args(["-v", "NAME=x"])
+            .arg(fixture("bom.txt"))
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        assert_eq!(output, b"\xef\xbb\xbfname=x\n\xef\xbb\xbfinner\n");
+    }
+}
+
+#[test]
+fn test_add_bom() {
+    for flags in [&["--add-bom"][..], &["--add-bom", "--stream"][..]] {
+        for input in [&b"\xef\xbb\xbf${A}"[..], &b"${A}"[..]] {
+            let output = varsubst()
+                .args(flags)
+                .args(["-v", "A=1"])
+                .write_stdin(input)
+                .assert()
+                .success()
+                .get_output()
+                .stdout
+                .clone();
+            assert_eq!(output, b"\xef\xbb\xbf1", "{:?} {:?}", flags, input);
+        }
+    }
+    varsubst()
+        .args(["--add-bom", "--keep-bom"])
+        .write_stdin("")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_stripped_bom_still_counts_in_error_offsets() {
+    let output = varsubst()
+        .args(["--json-errors", "--missing", "error"])
+        .write_stdin(&b"\xef\xbb\xbf${NOPE}"[..])
+        .assert()
+        .code(5)
+        .get_output()
+        .stderr
+        .clone();
+    let diagnostics = json_diagnostics(&output);
+    assert_eq!(diagnostics[0]["span"], serde_json::json!([3, 10]));
+    assert_eq!(diagnostics[0]["column"], 1);
+}
+
+/// A tree of `count` templates of varying size, each naming itself
+fn generated_tree(count: usize) -> TempDir {
+    let files: Vec<(String, String)> = (0..count)
+        .map(|i| {
+            let body = "line ${A} ${B}\n".repeat(1 + (i * 37) % 200);
+            (format!("t/{:03}.tmpl", i), format!("file {}\n{}", i, body))
+        })
+        .collect();
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.as_str()))
+        .collect();
+    temp_tree(&files)
+}
+
+#[test]
+fn test_jobs_stdout_matches_serial_order() {
+    let dir = generated_tree(120);
+    let run = |jobs: &str| {
+        varsubst()
+            .current_dir(dir.path())
+            .args([
+                "--jobs", jobs, "-v", "A=1", "-v", "B=2", "--glob", "t/*.tmpl",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+    let serial = run("1");
+    assert!(serial.starts_with(b"file 0\nline 1 2\n"));
+    assert_eq!(run("8"), serial);
+}
+
+#[test]
+fn test_jobs_output_tree_matches_serial() {
+    let dir = generated_tree(120);
+    for jobs in ["1", "8"] {
+        varsubst()
+            .current_dir(dir.path())
+            .args(["-j", jobs, "-v", "A=x", "--recursive", "t", "--output-dir"])
+            .arg(format!("out{}", jobs))
+            .assert()
+            .success()
+            .stderr("120 rendered, 0 copied, 0 skipped\n");
+    }
+    for i in 0..120 {
+        let name = format!("{:03}.tmpl", i);
+        let serial = fs::read(dir.path().join("out1").join(&name)).unwrap();
+        let parallel = fs::read(dir.path().join("out8").join(&name)).unwrap();
+        assert_eq!(serial, parallel, "{}", name);
+    }
+}
+
+#[test]
+fn test_jobs_report_first_error_in_input_order() {
+    let dir = generated_tree(60);
+    fs::write(dir.path().join("t/020.tmpl"), "${BAD").unwrap();
+    fs::write(dir.path().join("t/050.tmpl"), "${").unwrap();
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-j", "8", "--fail-fast", "--glob", "t/*.tmpl"])
+        .assert()
+        .code(4)
+        .stdout(predicate::str::contains("file 19\n"))
+        .stdout(predicate::str::contains("file 21\n").not())
+        .stderr(predicate::str::contains("t/020.tmpl"))
+        .stderr(predicate::str::contains("t/050.tmpl").not());
+}
+
+#[test]
+fn test_invalid_jobs() {
+    varsubst()
+        .args(["--jobs", "0"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid number of jobs '0'"));
+}
+
+/// Templates where b.tmpl has a syntax error and d.tmpl an undefined variable
+fn mixed_tree() -> TempDir {
+    temp_tree(&[
+        ("t/a.tmpl", "a=${A}\n"),
+        ("t/b.tmpl", "b=${A\n"),
+        ("t/c.tmpl", "c=${A}\n"),
+        ("t/d.tmpl", "d=${NOPE}\n"),
+        ("t/e.tmpl", "e=${A}\n"),
+    ])
+}
+
+#[test]
+fn test_without_keep_going_first_error_aborts() {
+    let dir = mixed_tree();
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-j",
+            "1",
+            "--fail-fast",
+            "-f",
+            "-v",
+            "A=1",
+            "--recursive",
+            "t",
+            "--output-dir",
+            "out",
+        ])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("b.tmpl"))
+        .stderr(predicate::str::contains("d.tmpl").not());
+    assert!(dir.path().join("out/a.tmpl").exists());
+    assert!(!dir.path().join("out/c.tmpl").exists());
+}
+
+#[test]
+fn test_without_fail_fast_every_error_is_reported() {
+    let dir = mixed_tree();
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-f", "-v", "A=1", "--recursive", "t", "--output-dir", "out"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Substitution error in 'b.tmpl'"))
+        .stderr(predicate::str::contains("Undefined variable 'NOPE'"))
+        .stderr(predicate::str::contains("rendered").not());
+    // Nothing after the first failure is written
+    assert!(dir.path().join("out/a.tmpl").exists());
+    assert!(!dir.path().join("out/c.tmpl").exists());
+    assert!(!dir.path().join("out/e.tmpl").exists());
+}
+
+#[test]
+fn test_without_fail_fast_every_error_in_a_file() {
+    let (_dir, path) = temp_file("two.conf", "a=${}\nb=${B");
+    varsubst()
+        .arg(&path)
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "Invalid variable name '' at position 2",
+        ))
+        .stderr(predicate::str::contains("Unclosed brace at position 8"));
+
+    varsubst()
+        .args(["--fail-fast"])
+        .arg(&path)
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Invalid variable name"))
+        .stderr(predicate::str::contains("Unclosed brace").not());
+
+    varsubst()
+        .args(["--fail-fast", "--no-fail-fast"])
+        .arg(&path)
+        .assert()
+        .stderr(predicate::str::contains("Unclosed brace"));
+}
+
+#[test]
+fn test_without_fail_fast_exit_status_is_most_severe() {
+    // An undefined variable comes first, but the syntax error outranks it
+    let dir = temp_tree(&[("a.tmpl", "${NOPE} ${A"), ("b.tmpl", "${}\n")]);
+    let output = varsubst()
+        .current_dir(dir.path())
+        .args(["--json-errors", "-f", "a.tmpl", "b.tmpl"])
+        .assert()
+        .code(4)
+        .get_output()
+        .clone();
+    let diagnostics = json_diagnostics(&output.stderr);
+    let codes: Vec<&str> = (diagnostics.iter())
+        .map(|d| d["code"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        codes,
+        ["undefined-variable", "unclosed-brace", "invalid-name"]
+    );
+    assert_eq!(diagnostics[2]["file"], "b.tmpl");
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--fail-fast", "-f", "a.tmpl", "b.tmpl"])
+        .assert()
+        .code(5);
+}
+
+#[test]
+fn test_fail_fast_with_keep_going() {
+    // --keep-going still renders every file; --fail-fast limits each to one
+    // error
+    let dir = temp_tree(&[
+        ("a.tmpl", "${}\n${A"),
+        ("b.tmpl", "b=${B}\n"),
+        ("c.tmpl", "${}\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-k",
+            "--fail-fast",
+            "-v",
+            "B=1",
+            "a.tmpl",
+            "b.tmpl",
+            "c.tmpl",
+        ])
+        .assert()
+        .code(4)
+        .stdout("b=1\n")
+        .stderr(predicate::str::contains("'a.tmpl': Invalid"))
+        .stderr(predicate::str::contains("'a.tmpl': Unclosed").not())
+        .stderr(predicate::str::contains("'c.tmpl': Invalid"))
+        .stderr(predicate::str::contains("1 rendered, 2 failed"));
+
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-k", "-v", "B=1", "a.tmpl", "b.tmpl", "c.tmpl"])
+        .assert()
+        .code(4)
+        .stdout("b=1\n")
+        .stderr(predicate::str::contains("'a.tmpl': Unclosed"));
+}
+
+#[test]
+fn test_keep_going_renders_the_rest() {
+    let dir = mixed_tree();
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "-k",
+            "-f",
+            "-v",
+            "A=1",
+            "--recursive",
+            "t",
+            "--output-dir",
+            "out",
+        ])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Substitution error in 'b.tmpl'"))
+        .stderr(predicate::str::contains("Undefined variable 'NOPE'"))
+        .stderr(predicate::str::ends_with(
+            "3 rendered, 0 copied, 0 skipped, 2 failed\n",
+        ));
+    for (name, exists) in [
+        ("a", true),
+        ("b", false),
+        ("c", true),
+        ("d", false),
+        ("e", true),
+    ] {
+        let path = dir.path().join(format!("out/{}.tmpl", name));
+        assert_eq!(path.exists(), exists, "{}", name);
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/e.tmpl")).unwrap(),
+        "e=1\n"
+    );
+}
+
+#[test]
+fn test_keep_going_exit_status_and_summary() {
+    let dir = temp_tree(&[("a.tmpl", "${A}"), ("b.tmpl", "${NOPE}")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args([
+            "--keep-going",
+            "-f",
+            "-v",
+            "A=1",
+            "a.tmpl",
+            "b.tmpl",
+            "missing.tmpl",
+        ])
+        .assert()
+        .code(3)
+        .stdout("1")
+        .stderr(predicate::str::contains(
+            "Error reading input 'missing.tmpl'",
+        ))
+        .stderr(predicate::str::ends_with("1 rendered, 2 failed\n"));
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--keep-going", "-v", "A=1", "a.tmpl", "b.tmpl"])
+        .assert()
+        .success()
+        .stdout("1${NOPE}")
+        .stderr("2 rendered, 0 failed\n");
+}
+
+#[test]
+fn test_diff_against_in_place_file() {
+    let dir = temp_tree(&[("app.conf", "name=app\nport=${PORT}\nhost=${HOST}\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "-i", "-v", "PORT=80", "app.conf"])
+        .assert()
+        .code(1)
+        .stdout(
+            "--- app.conf\n+++ app.conf (rendered)\n@@ -1,3 +1,3 @@\n name=app\n\
+             -port=${PORT}\n+port=80\n host=${HOST}\n",
+        );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("app.conf")).unwrap(),
+        "name=app\nport=${PORT}\nhost=${HOST}\n"
+    );
+}
+
+#[test]
+fn test_diff_against_output_file() {
+    let dir = temp_tree(&[("t.tmpl", "a=${A}\n"), ("out.conf", "a=1\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "-v", "A=1", "t.tmpl", "-o", "out.conf"])
+        .assert()
+        .success()
+        .stdout("");
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "-v", "A=2", "t.tmpl", "-o", "out.conf"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("-a=1\n+a=2\n"));
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "-v", "A=2", "t.tmpl", "-o", "new.conf"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("@@ -0,0 +1 @@\n+a=2\n"));
+    assert!(!dir.path().join("new.conf").exists());
+}
+
+#[test]
+fn test_diff_multiple_files_labels_each_changed_file() {
+    let dir = temp_tree(&[
+        ("t/a.conf", "${A}\n"),
+        ("t/b.conf", "plain\n"),
+        ("t/c.conf", "${A}\n"),
+    ]);
+    let output = varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "--color", "never", "-v", "A=1", "-j", "4"])
+        .args(["t/a.conf", "t/b.conf", "t/c.conf"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("--- t/a.conf\n"), "{}", output);
+    assert!(output.contains("--- t/c.conf\n+++ t/c.conf (rendered)\n"));
+    assert!(!output.contains("t/b.conf"));
+
+    let output = varsubst()
+        .current_dir(dir.path())
+        .args(["--diff", "--color", "always", "-v", "A=1", "t/a.conf"])
+        .assert()
+        .code(1)
+        .get_output()
+        .stdout
+        .clone();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("\x1b[32m+1\x1b[0m\n"));
+}
+
+#[test]
+fn test_report_describes_each_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let report = dir.path().join("report.json");
+    varsubst()
+        .args(["--env-file", "tests/fixtures/app.env", "-v", "EXTRA=1"])
+        .args(["tests/fixtures/env_file.txt", "tests/fixtures/missing.txt"])
+        .args(["--report", report.to_str().unwrap()])
+        .assert()
+        .success();
+    let text = fs::read_to_string(&report).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+    assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(report["options"]["missing"], "keep");
+    assert_eq!(report["options"]["open"], "${");
+    assert_eq!(report["sources"].as_array().unwrap().len(), 2);
+    assert!(report["elapsed_ms"].as_f64().unwrap() >= 0.0);
+    assert_eq!(
+        report["summary"],
+        serde_json::json!({ "rendered": 2, "copied": 0, "changed": 0, "unchanged": 0, "failed": 0 })
+    );
+
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    let first = &files[0];
+    assert_eq!(first["input"], "tests/fixtures/env_file.txt");
+    assert_eq!(first["output"], serde_json::Value::Null);
+    assert_eq!(first["outcome"], "printed");
+    assert_eq!(first["copied"], false);
+    let names: Vec<&str> = (first["substituted"].as_array().unwrap().iter())
+        .map(|entry| entry["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["DB_HOST", "DB_PORT", "DB_NAME", "DB_PASS"]);
+    assert_eq!(first["undefined"], serde_json::json!([]));
+    assert_eq!(first["unused"], serde_json::json!(["EXTRA"]));
+    assert_eq!(first["input_bytes"], 46);
+    assert_eq!(first["error"], serde_json::Value::Null);
+
+    let second = &files[1];
+    assert_eq!(
+        second["undefined"],
+        serde_json::json!([{ "name": "HOST", "count": 1 }, { "name": "PORT", "count": 1 }])
+    );
+    assert_eq!(second["input_bytes"], second["output_bytes"]);
+
+    // Only names are reported, never values
+    assert!(!text.contains("db.internal"), "{}", text);
+    assert!(!text.contains("p@ss"), "{}", text);
+}
+
+#[test]
+fn test_report_records_outcomes_and_failures() {
+    let dir = temp_tree(&[
+        ("t/a.tmpl", "a=${A}\n"),
+        ("t/b.tmpl", "b=${B}\n"),
+        ("out/a.tmpl", "a=1\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-f", "-v", "A=1", "--keep-going", "--if-changed"])
+        .args([
+            "t/a.tmpl",
+            "t/b.tmpl",
+            "--output-dir",
+            "out",
+            "--report",
+            "report.json",
+        ])
+        .assert()
+        .code(5);
+    let text = fs::read_to_string(dir.path().join("report.json")).unwrap();
+    let report: serde_json::Value = serde_json::from_str(&text).unwrap();
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files[0]["outcome"], "unchanged");
+    assert_eq!(files[0]["output"], "out/a.tmpl");
+    assert_eq!(files[1]["outcome"], "failed");
+    assert_eq!(files[1]["output_bytes"], serde_json::Value::Null);
+    assert!(files[1]["error"].as_str().unwrap().contains("'B'"));
+    assert_eq!(report["summary"]["failed"], 1);
+    assert_eq!(report["summary"]["unchanged"], 1);
+}
+
+#[test]
+fn test_report_conflicts_with_stream() {
+    varsubst()
+        .args(["--stream", "--report", "r.json"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_append_to_environment() {
+    varsubst_with_env(&[("CFLAGS", "-Wall")])
+        .args(["-v", "CFLAGS+=-O2"])
+        .write_stdin("${CFLAGS}")
+        .assert()
+        .success()
+        .stdout("-Wall-O2");
+}
+
+#[test]
+fn test_append_to_missing_defines() {
+    varsubst()
+        .args(["--append-sep", " ", "-v", "CFLAGS+=-O2"])
+        .write_stdin("[${CFLAGS}]")
+        .assert()
+        .success()
+        .stdout("[-O2]");
+}
+
+#[test]
+fn test_repeated_appends_with_separator() {
+    varsubst_with_env(&[("CFLAGS", "-Wall")])
+        .args(["--append-sep", " ", "-v", "CFLAGS+=-O2", "-v", "CFLAGS+=-g"])
+        .write_stdin("${CFLAGS}")
+        .assert()
+        .success()
+        .stdout("-Wall -O2 -g");
+    // A plain definition resets the value appended to
+    varsubst()
+        .args(["--append-sep", ",", "-v", "L+=a", "-v", "L=b", "-v", "L+=c"])
+        .write_stdin("${L}")
+        .assert()
+        .success()
+        .stdout("b,c");
+}
+
+#[test]
+fn test_append_to_env_file_value() {
+    varsubst()
+        .args(["--env-file", "tests/fixtures/app.env", "--append-sep", "/"])
+        .args(["-v", "DB_NAME+=replica"])
+        .write_stdin("${DB_NAME}")
+        .assert()
+        .success()
+        .stdout("app \"prod\"/replica");
+}
+
+#[test]
+fn test_plus_in_variable_name_is_rejected() {
+    varsubst()
+        .args(["-v", "A+B=1"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("'+' is not allowed"));
+}
+
+#[test]
+fn test_var_prompt_without_terminal_fails_fast() {
+    varsubst()
+        .args(["--var-prompt", "-v", "B=1"])
+        .write_stdin("${A} ${B} ${C}")
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .code(5)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Cannot prompt for undefined variables (A, C): no terminal available",
+        ));
+}
+
+#[test]
+fn test_var_prompt_with_everything_defined_reads_stdin_template() {
+    varsubst()
+        .args(["--var-prompt", "-v", "A=1"])
+        .write_stdin("a=${A}\n")
+        .assert()
+        .success()
+        .stdout("a=1\n");
+}
+
+#[test]
+fn test_var_prompt_hidden_requires_var_prompt() {
+    varsubst()
+        .args(["--var-prompt-hidden", "*PASS*"])
+        .write_stdin("")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_secret_from_environment() {
+    varsubst_with_env(&[("DB_PASSWORD", "hunter2")])
+        .args(["--no-env", "--secret", "DB_PASSWORD"])
+        .write_stdin("pass=${DB_PASSWORD}")
+        .assert()
+        .success()
+        .stdout("pass=hunter2");
+}
+
+#[test]
+fn test_secret_is_redacted_from_verbose_and_dry_run() {
+    let dir = temp_tree(&[("t.tmpl", "user=${USER_NAME} pass=${DB_PASSWORD}\n")]);
+    let output = varsubst_with_env(&[("DB_PASSWORD", "hunter2")])
+        .current_dir(dir.path())
+        .args(["--no-env", "--secret", "DB_PASSWORD", "-v", "USER_NAME=bob"])
+        .args(["--verbose", "--verbose", "t.tmpl", "-o", "out.conf"])
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(output).unwrap();
+    assert!(
+        stderr.contains("DB_PASSWORD=<redacted> from --secret"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("hunter2"), "{}", stderr);
+
+    varsubst_with_env(&[("DB_PASSWORD", "hunter2")])
+        .current_dir(dir.path())
+        .args(["--no-env", "--secret", "DB_PASSWORD", "-v", "USER_NAME=bob"])
+        .args(["--dry-run", "t.tmpl", "-o", "other.conf"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "substituted: USER_NAME, DB_PASSWORD=<redacted>",
+        ))
+        .stderr(predicate::str::contains("hunter2").not());
+}
+
+#[test]
+fn test_secret_is_redacted_from_report_and_diff() {
+    let dir = temp_tree(&[("t.tmpl", "user=${USER_NAME}\npass=${DB_PASSWORD}\n")]);
+    varsubst_with_env(&[("DB_PASSWORD", "hunter2")])
+        .current_dir(dir.path())
+        .args(["--no-env", "--secret", "DB_PASSWORD", "-v", "USER_NAME=bob"])
+        .args(["t.tmpl", "--report", "report.json"])
+        .assert()
+        .success()
+        .stdout("user=bob\npass=hunter2\n");
+    let text = fs::read_to_string(dir.path().join("report.json")).unwrap();
+    assert!(!text.contains("hunter2"), "{}", text);
+    let report: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(
+        report["files"][0]["substituted"],
+        serde_json::json!([
+            { "name": "USER_NAME", "count": 1 },
+            { "name": "DB_PASSWORD", "count": 1, "value": "<redacted>" }
+        ])
+    );
+
+    varsubst_with_env(&[("DB_PASSWORD", "hunter2")])
+        .current_dir(dir.path())
+        .args(["--no-env", "--secret", "DB_PASSWORD", "-v", "USER_NAME=bob"])
+        .args(["--diff", "t.tmpl"])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("+pass=<redacted>\n"))
+        .stdout(predicate::str::contains("hunter2").not());
+}
+
+#[test]
+fn test_secret_without_environment_or_terminal_fails() {
+    varsubst_with_env(&[])
+        .args(["--secret", "DB_PASSWORD"])
+        .write_stdin("${DB_PASSWORD}")
+        .timeout(std::time::Duration::from_secs(10))
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "Secret 'DB_PASSWORD' is not in the environment",
+        ));
+}
+
+#[test]
+fn test_frontmatter_defaults() {
+    varsubst()
+        .args(["--frontmatter", "tests/fixtures/frontmatter.txt"])
+        .assert()
+        .success()
+        .stdout("listen localhost:8080\n");
+}
+
+#[test]
+fn test_frontmatter_is_overridden_by_other_sources() {
+    varsubst()
+        .args(["--frontmatter", "-v", "PORT=9090"])
+        .arg("tests/fixtures/frontmatter.txt")
+        .assert()
+        .success()
+        .stdout("listen localhost:9090\n");
+    varsubst_with_env(&[("HOST", "example.com")])
+        .args(["--frontmatter", "tests/fixtures/frontmatter.txt"])
+        .assert()
+        .success()
+        .stdout("listen example.com:8080\n");
+}
+
+#[test]
+fn test_frontmatter_is_optional_and_off_by_default() {
+    varsubst()
+        .args(["--frontmatter", "-v", "A=1"])
+        .write_stdin("a=${A}\n---\n")
+        .assert()
+        .success()
+        .stdout("a=1\n---\n");
+    varsubst()
+        .arg("tests/fixtures/frontmatter.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("---varsubst\n"));
+}
+
+#[test]
+fn test_frontmatter_errors_report_file_lines() {
+    let dir = temp_tree(&[
+        ("bad.tmpl", "---varsubst\nA=1\noops\n---\n${A}\n"),
+        ("undefined.tmpl", "---varsubst\nA=1\n---\n${A}\n${B}\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--frontmatter", "bad.tmpl"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Invalid frontmatter in 'bad.tmpl': line 3: expected KEY=VALUE, found 'oops'",
+        ));
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--frontmatter", "--missing", "error", "undefined.tmpl"])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("undefined.tmpl:5:1"));
+}
+
+#[test]
+fn test_strict_names_upper_rejects_lowercase() {
+    let dir = temp_tree(&[("app.conf", "host=${HOST}\nport=${port}\n")]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--strict-names", "upper", "-v", "HOST=h", "-v", "port=1"])
+        .arg("app.conf")
+        .assert()
+        .code(7)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "app.conf:2:6: reference to 'port' is not SCREAMING_SNAKE_CASE",
+        ));
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--strict-names", "any", "-v", "HOST=h", "-v", "port=1"])
+        .arg("app.conf")
+        .assert()
+        .success()
+        .stdout("host=h\nport=1\n");
+}
+
+#[test]
+fn test_strict_names_regex() {
+    varsubst()
+        .args(["--strict-names", "regex=APP_[A-Z]+", "-v", "APP_HOST=h"])
+        .write_stdin("${APP_HOST} ${MY_APP_HOST}")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "reference to 'MY_APP_HOST' is not matched by --strict-names regex 'APP_[A-Z]+'",
+        ));
+    varsubst()
+        .args(["--strict-names", "regex=("])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid regex '('"));
+}
+
+#[test]
+fn test_strict_names_in_check_mode() {
+    let dir = temp_tree(&[
+        ("good.tmpl", "${HOST}\n"),
+        ("bad.tmpl", "${HOST} ${port}\n"),
+    ]);
+    cargo_bin_cmd!("varsubst")
+        .current_dir(dir.path())
+        .args(["check", "--strict-names", "upper", "good.tmpl", "bad.tmpl"])
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "bad.tmpl:1:9: reference to 'port' is not SCREAMING_SNAKE_CASE",
+        ))
+        .stderr(predicate::str::contains("1 error(s) in 1 of 2 file(s)"));
+    // Syntax errors are more severe
+    fs::write(dir.path().join("bad.tmpl"), "${port} ${BROKEN").unwrap();
+    varsubst()
+        .current_dir(dir.path())
+        .args(["--check", "--strict-names", "upper", "bad.tmpl"])
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn test_ignore_prunes_recursive_walk() {
+    let out = TempDir::new().unwrap();
+    // node_modules holds invalid UTF-8, so rendering anything in it fails
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignored")])
+        .args(["--ignore", "node_modules/**", "--ignore", "*.min.js"])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr("2 rendered, 0 copied, 0 skipped, 2 ignored\n");
+    assert_eq!(tree_files(out.path()), vec!["app.conf", "static/app.js"]);
+
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignored")])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_ignore_lists_paths_with_verbose() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignored")])
+        .args(["--ignore", "node_modules/", "--ignore", "static/*.min.js"])
+        .arg("--verbose")
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("node_modules: ignored\n"))
+        .stderr(predicate::str::contains("static/app.min.js: ignored\n"));
+}
+
+#[test]
+fn test_ignore_filters_glob_matches() {
+    let dir = temp_tree(&[
+        ("web/app.tmpl", "a=${A}\n"),
+        ("web/node_modules/dep.tmpl", "b=${A}\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "A=1", "--glob", "web/**/*.tmpl"])
+        .args(["--ignore", "node_modules/"])
+        .assert()
+        .success()
+        .stdout("a=1\n");
+}
+
+#[test]
+fn test_ignore_file_negation_and_directories() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignorefile")])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr("3 rendered, 0 copied, 2 skipped, 3 ignored\n");
+    // The ignore files themselves are neither rendered nor copied
+    assert_eq!(
+        tree_files(out.path()),
+        vec!["app.conf", "docs/guide.conf", "keep.tmpl"]
+    );
+    assert_eq!(
+        fs::read_to_string(out.path().join("keep.tmpl")).unwrap(),
+        "keep=demo\n"
+    );
+}
+
+#[test]
+fn test_ignore_flag_applies_after_ignore_file() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignorefile")])
+        .args(["--ignore", "keep.tmpl"])
+        .arg("--verbose")
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("keep.tmpl: ignored\n"))
+        .stderr(predicate::str::contains("docs/local.conf: ignored\n"));
+    assert_eq!(tree_files(out.path()), vec!["app.conf", "docs/guide.conf"]);
+}
+
+#[test]
+fn test_no_ignore_file() {
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .args(["-v", "APP=demo", "--recursive", &fixture("ignorefile")])
+        .args(["--no-ignore-file", "--ext", ".conf"])
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .success();
+    assert_eq!(
+        tree_files(out.path()),
+        vec![
+            "app.conf",
+            "build/out.conf",
+            "docs/guide.conf",
+            "docs/local.conf"
+        ]
+    );
+
+    varsubst()
+        .args(["--no-ignore-file", "in.txt"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_invalid_ignore_file() {
+    let dir = temp_tree(&[(".varsubstignore", "ok\n[\n"), ("a.txt", "a\n")]);
+    let out = TempDir::new().unwrap();
+    varsubst()
+        .arg("--recursive")
+        .arg(dir.path())
+        .arg("--output-dir")
+        .arg(out.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(".varsubstignore' at line 2: '['"));
+}
+
+#[test]
+fn test_envsubst_compat_substitutes_everything() {
+    // echo 'Hello $USER, home is ${HOME}' | envsubst
+    varsubst_with_env(&[("USER", "alice"), ("HOME", "/home/alice")])
+        .arg("--envsubst-compat")
+        .write_stdin("Hello $USER, home is ${HOME}; $UNSET${UNSET}.\n")
+        .assert()
+        .success()
+        .stdout("Hello alice, home is /home/alice; .\n");
+}
+
+#[test]
+fn test_envsubst_compat_shell_format() {
+    // envsubst '$USER ${SHELL}' leaves every other reference alone
+    varsubst_with_env(&[("USER", "alice"), ("HOME", "/home/alice")])
+        .args(["--envsubst-compat", "$USER ${SHELL}"])
+        .write_stdin("$USER ${HOME} $SHELL|\n")
+        .assert()
+        .success()
+        .stdout("alice ${HOME} |\n");
+}
+
+#[test]
+fn test_envsubst_compat_is_lenient() {
+    varsubst_with_env(&[("A", "1")])
+        .arg("--envsubst-compat")
+        .write_stdin("\\$A ${A ${} $$A $5 ${A}\n")
+        .assert()
+        .success()
+        .stdout("\\1 ${A ${} $1 $5 1\n");
+}
+
+#[test]
+fn test_envsubst_compat_uses_variable_sources() {
+    varsubst()
+        .args(["-v", "A=from-flag", "--envsubst-compat"])
+        .write_stdin("$A\n")
+        .assert()
+        .success()
+        .stdout("from-flag\n");
+}
+
+#[test]
+fn test_envsubst_compat_usage() {
+    varsubst()
+        .args(["--envsubst-compat", "$A", "$B"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("single SHELL-FORMAT argument"));
+    varsubst()
+        .args([
+            "--envsubst-compat",
+            "--recursive",
+            "dir",
+            "--output-dir",
+            "out",
+        ])
+        .assert()
+        .code(2);
+}
+
+/// A value that breaks out of shell quotes, markup and JSON strings
+const HOSTILE: &str = "V=it's \"<script>alert(1)</script>\" & more";
+
+#[test]
+fn test_escape_values_modes() {
+    for (mode, value) in [
+        ("none", "it's \"<script>alert(1)</script>\" & more"),
+        ("shell", "'it'\\''s \"<script>alert(1)</script>\" & more'"),
+        (
+            "html",
+            "it&#39;s &quot;&lt;script&gt;alert(1)&lt;/script&gt;&quot; &amp; more",
+        ),
+        (
+            "xml",
+            "it&apos;s &quot;&lt;script&gt;alert(1)&lt;/script&gt;&quot; &amp; more",
+        ),
+        ("json", "it's \\\"<script>alert(1)</script>\\\" & more"),
+        (
+            "url",
+            "it%27s%20%22%3Cscript%3Ealert%281%29%3C%2Fscript%3E%22%20%26%20more",
+        ),
+    ] {
+        // The template's own markup and quotes are left alone
+        varsubst()
+            .args(["-v", HOSTILE, "--escape-values", mode])
+            .arg(fixture("escape_values.txt"))
+            .assert()
+            .success()
+            .stdout(format!(
+                "<p title=\"{value}\">{value}</p> & it's kept\n",
+                value = value
+            ));
+    }
+}
+
+#[test]
+fn test_escape_values_applies_to_frontmatter_defaults() {
+    varsubst()
+        .args(["--frontmatter", "--escape-values", "html"])
+        .write_stdin("---varsubst\nA=\"a<b\"\n---\n<i>${A}</i>\n")
+        .assert()
+        .success()
+        .stdout("<i>a&lt;b</i>\n");
+}
+
+#[test]
+fn test_escape_values_in_report() {
+    let dir = TempDir::new().unwrap();
+    let report = dir.path().join("report.json");
+    varsubst()
+        .args(["-v", "A=1", "--escape-values", "shell", "--report"])
+        .arg(&report)
+        .write_stdin("${A}\n")
+        .assert()
+        .success()
+        .stdout("'1'\n");
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(report["options"]["escape_values"], "shell");
+}
+
+#[test]
+fn test_escape_values_invalid_combinations() {
+    for flag in ["--binary-safe", "--recursive-values"] {
+        varsubst()
+            .args(["--escape-values", "html", flag])
+            .write_stdin("")
+            .assert()
+            .code(2);
+    }
+    varsubst()
+        .args(["--escape-values", "sql"])
+        .write_stdin("")
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_default_applies_only_when_undefined() {
+    varsubst_with_env(&[("HOST", "env-host")])
+        .args(["--default", "HOST=0.0.0.0", "--default", "PORT=8080"])
+        .args(["--default", "USER=nobody", "-v", "USER=alice"])
+        .write_stdin("${HOST}:${PORT} ${USER}\n")
+        .assert()
+        .success()
+        .stdout("env-host:8080 alice\n");
+}
+
+#[test]
+fn test_default_repeated_last_wins() {
+    varsubst()
+        .args(["--default", "PORT=80", "--default", "PORT=8080"])
+        .write_stdin("${PORT}\n")
+        .assert()
+        .success()
+        .stdout("8080\n");
+    varsubst()
+        .args(["--default", "PORT"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected KEY=VALUE"));
+}
+
+#[test]
+fn test_default_shown_as_defaulted_in_verbose() {
+    varsubst()
+        .args([
+            "--verbose",
+            "--verbose",
+            "-v",
+            "HOST=h",
+            "--default",
+            "PORT=80",
+        ])
+        .args(["--default", "HOST=unused"])
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success()
+        .stdout("host=h port=80\n")
+        .stderr(predicate::str::contains("--default (1)"))
+        .stderr(predicate::str::contains(format!(
+            "{}: 2 reference(s), 2 substituted, 0 undefined, 1 defaulted (PORT)",
+            fixture("missing.txt")
+        )))
+        .stderr(predicate::str::contains("  1:6: HOST from -v"))
+        .stderr(predicate::str::contains("  1:19: PORT defaulted"));
+}
+
+#[test]
+fn test_default_in_report() {
+    let dir = TempDir::new().unwrap();
+    let report = dir.path().join("report.json");
+    varsubst()
+        .args(["-v", "HOST=h", "--default", "PORT=80", "--report"])
+        .arg(&report)
+        .arg(fixture("missing.txt"))
+        .assert()
+        .success();
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+    let substituted = &report["files"][0]["substituted"];
+    assert_eq!(
+        substituted[0],
+        serde_json::json!({ "name": "HOST", "count": 1 })
+    );
+    assert_eq!(
+        substituted[1],
+        serde_json::json!({ "name": "PORT", "count": 1, "defaulted": true })
+    );
+    assert_eq!(report["files"][0]["unused"], serde_json::json!([]));
+}
+
+#[test]
+fn test_unused_defaults_only_fail_behind_flag() {
+    let run = |extra: &[&str]| {
+        let mut cmd = varsubst();
+        cmd.args(["--fail-on-unused", "-v", "HOST=h", "--default", "HOST=x"])
+            .args(["--default", "PORT=80", "--default", "DEBUG=0"])
+            .args(extra)
+            .arg(fixture("missing.txt"));
+        cmd.assert()
+    };
+    run(&[]).success().stdout("host=h port=80\n");
+    run(&["--fail-on-unused-defaults"])
+        .code(6)
+        .stderr(predicate::str::contains(
+            "Unused default for 'HOST' (overridden by -v)",
+        ))
+        .stderr(predicate::str::contains("Unused default for 'DEBUG'"))
+        .stderr(predicate::str::contains("'PORT'").not());
+    run(&["--fail-on-unused-defaults", "--allow-unused", "HOST"])
+        .code(6)
+        .stderr(predicate::str::contains("'HOST'").not());
+}
+
+#[test]
+fn test_strict_dollar() {
+    varsubst()
+        .args(["--strict-dollar", "-v", "X=1"])
+        .write_stdin("${X} $ {X}\n")
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "'$' does not start a reference at position 5",
+        ));
+    varsubst()
+        .args(["--strict-dollar", "--escape", "-v", "X=1"])
+        .write_stdin("${X} \\$5\n")
+        .assert()
+        .success()
+        .stdout("1 $5\n");
+}
+
+#[test]
+fn test_undefined_variable_suggestion() {
+    varsubst()
+        .args(["--fail-on-undefined", "-v", "DB_HOST=h"])
+        .write_stdin("host=${DB_HSOT}\n")
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "Undefined variable 'DB_HSOT' at position 5 (did you mean 'DB_HOST'?)",
+        ))
+        .stderr(predicate::str::contains(
+            "help: a variable named 'DB_HOST' exists\n",
+        ));
+}
+
+#[test]
+fn test_quoted_names() {
+    varsubst()
+        .args(["--quoted-names", "-v", "Display Name=Alice"])
+        .write_stdin("Hi ${'Display Name'} ${'Other Name'}\n")
+        .assert()
+        .success()
+        .stdout("Hi Alice ${'Other Name'}\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_var_cmd() {
+    varsubst()
+        .args(["--var-cmd", "GREETING=echo 'hello  world'"])
+        .args(["--var-cmd", "WHO=echo nobody", "-v", "WHO=you"])
+        .write_stdin("${GREETING}, ${WHO}\n")
+        .assert()
+        .success()
+        .stdout("hello  world, you\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_var_cmd_shell() {
+    varsubst()
+        .args(["--var-cmd-shell", "--var-cmd", "X=echo a | tr a b"])
+        .write_stdin("${X}")
+        .assert()
+        .success()
+        .stdout("b");
+    // Without the shell, the pipe is just another argument
+    varsubst()
+        .args(["--var-cmd", "X=echo a | tr a b"])
+        .write_stdin("${X}")
+        .assert()
+        .success()
+        .stdout("a | tr a b");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_var_cmd_failure() {
+    varsubst()
+        .args(["--var-cmd", "SHA=false"])
+        .write_stdin("unused\n")
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Command for 'SHA' failed: false: exited with status 1",
+        ));
+    varsubst()
+        .args(["--var-cmd", "X=no-such-command-varsubst"])
+        .write_stdin("")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "Command for 'X' failed: no-such-command-varsubst:",
+        ));
+    varsubst()
+        .args(["--var-cmd", "X"])
+        .write_stdin("")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected KEY=COMMAND"));
+}
+
+#[test]
+fn test_shell_quote_transform() {
+    varsubst()
+        .args(["-v", "SRC=my file's.txt", "-v", "DEST="])
+        .write_stdin("cp -- ${SRC@Q} ${DEST@Q} ${OTHER@Q}\n")
+        .assert()
+        .success()
+        .stdout("cp -- 'my file'\\''s.txt' '' ${OTHER@Q}\n");
+    varsubst()
+        .args(["--json-errors", "--check"])
+        .write_stdin("a ${X@Z}\n")
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(r#""code":"unknown-transform""#))
+        .stderr(predicate::str::contains(r#""span":[2,8]"#));
+}
+
+#[test]
+fn test_var_from_file() {
+    let dir = temp_tree(&[
+        ("server.crt", "-----BEGIN-----\nabc\n-----END-----\n"),
+        ("t.tmpl", "cert:\n${CERT}|\n"),
+    ]);
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "CERT=@server.crt", "t.tmpl"])
+        .assert()
+        .success()
+        .stdout("cert:\n-----BEGIN-----\nabc\n-----END-----|\n");
+    // @@ keeps the trailing newline
+    varsubst()
+        .current_dir(dir.path())
+        .args(["-v", "CERT=@@server.crt", "t.tmpl"])
+        .assert()
+        .success()
+        .stdout("cert:\n-----BEGIN-----\nabc\n-----END-----\n|\n");
+}
+
+#[test]
+fn test_var_literal_at() {
+    varsubst()
+        .args(["-v", r"HANDLE=\@alice", "-v", r"PATHISH=\x"])
+        .write_stdin("${HANDLE} ${PATHISH}")
+        .assert()
+        .success()
+        .stdout(r"@alice \x");
+}
+
+#[test]
+fn test_var_from_missing_file() {
+    varsubst()
+        .args(["-v", "CERT=@no-such.crt"])
+        .write_stdin("${CERT}")
+        .assert()
+        .code(3)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Error reading value of 'CERT' from 'no-such.crt':",
+        ));
+}
@@ -0,0 +1,46 @@
+//! The module written by `varsubst::codegen`, compiled and used
+
+use std::collections::HashMap;
+use std::fs;
+use varsubst::codegen::generate;
+use varsubst::Template;
+
+const TEMPLATES: &str = "tests/fixtures/codegen/templates";
+const MODULE: &str = "tests/fixtures/codegen/templates.rs";
+
+mod templates {
+    include!("fixtures/codegen/templates.rs");
+}
+
+#[test]
+fn test_module_is_up_to_date() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("templates.rs");
+    generate(TEMPLATES, &out).unwrap();
+    assert_eq!(
+        fs::read_to_string(&out).unwrap(),
+        fs::read_to_string(MODULE).unwrap(),
+        "regenerate {} after changing its templates",
+        MODULE
+    );
+}
+
+#[test]
+fn test_statics_render_like_parse() {
+    let vars = HashMap::from([
+        ("NAME", "World"),
+        ("DB_PORT", "6543"),
+        ("DB_USER", "o'neil"),
+    ]);
+    let statics: [(&Template, &str); 2] = [
+        (&templates::GREETING, "greeting.tmpl"),
+        (&templates::DB_CONFIG, "db-config.tmpl"),
+    ];
+    for (template, file) in statics {
+        let source = fs::read_to_string(format!("{}/{}", TEMPLATES, file)).unwrap();
+        let parsed = Template::parse(&source).unwrap();
+        assert_eq!(template, &parsed, "{}", file);
+        assert_eq!(template.render(&vars), parsed.render(&vars), "{}", file);
+    }
+    assert_eq!(templates::GREETING.render(&vars).unwrap(), "Hello World!\n");
+}
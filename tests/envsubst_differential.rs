@@ -0,0 +1,216 @@
+//! Differential testing of `--envsubst-compat` against GNU `envsubst`.
+//!
+//! `test_differential_random_templates` is ignored by default, since it
+//! needs GNU `envsubst` installed; it skips itself if the binary cannot be
+//! found, and `$ENVSUBST` may point at a specific one. Run it with
+//!
+//! ```sh
+//! cargo test --features cli --test envsubst_differential -- --ignored
+//! ```
+//!
+//! `$VARSUBST_DIFF_SEED` and `$VARSUBST_DIFF_CASES` choose the random seed and
+//! the number of templates. A divergence is minimized before it is reported,
+//! and should be added to [`REGRESSIONS`], which always runs.
+
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// The environment both commands run with: a defined, an empty and a
+/// multi-word value; other names in templates are undefined
+const ENV: &[(&str, &str)] = &[("A", "1"), ("B", ""), ("LONG_NAME_2", "x y")];
+
+/// Cases where compatibility is easy to get wrong: template, SHELL-FORMAT
+/// and the output GNU `envsubst` gives for them
+const REGRESSIONS: &[(&[u8], Option<&str>, &[u8])] = &[
+    (b"$A${A}", None, b"11"),
+    (b"${A", None, b"${A"),
+    (b"${A }", None, b"${A }"),
+    (b"${}", None, b"${}"),
+    (b"$", None, b"$"),
+    (b"${", None, b"${"),
+    (b"$$A", None, b"$1"),
+    (b"$${A}", None, b"$1"),
+    (b"\\$A \\${A}", None, b"\\1 \\1"),
+    (b"$1A ${9}", None, b"$1A ${9}"),
+    (b"$A_B $AB ${A}B", None, b"  1B"),
+    (b"${A}} {$A}", None, b"1} {1}"),
+    (b"$UNSET. $B|", None, b". |"),
+    (b"${A:-x} ${A-x}", None, b"${A:-x} ${A-x}"),
+    (b"\xff$A\xfe\n$LONG_NAME_2\n", None, b"\xff1\xfe\nx y\n"),
+    (
+        b"$A $UNSET ${LONG_NAME_2}",
+        Some("$A"),
+        b"1 $UNSET ${LONG_NAME_2}",
+    ),
+    (b"$A ${A}", Some("${A"), b"$A ${A}"),
+    (b"$B$A", Some("$B"), b"$A"),
+];
+
+/// varsubst in compat mode, or GNU `envsubst` at `program`
+fn run(program: Option<&PathBuf>, template: &[u8], shell_format: Option<&str>) -> Vec<u8> {
+    let mut command = match program {
+        Some(path) => Command::new(path),
+        None => {
+            let mut command = Command::new(env!("CARGO_BIN_EXE_varsubst"));
+            command.arg("--envsubst-compat");
+            command
+        }
+    };
+    command
+        .args(shell_format)
+        .env_clear()
+        .envs(ENV.iter().copied());
+    let mut child = (command.stdin(Stdio::piped()))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("command starts");
+    child.stdin.take().unwrap().write_all(template).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed on {:?}: {}",
+        command.get_program(),
+        String::from_utf8_lossy(template),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+fn test_regressions() {
+    for (template, shell_format, expected) in REGRESSIONS {
+        assert_eq!(
+            run(None, template, *shell_format),
+            *expected,
+            "template {:?} with {:?}",
+            String::from_utf8_lossy(template),
+            shell_format
+        );
+    }
+}
+
+/// GNU `envsubst`: `$ENVSUBST`, or the first one on `PATH`
+fn find_envsubst() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("ENVSUBST") {
+        return Some(PathBuf::from(path));
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join("envsubst"))
+        .find(|path| path.is_file())
+}
+
+/// xorshift64*, so a seed reproduces a run without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const NAMES: &[&str] = &["A", "B", "LONG_NAME_2", "UNSET", "_x", "A_B"];
+
+/// Pieces of a random template: literals, references and stray characters
+fn template_pieces(rng: &mut Rng) -> Vec<Vec<u8>> {
+    (0..rng.below(12))
+        .map(|_| {
+            let name = rng.pick(NAMES);
+            match rng.below(9) {
+                0 => format!("${}", name).into_bytes(),
+                1 => format!("${{{}}}", name).into_bytes(),
+                2 => format!("${{{}", name).into_bytes(),
+                3 => rng
+                    .pick(&["$", "{", "}", "\\", "$$", "${}", "$1"])
+                    .as_bytes()
+                    .to_vec(),
+                4 => rng.pick(&[" ", "\n", "-", ":", "9"]).as_bytes().to_vec(),
+                5 => vec![0xff],
+                _ => rng.pick(&["text", "a", "é", "_", "B"]).as_bytes().to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// The shortest template found, by dropping pieces and then bytes, on which
+/// the outputs still differ
+fn minimize(envsubst: &PathBuf, pieces: Vec<Vec<u8>>, shell_format: Option<&str>) -> Vec<u8> {
+    let differs = |template: &[u8]| {
+        run(Some(envsubst), template, shell_format) != run(None, template, shell_format)
+    };
+    let mut pieces = pieces;
+    let mut i = 0;
+    while i < pieces.len() {
+        let mut fewer = pieces.clone();
+        fewer.remove(i);
+        match differs(&fewer.concat()) {
+            true => pieces = fewer,
+            false => i += 1,
+        }
+    }
+    let mut template = pieces.concat();
+    let mut i = 0;
+    while i < template.len() {
+        let mut shorter = template.clone();
+        shorter.remove(i);
+        match differs(&shorter) {
+            true => template = shorter,
+            false => i += 1,
+        }
+    }
+    template
+}
+
+#[test]
+#[ignore = "needs GNU envsubst; run with --ignored"]
+fn test_differential_random_templates() {
+    let Some(envsubst) = find_envsubst() else {
+        eprintln!("skipped: GNU envsubst not found (set ENVSUBST to its path)");
+        return;
+    };
+    let seed = env::var("VARSUBST_DIFF_SEED").map_or(0x5eed, |seed| seed.parse().unwrap());
+    let cases = env::var("VARSUBST_DIFF_CASES").map_or(500, |cases| cases.parse().unwrap());
+
+    for (template, shell_format, _) in REGRESSIONS {
+        assert_eq!(
+            run(Some(&envsubst), template, *shell_format),
+            run(None, template, *shell_format),
+            "regression {:?} with {:?}",
+            String::from_utf8_lossy(template),
+            shell_format
+        );
+    }
+
+    let mut rng = Rng(seed | 1);
+    for case in 0..cases {
+        let pieces = template_pieces(&mut rng);
+        let shell_format = match rng.below(4) {
+            0 => Some(rng.pick(&["$A", "${B} $UNSET", "$LONG_NAME_2,$_x", "nothing"])),
+            _ => None,
+        }
+        .copied();
+        let template = pieces.concat();
+        if run(Some(&envsubst), &template, shell_format) != run(None, &template, shell_format) {
+            let minimal = minimize(&envsubst, pieces, shell_format);
+            panic!(
+                "case {} (seed {}) differs; minimized template {:?} with {:?}:\n\
+                 envsubst: {:?}\nvarsubst: {:?}",
+                case,
+                seed,
+                String::from_utf8_lossy(&minimal),
+                shell_format,
+                String::from_utf8_lossy(&run(Some(&envsubst), &minimal, shell_format)),
+                String::from_utf8_lossy(&run(None, &minimal, shell_format)),
+            );
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// @generated by varsubst::codegen from tests/fixtures/codegen/templates
+
+/// `db-config.tmpl`
+pub static DB_CONFIG: ::std::sync::LazyLock<::varsubst::Template> =
+    ::std::sync::LazyLock::new(|| {
+        ::varsubst::Template::from_bytes(b"VSTP\x01\x00i\x00\x00\x00host = \"${DB_HOST:-localhost}\"\nport = ${DB_PORT:-5432}\nuser = ${DB_USER@Q}\n# \\${NOT_SUBSTITUTED} is kept\n7\x00\x00\x00host = \"\"\nport = \nuser = \n# ${NOT_SUBSTITUTED} is kept\n\x07\x00\x00\x00\x00\x00\x00\x00\x00\x08\x00\x00\x00\x01\n\x00\x00\x00\x11\x00\x00\x00\x08\x00\x00\x00\x1d\x00\x00\x00\x04\x00-\x13\x00\x00\x00\x1c\x00\x00\x00\x00\x08\x00\x00\x00\x11\x00\x00\x00\x01(\x00\x00\x00/\x00\x00\x00&\x00\x00\x006\x00\x00\x00\x04\x00-1\x00\x00\x005\x00\x00\x00\x00\x11\x00\x00\x00\x19\x00\x00\x00\x01@\x00\x00\x00G\x00\x00\x00>\x00\x00\x00J\x00\x00\x00\x00Q\x00\x00\x19\x00\x00\x007\x00\x00\x00")
+            .expect("db-config.tmpl was compiled by another version of varsubst")
+    });
+
+/// `greeting.tmpl`
+pub static GREETING: ::std::sync::LazyLock<::varsubst::Template> =
+    ::std::sync::LazyLock::new(|| {
+        ::varsubst::Template::from_bytes(b"VSTP\x01\x00\x0f\x00\x00\x00Hello ${NAME}!\n\x08\x00\x00\x00Hello !\n\x03\x00\x00\x00\x00\x00\x00\x00\x00\x06\x00\x00\x00\x01\x08\x00\x00\x00\x0c\x00\x00\x00\x06\x00\x00\x00\r\x00\x00\x00\x00\x00\x00\x00\x06\x00\x00\x00\x08\x00\x00\x00")
+            .expect("greeting.tmpl was compiled by another version of varsubst")
+    });